@@ -1,16 +1,14 @@
 use ark_std::{end_timer, start_timer};
 use cupcake::integer_arith::scalar::Scalar;
 use cupcake::integer_arith::ArithUtils;
+use quail::common::params::ParameterSet;
 use quail::rlwe::context::{NTTContext, ShamirContext};
-use quail::rlwe::NUM_DIMENSION;
 use tokio::io::AsyncReadExt;
 use tokio::net::TcpListener;
 mod util;
 use crate::util::config::ConfigUtils;
 use std::sync::{Arc, Mutex};
 
-pub const MODULUS: [u64; 3] = [0xffffee001u64, 0xffffc4001u64, 0x1ffffe0001u64];
-
 fn serialize_shares_into(s0: &Vec<u64>, s1: &Vec<u64>, s2: &Vec<u64>, buf: &mut [u8]) {
     assert!(buf.len() >= s0.len() * 5 * 3);
     s0.iter()
@@ -68,24 +66,17 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .map(|x| x.into_str().unwrap())
         .collect();
     let aggregator_addr = config.get("aggregator");
-    // truncated nr_bits / NUM_DIMENSION
-    let nr_bits = config.get_int("nr_parameter_committee") as usize * 60 / NUM_DIMENSION;
+    let params = ParameterSet::with_dimension(config.get_int("num_dimension") as usize);
+    // truncated nr_bits / num_dimension
+    let nr_bits = config.get_int("nr_parameter_committee") as usize * 60 / params.num_dimension;
 
     let nr_players = players.len();
     let threshold = config.get_int("threshold") as usize;
     let listener = TcpListener::bind(&aggregator_addr).await?;
 
-    let mut shamir_context = vec![
-        ShamirContext::init(MODULUS[0], nr_players, threshold),
-        ShamirContext::init(MODULUS[1], nr_players, threshold),
-        ShamirContext::init(MODULUS[2], nr_players, threshold),
-    ];
+    let mut shamir_context = ShamirContext::init_all(&params, nr_players, threshold);
 
-    let ntt_context = vec![
-        NTTContext::init(MODULUS[0]),
-        NTTContext::init(MODULUS[1]),
-        NTTContext::init(MODULUS[2]),
-    ];
+    let ntt_context = NTTContext::init_all(&params);
 
     // recv_bits[0..2][0..nr_players][0..all bits]
     let mut recv_bits: Vec<Vec<Vec<u64>>> = vec![vec![Vec::new(); nr_players]; 3];
@@ -1,16 +1,21 @@
 use ark_std::{end_timer, start_timer};
 use cupcake::integer_arith::scalar::Scalar;
 use cupcake::integer_arith::ArithUtils;
+use quail::common::params::ParameterSet;
+use quail::common::{committee_pks, rsa_verify};
 use quail::rlwe::context::{NTTContext, ShamirContext};
-use quail::rlwe::NUM_DIMENSION;
 use ring_algorithm::chinese_remainder_theorem;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::TcpListener;
 mod util;
 use crate::util::config::ConfigUtils;
+use bincode::deserialize_from;
+use std::fs::File;
+use std::io::BufReader;
 use std::sync::{Arc, Mutex};
-
-pub const MODULUS: [u64; 3] = [0xffffee001u64, 0xffffc4001u64, 0x1ffffe0001u64];
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
 
 fn serialize_shares_into(s0: &Vec<u64>, s1: &Vec<u64>, s2: &Vec<u64>, buf: &mut [u8]) {
     assert!(buf.len() >= s0.len() * 5 * 3);
@@ -57,6 +62,42 @@ fn deserialize_shares(buf: &[u8]) -> (Vec<u64>, Vec<u64>, Vec<u64>) {
     (s0, s1, s2)
 }
 
+/// what a member signs with its identity key when it answers the ct `ct_buf` with the shares
+/// `buf`, its id first
+fn signed_digest(ct_buf: &[u8], buf: &[u8]) -> [u8; 32] {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(ct_buf);
+    hasher.update(buf);
+    hasher.finalize().into()
+}
+
+/// reconstruct every slot mod every RNS prime from the shares of the players `ids` on a polynomial
+/// of `degree`, with the players whose shares are wrong; None if too many of them are
+fn decode(
+    shamir_context: &[ShamirContext],
+    shares: &[Vec<Vec<u64>>],
+    ids: &[usize],
+    degree: usize,
+    nr_bits: usize,
+) -> Option<(Vec<Vec<u64>>, Vec<usize>)> {
+    let mut wrong = Vec::new();
+    let mut ret = Vec::with_capacity(shamir_context.len());
+    for (k, context) in shamir_context.iter().enumerate() {
+        let decoder = context.robust_decoder(ids, degree)?;
+        let mut x = Vec::with_capacity(nr_bits);
+        for i in 0..nr_bits {
+            let y: Vec<u64> = ids.iter().map(|&j| shares[k][j][i]).collect();
+            let r = context.robust_reconstruct_with(&decoder, &y)?;
+            wrong.extend(r.wrong);
+            x.push(r.secret);
+        }
+        ret.push(x);
+    }
+    wrong.sort_unstable();
+    wrong.dedup();
+    Some((ret, wrong))
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let config = ConfigUtils::init("config.yaml");
@@ -73,81 +114,145 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     let nr_players = players.len();
     let threshold = config.get_int("threshold") as usize;
+    let params = ParameterSet::with_dimension(config.get_int("num_dimension") as usize);
+    let timeout = Duration::from_secs(config.get_int("committee_timeout") as u64);
     let listener = TcpListener::bind(&aggregator_addr).await?;
 
-    let mut shamir_context = vec![
-        ShamirContext::init(MODULUS[0], nr_players, threshold),
-        ShamirContext::init(MODULUS[1], nr_players, threshold),
-        ShamirContext::init(MODULUS[2], nr_players, threshold),
-    ];
+    let shamir_context = ShamirContext::init_all(&params, nr_players, threshold);
+    // the members sign their shares with the identity keys of committee_identity
+    let committee = Arc::new(committee_pks(nr_players));
+
+    let ntt_context = NTTContext::init_all(&params);
+
+    // the ct in NTT form written by ciphertext_generator, repeated over all the slots
+    let ct_buf = {
+        let f = BufReader::new(File::open("./data/ciphertext.txt").unwrap());
+        let ct: Vec<Vec<u64>> = deserialize_from(f).unwrap();
+        let ct: Vec<Vec<u64>> = ct
+            .iter()
+            .map(|c| c.iter().cycle().take(nr_bits).cloned().collect())
+            .collect();
+        let mut buf = vec![0u8; nr_bits * 15 + 1];
+        serialize_shares_into(&ct[0], &ct[1], &ct[2], &mut buf[1..]);
+        Arc::new(buf)
+    };
 
-    let ntt_context = vec![
-        NTTContext::init(MODULUS[0]),
-        NTTContext::init(MODULUS[1]),
-        NTTContext::init(MODULUS[2]),
-    ];
+    // the noise is shared at degree 2t, see committee_member::share_noise
+    let nr_shares = 2 * threshold + 1;
+    let deadline = Instant::now() + timeout;
 
-    // recv_bits[0..2][0..nr_players][0..all noise]
-    let mut recv_bits: Vec<Vec<Vec<u64>>> = vec![vec![Vec::new(); nr_players]; 3];
+    // recv_bits[0..2][0..nr_players][0..all noise], filled for the players in received
+    let recv_bits: Vec<Vec<Vec<u64>>> = vec![vec![Vec::new(); nr_players]; 3];
+    let received: Vec<usize> = Vec::with_capacity(nr_players);
 
-    let mutex_bits = Arc::new(Mutex::new(recv_bits));
+    let mutex_bits = Arc::new(Mutex::new((recv_bits, received)));
     let mb = mutex_bits.clone();
+    let (arrived_tx, mut arrived_rx) = mpsc::unbounded_channel::<usize>();
 
-    let f = tokio::spawn(async move {
-        let mut handles = Vec::new();
-        for _ in 0..nr_players {
+    tokio::spawn(async move {
+        loop {
             // maybe we can new a thread for each socket to improve latency
-            let (mut socket, _) = listener.accept().await.unwrap();
+            let (mut socket, _) = match listener.accept().await {
+                Ok(x) => x,
+                Err(e) => {
+                    eprintln!("failed to accept; err = {:?}", e);
+                    continue;
+                }
+            };
 
             let mbits = mutex_bits.clone();
+            let ct_buf = ct_buf.clone();
+            let arrived_tx = arrived_tx.clone();
+            let committee = committee.clone();
 
-            handles.push(tokio::spawn(async move {
-                let mut buf = vec![0u8; nr_bits * 15 + 1];
+            tokio::spawn(async move {
+                let mut buf = ct_buf.as_ref().clone();
 
                 // write it to the committee first
-                socket.write_all(&buf).await.unwrap();
-                let n = match socket.read_exact(&mut buf).await {
-                    // socket closed
-                    Ok(n) if n == 0 => return,
-                    Ok(n) => n,
-                    Err(e) => {
-                        eprintln!("failed to read from socket; err = {:?}", e);
-                        return;
-                    }
-                };
+                if let Err(e) = socket.write_all(&buf).await {
+                    eprintln!("failed to write to socket; err = {:?}", e);
+                    return;
+                }
+                if let Err(e) = socket.read_exact(&mut buf).await {
+                    eprintln!("failed to read from socket; err = {:?}", e);
+                    return;
+                }
+                // the signature follows the shares
+                let mut signature = Vec::new();
+                if let Err(e) = socket.read_to_end(&mut signature).await {
+                    eprintln!("failed to read from socket; err = {:?}", e);
+                    return;
+                }
+                // the shares only count toward the 2t + 1 once their sender is known
                 let src = buf[0] as usize;
-                let (s0, s1, s2) = deserialize_shares(&buf[1..n]);
+                if src >= nr_players
+                    || !rsa_verify(&committee[src], &signed_digest(&ct_buf, &buf), &signature)
+                {
+                    eprintln!("shares not signed by player {}", src);
+                    return;
+                }
+                let (s0, s1, s2) = deserialize_shares(&buf[1..]);
                 {
                     let mut l = mbits.as_ref().lock().unwrap();
-                    l[0][src].extend(s0);
-                    l[1][src].extend(s1);
-                    l[2][src].extend(s2);
+                    let (bits, received) = &mut *l;
+                    if received.contains(&src) {
+                        eprintln!("duplicate shares from player {}", src);
+                        return;
+                    }
+                    bits[0][src] = s0;
+                    bits[1][src] = s1;
+                    bits[2][src] = s2;
+                    received.push(src);
                 }
-            }));
+                let _ = arrived_tx.send(src);
+            });
         }
-        futures::future::join_all(handles).await;
     });
 
-    f.await?;
-    let rb = mb.lock().unwrap();
-    //shamir_context[0].threshold *= 2;
-    //shamir_context[1].threshold *= 2;
-    //shamir_context[2].threshold *= 2;
-    let mut ret: Vec<Vec<u64>> = vec![Vec::with_capacity(nr_bits); 3];
-    for k in 0..3usize {
-        for i in 0..nr_bits {
-            let mut shares = vec![0u64; nr_players];
-            for j in 0..nr_players {
-                shares[j] = rb[k][j][i];
+    // decode as soon as the shares agree. Any 2t + 1 shares fit a polynomial of degree 2t, so a
+    // decoding is only trusted with one share more than it needs to correct the wrong ones it
+    // finds; otherwise wait for another share, till every player is in or the deadline
+    let mut nr_arrived = 0;
+    let mut last = false;
+    let (ids, mut ret, wrong) = loop {
+        match tokio::time::timeout_at(deadline, arrived_rx.recv()).await {
+            Ok(Some(_)) => nr_arrived += 1,
+            _ => last = true,
+        }
+        last |= nr_arrived == nr_players;
+        if nr_arrived < nr_shares {
+            if last {
+                return Err(format!("only {} of {} shares received", nr_arrived, nr_shares).into());
+            }
+            continue;
+        }
+        let l = mb.lock().unwrap();
+        let (rb, received) = &*l;
+        let mut ids = received.clone();
+        ids.sort_unstable();
+        match decode(&shamir_context, rb, &ids, 2 * threshold, nr_bits) {
+            Some((ret, wrong)) if last || ids.len() > nr_shares + 2 * wrong.len() => {
+                break (ids, ret, wrong)
+            }
+            _ if last => {
+                let e = format!("too many wrong shares among the {} received", ids.len());
+                return Err(e.into());
             }
-            ret[k].push(shamir_context[k].reconstruct(&shares));
+            _ => {}
         }
+    };
+    for j in (0..nr_players).filter(|j| !ids.contains(j)) {
+        eprintln!("player {} is late", j);
+    }
+    for j in wrong {
+        eprintln!("player {} sent wrong shares", j);
     }
 
-    for k in (0..ret[0].len()).step_by(NUM_DIMENSION) {
-        ntt_context[0].lazy_inverse_ntt_inplace(&mut ret[0][k..k + NUM_DIMENSION]);
-        ntt_context[1].lazy_inverse_ntt_inplace(&mut ret[1][k..k + NUM_DIMENSION]);
-        ntt_context[2].lazy_inverse_ntt_inplace(&mut ret[2][k..k + NUM_DIMENSION]);
+    let n = params.num_dimension;
+    for k in (0..ret[0].len()).step_by(n) {
+        ntt_context[0].lazy_inverse_ntt_inplace(&mut ret[0][k..k + n]);
+        ntt_context[1].lazy_inverse_ntt_inplace(&mut ret[1][k..k + n]);
+        ntt_context[2].lazy_inverse_ntt_inplace(&mut ret[2][k..k + n]);
     }
 
     //let q = vec![
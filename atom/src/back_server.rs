@@ -1,50 +1,77 @@
-use ark_groth16::verifier;
-use rayon::ThreadPool;
-use std::{borrow::BorrowMut, process::Command};
+use rayon::{prelude::*, ThreadPool};
 
+use crate::committee_driver::CommitteeDriver;
 use crate::common::aggregation::{
+    index::KeyIndex,
     merkle::*,
-    node::{CommitEntry, SummationEntry, SummationLeaf},
+    node::{CommitEntry, SummationLeaf},
+    sample::{Sample, SampledNodes},
     McTree, MsTree,
 };
+use crate::common::board_service::{sign_post, BoardServiceClient, Post};
+use crate::common::params::ParameterSet;
+use crate::common::server_service::AggregationError;
+use crate::common::{parse_rsa_pk, server_sk};
+use crate::rlwe::{aggregate::Aggregate, encoding::Encoder};
 use cancellable_timer::{Canceller, Timer};
-use quail::{
-    rlwe::NUM_DIMENSION,
-    zksnark::{Prover, Verifier},
-};
-use rand::{Rng, SeedableRng};
+use futures::Future;
+use quail::zksnark::{statement, Verifier};
+use rsa::{RsaPrivateKey, RsaPublicKey};
 use std::{
-    convert::Into,
     sync::{Arc, Condvar, Mutex, MutexGuard, RwLock},
-    time::Duration,
+    time::{Duration, SystemTime},
 };
-use tracing::{error, event, instrument, span, warn, Level};
-
-use crate::util::{config::ConfigUtils, log::init_tracing};
-use std::process::Child;
+use tarpc::{client::RpcError, context};
+use tokio::{runtime::Handle, task::JoinHandle};
+use tracing::{error, instrument, warn};
 
 // 2 hours
 const WAITTIME: u64 = 7200;
+// the committee is asked again for the random point, waiting twice as long each time
+const RANDOM_PT_ATTEMPTS: u32 = 4;
 
 #[derive(Debug, Clone, Copy)]
 #[repr(u8)]
 pub enum STAGE {
     Commit,
     Data,
+    // Ms is full and summed up, which takes the committee for the random point
+    Summation,
     Verify,
 }
 pub type STATE = (STAGE, u32);
+
+/// a short id of the client for the logs
+fn fingerprint(rsa_pk: &[u8]) -> String {
+    blake3::hash(rsa_pk).to_hex()[..16].to_string()
+}
+
 #[derive(Clone)]
 pub struct Server {
     mc: Arc<RwLock<Vec<McTree>>>,
     cond: Arc<(Mutex<STATE>, Condvar)>,
     ms: Arc<RwLock<Vec<MsTree>>>,
+    // the rsa_pk which have committed or uploaded in this round
+    index: Arc<Mutex<KeyIndex>>,
     nr_parameter: u32,
     pool: Arc<ThreadPool>,
     //pvk: Arc<Vec<u8>>,
-    //verifier: Arc<Verifier>,
+    verifier: Arc<Verifier>,
     canceller: Arc<RwLock<Canceller>>,
-    child: Arc<Mutex<Child>>,
+    committee: CommitteeDriver,
+    // the random bits generation of the coming round, returning # of ready members
+    random_bits: Arc<Mutex<Option<JoinHandle<usize>>>>,
+    handle: Handle,
+    nr_slots: usize,
+    board: BoardServiceClient,
+    // signs the posts to the board
+    identity: Arc<RsaPrivateKey>,
+    params: ParameterSet,
+    // the running sum of the cts accepted in this round
+    aggregate: Arc<Mutex<Aggregate>>,
+    encoder: Encoder,
+    // the clients upload their model updates, the global model moves by their mean
+    model: Arc<RwLock<Vec<f32>>>,
 }
 
 impl Server {
@@ -54,18 +81,23 @@ impl Server {
         nr_sybil: u32,
         nr_parameter: u32,
         pool: &Arc<ThreadPool>,
+        committee: CommitteeDriver,
+        board: BoardServiceClient,
+        params: ParameterSet,
+        encoder: Encoder,
     ) -> Self {
-        let nr_ct = (nr_parameter / 4096) as usize;
+        let nr_ct = params.nr_ct(nr_parameter as usize);
+        let nr_slots = nr_ct * params.num_dimension;
         let mc = (0..nr_ct)
             .into_iter()
-            .map(|_| McTree::new(nr_real + nr_sim, nr_sybil))
+            .map(|_| McTree::new(nr_real + nr_sim, nr_sybil, params.hash_fn))
             .collect();
         let ms = (0..nr_ct)
             .into_iter()
-            .map(|_| MsTree::new(nr_real + nr_sim, nr_sybil))
+            .map(|_| MsTree::new(nr_real + nr_sim, nr_sybil, &params))
             .collect();
-        //let verifier = Verifier::new("./data/verifying_key");
-        //let verifier_ref = Arc::new(verifier);
+        let aggregate = Aggregate::new(&params, nr_ct);
+        let verifier = Verifier::new("./data/verifying_key.txt");
 
         let mc_ref = Arc::new(RwLock::new(mc));
         let ms_ref = Arc::new(RwLock::new(ms));
@@ -74,25 +106,54 @@ impl Server {
         //let timer_cond = cond.clone();
 
         let canceller = Timer::after(Duration::from_secs(WAITTIME), move |_| {}).unwrap();
-        warn!("Atom: Asking committee to generate random bits");
-        let child = Command::new("bash")
-            .arg("/home/ubuntu/quail/test.sh")
-            .arg("offline")
-            .spawn()
-            .unwrap();
+        let handle = Handle::current();
+        let random_bits = Self::generate_random_bits(&handle, &committee, nr_slots);
 
         Self {
             mc: mc_ref,
             ms: ms_ref,
+            index: Arc::new(Mutex::new(KeyIndex::new())),
             cond: cond,
             nr_parameter,
             // pvk: pvk.clone(),
-            //verifier: verifier.clone(),
+            verifier: Arc::new(verifier),
             pool: pool.clone(),
             canceller: Arc::new(RwLock::new(canceller)),
-            child: Arc::new(Mutex::new(child)),
+            committee,
+            random_bits: Arc::new(Mutex::new(Some(random_bits))),
+            handle,
+            nr_slots,
+            board,
+            identity: Arc::new(server_sk()),
+            params,
+            aggregate: Arc::new(Mutex::new(aggregate)),
+            encoder,
+            model: Arc::new(RwLock::new(vec![0f32; nr_parameter as usize])),
         }
     }
+
+    /// post to the board in the background, the board returns false if it rejects the post
+    fn post<F>(handle: &Handle, what: &'static str, post: F)
+    where
+        F: Future<Output = Result<bool, RpcError>> + Send + 'static,
+    {
+        handle.spawn(async move {
+            match post.await {
+                Ok(true) => {}
+                r => error!("Atom: fail to post {} to the board: {:?}", what, r),
+            }
+        });
+    }
+
+    fn generate_random_bits(
+        handle: &Handle,
+        committee: &CommitteeDriver,
+        nr_slots: usize,
+    ) -> JoinHandle<usize> {
+        warn!("Atom: Asking committee to generate random bits");
+        let committee = committee.clone();
+        handle.spawn(async move { committee.generate_random_bits(nr_slots as u32).await })
+    }
     #[inline]
     pub fn is_waitable(current: &STATE, target: STATE) -> bool {
         // get model: in Verify/Commit wait for commit
@@ -101,7 +162,7 @@ impl Server {
         // get mc proof: in Commit/Data wait for data
         // aggregate data: in Data wait for data
         // ----enough data or timeout----
-        // get ms proof: in Data/verify wait for verify
+        // get ms proof: in Data/Summation/Verify wait for verify
         // ----anounce the summed CT
         // verify: in Verify wait for verify
         match (current.0, target.0) {
@@ -110,47 +171,163 @@ impl Server {
             (STAGE::Commit, STAGE::Data) => current.1 == target.1,
             (STAGE::Data, STAGE::Data) => current.1 == target.1,
             (STAGE::Data, STAGE::Verify) => current.1 == target.1,
+            (STAGE::Summation, STAGE::Verify) => current.1 == target.1,
             (STAGE::Verify, STAGE::Verify) => current.1 == target.1,
             _ => true,
         }
     }
 
     #[instrument(skip_all)]
-    pub fn aggregate_commit(&self, round: u32, rsa_pk: Vec<u8>, commitment: Vec<[u8; 32]>) {
+    pub fn aggregate_commit(
+        &self,
+        round: u32,
+        rsa_pk: Vec<u8>,
+        commitment: Vec<[u8; 32]>,
+        signatures: Vec<Vec<u8>>,
+    ) -> Result<(), AggregationError> {
         // wait for enough commitments
-        // TODO should check if duplicate commitments exist
         // TODO maybe wait for some time rather than some # of commitments
+        let nr_ct = self.mc.as_ref().read().unwrap().len();
+        if commitment.len() != nr_ct || signatures.len() != nr_ct {
+            warn!(
+                "Atom: reject malformed commitment of {} in round {}",
+                fingerprint(&rsa_pk),
+                round
+            );
+            return Err(AggregationError::MalformedUpload);
+        }
+        let entries: Vec<CommitEntry> = commitment
+            .iter()
+            .zip(signatures.into_iter())
+            .map(|(hash, signature)| CommitEntry {
+                rsa_pk: rsa_pk.clone(),
+                hash: *hash,
+                signature,
+            })
+            .collect();
+        let hash_fn = self.params.hash_fn;
+        self.verify_signatures(round, &rsa_pk, nr_ct, |pk, j| {
+            entries[j].verify_signature(pk, hash_fn, round, j)
+        })?;
 
         let (lock, cvar) = &*self.cond;
-        let mut state = lock.lock().unwrap();
+        let state = lock.lock().unwrap();
         match *state {
-            (STAGE::Commit, round) => {}
-            _ => return,
+            (STAGE::Commit, r) if r == round => {}
+            _ => return Err(AggregationError::WrongStage),
         }
 
         let mut mc = self.mc.as_ref().write().unwrap();
-        for i in 0..commitment.len() {
-            mc[i].insert_node(CommitEntry {
-                rsa_pk: rsa_pk.clone(),
-                hash: commitment[i],
-            });
+        if mc[0].is_full() {
+            return Err(AggregationError::TreeFull);
         }
-        // TODO ideally all gen_tree should return true; maybe check this for robutness
-        let flag = {
-            let mut flag = false;
-            for i in 0..commitment.len() {
-                flag = mc[i].gen_tree()
-            }
-            flag
-        };
-        // if we've got enough elements, move to next stage
-        if flag {
-            *state = (STAGE::Data, state.1);
-            warn!("Server move to stage {:?}", *state);
-            cvar.notify_all();
+        if let Err(e) = self.index.lock().unwrap().commit(&rsa_pk) {
+            warn!(
+                "Atom: reject commitment of {} in round {}: {:?}",
+                fingerprint(&rsa_pk),
+                round,
+                e
+            );
+            return Err(e);
         }
-        drop(mc);
+        // the key is new and the trees have room, so every tree takes the leaf
+        for (t, entry) in mc.iter_mut().zip(entries.into_iter()) {
+            let inserted = t.insert_node(entry);
+            assert!(inserted, "Mc rejects a new commitment");
+        }
+        // if we've got enough elements, publish Mc and move to next stage
+        if !mc[0].is_full() {
+            return Ok(());
+        }
+        // only the commitment filling the trees gets here, build them without blocking the
+        // RPCs waiting for the state; the others wait for the trees, then find them full
+        let round = state.1;
         drop(state);
+        mc.par_iter_mut().for_each(|t| {
+            t.gen_tree();
+        });
+        let roots: Vec<[u8; 32]> = mc.iter().map(|t| t.get_merkle_root().unwrap()).collect();
+        let nr_leaf = mc[0].commit_array.len() as u32;
+        drop(mc);
+
+        let board = self.board.clone();
+        let signature = sign_post(&self.identity, Post::McRoots, round, &(&roots, nr_leaf));
+        Self::post(&self.handle, "Mc roots", async move {
+            board
+                .post_mc_roots(context::current(), round, roots, nr_leaf, signature)
+                .await
+        });
+        let mut state = lock.lock().unwrap();
+        *state = (STAGE::Data, round);
+        warn!("Server move to stage {:?}", *state);
+        cvar.notify_all();
+        Ok(())
+    }
+
+    /// check the signatures of the nr_ct entries of an upload under rsa_pk, `verify(pk, j)`
+    /// checking the j-th one
+    fn verify_signatures<F>(
+        &self,
+        round: u32,
+        rsa_pk: &[u8],
+        nr_ct: usize,
+        verify: F,
+    ) -> Result<(), AggregationError>
+    where
+        F: Fn(&RsaPublicKey, usize) -> bool + Sync,
+    {
+        let pk = parse_rsa_pk(rsa_pk).ok_or_else(|| {
+            warn!(
+                "Atom: reject the upload of {} in round {}, not an RSA public key",
+                fingerprint(rsa_pk),
+                round
+            );
+            AggregationError::InvalidKey
+        })?;
+        let ids: Vec<usize> = self.pool.install(|| {
+            (0..nr_ct)
+                .into_par_iter()
+                .filter(|j| !verify(&pk, *j))
+                .collect()
+        });
+        if ids.is_empty() {
+            Ok(())
+        } else {
+            warn!(
+                "Atom: reject the upload of {} in round {}, invalid signatures of {:?}",
+                fingerprint(rsa_pk),
+                round,
+                ids
+            );
+            Err(AggregationError::InvalidSignature(ids))
+        }
+    }
+
+    fn verify_proofs(
+        &self,
+        round: u32,
+        rsa_pk: &[u8],
+        leafs: &[SummationLeaf],
+        proofs: &[Vec<u8>],
+    ) -> Result<(), AggregationError> {
+        // each proof is of its ct under the commitment to it, i.e., with the uploaded nonce
+        let hash_fn = self.params.hash_fn;
+        let inputs: Vec<Vec<i128>> = leafs
+            .iter()
+            .map(|x| statement(&x.c0, &x.c1, &x.commitment(hash_fn)))
+            .collect();
+        let verifier = self.verifier.clone();
+        self.pool
+            .install(|| verifier.verify_proofs_batch_from_bytes(proofs, &inputs))
+            .map_err(|ids| {
+                warn!(
+                    "Atom: reject data of {} in round {}, invalid proofs of ct {:?}",
+                    fingerprint(rsa_pk),
+                    round,
+                    ids
+                );
+                AggregationError::InvalidProof(ids)
+            })
     }
 
     #[instrument(skip_all)]
@@ -162,67 +339,200 @@ impl Server {
         c1: Vec<Vec<i128>>,
         nonce: Vec<[u8; 16]>,
         proofs: Vec<Vec<u8>>,
-    ) {
-        let (lock, cvar) = &*self.cond;
-        let mut state = lock.lock().unwrap();
-        // if never possible to get the lock, return
-        match *state {
-            (STAGE::Data, round) => {}
-            _ => return,
+        signatures: Vec<Vec<u8>>,
+    ) -> Result<(), AggregationError> {
+        let nr_ct = self.ms.as_ref().read().unwrap().len();
+        if [
+            c0.len(),
+            c1.len(),
+            nonce.len(),
+            proofs.len(),
+            signatures.len(),
+        ]
+        .iter()
+        .any(|x| *x != nr_ct)
+        {
+            warn!(
+                "Atom: reject malformed data of {} in round {}",
+                fingerprint(&rsa_pk),
+                round
+            );
+            return Err(AggregationError::MalformedUpload);
         }
-        let mut ms = self.ms.as_ref().write().unwrap();
-
-        // TODO ideally all gen_tree should return true; maybe check this for robutness
-        let mut flag = false;
-        for i in 0..nonce.len() {
-            flag = {
-                ms[i].insert_node(SummationLeaf::from_ct(
+        // only the committed clients may upload, checked again once the data is verified
+        if let Err(e) = self.index.lock().unwrap().check_upload(&rsa_pk) {
+            warn!(
+                "Atom: reject data of {} in round {}: {:?}",
+                fingerprint(&rsa_pk),
+                round,
+                e
+            );
+            return Err(e);
+        }
+        let leafs: Vec<SummationLeaf> = signatures
+            .into_iter()
+            .enumerate()
+            .map(|(i, signature)| {
+                SummationLeaf::from_ct(
                     rsa_pk.clone(),
                     c0[i].clone(),
                     c1[i].clone(),
                     nonce[i],
                     proofs[i].clone(),
-                ));
-                ms[i].gen_tree()
-            };
+                )
+                .with_signature(signature)
+            })
+            .collect();
+        // verify the signatures and the proofs before taking the lock, so the uploads are
+        // verified in parallel
+        let hash_fn = self.params.hash_fn;
+        self.verify_signatures(round, &rsa_pk, nr_ct, |pk, j| {
+            leafs[j].verify_signature(pk, hash_fn, round, j)
+        })?;
+        // whatever the features, so the canned proof of a simulation build is turned down too
+        self.verify_proofs(round, &rsa_pk, &leafs, &proofs)?;
+
+        let (lock, cvar) = &*self.cond;
+        let state = lock.lock().unwrap();
+        // if never possible to get the lock, return
+        match *state {
+            (STAGE::Data, r) if r == round => {}
+            _ => return Err(AggregationError::WrongStage),
         }
+        let mut ms = self.ms.as_ref().write().unwrap();
 
-        // TODO also verify the proofs
-        // Since verification cost is little and we don't focus on aggregator cost, we don't really check the proofs here
-        if flag {
+        if ms[0].is_full() {
+            return Err(AggregationError::TreeFull);
+        }
+        if let Err(e) = self.index.lock().unwrap().upload(&rsa_pk) {
+            warn!(
+                "Atom: reject data of {} in round {}: {:?}",
+                fingerprint(&rsa_pk),
+                round,
+                e
+            );
+            return Err(e);
+        }
+        // the key is committed and the trees have room, so every tree takes the leaf
+        for (t, leaf) in ms.iter_mut().zip(leafs.into_iter()) {
+            let inserted = t.insert_node(leaf);
+            assert!(inserted, "Ms rejects the data of a committed client");
+        }
+        self.aggregate.lock().unwrap().add(&c0, &c1);
+
+        if ms[0].is_full() {
+            // only the data filling the trees gets here. The uploads coming later find the round
+            // summing up and are turned down before they wait for Ms, and Ms is only held while
+            // the trees are built, not while the committee and the board are asked
+            let mut state = state;
+            *state = (STAGE::Summation, state.1);
+            let round = state.1;
+            drop(state);
+            ms.par_iter_mut().for_each(|t| {
+                t.gen_tree();
+            });
+            let leaf_roots: Vec<[u8; 32]> = ms.iter().map(|t| t.get_leaf_root().unwrap()).collect();
+            drop(ms);
             //let _ = self.canceller.as_ref().read().unwrap().cancel();
-            *state = (STAGE::Verify, state.1);
+            // the leafs are fixed, now draw the random point and sum them up
+            let random_pt = match self.draw_random_pt(round, leaf_roots) {
+                Some(random_pt) => random_pt,
+                None => {
+                    // no point, no summation: drop the data and move on to the next round
+                    error!("Atom: fail to draw the random point, abort round {}", round);
+                    let mut state = lock.lock().unwrap();
+                    *state = (STAGE::Commit, round + 1);
+                    self.ms.write().unwrap().iter_mut().for_each(|t| t.clear());
+                    self.mc.write().unwrap().iter_mut().for_each(|t| t.clear());
+                    self.index.lock().unwrap().clear();
+                    self.aggregate.lock().unwrap().clear();
+                    warn!("Server move to stage {:?}", *state);
+                    cvar.notify_all();
+                    return Ok(());
+                }
+            };
+            let mut ms = self.ms.as_ref().write().unwrap();
+            ms.par_iter_mut().for_each(|t| t.gen_summation(random_pt));
+            let roots: Vec<[u8; 32]> = ms.iter().map(|t| t.get_merkle_root().unwrap()).collect();
+            drop(ms);
+            let board = self.board.clone();
+            let signature = sign_post(&self.identity, Post::MsRoots, round, &roots);
+            Self::post(&self.handle, "Ms roots", async move {
+                board
+                    .post_ms_roots(context::current(), round, roots, signature)
+                    .await
+            });
+            // the sum the roots evaluate, which the verifiers check and the committee decrypts
+            let (c_0, c_1) = self.aggregate.lock().unwrap().summed_ct();
+            let board = self.board.clone();
+            let signature = sign_post(&self.identity, Post::SummedCt, round, &(&c_0, &c_1));
+            Self::post(&self.handle, "summed ct", async move {
+                board
+                    .post_summed_ct(context::current(), round, c_0, c_1, signature)
+                    .await
+            });
+            let mut state = lock.lock().unwrap();
+            *state = (STAGE::Verify, round);
             warn!("Server move to stage {:?}", *state);
             cvar.notify_all();
             let cond = self.cond.clone();
             let mc = self.mc.clone();
             let ms = self.ms.clone();
-            let child_ref = self.child.clone();
+            let index = self.index.clone();
+            let committee = self.committee.clone();
+            let random_bits = self.random_bits.clone();
+            let handle = self.handle.clone();
+            let nr_slots = self.nr_slots;
+            let board = self.board.clone();
+            let identity = self.identity.clone();
+            let aggregate = self.aggregate.clone();
+            let encoder = self.encoder.clone();
+            let model = self.model.clone();
             let canceller = Timer::after(Duration::from_secs(3600), move |_| {
                 let (lock, cvar) = &*cond.clone();
                 let mut state = lock.lock().unwrap();
                 if let STAGE::Verify = state.0 {
-                    // TODO update the global model
+                    let mut random_bits = random_bits.lock().unwrap();
+                    if let Some(bits) = random_bits.take() {
+                        let nr_ready = handle.block_on(bits).unwrap_or(0);
+                        warn!("Atom: {} committee members have random bits", nr_ready);
+                    }
+                    // decrypt the summed ct on the board and update the global model
                     warn!("Atom: Asking committee to decrypt");
-                    let mut child = child_ref.lock().unwrap();
-                    child.wait().unwrap();
-                    // decrypt
-                    Command::new("bash")
-                        .arg("/home/ubuntu/quail/test.sh")
-                        .arg("online")
-                        .output()
-                        .expect("failed to execute process");
+                    let mut aggregate = aggregate.lock().unwrap();
+                    let round = state.1;
+                    match handle.block_on(committee.decrypt(round, aggregate.nr_slots())) {
+                        Some(pt) => {
+                            let sum = aggregate.decrypt(&pt);
+                            let nr_summands = aggregate.nr_summands;
+                            let update = encoder.decode(&sum, nr_summands);
+                            model
+                                .write()
+                                .unwrap()
+                                .iter_mut()
+                                .zip(update.iter())
+                                .for_each(|(w, u)| *w += u / nr_summands as f32);
+                            warn!("Atom: global model updated by {} clients", nr_summands);
+                            let b = board.clone();
+                            let signature = sign_post(&identity, Post::Decryption, round, &pt);
+                            Self::post(&handle, "decryption", async move {
+                                b.post_decryption(context::current(), round, pt, signature)
+                                    .await
+                            });
+                        }
+                        None => error!(
+                            "Atom: committee fails to decrypt, status {:?}",
+                            committee.status()
+                        ),
+                    }
                     // start next random bit generation
-                    warn!("Atom: Asking committee to generate random bits");
-                    *child = Command::new("bash")
-                        .arg("/home/ubuntu/quail/test.sh")
-                        .arg("offline")
-                        .spawn()
-                        .unwrap();
+                    *random_bits = Some(Self::generate_random_bits(&handle, &committee, nr_slots));
                     *state = (STAGE::Commit, state.1 + 1);
                     //println!("Server move to stage {:?}", *state);
                     mc.write().unwrap().iter_mut().for_each(|t| t.clear());
                     ms.write().unwrap().iter_mut().for_each(|t| t.clear());
+                    index.lock().unwrap().clear();
+                    aggregate.clear();
                     warn!("Server move to stage {:?}", *state);
                     cvar.notify_all();
                 }
@@ -230,126 +540,132 @@ impl Server {
             .unwrap();
             *self.canceller.as_ref().write().unwrap() = canceller;
         }
-        drop(state);
+        Ok(())
     }
 
-    //type GetMcProofFut = Ready<MerkleProof>;
-    pub fn get_mc_proof(&self, round: u32, rsa_pk: Vec<u8>) {
-        //pub fn get_mc_proof(&self, round: u32, rsa_pk: Vec<u8>) -> Vec<MerkleProof> {
+    /// publish the roots of the Ms leafs, then ask the committee to fix the random point on the
+    /// board; the members missing the first time are asked again with a backoff
+    fn draw_random_pt(&self, round: u32, leaf_roots: Vec<[u8; 32]>) -> Option<i128> {
+        let signature = sign_post(&self.identity, Post::LeafRoots, round, &leaf_roots);
+        self.handle.block_on(async {
+            if !self
+                .board
+                .post_leaf_roots(context::current(), round, leaf_roots, signature)
+                .await
+                .unwrap_or(false)
+            {
+                return None;
+            }
+            let mut backoff = Duration::from_secs(1);
+            for attempt in 0..RANDOM_PT_ATTEMPTS {
+                self.committee.random_point(round).await;
+                let mut ctx = context::current();
+                ctx.deadline = SystemTime::now() + backoff;
+                if let Ok(random_pt) = self.board.get_random_pt(ctx, round).await {
+                    return Some(random_pt);
+                }
+                warn!(
+                    "Atom: no random point of round {} after {} attempts",
+                    round,
+                    attempt + 1
+                );
+                backoff *= 2;
+            }
+            None
+        })
+    }
+
+    /// wait till the server reaches `target`, return None if the round has already moved on
+    fn wait_for(&self, target: STATE) -> Option<MutexGuard<STATE>> {
         let (lock, cvar) = &*self.cond;
-        let mut state = lock.lock().unwrap();
+        let state = lock.lock().unwrap();
         // if never possible to get the lock, return
-        if !Self::is_waitable(&*state, (STAGE::Data, round)) {
-            return;
-            //return vec![MerkleProof {
-            //    lemma: Vec::new(),
-            //    path: Vec::new(),
-            //}];
+        if !Self::is_waitable(&*state, target) {
+            return None;
         }
         // otherwise wait till the state
-        state = cvar
-            .wait_while(state, |state| match state.0 {
-                STAGE::Data => false,
-                _ => true,
+        let state = cvar
+            .wait_while(state, |state| {
+                state.1 < target.1 || (state.1 == target.1 && (state.0 as u8) < (target.0 as u8))
             })
             .unwrap();
+        if state.1 == target.1 {
+            Some(state)
+        } else {
+            None
+        }
+    }
+
+    pub fn get_mc_proof(&self, round: u32, rsa_pk: Vec<u8>) -> Vec<MerkleProof> {
+        let state = match self.wait_for((STAGE::Data, round)) {
+            Some(state) => state,
+            None => return Vec::new(),
+        };
         let mc = self.mc.as_ref().read().unwrap();
         drop(state);
-        //let ret = match state.0 {
-        //    STAGE::Data => {
-        //        let mc = self.mc.as_ref().read().unwrap();
-        //        mc.iter().map(|x| x.get_proof(&rsa_pk)).collect()
-        //    }
-        //    _ => Vec::new(),
-        //};
-        //ret
-        //mc.iter().map(|x| x.get_proof(&rsa_pk)).collect()
+        // a client not included in Mc gets nothing back
+        mc.iter()
+            .map(|x| x.get_proof(&rsa_pk))
+            .collect::<Option<Vec<MerkleProof>>>()
+            .unwrap_or_default()
     }
 
-    //type GetMsProofFut = Ready<MerkleProof>;
-    pub fn get_ms_proof(&self, round: u32, rsa_pk: Vec<u8>) {
-        //pub fn get_ms_proof(&self, round: u32, rsa_pk: Vec<u8>) -> Vec<MerkleProof> {
-        let (lock, cvar) = &*self.cond;
-        let mut state = lock.lock().unwrap();
-        //if never possible to get the lock, return
-        if !Self::is_waitable(&*state, (STAGE::Verify, round)) {
-            //return vec![MerkleProof {
-            //    lemma: Vec::new(),
-            //    path: Vec::new(),
-            //}];
-        }
-        // otherwise wait till the state
-        state = cvar
-            .wait_while(state, |state| match state.0 {
-                STAGE::Verify => false,
-                _ => true,
-            })
-            .unwrap();
+    pub fn get_ms_proof(&self, round: u32, rsa_pk: Vec<u8>) -> Vec<MerkleProof> {
+        let state = match self.wait_for((STAGE::Verify, round)) {
+            Some(state) => state,
+            None => return Vec::new(),
+        };
         let ms = self.ms.as_ref().read().unwrap();
         drop(state);
-        //ms.iter().map(|x| x.get_proof(&rsa_pk)).collect()
-        //let ret = match state.0 {
-        //    STAGE::Verify => {
-        //        let ms = self.ms.as_ref().read().unwrap();
-        //        drop(state);
-        //        ms.iter().map(|x| x.get_proof(&rsa_pk)).collect()
-        //    }
-        //    _ => Vec::new(),
-        //};
-        //ret
+        ms.iter()
+            .map(|x| x.get_proof(&rsa_pk))
+            .collect::<Option<Vec<MerkleProof>>>()
+            .unwrap_or_default()
     }
 
     //type VerifyFut = Ready<Vec<(SummationEntry, MerkleProof)>>;
+    /// the nodes sampled by a verifier from the trees of each ct in ct_id, see `Sample::collect`
     pub fn verify(
         &self,
         round: u32,
+        s: u32,
         vinit: u32,
-        non_leaf_id: Vec<u32>,
+        grandparents: Vec<u32>,
         ct_id: Vec<usize>,
-    ) -> Vec<Vec<(SummationEntry, MerkleProof)>> {
+    ) -> Vec<SampledNodes> {
         let (lock, _cvar) = &*self.cond;
         let state = lock.lock().unwrap();
 
         // if not verify phase, just return
-        match *state {
-            (STAGE::Verify, round) => {}
-            _ => return Vec::new(),
-        };
+        if !matches!(*state, (STAGE::Verify, r) if r == round) {
+            return Vec::new();
+        }
         let ms = self.ms.as_ref().read().unwrap();
         let mc = self.mc.as_ref().read().unwrap();
         drop(state);
-        //first all the leafs
-        let mut ret: Vec<Vec<(SummationEntry, MerkleProof)>> = Vec::new();
-        for k in ct_id {
-            let mut t: Vec<(SummationEntry, MerkleProof)> = Vec::new();
-            if k >= mc.len() {
-                warn!("K larger than Mc len");
-                continue;
-            }
-            for i in 0..5 + 1 {
-                let node: SummationEntry = ms[k].get_leaf_node(i as u32 + vinit);
-                if let SummationEntry::Leaf(_) = node {
-                    let mc_proof: MerkleProof = mc[k].get_proof_by_id(i + vinit).into();
-                    let ms_proof: MerkleProof = ms[k].get_proof_by_id(i + vinit).into();
-                    t.push((SummationEntry::Commit(mc[k].get_node(i + vinit)), mc_proof));
-                    t.push((node, ms_proof));
-                } else {
-                    warn!("Atom: verify not a leaf node");
-                }
-            }
-            for i in &non_leaf_id {
-                let ms_proof: MerkleProof = ms[k].get_proof_by_id(*i).into();
-                t.push((ms[k].get_nonleaf_node(*i), ms_proof));
-            }
-            ret.push(t);
+        let n = mc[0].commit_array.len() as u32;
+        if n == 0 {
+            return Vec::new();
         }
-        ret
+        let sample = Sample::new(n, s, vinit, grandparents);
+        // all or nothing, so the i-th reply is of the i-th ct asked for
+        ct_id
+            .iter()
+            .map(|k| {
+                if *k >= mc.len() {
+                    warn!("K larger than Mc len");
+                    return None;
+                }
+                sample.collect(&mc[*k], &ms[*k])
+            })
+            .collect::<Option<Vec<_>>>()
+            .unwrap_or_default()
     }
 
     //type RetrieveModelFut = Ready<Vec<u8>>;
     pub fn retrieve_model(&self, round: u32) -> Vec<u8> {
         let (lock, cvar) = &*self.cond;
-        let mut state = lock.lock().unwrap();
+        let state = lock.lock().unwrap();
         // if never possible to get the lock, return
         if !Self::is_waitable(&*state, (STAGE::Commit, round)) {
             return Vec::new();
@@ -357,18 +673,18 @@ impl Server {
         // otherwise wait till the state
         let _state = cvar
             .wait_while(state, |state| match *state {
-                (STAGE::Commit, round) => false,
+                (STAGE::Commit, _) => false,
                 _ => true,
             })
             .unwrap();
 
-        let mut rng = rand::rngs::StdRng::from_entropy();
-        //(0..self.nr_parameter).map(|_| rng.gen::<u8>()).collect()
-        (0..self.nr_parameter).map(|_| 0u8).collect()
+        // the parameters of the global model as little-endian f32
+        let model = self.model.read().unwrap();
+        model.iter().flat_map(|w| w.to_le_bytes()).collect()
     }
 
     //type RetrieveProvingKeyFut = Ready<Vec<u8>>;
-    pub fn retrieve_proving_key(&self, round: u32) -> Vec<u8> {
+    pub fn retrieve_proving_key(&self) -> Vec<u8> {
         //future::ready(self.pvk.as_ref().clone())
         vec![0u8; 1]
     }
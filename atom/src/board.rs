@@ -1,3 +1,4 @@
+mod common;
 mod rlwe;
 mod zksnark;
 use std::borrow::Borrow;
@@ -5,7 +6,7 @@ use std::io::BufRead;
 
 use crate::rlwe::context::*;
 //use crate::zksnark::*;
-use crate::zksnark::Prover;
+use crate::zksnark::{statement, Prover};
 use crate::zksnark::ProverOffline;
 use crate::zksnark::ProverOnline;
 use ark_groth16::lonhh_create_proof;
@@ -13,7 +14,6 @@ use cupcake::integer_arith::scalar::Scalar;
 use cupcake::integer_arith::ArithUtils;
 use cupcake::polyarith::lazy_ntt::{lazy_inverse_ntt_u64, lazy_ntt_u64};
 use cupcake::rqpoly::RqPolyContext;
-use quail::rlwe::context::MODULUS;
 //use quail::rlwe::context::{self, Context};
 use ark_relations::r1cs::{
     ConstraintMatrices, ConstraintSynthesizer, ConstraintSystem, ConstraintSystemRef,
@@ -21,12 +21,13 @@ use ark_relations::r1cs::{
 };
 use ark_std::{end_timer, start_timer};
 use quail::rlwe::context::{NTTContext, ShamirContext};
-use quail::rlwe::NUM_DIMENSION;
 use quail::zksnark::Verifier;
 use ring_algorithm::chinese_remainder_theorem;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::{TcpListener, TcpStream};
 mod util;
+use crate::common::aggregation::node::{ct_commitment, rsa_pk_digest};
+use crate::common::params::ParameterSet;
 use crate::rlwe::PublicKey;
 use crate::util::config::ConfigUtils;
 use bincode::deserialize_from;
@@ -40,37 +41,12 @@ use std::sync::{Arc, Mutex};
 
 fn main() {
     //{
-    let prover = Prover::setup("./data/encryption.txt");
-    let enc_pk = {
-        let (pk0, pk1) = {
-            let mut pk_0 = [0i128; 4096];
-            let mut pk_1 = [0i128; 4096];
-            let file = match File::open("./data/encryption.txt") {
-                Ok(f) => f,
-                Err(_) => panic!(),
-            };
-            let reader = BufReader::new(file);
-            for line in reader.lines() {
-                if let Ok(l) = line {
-                    let vec = l.split(" ").collect::<Vec<&str>>();
-                    for i in 1..vec.len() {
-                        if l.contains("pk_0") {
-                            if let Ok(x) = i128::from_str_radix(vec[i], 10) {
-                                pk_0[i - 1] = x;
-                            }
-                        } else if l.contains("pk_1") {
-                            if let Ok(x) = i128::from_str_radix(vec[i], 10) {
-                                pk_1[i - 1] = x;
-                            }
-                        }
-                    }
-                }
-            }
-            (pk_0.to_vec(), pk_1.to_vec())
-        };
-        PublicKey::new(&pk0, &pk1)
-    };
-    let (r, e0, e1, d0, d1, ct) = enc_pk.encrypt(&[0u8; 4096]);
+    let config = ConfigUtils::init("config.yaml");
+    let params = ParameterSet::with_dimension(config.get_int("num_dimension") as usize)
+        .with_plaintext_bits(config.get_int("plaintext_bits") as usize);
+    let prover = Prover::setup(&params, "./data/encryption.txt");
+    let enc_pk = PublicKey::from_file(&params, "./data/encryption.txt");
+    let (r, e0, e1, d0, d1, ct) = enc_pk.encrypt(&vec![0u16; params.num_dimension]);
     //let prover = Prover::new("./data/encryption.txt", "./data/proving_key.txt");
     let mut c0s = Vec::new();
     let mut c1s = Vec::new();
@@ -87,8 +63,15 @@ fn main() {
     d1s.push(d1);
     c0s.push(ct.c_0);
     c1s.push(ct.c_1);
-    m.push(vec![0i128; 4096]);
-    let ret = prover.create_proof_in_bytes(&c0s, &c1s, &rs, &e0s, &e1s, &d0s, &d1s, &m);
+    m.push(vec![0i128; params.num_dimension]);
+    // the proof is reused by every client in simulation, so under the commitment of an empty
+    // rsa_pk and a zero nonce
+    let nonce = vec![[0u8; 16]];
+    let cm = vec![ct_commitment(params.hash_fn, &[], &nonce[0], &c0s[0], &c1s[0])];
+    let digest = rsa_pk_digest(params.hash_fn, &[]);
+    let ret = prover.create_proof_in_bytes(
+        &c0s, &c1s, &cm, &digest, &nonce, &rs, &e0s, &e1s, &d0s, &d1s, &m,
+    );
     println!("{:?}", ret[0].len());
     let verifier = Verifier::new("./data/verifying_key.txt");
     let inputs: Vec<i128> = c0s[0]
@@ -117,7 +100,7 @@ fn main() {
         let mut f = BufReader::new(File::open("./data/ct1.txt").unwrap());
         deserialize_from(&mut f).unwrap()
     };
-    let inputs: Vec<i128> = ct0.iter().cloned().chain(ct1.iter().cloned()).collect();
+    let inputs = statement(&ct0, &ct1, &cm[0]);
     let mut file = File::open("./data/proof.txt").unwrap();
     let mut buffer = Vec::<u8>::new();
     file.read_to_end(&mut buffer).unwrap();
@@ -126,7 +109,6 @@ fn main() {
     //let prover = ProverOnline::setup("./data/encryption.txt");
     //let prover = ProverOffline::setup("./data/encryption.txt");
     //}
-    //let config = ConfigUtils::init("config.yaml");
     //let players: Vec<String> = config
     //    .settings
     //    .get_array("players")
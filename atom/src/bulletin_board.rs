@@ -0,0 +1,367 @@
+use futures::{future, prelude::*};
+use rsa::RsaPublicKey;
+use std::{
+    collections::BTreeMap,
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex},
+};
+use tarpc::serde::Serialize;
+use tarpc::{
+    context,
+    server::{self, Channel},
+    tokio_serde::formats::Bincode,
+};
+use tokio::sync::Notify;
+use tracing::warn;
+use tracing_subscriber::filter::LevelFilter;
+mod common;
+use crate::common::board_service::{reshare_digest, verify_post, BoardService, Post, Reshare};
+use crate::common::params::ParameterSet;
+use crate::common::random_point::{self, Contribution};
+use crate::common::rsa_verify;
+mod util;
+use crate::util::{config::ConfigUtils, log::init_tracing};
+
+/// # of rounds whose records are kept, counting the last decrypted one
+const KEPT_ROUNDS: u32 = 4;
+
+/// everything published for one round, each field is written once
+#[derive(Default)]
+pub struct RoundRecord {
+    pub mc_roots: Option<Vec<[u8; 32]>>,
+    pub nr_leaf: Option<u32>,
+    pub leaf_roots: Option<Vec<[u8; 32]>>,
+    /// the contributions to the random point accepted so far, the first t + 1 fix it
+    pub contributions: Vec<Contribution>,
+    pub random_pt: Option<i128>,
+    pub ms_roots: Option<Vec<[u8; 32]>>,
+    pub summed_ct: Option<(Vec<Vec<u64>>, Vec<Vec<u64>>)>,
+    pub decryption: Option<Vec<Vec<u64>>>,
+}
+
+#[derive(Clone)]
+pub struct Board {
+    peer: SocketAddr,
+    // only the server can post, signing with this identity key
+    server: Arc<RsaPublicKey>,
+    // the identity keys of the committee members, who post the contributions
+    committee: Arc<Vec<RsaPublicKey>>,
+    threshold: usize,
+    modulus: i128,
+    records: Arc<Mutex<BTreeMap<u32, RoundRecord>>>,
+    // what the committee members publish in a resharing, by epoch, kind and member
+    reshare: Arc<Mutex<BTreeMap<(u32, Reshare, u32), Vec<u8>>>>,
+    notify: Arc<Notify>,
+}
+
+impl Board {
+    /// post `x` to `round` if the server signed it, `f` writing it into the record
+    fn post<T, F>(&self, round: u32, kind: Post, x: T, signature: &[u8], f: F) -> bool
+    where
+        T: Serialize,
+        F: FnOnce(&mut RoundRecord, T) -> bool,
+    {
+        if !verify_post(&self.server, kind, round, &x, signature) {
+            warn!(
+                "Atom: {} posts {:?} of round {} not signed by the server",
+                self.peer, kind, round
+            );
+            return false;
+        }
+        let mut records = self.records.lock().unwrap();
+        let flag = f(records.entry(round).or_default(), x);
+        if flag {
+            // the rounds before the last few are over, the clients and verifiers are done
+            if kind == Post::Decryption {
+                records.retain(|r, _| *r + KEPT_ROUNDS > round);
+            }
+            self.notify.notify_waiters();
+        } else {
+            warn!("Atom: {:?} of round {} has been posted", kind, round);
+        }
+        flag
+    }
+
+    /// wait till `f` finds the value in the record of `round`
+    async fn get<T, F>(&self, round: u32, f: F) -> T
+    where
+        F: Fn(&RoundRecord) -> Option<T>,
+    {
+        loop {
+            // register before checking, so no post in between is missed
+            let notified = self.notify.notified();
+            if let Some(x) = self.records.lock().unwrap().get(&round).and_then(&f) {
+                return x;
+            }
+            notified.await;
+        }
+    }
+}
+
+fn set_once<T>(field: &mut Option<T>, value: T) -> bool {
+    if field.is_some() {
+        return false;
+    }
+    *field = Some(value);
+    true
+}
+
+#[tarpc::server]
+impl BoardService for Board {
+    async fn post_mc_roots(
+        self,
+        _: context::Context,
+        round: u32,
+        roots: Vec<[u8; 32]>,
+        nr_leaf: u32,
+        signature: Vec<u8>,
+    ) -> bool {
+        self.post(
+            round,
+            Post::McRoots,
+            (roots, nr_leaf),
+            &signature,
+            |r, (roots, nr_leaf)| {
+                set_once(&mut r.mc_roots, roots) && set_once(&mut r.nr_leaf, nr_leaf)
+            },
+        )
+    }
+
+    async fn post_leaf_roots(
+        self,
+        _: context::Context,
+        round: u32,
+        roots: Vec<[u8; 32]>,
+        signature: Vec<u8>,
+    ) -> bool {
+        self.post(round, Post::LeafRoots, roots, &signature, |r, x| {
+            set_once(&mut r.leaf_roots, x)
+        })
+    }
+
+    async fn post_contribution(
+        self,
+        _: context::Context,
+        round: u32,
+        contribution: Contribution,
+    ) -> bool {
+        let mut records = self.records.lock().unwrap();
+        // the point must not be known before the leafs are fixed, and only the server opens a
+        // round
+        let r = records.get_mut(&round);
+        let seed = match r.as_ref().and_then(|r| r.leaf_roots.as_ref()) {
+            Some(x) => random_point::seed(round, x),
+            None => {
+                warn!(
+                    "Atom: contribution of round {} before the leaf roots",
+                    round
+                );
+                return false;
+            }
+        };
+        let r = r.unwrap();
+        // the late ones are not needed
+        if r.random_pt.is_some()
+            || r.contributions
+                .iter()
+                .any(|x| x.member == contribution.member)
+        {
+            return false;
+        }
+        let signed = self
+            .committee
+            .get(contribution.member as usize)
+            .map_or(false, |pk| contribution.verify(pk, &seed));
+        if !signed {
+            warn!(
+                "Atom: {} posts a contribution of round {} not signed by member {}",
+                self.peer, round, contribution.member
+            );
+            return false;
+        }
+        r.contributions.push(contribution);
+        if r.contributions.len() == self.threshold + 1 {
+            r.contributions.sort_by_key(|x| x.member);
+            r.random_pt = random_point::random_point(
+                &seed,
+                &r.contributions,
+                &self.committee,
+                self.threshold,
+                self.modulus,
+            );
+            self.notify.notify_waiters();
+        }
+        true
+    }
+
+    async fn post_ms_roots(
+        self,
+        _: context::Context,
+        round: u32,
+        roots: Vec<[u8; 32]>,
+        signature: Vec<u8>,
+    ) -> bool {
+        self.post(round, Post::MsRoots, roots, &signature, |r, x| {
+            set_once(&mut r.ms_roots, x)
+        })
+    }
+
+    async fn post_summed_ct(
+        self,
+        _: context::Context,
+        round: u32,
+        c_0: Vec<Vec<u64>>,
+        c_1: Vec<Vec<u64>>,
+        signature: Vec<u8>,
+    ) -> bool {
+        self.post(round, Post::SummedCt, (c_0, c_1), &signature, |r, x| {
+            set_once(&mut r.summed_ct, x)
+        })
+    }
+
+    async fn post_decryption(
+        self,
+        _: context::Context,
+        round: u32,
+        pt: Vec<Vec<u64>>,
+        signature: Vec<u8>,
+    ) -> bool {
+        self.post(round, Post::Decryption, pt, &signature, |r, x| {
+            set_once(&mut r.decryption, x)
+        })
+    }
+
+    async fn post_reshare(
+        self,
+        _: context::Context,
+        epoch: u32,
+        kind: Reshare,
+        member: u32,
+        payload: Vec<u8>,
+        signature: Vec<u8>,
+    ) -> bool {
+        let digest = reshare_digest(epoch, kind, member, &payload);
+        let signed = self
+            .committee
+            .get(member as usize)
+            .map_or(false, |pk| rsa_verify(pk, &digest, &signature));
+        if !signed {
+            warn!(
+                "Atom: {} posts {:?} of resharing {} not signed by member {}",
+                self.peer, kind, epoch, member
+            );
+            return false;
+        }
+        let mut reshare = self.reshare.lock().unwrap();
+        if reshare.contains_key(&(epoch, kind, member)) {
+            warn!(
+                "Atom: {:?} of member {} in resharing {} has been posted",
+                kind, member, epoch
+            );
+            return false;
+        }
+        // the resharings before the last one are over
+        reshare.retain(|k, _| k.0 + 1 >= epoch);
+        reshare.insert((epoch, kind, member), payload);
+        true
+    }
+
+    async fn get_reshare(
+        self,
+        _: context::Context,
+        epoch: u32,
+        kind: Reshare,
+        member: u32,
+    ) -> Option<Vec<u8>> {
+        self.reshare
+            .lock()
+            .unwrap()
+            .get(&(epoch, kind, member))
+            .cloned()
+    }
+
+    async fn get_mc_roots(self, _: context::Context, round: u32) -> Vec<[u8; 32]> {
+        self.get(round, |r| r.mc_roots.clone()).await
+    }
+
+    async fn get_nr_leaf(self, _: context::Context, round: u32) -> u32 {
+        self.get(round, |r| r.nr_leaf).await
+    }
+
+    async fn get_leaf_roots(self, _: context::Context, round: u32) -> Vec<[u8; 32]> {
+        self.get(round, |r| r.leaf_roots.clone()).await
+    }
+
+    async fn get_random_pt(self, _: context::Context, round: u32) -> i128 {
+        self.get(round, |r| r.random_pt).await
+    }
+
+    async fn get_contributions(self, _: context::Context, round: u32) -> Vec<Contribution> {
+        // hidden till the point is fixed, so no contribution is chosen after seeing the others
+        self.get(round, |r| r.random_pt.map(|_| r.contributions.clone()))
+            .await
+    }
+
+    async fn get_ms_roots(self, _: context::Context, round: u32) -> Vec<[u8; 32]> {
+        self.get(round, |r| r.ms_roots.clone()).await
+    }
+
+    async fn get_summed_ct(
+        self,
+        _: context::Context,
+        round: u32,
+    ) -> (Vec<Vec<u64>>, Vec<Vec<u64>>) {
+        self.get(round, |r| r.summed_ct.clone()).await
+    }
+
+    async fn get_decryption(self, _: context::Context, round: u32) -> Vec<Vec<u64>> {
+        self.get(round, |r| r.decryption.clone()).await
+    }
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let config = ConfigUtils::init("config.yaml");
+    init_tracing("Atom Board", config.get_agent_endpoint(), LevelFilter::WARN)?;
+
+    let board_addr = (
+        IpAddr::V4(config.get_addr("board_addr")),
+        config.get_int("board_port") as u16,
+    );
+    let server = Arc::new(common::server_pk());
+    let nr_members = config.settings.get_array("players").unwrap().len();
+    let committee = Arc::new(common::committee_pks(nr_members));
+    let threshold = config.get_int("threshold") as usize;
+    let modulus = ParameterSet::with_dimension(config.get_int("num_dimension") as usize).modulus;
+    let records = Arc::new(Mutex::new(BTreeMap::new()));
+    let reshare = Arc::new(Mutex::new(BTreeMap::new()));
+    let notify = Arc::new(Notify::new());
+
+    let mut listener = tarpc::serde_transport::tcp::listen(&board_addr, Bincode::default).await?;
+    listener.config_mut().max_frame_length(usize::MAX);
+
+    println!("Atom: board starts listening");
+
+    listener
+        // Ignore accept errors.
+        .filter_map(|r| future::ready(r.ok()))
+        .map(server::BaseChannel::with_defaults)
+        .map(|channel| {
+            let board = Board {
+                peer: channel.transport().peer_addr().unwrap(),
+                server: server.clone(),
+                committee: committee.clone(),
+                threshold,
+                modulus,
+                records: records.clone(),
+                reshare: reshare.clone(),
+                notify: notify.clone(),
+            };
+            channel.execute(board.serve())
+        })
+        .buffer_unordered(999)
+        .for_each(|_| async {})
+        .await;
+
+    Ok(())
+}
@@ -0,0 +1,28 @@
+use quail::common::params::ParameterSet;
+use quail::rlwe::context::NTTContext;
+mod util;
+use crate::util::config::ConfigUtils;
+use bincode::serialize_into;
+use std::fs::File;
+use std::io::BufWriter;
+
+// a ciphertext of all ones in NTT form, one vector per RNS prime, for aggregator_online to
+// decrypt; the secret key shares and the public key come from committee_keygen
+fn main() {
+    let config = ConfigUtils::init("config.yaml");
+    let params = ParameterSet::with_dimension(config.get_int("num_dimension") as usize);
+    let num_dimension = params.num_dimension;
+
+    let ntt_context = NTTContext::init_all(&params);
+
+    // write the ct into a file
+    {
+        let file_name = format!("./data/ciphertext.txt");
+        let mut f = BufWriter::new(File::create(file_name).unwrap());
+        let mut ct = vec![vec![1u64; num_dimension]; 3];
+        ntt_context[0].lazy_ntt_inplace(&mut ct[0]);
+        ntt_context[1].lazy_ntt_inplace(&mut ct[1]);
+        ntt_context[2].lazy_ntt_inplace(&mut ct[2]);
+        serialize_into(&mut f, &ct).unwrap();
+    }
+}
@@ -0,0 +1,201 @@
+use futures::{future, prelude::*};
+use quail::common::params::ParameterSet;
+use quail::rlwe::{aggregate::to_ntt, context::NTTContext};
+use rand::SeedableRng;
+use rsa::RsaPrivateKey;
+use std::{
+    env,
+    fs::File,
+    io::BufReader,
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+use tarpc::{
+    client, context,
+    server::{self, Channel},
+    tokio_serde::formats::Bincode,
+};
+use tracing::{error, warn};
+use tracing_subscriber::filter::LevelFilter;
+mod common;
+use crate::common::board_service::BoardServiceClient;
+use crate::common::committee_service::CommitteeService;
+use crate::common::random_point::{self, Contribution};
+mod committee_member;
+mod util;
+use crate::util::{config::ConfigUtils, log::init_tracing};
+
+/// one committee member holding its secret key share and the noise of the coming decryption
+#[derive(Clone)]
+pub struct CommitteeMember {
+    id: usize,
+    players: Arc<Vec<String>>,
+    threshold: usize,
+    timeout: Duration,
+    sk: Arc<Vec<Vec<u64>>>,
+    // signs the contributions to the random point
+    identity: Arc<RsaPrivateKey>,
+    board: BoardServiceClient,
+    noise: Arc<Mutex<Vec<Vec<u64>>>>,
+    ntt_context: Arc<Vec<NTTContext>>,
+    params: Arc<ParameterSet>,
+}
+
+#[tarpc::server]
+impl CommitteeService for CommitteeMember {
+    async fn generate_random_bits(self, _: context::Context, nr_slots: u32) -> bool {
+        match committee_member::generate_noise(
+            self.id,
+            &self.players,
+            &self.identity,
+            self.threshold,
+            nr_slots as usize,
+            self.timeout,
+            &self.params,
+        )
+        .await
+        {
+            Ok(noise) => {
+                *self.noise.lock().unwrap() = noise;
+                true
+            }
+            Err(e) => {
+                error!(
+                    "Atom: committee {} fails to generate random bits: {}",
+                    self.id, e
+                );
+                false
+            }
+        }
+    }
+
+    async fn partial_decrypt(self, _: context::Context, round: u32) -> Vec<Vec<u64>> {
+        // only the c_0 everyone sees on the board, the sum the Ms roots are checked against
+        let mut ctx = context::current();
+        ctx.deadline = SystemTime::now() + self.timeout;
+        let u = match self.board.get_summed_ct(ctx, round).await {
+            Ok((c_0, _)) => c_0,
+            Err(e) => {
+                error!("Atom: committee {} gets no summed ct: {}", self.id, e);
+                return Vec::new();
+            }
+        };
+        let nr_moduli = self.params.rns_moduli.len();
+        let n = self.params.num_dimension;
+        if u.len() != nr_moduli || u.iter().any(|x| x.len() != u[0].len() || x.len() % n != 0) {
+            warn!("Atom: committee {} gets a malformed summed ct", self.id);
+            return Vec::new();
+        }
+        // the noise is used only once
+        let mut noise = std::mem::take(&mut *self.noise.lock().unwrap());
+        if noise.len() != nr_moduli || (0..nr_moduli).any(|k| noise[k].len() < u[k].len()) {
+            warn!("Atom: committee {} has not enough random bits", self.id);
+            return Vec::new();
+        }
+        tokio::task::spawn_blocking(move || {
+            let ntt_u = to_ntt(&self.ntt_context, &u);
+            committee_member::partial_decrypt(&self.ntt_context, &self.sk, &mut noise, &ntt_u)
+        })
+        .await
+        .unwrap_or_default()
+    }
+
+    async fn random_point(self, _: context::Context, round: u32) -> bool {
+        // bound to the leaf roots on the board rather than to anything from the server
+        let mut ctx = context::current();
+        ctx.deadline = SystemTime::now() + self.timeout;
+        let leaf_roots = match self.board.get_leaf_roots(ctx, round).await {
+            Ok(x) => x,
+            Err(e) => {
+                error!("Atom: committee {} gets no leaf roots: {}", self.id, e);
+                return false;
+            }
+        };
+        let seed = random_point::seed(round, &leaf_roots);
+        let mut rng = rand::rngs::StdRng::from_entropy();
+        let contribution = Contribution::new(&self.identity, self.id as u32, &seed, &mut rng);
+        let mut ctx = context::current();
+        ctx.deadline = SystemTime::now() + self.timeout;
+        self.board
+            .post_contribution(ctx, round, contribution)
+            .await
+            .unwrap_or(false)
+    }
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 2 {
+        panic!("provide player id");
+    }
+    let config = ConfigUtils::init("config.yaml");
+    init_tracing(
+        "Atom Committee",
+        config.get_agent_endpoint(),
+        LevelFilter::WARN,
+    )?;
+    // read the address of players
+    let players: Vec<String> = config
+        .settings
+        .get_array("players")
+        .unwrap()
+        .into_iter()
+        .map(|x| x.into_str().unwrap())
+        .collect();
+    let id = usize::from_str_radix(&args[1], 10).unwrap();
+    let threshold = config.get_int("threshold") as usize;
+    let timeout = Duration::from_secs(config.get_int("committee_timeout") as u64);
+    let params = ParameterSet::with_dimension(config.get_int("num_dimension") as usize);
+
+    let sk: Vec<Vec<u64>> = {
+        let file_name = format!("./data/sk_share{}.txt", id);
+        let mut f = BufReader::new(File::open(file_name).unwrap());
+        let share0: Vec<u64> = bincode::deserialize_from(&mut f).unwrap();
+        let share1: Vec<u64> = bincode::deserialize_from(&mut f).unwrap();
+        let share2: Vec<u64> = bincode::deserialize_from(&mut f).unwrap();
+        vec![share0, share1, share2]
+    };
+
+    let board_addr = (
+        IpAddr::V4(config.get_addr("board_addr")),
+        config.get_int("board_port") as u16,
+    );
+    let mut transport = tarpc::serde_transport::tcp::connect(board_addr, Bincode::default);
+    transport.config_mut().max_frame_length(usize::MAX);
+    let board = BoardServiceClient::new(client::Config::default(), transport.await?).spawn();
+
+    let member = CommitteeMember {
+        id,
+        threshold,
+        timeout,
+        sk: Arc::new(sk),
+        identity: Arc::new(common::committee_sk(id)),
+        board,
+        noise: Arc::new(Mutex::new(Vec::new())),
+        ntt_context: Arc::new(NTTContext::init_all(&params)),
+        params: Arc::new(params),
+        players: Arc::new(players),
+    };
+
+    // the random bits are exchanged on the player address, the service listens on a shifted port
+    let mut addr: SocketAddr = member.players[id].parse()?;
+    addr.set_port(addr.port() + config.get_int("committee_port_offset") as u16);
+
+    let mut listener = tarpc::serde_transport::tcp::listen(&addr, Bincode::default).await?;
+    listener.config_mut().max_frame_length(usize::MAX);
+
+    println!("Atom: committee {} starts listening", id);
+
+    listener
+        // Ignore accept errors.
+        .filter_map(|r| future::ready(r.ok()))
+        .map(server::BaseChannel::with_defaults)
+        .map(|channel| channel.execute(member.clone().serve()))
+        .buffer_unordered(10)
+        .for_each(|_| async {})
+        .await;
+
+    Ok(())
+}
@@ -0,0 +1,477 @@
+use crate::common::committee_service::CommitteeServiceClient;
+use crate::common::params::ParameterSet;
+use futures::stream::{FuturesUnordered, StreamExt};
+use quail::rlwe::context::{NTTContext, Reconstruction, ShamirContext};
+use rayon::prelude::*;
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+use tarpc::{
+    client::{self, RpcError},
+    context,
+    tokio_serde::formats::Bincode,
+};
+use tracing::{error, warn};
+
+#[derive(Debug, Clone)]
+pub struct MemberStatus {
+    pub addr: String,
+    pub nr_error: u32,
+    pub nr_timeout: u32,
+    // # of decryptions it sent a wrong partial decryption to
+    pub nr_wrong: u32,
+}
+
+/// Drive the committee through CommitteeService: the random bits generation and the decryption
+#[derive(Clone)]
+pub struct CommitteeDriver {
+    members: Arc<Vec<Option<CommitteeServiceClient>>>,
+    status: Arc<Mutex<Vec<MemberStatus>>>,
+    timeout: Duration,
+    threshold: usize,
+    // the noise is shared at degree 2t, see committee_member::share_noise, so 2t+1 partial
+    // decryptions are needed
+    nr_shares: usize,
+    // the # of wrong partial decryptions the whole committee can correct, two more shares each
+    max_wrong: usize,
+    shamir_context: Arc<Vec<ShamirContext>>,
+    ntt_context: Arc<Vec<NTTContext>>,
+    params: ParameterSet,
+}
+
+impl CommitteeDriver {
+    pub async fn connect(
+        addrs: Vec<String>,
+        threshold: usize,
+        timeout: Duration,
+        params: &ParameterSet,
+    ) -> Self {
+        let mut members = Vec::with_capacity(addrs.len());
+        let mut status = Vec::with_capacity(addrs.len());
+        for addr in addrs {
+            let mut transport = tarpc::serde_transport::tcp::connect(&addr, Bincode::default);
+            transport.config_mut().max_frame_length(usize::MAX);
+            let mut nr_error = 0;
+            match tokio::time::timeout(timeout, transport).await {
+                Ok(Ok(transport)) => members.push(Some(
+                    CommitteeServiceClient::new(client::Config::default(), transport).spawn(),
+                )),
+                _ => {
+                    warn!("Atom: cannot connect to committee member {}", addr);
+                    members.push(None);
+                    nr_error += 1;
+                }
+            }
+            status.push(MemberStatus {
+                addr,
+                nr_error,
+                nr_timeout: 0,
+                nr_wrong: 0,
+            });
+        }
+        let nr_players = members.len();
+        Self {
+            members: Arc::new(members),
+            status: Arc::new(Mutex::new(status)),
+            timeout,
+            threshold,
+            nr_shares: 2 * threshold + 1,
+            max_wrong: nr_players.saturating_sub(2 * threshold + 1) / 2,
+            shamir_context: Arc::new(
+                params
+                    .rns_moduli
+                    .iter()
+                    .map(|q| ShamirContext::init(*q, nr_players, threshold))
+                    .collect(),
+            ),
+            ntt_context: Arc::new(
+                params
+                    .rns_moduli
+                    .iter()
+                    .map(|q| NTTContext::init(*q, params.num_dimension))
+                    .collect(),
+            ),
+            params: params.clone(),
+        }
+    }
+
+    pub fn status(&self) -> Vec<MemberStatus> {
+        self.status.lock().unwrap().clone()
+    }
+
+    fn context(&self) -> context::Context {
+        let mut ctx = context::current();
+        ctx.deadline = SystemTime::now() + self.timeout;
+        ctx
+    }
+
+    fn record_failure(&self, id: usize, err: Option<RpcError>) {
+        let mut status = self.status.lock().unwrap();
+        let s = &mut status[id];
+        match err {
+            Some(RpcError::DeadlineExceeded) => s.nr_timeout += 1,
+            _ => s.nr_error += 1,
+        }
+        warn!(
+            "Atom: committee member {} fails ({:?}), {} errors {} timeouts so far",
+            s.addr, err, s.nr_error, s.nr_timeout
+        );
+    }
+
+    fn record_wrong(&self, wrong: &[bool]) {
+        let mut status = self.status.lock().unwrap();
+        for (s, _) in status.iter_mut().zip(wrong.iter()).filter(|(_, w)| **w) {
+            s.nr_wrong += 1;
+            warn!(
+                "Atom: committee member {} sends a wrong partial decryption, {} so far",
+                s.addr, s.nr_wrong
+            );
+        }
+    }
+
+    /// ask every member to prepare the noise of `nr_slots` numbers, return how many succeed
+    pub async fn generate_random_bits(&self, nr_slots: u32) -> usize {
+        let calls: FuturesUnordered<_> = self
+            .members
+            .iter()
+            .enumerate()
+            .filter_map(|(i, m)| m.clone().map(|m| (i, m)))
+            .map(|(i, m)| {
+                let ctx = self.context();
+                async move { (i, m.generate_random_bits(ctx, nr_slots).await) }
+            })
+            .collect();
+        let results: Vec<_> = calls.collect().await;
+        let mut nr_ready = 0;
+        for (i, r) in results {
+            match r {
+                Ok(true) => nr_ready += 1,
+                Ok(false) => self.record_failure(i, None),
+                Err(e) => self.record_failure(i, Some(e)),
+            }
+        }
+        nr_ready
+    }
+
+    /// ask every member to post a contribution to the random point of `round` to the board,
+    /// return how many the board accepts; it fixes the point with the first t + 1, so one honest
+    /// member makes it random. The calls still pending once t + 1 are accepted are dropped, as
+    /// the board turns the later contributions down anyway.
+    pub async fn random_point(&self, round: u32) -> usize {
+        let mut calls: FuturesUnordered<_> = self
+            .members
+            .iter()
+            .enumerate()
+            .filter_map(|(i, m)| m.clone().map(|m| (i, m)))
+            .map(|(i, m)| {
+                let ctx = self.context();
+                async move { (i, m.random_point(ctx, round).await) }
+            })
+            .collect();
+        let mut nr_accepted = 0;
+        while let Some((i, r)) = calls.next().await {
+            match r {
+                Ok(true) => nr_accepted += 1,
+                Ok(false) => {}
+                Err(e) => self.record_failure(i, Some(e)),
+            }
+            if nr_accepted == self.threshold + 1 {
+                return nr_accepted;
+            }
+        }
+        error!(
+            "Atom: only {} contributions to the random point of round {}",
+            nr_accepted, round
+        );
+        nr_accepted
+    }
+
+    /// Decrypt with the committee the summed c_0 of `round` on the board, of `nr_slots` slots per
+    /// RNS prime: the result is c_0 * sk + noise in coefficient form, or None if too few members
+    /// respond or too many of them are wrong
+    pub async fn decrypt(&self, round: u32, nr_slots: usize) -> Option<Vec<Vec<u64>>> {
+        let call = |i: usize| {
+            let m = self.members[i].clone().unwrap();
+            let ctx = self.context();
+            async move { (i, m.partial_decrypt(ctx, round).await) }
+        };
+
+        // start with enough members to correct max_wrong wrong partial decryptions, and ask
+        // another one whenever some member fails
+        let nr_wanted = self.nr_shares + 2 * self.max_wrong;
+        let mut candidates = (0..self.members.len()).filter(|i| self.members[*i].is_some());
+        let mut pending: FuturesUnordered<_> =
+            candidates.by_ref().take(nr_wanted).map(call).collect();
+        let mut ids = Vec::with_capacity(nr_wanted);
+        let mut shares = Vec::with_capacity(nr_wanted);
+        while let Some((i, r)) = pending.next().await {
+            match r {
+                Ok(share)
+                    if share.len() == self.params.rns_moduli.len()
+                        && share.iter().all(|s| s.len() == nr_slots) =>
+                {
+                    ids.push(i);
+                    shares.push(share);
+                    if ids.len() == nr_wanted {
+                        break;
+                    }
+                    continue;
+                }
+                Ok(_) => self.record_failure(i, None),
+                Err(e) => self.record_failure(i, Some(e)),
+            }
+            if let Some(j) = candidates.next() {
+                pending.push(call(j));
+            }
+        }
+        if ids.len() < self.nr_shares {
+            error!(
+                "Atom: only {} of {} partial decryptions received",
+                ids.len(),
+                self.nr_shares
+            );
+            return None;
+        }
+        if ids.len() < nr_wanted {
+            warn!(
+                "Atom: only {} partial decryptions, {} wrong ones can be corrected instead of {}",
+                ids.len(),
+                (ids.len() - self.nr_shares) / 2,
+                self.max_wrong
+            );
+        }
+
+        // every partial decryption received is used, so some wrong ones can be corrected
+        let mut wrong = vec![false; self.members.len()];
+        let mut ret: Vec<Vec<u64>> = Vec::with_capacity(self.params.rns_moduli.len());
+        for k in 0..self.params.rns_moduli.len() {
+            let context = &self.shamir_context[k];
+            let decoder = context.robust_decoder(&ids, 2 * self.threshold).unwrap();
+            let slots: Option<Vec<Reconstruction>> = (0..nr_slots)
+                .into_par_iter()
+                .map(|i| {
+                    let y: Vec<u64> = shares.iter().map(|s| s[k][i]).collect();
+                    context.robust_reconstruct_with(&decoder, &y)
+                })
+                .collect();
+            let slots = match slots {
+                Some(x) => x,
+                None => {
+                    error!(
+                        "Atom: too many wrong partial decryptions in round {}",
+                        round
+                    );
+                    return None;
+                }
+            };
+            for j in slots.iter().flat_map(|r| r.wrong.iter()) {
+                wrong[*j] = true;
+            }
+            ret.push(slots.into_iter().map(|r| r.secret).collect());
+        }
+        self.record_wrong(&wrong);
+        let n = self.params.num_dimension;
+        for k in 0..self.params.rns_moduli.len() {
+            for j in (0..nr_slots).step_by(n) {
+                self.ntt_context[k].lazy_inverse_ntt_inplace(&mut ret[k][j..j + n]);
+            }
+        }
+        Some(ret)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::committee_member;
+    use crate::common::committee_service::CommitteeService;
+    use crate::rlwe::{
+        aggregate::Aggregate,
+        encoding::{Clipping, Encoder},
+        keygen::KeyGenContext,
+    };
+    use futures::future;
+    use rand::{Rng, SeedableRng};
+    use tarpc::server::{self, Channel};
+
+    /// a member that answers every round with the same partial decryption
+    #[derive(Clone)]
+    struct Member {
+        partial: Arc<Vec<Vec<u64>>>,
+    }
+
+    #[tarpc::server]
+    impl CommitteeService for Member {
+        async fn generate_random_bits(self, _: context::Context, _: u32) -> bool {
+            true
+        }
+
+        async fn partial_decrypt(self, _: context::Context, _: u32) -> Vec<Vec<u64>> {
+            self.partial.as_ref().clone()
+        }
+
+        async fn random_point(self, _: context::Context, _: u32) -> bool {
+            true
+        }
+    }
+
+    /// serve every partial decryption on a local port, return the addresses
+    async fn serve(partials: Vec<Vec<Vec<u64>>>) -> Vec<String> {
+        let mut addrs = Vec::with_capacity(partials.len());
+        for partial in partials {
+            let mut listener = tarpc::serde_transport::tcp::listen("127.0.0.1:0", Bincode::default)
+                .await
+                .unwrap();
+            listener.config_mut().max_frame_length(usize::MAX);
+            addrs.push(listener.local_addr().to_string());
+            let member = Member {
+                partial: Arc::new(partial),
+            };
+            tokio::spawn(
+                listener
+                    .filter_map(|r| future::ready(r.ok()))
+                    .map(server::BaseChannel::with_defaults)
+                    .map(move |channel| channel.execute(member.clone().serve()))
+                    .buffer_unordered(10)
+                    .for_each(|_| async {}),
+            );
+        }
+        addrs
+    }
+
+    /// The partial decryptions of a committee of `nr_players` at `threshold` for the sum of a few
+    /// encrypted updates, with the key of a keygen among them and the noise dealt as in
+    /// `committee_member::generate_noise`. Return the updates, their aggregate and the partial
+    /// decryptions.
+    fn partial_decryptions(
+        params: &ParameterSet,
+        encoder: &Encoder,
+        nr_players: usize,
+        threshold: usize,
+    ) -> (Vec<Vec<f32>>, Aggregate, Vec<Vec<Vec<u64>>>) {
+        let n = params.num_dimension;
+        let mut rng = rand::rngs::StdRng::from_entropy();
+        let keygen_context = KeyGenContext::new(params, nr_players, threshold, rng.gen());
+        let contributions: Vec<_> = (0..nr_players)
+            .map(|_| keygen_context.contribute())
+            .collect();
+        let b: Vec<Vec<Vec<u64>>> = contributions.iter().map(|x| x.2.clone()).collect();
+        let pk = keygen_context.public_key(&b);
+
+        // updates in steps of 1/16 within the clipping bound, which the encoding holds exactly
+        let nr_ct = 2;
+        let updates: Vec<Vec<f32>> = (0..3)
+            .map(|_| {
+                (0..nr_ct * n)
+                    .map(|_| rng.gen_range(-64i32..64) as f32 / 16.0)
+                    .collect()
+            })
+            .collect();
+        let mut aggregate = Aggregate::new(params, nr_ct);
+        for x in updates.iter() {
+            let (c_0, c_1): (Vec<Vec<i128>>, Vec<Vec<i128>>) = encoder
+                .encode(x)
+                .chunks(n)
+                .map(|m| {
+                    let ct = pk.encrypt(m).5;
+                    (ct.c_0, ct.c_1)
+                })
+                .unzip();
+            aggregate.add(&c_0, &c_1);
+        }
+
+        // the members work with the types of the library
+        let member_params = quail::common::params::ParameterSet::with_dimension(n);
+        let ntt_context = NTTContext::init_all(&member_params);
+        let nr_slots = aggregate.nr_slots();
+        let dealt: Vec<Vec<Vec<Vec<u64>>>> = (0..nr_players)
+            .map(|_| committee_member::share_noise(&member_params, nr_players, threshold, nr_slots))
+            .collect();
+        let ntt_c_0 = aggregate.ntt_c_0();
+        let partials = (0..nr_players)
+            .map(|j| {
+                let subshares: Vec<_> = contributions.iter().map(|x| x.0[j].clone()).collect();
+                let sk = keygen_context.combine(&subshares).share;
+                let mut sum = vec![vec![0u64; nr_slots]; member_params.rns_moduli.len()];
+                for x in dealt.iter() {
+                    committee_member::add_noise_shares(&member_params.rns_moduli, &mut sum, &x[j]);
+                }
+                let mut noise = quail::rlwe::aggregate::to_ntt(&ntt_context, &sum);
+                committee_member::partial_decrypt(&ntt_context, &sk, &mut noise, &ntt_c_0)
+            })
+            .collect();
+        (updates, aggregate, partials)
+    }
+
+    /// decrypt with the committee, then decode, and compare with the sum of the updates
+    async fn check_decrypt(
+        committee: &CommitteeDriver,
+        encoder: &Encoder,
+        updates: &[Vec<f32>],
+        aggregate: &Aggregate,
+    ) {
+        let c_0_sk = committee.decrypt(0, aggregate.nr_slots()).await.unwrap();
+        let decoded = encoder.decode(&aggregate.decrypt(&c_0_sk), aggregate.nr_summands);
+        for i in 0..aggregate.nr_slots() {
+            let expected: f32 = updates.iter().map(|x| x[i]).sum();
+            assert_eq!(decoded[i], expected, "{}", i);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_decrypt_with_committee() {
+        let params = ParameterSet::with_dimension(1024);
+        let encoder = Encoder::new(&params, 4, Clipping::LInf(4.0));
+        let (nr_players, threshold) = (5, 2);
+        let (updates, aggregate, partials) =
+            partial_decryptions(&params, &encoder, nr_players, threshold);
+        let committee = CommitteeDriver::connect(
+            serve(partials).await,
+            threshold,
+            Duration::from_secs(60),
+            &params,
+        )
+        .await;
+        check_decrypt(&committee, &encoder, &updates, &aggregate).await;
+        assert!(committee
+            .status()
+            .iter()
+            .all(|s| s.nr_error == 0 && s.nr_timeout == 0 && s.nr_wrong == 0));
+    }
+
+    #[tokio::test]
+    async fn test_decrypt_with_wrong_members() {
+        let params = ParameterSet::with_dimension(1024);
+        let encoder = Encoder::new(&params, 4, Clipping::LInf(4.0));
+        // 2t + 1 + 2e members correct e wrong partial decryptions
+        let (threshold, nr_wrong) = (2, 2);
+        let nr_players = 2 * threshold + 1 + 2 * nr_wrong;
+        let (updates, aggregate, mut partials) =
+            partial_decryptions(&params, &encoder, nr_players, threshold);
+        let mut rng = rand::rngs::StdRng::from_entropy();
+        let wrong = [1, 4];
+        for j in wrong.iter() {
+            for (x, p) in partials[*j].iter_mut().zip(params.rns_moduli.iter()) {
+                x.iter_mut()
+                    .for_each(|y| *y = (*y + rng.gen_range(1..*p)) % p);
+            }
+        }
+        let committee = CommitteeDriver::connect(
+            serve(partials).await,
+            threshold,
+            Duration::from_secs(60),
+            &params,
+        )
+        .await;
+        check_decrypt(&committee, &encoder, &updates, &aggregate).await;
+        let status = committee.status();
+        assert_eq!(
+            status.iter().map(|s| s.nr_wrong).sum::<u32>(),
+            nr_wrong as u32
+        );
+        for (j, s) in status.iter().enumerate() {
+            assert_eq!(s.nr_wrong, wrong.contains(&j) as u32, "{}", j);
+        }
+    }
+}
@@ -0,0 +1,35 @@
+use rand::SeedableRng;
+use rsa::{
+    pkcs8::{ToPrivateKey, ToPublicKey},
+    RsaPrivateKey, RsaPublicKey,
+};
+use std::env;
+
+/// Generate the identity key of a committee member, which signs what the member posts and
+/// sends, or with `server` the one of the server, which signs its posts to the board. Run before
+/// any other committee binary; the other members and the board read the public key, simulate
+/// the publish by writing to a file.
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 2 {
+        panic!("provide player id or server");
+    }
+    let (sk_file, pk_file) = if args[1] == "server" {
+        (
+            "./data/server_sk.pem".to_string(),
+            "./data/server_pk.pem".to_string(),
+        )
+    } else {
+        let id = usize::from_str_radix(&args[1], 10).unwrap();
+        (
+            format!("./data/committee_sk{}.pem", id),
+            format!("./data/committee_pk{}.pem", id),
+        )
+    };
+    let mut rng = rand::rngs::StdRng::from_entropy();
+    let sk = RsaPrivateKey::new(&mut rng, 2048).expect("failed to generate a key");
+    sk.write_pkcs8_pem_file(sk_file).unwrap();
+    RsaPublicKey::from(&sk)
+        .write_public_key_pem_file(pk_file)
+        .unwrap();
+}
@@ -0,0 +1,379 @@
+use ark_std::{end_timer, start_timer};
+use curve25519_dalek::ristretto::RistrettoPoint;
+use futures::future;
+use quail::common::params::ParameterSet;
+use quail::common::{committee_pks, committee_sk, rsa_sign, rsa_verify};
+use quail::rlwe::keygen::{commit, digest, KeyGenContext};
+use quail::rlwe::reshare::Subshare;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+mod util;
+use crate::util::config::ConfigUtils;
+
+use bincode::serialize_into;
+use std::env;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+// the rounds of the keygen: the subshares with the commitments to the polynomials and to b, then
+// b once the commitments are in, then the digest of the public key and of the sums of the
+// commitments once the bs are in
+const COMMIT: u8 = 0;
+const REVEAL: u8 = 1;
+const DIGEST: u8 = 2;
+
+type Commitments = Vec<Vec<RistrettoPoint>>;
+
+/// what a player receives from player i in each round, the subshares of the secret of player i
+/// dealt to it with the commitments of player i to its polynomials and to its b, then b, then
+/// the digest of the public key and of the commitments to the shares
+struct Inbox {
+    commits: Vec<Option<(Vec<Subshare>, Commitments, [u8; 32])>>,
+    reveals: Vec<Option<Vec<Vec<u64>>>>,
+    digests: Vec<Option<[u8; 32]>>,
+}
+
+impl Inbox {
+    fn new(nr_players: usize) -> Self {
+        Inbox {
+            commits: vec![None; nr_players],
+            reveals: vec![None; nr_players],
+            digests: vec![None; nr_players],
+        }
+    }
+
+    /// keep the first well-formed message of a player in each round
+    fn put(&mut self, kind: u8, src: usize, payload: &[u8]) {
+        let fresh = match kind {
+            COMMIT => put_first(&mut self.commits[src], payload),
+            REVEAL => put_first(&mut self.reveals[src], payload),
+            DIGEST => put_first(&mut self.digests[src], payload),
+            _ => Some(false),
+        };
+        match fresh {
+            Some(true) => {}
+            Some(false) => eprintln!("unexpected message from player {}", src),
+            None => eprintln!("malformed message from player {}", src),
+        }
+    }
+
+    /// whether every player of `from` sent its message of round `kind`
+    fn is_complete(&self, kind: u8, from: &[usize]) -> bool {
+        match kind {
+            COMMIT => from.iter().all(|&i| self.commits[i].is_some()),
+            REVEAL => from.iter().all(|&i| self.reveals[i].is_some()),
+            _ => from.iter().all(|&i| self.digests[i].is_some()),
+        }
+    }
+}
+
+/// Some(false) if `slot` is taken, None if `payload` is malformed
+fn put_first<T: serde::de::DeserializeOwned>(slot: &mut Option<T>, payload: &[u8]) -> Option<bool> {
+    if slot.is_some() {
+        return Some(false);
+    }
+    *slot = Some(bincode::deserialize(payload).ok()?);
+    Some(true)
+}
+
+/// what player `src` signs with its identity key when it sends the bincode of its message of
+/// round `kind` to player `dst`, in the keygen of the committee of `seed`
+fn signed_digest(seed: &[u8; 32], kind: u8, src: usize, dst: usize, payload: &[u8]) -> [u8; 32] {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(seed);
+    hasher.update(&[kind]);
+    hasher.update(&(src as u32).to_le_bytes());
+    hasher.update(&(dst as u32).to_le_bytes());
+    hasher.update(payload);
+    hasher.finalize().into()
+}
+
+/// the other players may not listen yet, so keep trying till the deadline
+async fn connect_until(addr: &str, deadline: Instant) -> std::io::Result<TcpStream> {
+    loop {
+        match TcpStream::connect(addr).await {
+            Ok(stream) => return Ok(stream),
+            Err(e) if Instant::now() >= deadline => return Err(e),
+            Err(_) => tokio::time::sleep(Duration::from_millis(100)).await,
+        }
+    }
+}
+
+/// receive the messages of round `kind` from the players of `from` till the deadline, keeping the
+/// ones of later rounds. The players whose message is not in by then are left to the caller.
+async fn receive(
+    rx: &mut mpsc::UnboundedReceiver<(u8, usize, Vec<u8>)>,
+    listener: &mut JoinHandle<std::io::Result<()>>,
+    inbox: &mut Inbox,
+    kind: u8,
+    from: &[usize],
+    deadline: Instant,
+) -> Result<(), Box<dyn std::error::Error>> {
+    while !inbox.is_complete(kind, from) {
+        match tokio::time::timeout_at(deadline, rx.recv()).await {
+            Ok(Some((k, src, payload))) => inbox.put(k, src, &payload),
+            // the listener only stops on an error
+            Ok(None) => {
+                listener.await??;
+                return Err("the listener stopped".into());
+            }
+            Err(_) => {
+                eprintln!("round {} timed out", kind);
+                break;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// the digest the players compare before publishing the public key: that of the key and of the
+/// commitments to the shares, so they also agree on what the first resharing checks
+fn agreed_digest(pk_digest: &[u8; 32], commitments: &[Vec<RistrettoPoint>]) -> [u8; 32] {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(pk_digest);
+    for x in commitments.iter().flatten() {
+        hasher.update(x.compress().as_bytes());
+    }
+    hasher.finalize().into()
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 2 {
+        panic!("provide player id");
+    }
+    let config = ConfigUtils::init("config.yaml");
+    // read the address of players
+    let players: Vec<String> = config
+        .settings
+        .get_array("players")
+        .unwrap()
+        .into_iter()
+        .map(|x| x.into_str().unwrap())
+        .collect();
+    let id = usize::from_str_radix(&args[1], 10).unwrap();
+    let nr_players = players.len();
+    let threshold = config.get_int("threshold") as usize;
+    let params = ParameterSet::with_dimension(config.get_int("num_dimension") as usize);
+    let timeout = Duration::from_secs(config.get_int("committee_timeout") as u64);
+    let listener = TcpListener::bind(&players[id]).await?;
+
+    // a is sampled from the committee itself, which every player knows before the keygen
+    let seed = *blake3::hash(players.join(" ").as_bytes()).as_bytes();
+    let keygen_context = KeyGenContext::new(&params, nr_players, threshold, seed);
+    // the players know each other by the identity keys of committee_identity
+    let identity = committee_sk(id);
+    let committee = committee_pks(nr_players);
+
+    let gc = start_timer!(|| "sample the contribution");
+    let (to_send, commitments, b) = keygen_context.contribute();
+    end_timer!(gc);
+
+    // the signed messages of the other players, of every round
+    let (tx, mut rx) = mpsc::unbounded_channel::<(u8, usize, Vec<u8>)>();
+    let mut f = tokio::spawn(async move {
+        loop {
+            let (mut socket, _) = listener.accept().await?;
+            let mut buf = Vec::new();
+            if let Err(e) = socket.read_to_end(&mut buf).await {
+                eprintln!("failed to read from socket; err = {:?}", e);
+                continue;
+            }
+            let (kind, src, signature, payload): (u8, u32, Vec<u8>, Vec<u8>) =
+                match bincode::deserialize(&buf) {
+                    Ok(x) => x,
+                    Err(e) => {
+                        eprintln!("malformed message; err = {:?}", e);
+                        continue;
+                    }
+                };
+            let src = src as usize;
+            if src >= nr_players || src == id {
+                eprintln!("message from unknown player {}", src);
+                continue;
+            }
+            if !rsa_verify(
+                &committee[src],
+                &signed_digest(&seed, kind, src, id, &payload),
+                &signature,
+            ) {
+                eprintln!("message not signed by player {}", src);
+                continue;
+            }
+            // the keygen is over
+            if tx.send((kind, src, payload)).is_err() {
+                return Ok::<(), std::io::Error>(());
+            }
+        }
+    });
+
+    // sign and send the message of round `kind` to every other player at once, payload[i] to
+    // player i. A player that can't be reached by the deadline misses the message.
+    let send = |kind: u8, payload: Vec<Vec<u8>>, deadline: Instant| {
+        let players = &players;
+        let identity = &identity;
+        let sent = payload
+            .into_iter()
+            .enumerate()
+            .filter(move |(i, _)| *i != id)
+            .map(move |(i, payload)| async move {
+                let signature = rsa_sign(identity, &signed_digest(&seed, kind, id, i, &payload));
+                let buf = bincode::serialize(&(kind, id as u32, signature, payload)).unwrap();
+                let sent = match connect_until(&players[i], deadline).await {
+                    Ok(mut stream) => stream.write_all(&buf).await,
+                    Err(e) => Err(e),
+                };
+                if let Err(e) = sent {
+                    eprintln!("failed to send to player {}; err = {:?}", i, e);
+                }
+            });
+        future::join_all(sent)
+    };
+    let mut inbox = Inbox::new(nr_players);
+    let others: Vec<usize> = (0..nr_players).filter(|&i| i != id).collect();
+
+    // commit to b with the subshares, and reveal it only once the commitments are in, so no
+    // player picks its b from those of the others
+    let deadline = Instant::now() + timeout;
+    send(
+        COMMIT,
+        (0..nr_players)
+            .map(|i| bincode::serialize(&(&to_send[i], &commitments, commit(&b))).unwrap())
+            .collect(),
+        deadline,
+    )
+    .await;
+    receive(&mut rx, &mut f, &mut inbox, COMMIT, &others, deadline).await?;
+    // the players whose subshares are not in by the deadline are out of the keygen, they deal
+    // nothing and their b is not in the public key
+    let committed: Vec<usize> = others
+        .iter()
+        .cloned()
+        .filter(|&j| inbox.commits[j].is_some())
+        .collect();
+    let deadline = Instant::now() + timeout;
+    send(
+        REVEAL,
+        vec![bincode::serialize(&b).unwrap(); nr_players],
+        deadline,
+    )
+    .await;
+    receive(&mut rx, &mut f, &mut inbox, REVEAL, &committed, deadline).await?;
+
+    // a dealer counts if it revealed the b it committed to and its subshares open its
+    // commitments. Every player decides for itself, so a dealer that cheats some players, or is
+    // late for some of them, ends the keygen at the digest round.
+    let gc = start_timer!(|| "verify the subshares");
+    let mut dealers = Vec::with_capacity(nr_players);
+    let mut subshares = Vec::with_capacity(nr_players);
+    let mut dealt = Vec::with_capacity(nr_players);
+    let mut bs = Vec::with_capacity(nr_players);
+    for j in 0..nr_players {
+        if j == id {
+            dealers.push(id);
+            subshares.push(to_send[id].clone());
+            dealt.push(commitments.clone());
+            bs.push(b.clone());
+            continue;
+        }
+        let (x, c, c_b) = match inbox.commits[j].take() {
+            Some(x) if committed.contains(&j) => x,
+            _ => {
+                eprintln!("player {} dealt nothing by the deadline", j);
+                continue;
+            }
+        };
+        let b_j = match inbox.reveals[j].take() {
+            Some(b_j) if commit(&b_j) == c_b => b_j,
+            Some(_) => {
+                eprintln!("player {} revealed a b it did not commit to", j);
+                continue;
+            }
+            None => {
+                eprintln!("player {} revealed no b by the deadline", j);
+                continue;
+            }
+        };
+        if !keygen_context.verify(id, &x, &c) {
+            eprintln!("the subshares of player {} don't open its commitments", j);
+            continue;
+        }
+        dealers.push(j);
+        subshares.push(x);
+        dealt.push(c);
+        bs.push(b_j);
+    }
+    end_timer!(gc);
+    if dealers.len() <= threshold {
+        return Err(format!(
+            "only {} dealers count, at least {} are needed",
+            dealers.len(),
+            threshold + 1
+        )
+        .into());
+    }
+    let share = keygen_context.combine(&subshares);
+    let commitments = keygen_context.combine_commitments(dealt);
+    let pk = keygen_context.public_key(&bs);
+
+    // check that the dealers got the same public key and commitments to the shares before the
+    // key is published. A dealer that does not answer is left out of the check.
+    let agreed = agreed_digest(&digest(&pk), &commitments);
+    let others: Vec<usize> = dealers.iter().cloned().filter(|&j| j != id).collect();
+    let deadline = Instant::now() + timeout;
+    send(
+        DIGEST,
+        vec![bincode::serialize(&agreed).unwrap(); nr_players],
+        deadline,
+    )
+    .await;
+    receive(&mut rx, &mut f, &mut inbox, DIGEST, &others, deadline).await?;
+    for &j in others.iter() {
+        match inbox.digests[j] {
+            Some(x) if x == agreed => {}
+            Some(_) => return Err(format!("player {} got another public key", j).into()),
+            None => eprintln!("player {} sent no digest by the deadline", j),
+        }
+    }
+    f.abort();
+
+    // only the share of the player is kept
+    {
+        let file_name = format!("./data/sk_share{}.txt", id);
+        let mut f = BufWriter::new(File::create(file_name).unwrap());
+        for x in share.share.iter() {
+            serialize_into(&mut f, x).unwrap();
+        }
+    }
+    // for the first resharing, which checks C_0 of every dealer against these
+    {
+        let file_name = format!("./data/reshare_opening{}.txt", id);
+        let mut f = BufWriter::new(File::create(file_name).unwrap());
+        serialize_into(&mut f, &(0u32, &share.blinding, &share.quotients)).unwrap();
+        let file_name = format!("./data/reshare_commitments{}.txt", id);
+        let mut f = BufWriter::new(File::create(file_name).unwrap());
+        serialize_into(&mut f, &commitments).unwrap();
+    }
+    // the players agree on the public key, so the first dealer publishes it, simulated by
+    // writing it where the clients and the board read it. The file is replaced at once, so no
+    // reader sees a partial one.
+    if id == dealers[0] {
+        let file_name = "./data/encryption.txt.tmp";
+        {
+            let mut f = BufWriter::new(File::create(file_name).unwrap());
+            for (name, x) in [("pk_0", &pk.pk_0), ("pk_1", &pk.pk_1)].iter() {
+                let coeffs: Vec<String> = x.iter().map(|y| y.to_string()).collect();
+                writeln!(f, "{} {}", name, coeffs.join(" "))?;
+            }
+            f.flush()?;
+        }
+        std::fs::rename(file_name, "./data/encryption.txt")?;
+    }
+
+    Ok(())
+}
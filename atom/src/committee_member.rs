@@ -0,0 +1,258 @@
+use cupcake::integer_arith::scalar::Scalar;
+use cupcake::integer_arith::ArithUtils;
+use quail::common::params::ParameterSet;
+use quail::common::{committee_pks, rsa_sign, rsa_verify};
+use quail::rlwe::aggregate::to_ntt;
+use quail::rlwe::context::{NTTContext, ShamirContext};
+use rand::{Rng, SeedableRng};
+use rsa::RsaPrivateKey;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tracing::warn;
+
+/// # of bytes of a share on the wire, which holds the RNS primes of every parameter set
+const SHARE_BYTES: usize = 5;
+
+fn serialize_shares_into(shares: &[Vec<u64>], buf: &mut [u8]) {
+    assert!(buf.len() >= shares.len() * shares[0].len() * SHARE_BYTES);
+    shares
+        .iter()
+        .flatten()
+        .flat_map(|x| x.to_le_bytes()[0..SHARE_BYTES].to_vec())
+        .zip(buf.iter_mut())
+        .for_each(|(x, y)| *y = x);
+}
+
+fn deserialize_shares(buf: &[u8], nr_moduli: usize) -> Vec<Vec<u64>> {
+    buf.chunks(buf.len() / nr_moduli)
+        .map(|s| {
+            s.chunks(SHARE_BYTES)
+                .map(|x| x.iter().rev().fold(0u64, |acc, y| (acc << 8) | *y as u64))
+                .collect()
+        })
+        .collect()
+}
+
+/// what player `src` signs with its identity key when it deals the noise shares `payload` to
+/// player `dst`, among the players of `seed`
+fn signed_digest(seed: &[u8; 32], src: usize, dst: usize, payload: &[u8]) -> [u8; 32] {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(seed);
+    hasher.update(&(src as u32).to_le_bytes());
+    hasher.update(&(dst as u32).to_le_bytes());
+    hasher.update(payload);
+    hasher.finalize().into()
+}
+
+/// the other players may not listen yet, so keep trying till the deadline
+async fn connect_until(addr: &str, deadline: Instant) -> anyhow::Result<TcpStream> {
+    loop {
+        match TcpStream::connect(addr).await {
+            Ok(stream) => return Ok(stream),
+            Err(e) if Instant::now() >= deadline => return Err(e.into()),
+            Err(_) => tokio::time::sleep(Duration::from_millis(100)).await,
+        }
+    }
+}
+
+/// Fresh noise in [-bound, bound] for `nr_bits` slots, with bound = params.smudging_bound, shared
+/// mod every RNS prime at degree 2t like the partial decryptions: ret[j][k] holds the shares of
+/// player j mod the k-th prime. The noise is an integer, so the sums of the shares reduce to the
+/// same value mod every prime.
+pub fn share_noise(
+    params: &ParameterSet,
+    nr_players: usize,
+    threshold: usize,
+    nr_bits: usize,
+) -> Vec<Vec<Vec<u64>>> {
+    let bound = params.smudging_bound(nr_players);
+    let mut rng = rand::rngs::StdRng::from_entropy();
+    let noise: Vec<i128> = (0..nr_bits)
+        .map(|_| rng.gen_range(-bound..=bound))
+        .collect();
+    let shamir_context = ShamirContext::init_all(params, nr_players, 2 * threshold);
+    let mut shares = vec![vec![Vec::with_capacity(nr_bits); shamir_context.len()]; nr_players];
+    for (k, context) in shamir_context.iter().enumerate() {
+        let p = context.modulus.rep() as i128;
+        for x in noise.iter() {
+            for (j, y) in context
+                .share(x.rem_euclid(p) as u64)
+                .into_iter()
+                .enumerate()
+            {
+                shares[j][k].push(y);
+            }
+        }
+    }
+    shares
+}
+
+/// add the noise shares dealt to a player by another one to the running sum, mod every RNS prime
+pub fn add_noise_shares(moduli: &[u64], sum: &mut [Vec<u64>], shares: &[Vec<u64>]) {
+    for ((s, x), p) in sum.iter_mut().zip(shares.iter()).zip(moduli.iter()) {
+        s.iter_mut()
+            .zip(x.iter())
+            .for_each(|(a, b)| *a = (*a + b % p) % p);
+    }
+}
+
+/// The offline phase of player `id`: every player shares bounded noise with all the others, see
+/// `share_noise`, and the noise of a player is the sum of the shares dealt to it, returned in NTT
+/// form per RNS prime. The committee adds at most nr_players * bound < Δ / 4 to a decryption, which
+/// smudges the noise of the ciphertexts and still rounds off.
+/// The dealers sign their shares with their identity keys, and each dealer counts once. The noise
+/// of a player misses that of any dealer whose shares are not in by the deadline, so it fails then.
+/// `nr_bits` should be a multiple of the dimension of `params`.
+pub async fn generate_noise(
+    id: usize,
+    players: &[String],
+    identity: &RsaPrivateKey,
+    threshold: usize,
+    nr_bits: usize,
+    timeout: Duration,
+    params: &ParameterSet,
+) -> anyhow::Result<Vec<Vec<u64>>> {
+    assert!(
+        params
+            .rns_moduli
+            .iter()
+            .all(|p| *p < 1 << (8 * SHARE_BYTES)),
+        "the RNS primes do not fit in {} bytes",
+        SHARE_BYTES
+    );
+    let deadline = Instant::now() + timeout;
+    let nr_players = players.len();
+    let nr_moduli = params.rns_moduli.len();
+    let listener = TcpListener::bind(&players[id]).await?;
+    // the players know each other by the identity keys of committee_identity
+    let committee = Arc::new(committee_pks(nr_players));
+    let seed = *blake3::hash(players.join(" ").as_bytes()).as_bytes();
+
+    let shares = share_noise(params, nr_players, threshold, nr_bits);
+    let nr_bytes = nr_bits * nr_moduli * SHARE_BYTES;
+
+    // the shares dealt to this player, each signed by its dealer
+    let (tx, mut rx) = mpsc::unbounded_channel::<(usize, Vec<Vec<u64>>)>();
+    let f = tokio::spawn(async move {
+        loop {
+            let (mut socket, _) = listener.accept().await?;
+            // the noise is generated
+            if tx.is_closed() {
+                return Ok::<(), std::io::Error>(());
+            }
+            let tx = tx.clone();
+            let committee = committee.clone();
+            tokio::spawn(async move {
+                let mut buf = Vec::new();
+                if let Err(e) = socket.read_to_end(&mut buf).await {
+                    warn!("failed to read from socket; err = {:?}", e);
+                    return;
+                }
+                let (src, signature, payload): (u32, Vec<u8>, Vec<u8>) =
+                    match bincode::deserialize(&buf) {
+                        Ok(x) => x,
+                        Err(e) => {
+                            warn!("malformed noise shares; err = {:?}", e);
+                            return;
+                        }
+                    };
+                let src = src as usize;
+                if src >= nr_players || src == id || payload.len() != nr_bytes {
+                    warn!("noise shares from unknown player {}", src);
+                    return;
+                }
+                if !rsa_verify(
+                    &committee[src],
+                    &signed_digest(&seed, src, id, &payload),
+                    &signature,
+                ) {
+                    warn!("noise shares not signed by player {}", src);
+                    return;
+                }
+                let _ = tx.send((src, deserialize_shares(&payload, nr_moduli)));
+            });
+        }
+    });
+
+    // sending data to other players
+    {
+        let mut payload = vec![0u8; nr_bytes];
+        for i in 0..players.len() {
+            if i != id {
+                serialize_shares_into(&shares[i], &mut payload);
+                let signature = rsa_sign(identity, &signed_digest(&seed, id, i, &payload));
+                let buf = bincode::serialize(&(id as u32, signature, &payload)).unwrap();
+                let mut stream = connect_until(&players[i], deadline).await?;
+                stream.write_all(&buf).await?;
+            }
+        }
+    }
+
+    // the shares of each dealer count once, and the noise is only smudging if every dealer is in
+    let mut sum = shares[id].clone();
+    drop(shares);
+    let mut dealt = vec![false; nr_players];
+    dealt[id] = true;
+    let received = async {
+        while !dealt.iter().all(|x| *x) {
+            match rx.recv().await {
+                Some((src, s)) if !dealt[src] => {
+                    dealt[src] = true;
+                    add_noise_shares(&params.rns_moduli, &mut sum, &s);
+                }
+                Some((src, _)) => warn!("duplicate noise shares from player {}", src),
+                None => break,
+            }
+        }
+    };
+    let timed_out =
+        tokio::time::timeout(deadline.saturating_duration_since(Instant::now()), received)
+            .await
+            .is_err();
+    f.abort();
+    let missing: Vec<usize> = (0..nr_players).filter(|i| !dealt[*i]).collect();
+    if !missing.is_empty() {
+        match f.await {
+            Ok(Err(e)) => return Err(e.into()),
+            _ => anyhow::bail!(
+                "no noise shares from players {:?}{}",
+                missing,
+                if timed_out { " by the deadline" } else { "" }
+            ),
+        }
+    }
+
+    // for each block of num_dimension numbers, run NTT
+    Ok(to_ntt(&NTTContext::init_all(params), &sum))
+}
+
+/// ntt(sk) * ntt(ct) + ntt(noise) for every num_dimension block of `ct`, consuming the noise
+pub fn partial_decrypt(
+    ntt_context: &[NTTContext],
+    sk: &[Vec<u64>],
+    noise: &mut [Vec<u64>],
+    ct: &[Vec<u64>],
+) -> Vec<Vec<u64>> {
+    let mut ret = vec![Vec::with_capacity(ct[0].len()); ntt_context.len()];
+    for k in 0..ntt_context.len() {
+        let n = ntt_context[k].num_dimension;
+        for j in (0..ct[k].len()).step_by(n) {
+            let ct_sk = ntt_context[k].coeff_mul_mod(&sk[k], &ct[k][j..j + n]);
+            for i in 0..n {
+                ret[k].push(
+                    Scalar::add_mod(
+                        &Scalar::from(noise[k][j + i]),
+                        &Scalar::from(ct_sk[i]),
+                        &ntt_context[k].modulus,
+                    )
+                    .rep(),
+                );
+            }
+        }
+        noise[k].drain(0..ct[k].len());
+    }
+    ret
+}
@@ -1,10 +1,10 @@
 use ark_std::{end_timer, start_timer};
-use cupcake::integer_arith::scalar::Scalar;
-use cupcake::integer_arith::ArithUtils;
+use quail::common::params::ParameterSet;
+use quail::common::{committee_sk, rsa_sign};
 use quail::rlwe::context::{NTTContext, ShamirContext};
-use quail::rlwe::NUM_DIMENSION;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::{TcpListener, TcpStream};
+mod committee_member;
 mod util;
 use crate::util::config::ConfigUtils;
 use bincode::deserialize_from;
@@ -16,8 +16,6 @@ use std::fs::File;
 use std::io::{BufReader, BufWriter};
 use std::sync::{Arc, Mutex};
 
-pub const MODULUS: [u64; 3] = [0xffffee001u64, 0xffffc4001u64, 0x1ffffe0001u64];
-
 fn serialize_shares_into(s0: &[u64], s1: &[u64], s2: &[u64], buf: &mut [u8]) {
     assert!(buf.len() >= s0.len() * 5 * 3);
     s0.iter()
@@ -63,6 +61,15 @@ fn deserialize_shares(buf: &[u8]) -> (Vec<u64>, Vec<u64>, Vec<u64>) {
     (s0, s1, s2)
 }
 
+/// what a member signs with its identity key when it answers the ct `ct_buf` with the shares
+/// `buf`, its id first
+fn signed_digest(ct_buf: &[u8], buf: &[u8]) -> [u8; 32] {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(ct_buf);
+    hasher.update(buf);
+    hasher.finalize().into()
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args: Vec<String> = env::args().collect();
@@ -84,19 +91,14 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let id = usize::from_str_radix(&args[1], 10).unwrap();
     let nr_players = players.len();
     let threshold = config.get_int("threshold") as usize;
+    let params = ParameterSet::with_dimension(config.get_int("num_dimension") as usize);
     //let listener = TcpListener::bind(&players[id]).await?;
 
-    let shamir_context = vec![
-        ShamirContext::init(MODULUS[0], nr_players, threshold),
-        ShamirContext::init(MODULUS[1], nr_players, threshold),
-        ShamirContext::init(MODULUS[2], nr_players, threshold),
-    ];
+    let shamir_context = ShamirContext::init_all(&params, nr_players, threshold);
 
-    let ntt_context = vec![
-        NTTContext::init(MODULUS[0]),
-        NTTContext::init(MODULUS[1]),
-        NTTContext::init(MODULUS[2]),
-    ];
+    let ntt_context = NTTContext::init_all(&params);
+    // the aggregator knows the members by the identity keys of committee_identity
+    let identity = committee_sk(id);
 
     // read from file
     let mut noise: Vec<Vec<u64>> = {
@@ -123,6 +125,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         let mut buf = vec![0u8; noise[0].len() * 5 * 3 + 1];
         // receive from aggregator
         stream.read_exact(&mut buf).await?;
+        let ct_buf = buf.clone();
         // read ciphertext first
         let ct: Vec<Vec<u64>> = {
             let (ct0, ct1, ct2) = deserialize_shares(&buf[1..]);
@@ -130,24 +133,13 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         };
 
         // local compute and sends shares to the aggregator
-        {
-            for k in 0..3 {
-                for j in (0..noise[0].len()).step_by(NUM_DIMENSION) {
-                    let ct_sk = ntt_context[k].coeff_mul_mod(&sk[k], &ct[k]);
-                    for i in 0..NUM_DIMENSION {
-                        noise[k][j + i] = Scalar::add_mod(
-                            &Scalar::from(noise[k][j + i]),
-                            &Scalar::from(ct_sk[i]),
-                            &ntt_context[k].modulus,
-                        )
-                        .rep();
-                    }
-                }
-            }
-        }
+        let ret = committee_member::partial_decrypt(&ntt_context, &sk, &mut noise, &ct);
         buf[0] = id as u8;
-        serialize_shares_into(&noise[0], &noise[1], &noise[2], &mut buf[1..]);
+        serialize_shares_into(&ret[0], &ret[1], &ret[2], &mut buf[1..]);
         stream.write_all(&buf).await?;
+        stream
+            .write_all(&rsa_sign(&identity, &signed_digest(&ct_buf, &buf)))
+            .await?;
         stream.shutdown().await?;
     }
     Ok(())
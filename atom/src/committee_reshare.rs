@@ -1,53 +1,133 @@
 use ark_std::{end_timer, start_timer};
-use cupcake::integer_arith::scalar::Scalar;
-use cupcake::integer_arith::ArithUtils;
 use curve25519_dalek::ristretto::RistrettoPoint;
-use quail::rlwe::context::{NTTContext, ShamirContext};
-use quail::rlwe::NUM_DIMENSION;
+use curve25519_dalek::scalar::Scalar as CurveScalar;
+use quail::common::board_service::{reshare_digest, BoardServiceClient, Reshare};
+use quail::common::params::ParameterSet;
+use quail::common::{committee_sk, rsa_sign};
+use quail::rlwe::reshare::{ReshareContext, Subshare};
+use rsa::RsaPrivateKey;
+use tarpc::{client, context, tokio_serde::formats::Bincode};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::{TcpListener, TcpStream};
 mod util;
 use crate::util::config::ConfigUtils;
-use curve25519_dalek::constants::RISTRETTO_BASEPOINT_TABLE;
-use curve25519_dalek::scalar::Scalar as curveScalar;
-use num_bigint::BigUint;
 
-use ark_ff::Field;
-use ark_ff::One;
 use bincode::deserialize_from;
 use bincode::serialize_into;
-use rand::{Rng, SeedableRng};
-use ring_algorithm::chinese_remainder_theorem;
+use serde::{de::DeserializeOwned, Serialize};
+use std::convert::TryInto;
 use std::env;
 use std::fs::File;
 use std::io::{BufReader, BufWriter};
+use std::net::IpAddr;
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use tokio::sync::Notify;
+use tokio::time::Instant;
 
-pub const MODULUS: [u64; 3] = [0xffffee001u64, 0xffffc4001u64, 0x1ffffe0001u64];
-pub const TOTAL_MODULUS: u128 = 649033470896967801447398927572993u128;
+// the subshares are the only message between the players, sent after [SHARE, src] and the length
+// of the bincode that follows; everything else is published
+const SHARE: u8 = 0;
 
-fn sample_polynomial(secret: u128, threshold: usize) -> Vec<curveScalar> {
-    let mut poly = vec![curveScalar::from(secret as u128)];
-    let mut rng = rand::rngs::StdRng::from_entropy();
-    poly.extend(
-        // TODO since the remainder theorem doesn't seem to work here, we just work in the first prime field
-        //(0..threshold).map(|_| curveScalar::from(rng.gen_range(0..TOTAL_MODULUS - 1) as u128)),
-        (0..threshold).map(|_| curveScalar::from(rng.gen_range(0..MODULUS[0] - 1) as u128)),
-    );
-    poly
+/// the commitments of a dealer to its polynomials, one vector per residue
+type Commitments = Vec<Vec<RistrettoPoint>>;
+/// the subshares a dealer publishes, with the players they were dealt to
+type Response = Vec<(usize, Vec<Subshare>)>;
+
+/// What a player receives from the others: shares[i], the subshares dealt by player i to it
+struct Inbox {
+    shares: Vec<Option<Vec<Subshare>>>,
+}
+
+impl Inbox {
+    fn new(nr_players: usize) -> Self {
+        Inbox {
+            shares: vec![None; nr_players],
+        }
+    }
 }
 
-fn evaluate_polynomial(
-    poly: &Vec<curveScalar>,
-    x: usize,
-    eval_matrix: &Vec<Vec<curveScalar>>,
-) -> curveScalar {
-    let mut s = curveScalar::zero();
-    for i in 0..poly.len() {
-        // the addition will not exceed 64-bit
-        s = s + eval_matrix[x][i] * poly[i];
+/// wait until `done` holds for the inbox, or until the deadline
+async fn wait_for<F: Fn(&Inbox) -> bool>(
+    inbox: &Mutex<Inbox>,
+    arrived: &Notify,
+    deadline: Instant,
+    done: F,
+) {
+    while !done(&inbox.lock().unwrap()) {
+        if tokio::time::timeout_at(deadline, arrived.notified())
+            .await
+            .is_err()
+        {
+            break;
+        }
     }
-    s
+}
+
+/// What player `i` publishes in the resharing of `epoch` goes to the board, keyed by both so
+/// nothing from an earlier resharing is taken, and signed with the identity key of the player.
+/// Everything is published once and at once, and a round ends for everyone when all the players
+/// have published in it or at the deadline, so the players decide from the same posts.
+async fn publish<T: Serialize>(
+    board: &BoardServiceClient,
+    identity: &RsaPrivateKey,
+    kind: Reshare,
+    epoch: u32,
+    id: usize,
+    x: &T,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let payload = bincode::serialize(x)?;
+    let signature = rsa_sign(identity, &reshare_digest(epoch, kind, id as u32, &payload));
+    if !board
+        .post_reshare(
+            context::current(),
+            epoch,
+            kind,
+            id as u32,
+            payload,
+            signature,
+        )
+        .await?
+    {
+        return Err(format!("the board turns down the {:?} of player {}", kind, id).into());
+    }
+    Ok(())
+}
+
+/// wait until the players `ids` have published `kind`, or until the deadline, and return what
+/// each player published, None if nothing or something malformed
+async fn wait_published<T: DeserializeOwned>(
+    board: &BoardServiceClient,
+    kind: Reshare,
+    epoch: u32,
+    ids: &[usize],
+    nr_players: usize,
+    deadline: Instant,
+) -> Vec<Option<T>> {
+    let mut published: Vec<Option<Vec<u8>>> = vec![None; nr_players];
+    loop {
+        let missing: Vec<usize> = ids
+            .iter()
+            .copied()
+            .filter(|&i| published[i].is_none())
+            .collect();
+        for i in missing {
+            let mut ctx = context::current();
+            ctx.deadline = SystemTime::now() + Duration::from_secs(1);
+            published[i] = board
+                .get_reshare(ctx, epoch, kind, i as u32)
+                .await
+                .unwrap_or_default();
+        }
+        if ids.iter().all(|&i| published[i].is_some()) || Instant::now() >= deadline {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    published
+        .into_iter()
+        .map(|x| x.and_then(|x| bincode::deserialize(&x).ok()))
+        .collect()
 }
 
 #[tokio::main]
@@ -68,179 +148,308 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let id = usize::from_str_radix(&args[1], 10).unwrap();
     let nr_players = players.len();
     let threshold = config.get_int("threshold") as usize;
+    let params = ParameterSet::with_dimension(config.get_int("num_dimension") as usize);
+    let timeout = Duration::from_secs(config.get_int("committee_timeout") as u64);
+    let num_dimension = params.num_dimension;
+    // the bincode of the subshares to a player, 2 scalars per residue
+    let nr_bytes = 64 * params.rns_moduli.len() * num_dimension + 8;
     let listener = TcpListener::bind(&players[id]).await?;
+    let identity = committee_sk(id);
+    let board_addr = (
+        IpAddr::V4(config.get_addr("board_addr")),
+        config.get_int("board_port") as u16,
+    );
+    let mut transport = tarpc::serde_transport::tcp::connect(board_addr, Bincode::default);
+    transport.config_mut().max_frame_length(usize::MAX);
+    let board = BoardServiceClient::new(client::Config::default(), transport.await?).spawn();
+
+    let reshare_context = ReshareContext::new(&params, nr_players, threshold);
 
-    let sk: Vec<u128> = {
-        let modulus = [MODULUS[0] as i128, MODULUS[1] as i128, MODULUS[2] as i128];
+    // the share of the secret key, one vector per RNS prime
+    let sk: Vec<Vec<u64>> = {
         let file_name = format!("./data/sk_share{}.txt", id);
         let mut f = BufReader::new(File::open(file_name).unwrap());
-        let share0: Vec<u64> = deserialize_from(&mut f).unwrap();
-        let share1: Vec<u64> = deserialize_from(&mut f).unwrap();
-        let share2: Vec<u64> = deserialize_from(&mut f).unwrap();
-        let mut share = Vec::with_capacity(NUM_DIMENSION);
-        for i in 0..NUM_DIMENSION {
-            // TODO for some reason, the remainder theorem doesn't seem to work here;
-            // for simplicity, we just use share0[i] maybe
-            // but it should be the same for 109-bit number, since the order of the curve is more than 250-bit
-            share.push(share0[i] as u128);
-            // share.push(
-            //     chinese_remainder_theorem(
-            //         &[share0[i] as i128, share1[i] as i128, share2[i] as i128],
-            //         &modulus,
-            //     )
-            //     .unwrap() as u128,
-            // );
-            // assert_eq!((share[i] % MODULUS[0] as u128), share0[i] as u128);
-            // assert_eq!((share[i] % MODULUS[1] as u128), share1[i] as u128);
-            // assert_eq!((share[i] % MODULUS[2] as u128), share2[i] as u128);
-        }
-        share
+        params
+            .rns_moduli
+            .iter()
+            .map(|_| deserialize_from(&mut f).unwrap())
+            .collect()
     };
-    let mut eval_matrix = vec![vec![curveScalar::one(); threshold + 1]; nr_players];
-    for i in 0..nr_players {
-        let pt = (i + 1) as u128;
-        let mut x_pow = (i + 1) as u128;
-        for j in 1..threshold + 1 {
-            eval_matrix[i][j] = curveScalar::from(x_pow);
-            x_pow = (x_pow * pt).rem_euclid(TOTAL_MODULUS);
+    // a share from the keygen or a previous resharing comes with the opening of its commitment,
+    // and the commitments to the polynomials it was on, a share dealt some other way has none.
+    // The epoch is the # of resharings the share went through, the same for every player.
+    let opening: Option<(u32, Vec<CurveScalar>, Vec<CurveScalar>)> =
+        File::open(format!("./data/reshare_opening{}.txt", id))
+            .ok()
+            .map(|f| deserialize_from(&mut BufReader::new(f)).unwrap());
+    let epoch = opening.as_ref().map_or(0, |x| x.0);
+    let old_commitments: Option<Commitments> =
+        File::open(format!("./data/reshare_commitments{}.txt", id))
+            .ok()
+            .map(|f| deserialize_from(&mut BufReader::new(f)).unwrap());
+    // only the shares of a keygen without commitments go without them, a share with an opening
+    // is checked against its commitments or not reshared at all
+    match (&opening, &old_commitments) {
+        (Some(_), None) => {
+            return Err(format!("no commitments to the old shares of epoch {}", epoch).into())
         }
+        (None, None) => {
+            eprintln!("no commitments to the old shares, the dealers can't be checked against them")
+        }
+        _ => {}
     }
-    //println!("eval_matrix {:?}", eval_matrix);
+    let others: Vec<usize> = (0..nr_players).filter(|&i| i != id).collect();
 
     // send to_send[i] to player i
     // s_{id}_{i}
-    let mut to_send = vec![vec![curveScalar::zero(); NUM_DIMENSION]; nr_players];
-    let mut proof = Vec::with_capacity(NUM_DIMENSION);
-    for i in 0..NUM_DIMENSION {
-        //let poly = sample_polynomial(sk[i], threshold);
-        let poly = sample_polynomial(sk[i], threshold);
-        let tproof: Vec<RistrettoPoint> = poly
-            .iter()
-            .map(|x| x * &RISTRETTO_BASEPOINT_TABLE)
-            .collect();
-        for j in 0..nr_players {
-            let y = evaluate_polynomial(&poly, j, &eval_matrix);
-            to_send[j][i] = y;
-        }
-        proof.push(tproof);
-    }
-    //for k in 0..nr_players {
-    //    println!("s[{}][{}]={:?}", id, k, to_send[k][0]);
-    //}
-    // simulate the publish by writing to a file
-    {
-        let file_name = format!("./data/reshare_proof{}.txt", id);
-        let mut f = BufWriter::new(File::create(file_name).unwrap());
-        serialize_into(&mut f, &proof).unwrap();
-    }
+    let (to_send, proof) = reshare_context.deal(&sk, opening.as_ref().map(|x| &x.1[..]));
+    // publish the commitments before dealing, with the quotients that open C_0
+    publish(
+        &board,
+        &identity,
+        Reshare::Proof,
+        epoch,
+        id,
+        &(&proof, opening.as_ref().map(|x| &x.2)),
+    )
+    .await?;
 
-    // simulate the CPU by verifying the proofs from itself rather than read it again
-    {
-        for i in 0..nr_players {
-            for j in 0..NUM_DIMENSION {
-                let recv_share = to_send[i][j];
-                let recv_group = &recv_share * &RISTRETTO_BASEPOINT_TABLE;
-                let mut compute_group = proof[j][0];
-                for k in 1..threshold + 1 {
-                    compute_group += &eval_matrix[i][k] * &proof[j][k];
-                }
-            }
-        }
-    }
-
-    // store from player i to `to_recv[i]`
-    // s_{i}_{id}
-    let mut to_recv = vec![vec![curveScalar::zero(); NUM_DIMENSION]; nr_players];
-    for i in 0..NUM_DIMENSION {
-        to_recv[id][i] = to_send[id][i];
-    }
-
-    let mutex = Arc::new(Mutex::new(to_recv));
-    let mb = mutex.clone();
+    // the subshares of the other players
+    let inbox = Arc::new(Mutex::new(Inbox::new(nr_players)));
+    let arrived = Arc::new(Notify::new());
+    let ib = inbox.clone();
+    let ar = arrived.clone();
 
+    // the first subshares from a player count
     let f = tokio::spawn(async move {
-        let mut handles = Vec::new();
-        for _ in 0..nr_players - 1 {
-            // maybe we can new a thread for each socket to improve latency
-            let (mut socket, _) = listener.accept().await.unwrap();
-
-            let mbits = mutex.clone();
-
-            handles.push(tokio::spawn(async move {
-                // 1 + 32 * 4096 + 8
-                let mut buf = vec![0u8; 131081];
-
-                let _ = match socket.read_exact(&mut buf).await {
-                    // socket closed
-                    Ok(n) if n == 0 => return,
-                    Ok(n) => n,
-                    Err(e) => {
-                        eprintln!("failed to read from socket; err = {:?}", e);
+        loop {
+            let (mut socket, _) = match listener.accept().await {
+                Ok(x) => x,
+                Err(e) => {
+                    eprintln!("failed to accept; err = {:?}", e);
+                    continue;
+                }
+            };
+            let ib = ib.clone();
+            let ar = ar.clone();
+            tokio::spawn(async move {
+                let mut header = [0u8; 10];
+                if let Err(e) = socket.read_exact(&mut header).await {
+                    eprintln!("failed to read from socket; err = {:?}", e);
+                    return;
+                }
+                let (kind, src) = (header[0], header[1] as usize);
+                let len = u64::from_le_bytes(header[2..].try_into().unwrap()) as usize;
+                if src >= nr_players || src == id || kind != SHARE || len > nr_bytes {
+                    eprintln!("unexpected message from player {}", src);
+                    return;
+                }
+                let mut buf = vec![0u8; len];
+                if let Err(e) = socket.read_exact(&mut buf).await {
+                    eprintln!("failed to read from socket; err = {:?}", e);
+                    return;
+                }
+                {
+                    let mut l = ib.as_ref().lock().unwrap();
+                    if l.shares[src].is_some() {
+                        eprintln!("duplicate message from player {}", src);
                         return;
                     }
-                };
-                let src = buf[0] as usize;
-                let recv_shares: Vec<curveScalar> = deserialize_from(&buf[1..]).unwrap();
-                let mut l = mbits.as_ref().lock().unwrap();
-                for i in 0..NUM_DIMENSION {
-                    l[src][i] = recv_shares[i];
+                    // malformed subshares count as wrong ones
+                    l.shares[src] = Some(deserialize_from(&buf[..]).unwrap_or_default());
                 }
-            }));
+                ar.notify_one();
+            });
         }
-        futures::future::join_all(handles).await;
     });
 
     // sending data to other players
-    {
-        // 1 + 32 * 4096 + 8
-        let mut buf = vec![0u8; 131081];
-        for i in 0..players.len() {
-            buf[0] = id as u8;
-            if i != id {
-                let mut stream = TcpStream::connect(&players[i]).await?;
-                serialize_into(&mut buf[1..], &to_send[i]).unwrap();
-                stream.write_all(&buf).await?;
-            }
+    for &i in others.iter() {
+        let mut buf = vec![SHARE, id as u8];
+        buf.extend_from_slice(&(nr_bytes as u64).to_le_bytes());
+        buf.resize(10 + nr_bytes, 0);
+        serialize_into(&mut buf[10..], &to_send[i]).unwrap();
+        let sent = match TcpStream::connect(&players[i]).await {
+            Ok(mut stream) => stream.write_all(&buf).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = sent {
+            eprintln!("failed to send to player {}; err = {:?}", i, e);
         }
     }
 
-    f.await?;
-    let mut rb = mb.lock().unwrap();
-    let shamir_context = vec![
-        ShamirContext::init(MODULUS[0], nr_players, threshold),
-        ShamirContext::init(MODULUS[1], nr_players, threshold),
-        ShamirContext::init(MODULUS[2], nr_players, threshold),
-    ];
-    //for k in 0..nr_players {
-    //    println!("s'[{}][{}]={:?}", k, id, rb[k][0]);
-    //}
-
-    let mut share: Vec<Vec<u64>> = vec![vec![0u64; NUM_DIMENSION]; 3];
-    for i in 0..NUM_DIMENSION {
-        for k in 0..1 {
-            //for k in 0..3 {
-            let subshare: Vec<u64> = (0..nr_players)
-                .map(|x| {
-                    let bigint = BigUint::from_bytes_le(rb[x][i].as_bytes());
-                    let u64_digits = (bigint % MODULUS[k]).to_u64_digits();
-                    if u64_digits.len() == 0 {
-                        0u64
-                    } else {
-                        u64_digits[0]
-                    }
-                })
-                .collect();
-            //println!("{:?}", subshare);
-            share[k][i] = shamir_context[k].reconstruct(&subshare);
+    // players that don't deal by the deadline are complained about
+    let deadline = Instant::now() + timeout;
+    wait_for(&inbox, &arrived, deadline, |l| {
+        l.shares.iter().filter(|x| x.is_some()).count() == nr_players - 1
+    })
+    .await;
+
+    // every dealer publishes its commitments before dealing, so a dealer whose subshares are in
+    // has them on the board, and the others had till the deadline. A dealer without them, or
+    // whose C_0 doesn't commit to its old share, is out for everyone.
+    let all: Vec<usize> = (0..nr_players).collect();
+    let published: Vec<Option<(Commitments, Option<Vec<CurveScalar>>)>> =
+        wait_published(&board, Reshare::Proof, epoch, &all, nr_players, deadline).await;
+    let gc = start_timer!(|| "verify the commitments to the old shares");
+    let proofs: Vec<Option<Commitments>> = published
+        .into_iter()
+        .enumerate()
+        .map(|(i, x)| match (x, &old_commitments) {
+            (Some((c, Some(quotients))), Some(old))
+                if reshare_context.verify_old_share(i, &c, old, &quotients) =>
+            {
+                Some(c)
+            }
+            (Some((c, _)), None) if epoch == 0 => Some(c),
+            _ => None,
+        })
+        .collect();
+    end_timer!(gc);
+    for i in (0..nr_players).filter(|&i| proofs[i].is_none()) {
+        eprintln!("dealer {} published no valid commitments", i);
+    }
+
+    let gc = start_timer!(|| "verify the subshares");
+    let complaints: Vec<bool> = {
+        let l = inbox.as_ref().lock().unwrap();
+        (0..nr_players)
+            .map(|i| match (&l.shares[i], &proofs[i]) {
+                _ if i == id => false,
+                (Some(x), Some(c)) => !reshare_context.verify(id, x, c),
+                _ => true,
+            })
+            .collect()
+    };
+    end_timer!(gc);
+    for i in (0..nr_players).filter(|&i| complaints[i]) {
+        eprintln!("player {} complains about dealer {}", id, i);
+    }
+
+    // publish the complaints, players that don't take part in the complaint round are out as
+    // dealers
+    publish(
+        &board,
+        &identity,
+        Reshare::Complaints,
+        epoch,
+        id,
+        &complaints,
+    )
+    .await?;
+    let published: Vec<Option<Vec<bool>>> = wait_published(
+        &board,
+        Reshare::Complaints,
+        epoch,
+        &all,
+        nr_players,
+        Instant::now() + timeout,
+    )
+    .await;
+    let (complaints, silent): (Vec<Vec<bool>>, Vec<bool>) = published
+        .into_iter()
+        .map(|x| match x {
+            Some(x) if x.len() == nr_players => (x, false),
+            _ => (vec![false; nr_players], true),
+        })
+        .unzip();
+    for i in (0..nr_players).filter(|&i| silent[i]) {
+        eprintln!("player {} published no complaints", i);
+    }
+    // complained_by[j]: the players complaining about dealer j
+    let complained_by: Vec<Vec<usize>> = (0..nr_players)
+        .map(|j| {
+            (0..nr_players)
+                .filter(|&i| i != j && complaints[i][j])
+                .collect()
+        })
+        .collect();
+
+    // publish the subshares of the players that complain about this dealer
+    let response: Response = complained_by[id]
+        .iter()
+        .map(|&i| (i, to_send[i].clone()))
+        .collect();
+    if !response.is_empty() {
+        publish(&board, &identity, Reshare::Response, epoch, id, &response).await?;
+    }
+
+    // a dealer is qualified if it answers every complaint with subshares that open its
+    // commitments, then the complaining players take those. Everything is decided from the
+    // board, so every player ends with the same qualified dealers.
+    let complained: Vec<usize> = (0..nr_players)
+        .filter(|&j| !complained_by[j].is_empty())
+        .collect();
+    let mut responses: Vec<Option<Response>> = wait_published(
+        &board,
+        Reshare::Response,
+        epoch,
+        &complained,
+        nr_players,
+        Instant::now() + timeout,
+    )
+    .await;
+    let gc = start_timer!(|| "verify the responses");
+    let mut rb = inbox.as_ref().lock().unwrap();
+    rb.shares[id] = Some(to_send[id].clone());
+    let mut qualified = Vec::with_capacity(nr_players);
+    for j in (0..nr_players).filter(|&j| proofs[j].is_some() && !silent[j]) {
+        if !complained_by[j].is_empty() {
+            let response = responses[j].take().unwrap_or_default();
+            let c = proofs[j].as_ref().unwrap();
+            let answered = complained_by[j].iter().all(|&i| {
+                response
+                    .iter()
+                    .any(|(k, x)| *k == i && reshare_context.verify(i, x, c))
+            });
+            if !answered {
+                eprintln!("dealer {} failed to answer the complaints", j);
+                continue;
+            }
+            if complaints[id][j] {
+                rb.shares[j] = response.into_iter().find(|(k, _)| *k == id).map(|x| x.1);
+            }
         }
+        qualified.push(j);
+    }
+    end_timer!(gc);
+    if qualified.len() <= threshold {
+        return Err(format!(
+            "only {} qualified dealers, at least {} are needed",
+            qualified.len(),
+            threshold + 1
+        )
+        .into());
     }
+    let subshares: Vec<Vec<Subshare>> = qualified
+        .iter()
+        .map(|x| rb.shares[*x].take().unwrap())
+        .collect();
+    let new_share = reshare_context.combine(&qualified, &subshares);
+    let mut proofs = proofs;
+    let dealt = qualified.iter().map(|x| proofs[*x].take().unwrap());
+    let new_commitments = reshare_context.combine_commitments(&qualified, dealt);
+    f.abort();
 
     {
         let file_name = format!("./data/sk_share_new{}.txt", id);
         let mut f = BufWriter::new(File::create(file_name).unwrap());
-        serialize_into(&mut f, &share[0]).unwrap();
-        serialize_into(&mut f, &share[1]).unwrap();
-        serialize_into(&mut f, &share[2]).unwrap();
+        for x in new_share.share.iter() {
+            serialize_into(&mut f, x).unwrap();
+        }
+    }
+    // for the next resharing, which checks C_0 of every dealer against these
+    {
+        let file_name = format!("./data/reshare_opening_new{}.txt", id);
+        let mut f = BufWriter::new(File::create(file_name).unwrap());
+        serialize_into(
+            &mut f,
+            &(epoch + 1, &new_share.blinding, &new_share.quotients),
+        )
+        .unwrap();
+        let file_name = format!("./data/reshare_commitments_new{}.txt", id);
+        let mut f = BufWriter::new(File::create(file_name).unwrap());
+        serialize_into(&mut f, &new_commitments).unwrap();
     }
 
     Ok(())
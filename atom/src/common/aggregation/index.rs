@@ -0,0 +1,89 @@
+use crate::common::server_service::AggregationError;
+use std::collections::HashMap;
+
+/// How far a client has got in the current round
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KeyStatus {
+    /// its commitment is in Mc
+    Committed,
+    /// its data is in Ms
+    Uploaded,
+}
+
+/// The rsa_pk seen in the current round, so each client commits once and uploads once,
+/// and only after committing.
+#[derive(Debug, Default)]
+pub struct KeyIndex {
+    keys: HashMap<Vec<u8>, KeyStatus>,
+}
+
+impl KeyIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn status(&self, rsa_pk: &[u8]) -> Option<KeyStatus> {
+        self.keys.get(rsa_pk).cloned()
+    }
+
+    /// whether rsa_pk may commit, i.e., it hasn't yet
+    pub fn check_commit(&self, rsa_pk: &[u8]) -> Result<(), AggregationError> {
+        match self.status(rsa_pk) {
+            None => Ok(()),
+            Some(_) => Err(AggregationError::DuplicateCommit),
+        }
+    }
+
+    /// whether rsa_pk may upload its data, i.e., it has committed and not uploaded yet
+    pub fn check_upload(&self, rsa_pk: &[u8]) -> Result<(), AggregationError> {
+        match self.status(rsa_pk) {
+            Some(KeyStatus::Committed) => Ok(()),
+            Some(KeyStatus::Uploaded) => Err(AggregationError::DuplicateUpload),
+            None => Err(AggregationError::NotCommitted),
+        }
+    }
+
+    pub fn commit(&mut self, rsa_pk: &[u8]) -> Result<(), AggregationError> {
+        self.check_commit(rsa_pk)?;
+        self.keys.insert(rsa_pk.to_vec(), KeyStatus::Committed);
+        Ok(())
+    }
+
+    pub fn upload(&mut self, rsa_pk: &[u8]) -> Result<(), AggregationError> {
+        self.check_upload(rsa_pk)?;
+        self.keys.insert(rsa_pk.to_vec(), KeyStatus::Uploaded);
+        Ok(())
+    }
+
+    /// # of clients which have committed, uploaded or not
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.keys.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_commit_then_upload_once() {
+        let mut index = KeyIndex::new();
+        let (a, b) = (vec![1u8; 8], vec![2u8; 8]);
+        assert_eq!(index.upload(&a), Err(AggregationError::NotCommitted));
+        assert_eq!(index.commit(&a), Ok(()));
+        assert_eq!(index.commit(&a), Err(AggregationError::DuplicateCommit));
+        assert_eq!(index.upload(&a), Ok(()));
+        assert_eq!(index.upload(&a), Err(AggregationError::DuplicateUpload));
+        // a client which has uploaded can't commit again either
+        assert_eq!(index.commit(&a), Err(AggregationError::DuplicateCommit));
+        assert_eq!(index.status(&b), None);
+        assert_eq!(index.len(), 1);
+
+        index.clear();
+        assert_eq!(index.commit(&a), Ok(()));
+    }
+}
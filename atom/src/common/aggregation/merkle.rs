@@ -1,121 +1,233 @@
-#[cfg(feature = "hashfn_blake3")]
-extern crate blake3;
-#[cfg(not(feature = "hashfn_blake3"))]
-use crypto::{digest::Digest, sha3::Sha3, sha3::Sha3Mode};
+use crate::common::hash::{Domain, HashFunction};
+use rayon::prelude::*;
 use tarpc::serde::{Deserialize, Serialize};
 
 //pub type MerkleHash = [u8; 32];
-pub type HashAlgorithm = hash_fn::HashAlgorithm;
-pub type MerkleTree = merkle_light::merkle::MerkleTree<[u8; 32], HashAlgorithm>;
 
+/// A merkle tree over some leafs followed by `nr_padding` leafs of [0u8; 32], with the same root
+/// and proofs as a merkle_light `MerkleTree` over all of them, hashing with `hash_fn`. The padding
+/// is never materialized: at each height, the nodes past the ones depending on the leafs are all
+/// the hash of an empty subtree.
+pub struct PaddedMerkleTree {
+    hash_fn: HashFunction,
+    // levels[h] are the nodes at height h depending on the leafs, from the leaf hashes to the root
+    levels: Vec<Vec<[u8; 32]>>,
+    // empty[h] is the node at height h of a subtree of padding
+    empty: Vec<[u8; 32]>,
+    // the # of nodes at each height, with the padding
+    widths: Vec<usize>,
+}
+
+impl PaddedMerkleTree {
+    pub fn new(hash_fn: HashFunction, leafs: &[[u8; 32]], nr_padding: usize) -> Self {
+        let mut width = leafs.len() + nr_padding;
+        assert!(width > 1, "a merkle tree needs at least 2 leafs");
+        let mut nodes: Vec<[u8; 32]> = leafs.par_iter().map(|x| leaf_hash(hash_fn, x)).collect();
+        let mut zero = leaf_hash(hash_fn, &[0u8; 32]);
+        let mut levels = Vec::new();
+        let mut empty = Vec::new();
+        let mut widths = Vec::new();
+        while width > 1 {
+            // an odd level repeats its last node, which is a padding node if there is any
+            if width & 1 == 1 && nodes.len() == width {
+                nodes.push(nodes[width - 1]);
+            }
+            let next: Vec<[u8; 32]> = nodes
+                .par_chunks(2)
+                .map(|x| node_hash(hash_fn, &x[0], x.get(1).unwrap_or(&zero)))
+                .collect();
+            levels.push(nodes);
+            empty.push(zero);
+            widths.push(width);
+            zero = node_hash(hash_fn, &zero, &zero);
+            nodes = next;
+            width = (width + 1) / 2;
+        }
+        levels.push(nodes);
+        empty.push(zero);
+        widths.push(width);
+        Self {
+            hash_fn,
+            levels,
+            empty,
+            widths,
+        }
+    }
+
+    /// the # of leafs with the padding
+    pub fn leafs(&self) -> usize {
+        self.widths[0]
+    }
+
+    pub fn root(&self) -> [u8; 32] {
+        self.node(self.levels.len() - 1, 0)
+    }
+
+    fn node(&self, height: usize, i: usize) -> [u8; 32] {
+        *self.levels[height].get(i).unwrap_or(&self.empty[height])
+    }
+
+    /// the proof of the i-th leaf, the same as `MerkleTree::gen_proof`
+    pub fn gen_proof(&self, i: usize) -> MerkleProof {
+        assert!(i < self.leafs());
+        let height = self.levels.len() - 1;
+        let mut lemma = Vec::with_capacity(height + 2);
+        let mut path = Vec::with_capacity(height);
+        lemma.push(self.node(0, i));
+        let mut j = i;
+        for h in 0..height {
+            lemma.push(self.node(h, j ^ 1));
+            path.push(j & 1 == 0);
+            j >>= 1;
+        }
+        lemma.push(self.root());
+        MerkleProof {
+            lemma,
+            path,
+            hash_fn: self.hash_fn,
+        }
+    }
+}
+
+/// the hash of an item into a leaf of the trees
+pub fn leaf_hash(hash_fn: HashFunction, item: &[u8; 32]) -> [u8; 32] {
+    hash_fn.hash(Domain::MerkleLeaf, &[item])
+}
+
+/// the hash of two children into their parent
+pub fn node_hash(hash_fn: HashFunction, left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    hash_fn.hash(Domain::MerkleNode, &[left, right])
+}
+
+/// An inclusion proof in the layout of merkle_light: the leaf hash, the siblings from the
+/// bottom up and the root, with path[h] set when the node at height h is a left child.
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct MerkleProof {
     pub lemma: Vec<[u8; 32]>,
     pub path: Vec<bool>,
+    /// the hash function of the tree, a proof only validates under the same one
+    pub hash_fn: HashFunction,
 }
 
 impl MerkleProof {
-    pub fn to_proof(self) -> merkle_light::proof::Proof<[u8; 32]> {
-        merkle_light::proof::Proof::<[u8; 32]>::from(self)
-    }
-}
-impl From<merkle_light::proof::Proof<[u8; 32]>> for MerkleProof {
-    fn from(proof: merkle_light::proof::Proof<[u8; 32]>) -> Self {
-        Self {
-            lemma: proof.lemma,
-            path: proof.path,
+    /// check the lemma hashes up to its root with `hash_fn`, the hash function of the round
+    pub fn validate(&self, hash_fn: HashFunction) -> bool {
+        let size = self.lemma.len();
+        if self.hash_fn != hash_fn || size < 2 || size != self.path.len() + 2 {
+            return false;
         }
+        let h = self.lemma[1..size - 1].iter().zip(self.path.iter()).fold(
+            self.lemma[0],
+            |h, (sibling, is_left)| {
+                if *is_left {
+                    node_hash(hash_fn, &h, sibling)
+                } else {
+                    node_hash(hash_fn, sibling, &h)
+                }
+            },
+        );
+        h == self.lemma[size - 1]
     }
-}
-impl From<MerkleProof> for merkle_light::proof::Proof<[u8; 32]> {
-    fn from(proof: MerkleProof) -> Self {
-        Self {
-            lemma: proof.lemma,
-            path: proof.path,
-        }
+
+    /// check the proof opens `item` (before the leaf hashing of the tree) under `root`
+    pub fn validate_against(
+        &self,
+        item: &[u8; 32],
+        root: &[u8; 32],
+        hash_fn: HashFunction,
+    ) -> bool {
+        self.validate(hash_fn)
+            && self.lemma[0] == leaf_hash(hash_fn, item)
+            && self.lemma[self.lemma.len() - 1] == *root
+    }
+
+    /// the position of the leaf in the tree, read off the path
+    pub fn index(&self) -> usize {
+        self.path
+            .iter()
+            .rev()
+            .fold(0, |i, is_left| (i << 1) | (!*is_left as usize))
     }
 }
-#[cfg(feature = "hashfn_blake3")]
-mod hash_fn {
+
+#[cfg(test)]
+mod tests {
+    use super::*;
     use merkle_light::hash::Algorithm;
+    use std::convert::TryInto;
     use std::hash::Hasher;
-    pub struct HashAlgorithm(blake3::Hasher);
-    impl HashAlgorithm {
-        pub fn new() -> HashAlgorithm {
-            HashAlgorithm(blake3::Hasher::new())
-        }
-    }
+    use std::iter::FromIterator;
 
-    impl Default for HashAlgorithm {
-        fn default() -> HashAlgorithm {
-            HashAlgorithm::new()
-        }
-    }
+    // merkle_light hashing like the trees with blake3, for comparing against its MerkleTree
+    #[derive(Default)]
+    struct Blake3Algorithm(blake3::Hasher);
 
-    impl Hasher for HashAlgorithm {
-        #[inline]
+    impl Hasher for Blake3Algorithm {
         fn write(&mut self, msg: &[u8]) {
             self.0.update(msg);
         }
 
-        #[inline]
         fn finish(&self) -> u64 {
-            unimplemented!()
+            let h = self.0.finalize();
+            u64::from_le_bytes(h.as_bytes()[0..8].try_into().unwrap())
         }
     }
 
-    impl Algorithm<[u8; 32]> for HashAlgorithm {
-        #[inline]
+    impl Algorithm<[u8; 32]> for Blake3Algorithm {
         fn hash(&mut self) -> [u8; 32] {
             self.0.finalize().into()
         }
 
-        #[inline]
-        fn reset(&mut self) {
-            self.0.reset();
-        }
-    }
-}
-#[cfg(not(feature = "hashfn_blake3"))]
-mod hash_fn {
-    use merkle_light::hash::Algorithm;
-    use std::hash::Hasher;
-    pub struct HashAlgorithm(Sha3);
-    impl HashAlgorithm {
-        pub fn new() -> HashAlgorithm {
-            HashAlgorithm(Sha3::new(Sha3Mode::Sha3_256))
+        fn leaf(&mut self, leaf: [u8; 32]) -> [u8; 32] {
+            leaf_hash(HashFunction::Blake3, &leaf)
         }
-    }
 
-    impl Default for HashAlgorithm {
-        fn default() -> HashAlgorithm {
-            HashAlgorithm::new()
+        fn node(&mut self, left: [u8; 32], right: [u8; 32]) -> [u8; 32] {
+            node_hash(HashFunction::Blake3, &left, &right)
         }
     }
 
-    impl Hasher for HashAlgorithm {
-        #[inline]
-        fn write(&mut self, msg: &[u8]) {
-            self.0.input(msg)
-        }
-
-        #[inline]
-        fn finish(&self) -> u64 {
-            unimplemented!()
+    #[test]
+    fn test_padded_tree_matches() {
+        let mut a = Blake3Algorithm::default();
+        a.write(b"leaf");
+        assert_eq!(a.hash(), *blake3::hash(b"leaf").as_bytes());
+        let sizes = [(2, 0), (3, 0), (5, 1), (7, 10), (1, 6), (0, 5), (13, 100)];
+        for (nr_leaf, nr_padding) in sizes.iter() {
+            let leafs: Vec<[u8; 32]> = (0..*nr_leaf).map(|i| [i as u8 + 1; 32]).collect();
+            let full = merkle_light::merkle::MerkleTree::<[u8; 32], Blake3Algorithm>::from_iter(
+                leafs
+                    .iter()
+                    .cloned()
+                    .chain((0..*nr_padding).map(|_| [0u8; 32])),
+            );
+            let padded = PaddedMerkleTree::new(HashFunction::Blake3, &leafs, *nr_padding);
+            assert_eq!(padded.root(), full.root());
+            assert_eq!(padded.leafs(), full.leafs());
+            for i in 0..full.leafs() {
+                let proof = padded.gen_proof(i);
+                assert_eq!(proof.lemma, full.gen_proof(i).lemma);
+                assert_eq!(proof.path, full.gen_proof(i).path);
+                assert!(proof.validate(HashFunction::Blake3));
+                assert_eq!(proof.index(), i);
+            }
         }
     }
 
-    impl Algorithm<[u8; 32]> for HashAlgorithm {
-        #[inline]
-        fn hash(&mut self) -> [u8; 32] {
-            let mut h = [0u8; 32];
-            self.0.result(&mut h);
-            h
-        }
-
-        #[inline]
-        fn reset(&mut self) {
-            self.0.reset();
+    #[test]
+    fn test_proof_hash_function() {
+        let leafs: Vec<[u8; 32]> = (0..5).map(|i| [i as u8; 32]).collect();
+        for hash_fn in [HashFunction::Sha3, HashFunction::Poseidon].iter() {
+            let tree = PaddedMerkleTree::new(*hash_fn, &leafs, 3);
+            let proof = tree.gen_proof(2);
+            assert!(proof.validate_against(&leafs[2], &tree.root(), *hash_fn));
+            assert!(!proof.validate_against(&leafs[1], &tree.root(), *hash_fn));
+            assert_eq!(proof.index(), 2);
+            // a proof is never checked with another hash function, even if relabeled
+            assert!(!proof.validate(HashFunction::Blake3));
+            let mut relabeled = proof.clone();
+            relabeled.hash_fn = HashFunction::Blake3;
+            assert!(!relabeled.validate(HashFunction::Blake3));
         }
     }
 }
@@ -1,29 +1,38 @@
+pub mod index;
 pub mod merkle;
-use merkle::MerkleTree;
+use merkle::PaddedMerkleTree;
+pub mod modq;
 pub mod node;
+pub mod sample;
 
 use self::{merkle::MerkleProof, node::SummationNonLeaf};
 use node::{CommitEntry, SummationEntry, SummationLeaf};
 use rayon::prelude::*;
-use std::iter::FromIterator;
+use std::collections::BTreeMap;
 use tracing::{error, instrument, warn};
 
 use ark_std::{end_timer, start_timer};
 
-use super::summation_array_size;
+use super::{hash::HashFunction, params::ParameterSet, summation_array_size};
 
 pub struct McTree {
     pub nr_real: u32,
     pub nr_sybil: u32,
+    /// the hash function of the round, see `ParameterSet::hash_fn`
+    pub hash_fn: HashFunction,
+    // the leafs inserted so far, sorted by rsa_pk, moved to commit_array by gen_tree
+    pending: BTreeMap<Vec<u8>, CommitEntry>,
     pub commit_array: Vec<CommitEntry>,
-    pub mc: Option<MerkleTree>,
+    pub mc: Option<PaddedMerkleTree>,
 }
 
 impl McTree {
-    pub fn new(nr_real: u32, nr_sybil: u32) -> Self {
+    pub fn new(nr_real: u32, nr_sybil: u32, hash_fn: HashFunction) -> Self {
         McTree {
             nr_real,
             nr_sybil,
+            hash_fn,
+            pending: BTreeMap::new(),
             commit_array: Vec::with_capacity(nr_real as usize),
             mc: None,
         }
@@ -41,66 +50,74 @@ impl McTree {
         }
     }
 
+    /// whether nr_real leafs have been inserted
+    pub fn is_full(&self) -> bool {
+        self.pending.len() + self.commit_array.len() >= self.nr_real as usize
+    }
+
+    /// build the tree once it is full, return false if it is not
     #[instrument(skip_all)]
     pub fn gen_tree(&mut self) -> bool {
-        if self.commit_array.len() < self.nr_real as usize {
-            warn!(
-                "Gen commit tree: {} < {}",
-                self.commit_array.len(),
-                self.nr_real
-            );
+        if !self.is_full() {
             false
         } else {
-            self.commit_array
-                .sort_by(|a, b| a.rsa_pk.partial_cmp(&b.rsa_pk).unwrap());
-            self.mc = Some(MerkleTree::from_iter(
-                self.commit_array
-                    .par_iter()
-                    .map(|x| x.hash())
-                    .chain((0..self.nr_sybil).into_par_iter().map(|_| [0u8; 32]))
-                    .collect::<Vec<[u8; 32]>>(),
-            ));
+            self.gen_tree_timout();
             true
         }
     }
 
+    /// build the tree over the leafs inserted so far, return the # of leafs
     #[instrument(skip_all)]
     pub fn gen_tree_timout(&mut self) -> usize {
+        // the map is sorted by rsa_pk, so are the leafs
+        let pending = std::mem::take(&mut self.pending);
         self.commit_array
-            .sort_by(|a, b| a.rsa_pk.partial_cmp(&b.rsa_pk).unwrap());
-        self.mc = Some(MerkleTree::from_iter(
-            self.commit_array
+            .extend(pending.into_iter().map(|(_, x)| x));
+        // the sybil slots are empty leafs, padded without being hashed
+        let hash_fn = self.hash_fn;
+        self.mc = Some(PaddedMerkleTree::new(
+            hash_fn,
+            &self
+                .commit_array
                 .par_iter()
-                .map(|x| x.hash())
-                .chain((0..self.nr_sybil).into_par_iter().map(|_| [0u8; 32]))
+                .map(|x| x.hash(hash_fn))
                 .collect::<Vec<[u8; 32]>>(),
+            self.nr_sybil as usize,
         ));
         self.commit_array.len()
     }
 
-    pub fn get_proof(&self, rsa_pk: &Vec<u8>) -> MerkleProof {
+    pub fn get_proof(&self, rsa_pk: &Vec<u8>) -> Option<MerkleProof> {
         if self.mc.is_none() {
             warn!("get_proof@McTree called while None Mc tree");
+            return None;
         }
         let id = self
             .commit_array
             .binary_search_by(|probe| probe.rsa_pk.cmp(rsa_pk))
-            .unwrap();
-        self.mc.as_ref().unwrap().gen_proof(id).into()
+            .ok()?;
+        Some(self.mc.as_ref().unwrap().gen_proof(id))
+    }
+
+    pub fn get_merkle_root(&self) -> Option<[u8; 32]> {
+        self.mc.as_ref().map(|t| t.root())
     }
 
     pub fn get_proof_by_id(&self, id: u32) -> MerkleProof {
-        self.mc.as_ref().unwrap().gen_proof(id as usize).into()
+        self.mc.as_ref().unwrap().gen_proof(id as usize)
     }
 
-    pub fn insert_node(&mut self, node: CommitEntry) {
-        if self.commit_array.len() >= self.nr_real as usize {
-            panic!("insert more then expected");
+    /// insert a leaf in O(log n), return false if the tree is full or built, or rsa_pk is already in
+    pub fn insert_node(&mut self, node: CommitEntry) -> bool {
+        if self.mc.is_some() || self.is_full() || self.pending.contains_key(&node.rsa_pk) {
+            return false;
         }
-        self.commit_array.push(node);
+        self.pending.insert(node.rsa_pk.clone(), node);
+        true
     }
 
     pub fn clear(&mut self) {
+        self.pending.clear();
         self.commit_array.clear();
         self.mc = None;
     }
@@ -111,20 +128,29 @@ pub struct MsTree {
     pub nr_sybil: u32,
     pub nr_non_leaf: u32,
     pub random_pt: i128,
+    // the leafs inserted so far, sorted by rsa_pk, moved to summation_array by gen_tree
+    pending: BTreeMap<Vec<u8>, SummationLeaf>,
     pub summation_array: Vec<SummationEntry>,
-    pub ms: Option<MerkleTree>,
+    // commits to the leafs before the random point is drawn
+    pub leaf_tree: Option<PaddedMerkleTree>,
+    pub ms: Option<PaddedMerkleTree>,
+    // the leafs are evaluated mod the ciphertext modulus of params
+    pub params: ParameterSet,
 }
 impl MsTree {
-    pub fn new(nr_real: u32, nr_sybil: u32) -> Self {
+    pub fn new(nr_real: u32, nr_sybil: u32, params: &ParameterSet) -> Self {
         let nr_non_leaf = summation_array_size(nr_real);
         MsTree {
             nr_real,
             nr_non_leaf,
             nr_sybil,
+            params: params.clone(),
+            pending: BTreeMap::new(),
             summation_array: Vec::with_capacity(nr_real as usize),
+            leaf_tree: None,
             ms: None,
-            // TODO get this from the committee
-            random_pt: 1i128,
+            // drawn by the committee after the leafs are committed
+            random_pt: 0i128,
         }
     }
 
@@ -159,98 +185,169 @@ impl MsTree {
         ret
     }
 
+    /// whether nr_real leafs have been inserted
+    pub fn is_full(&self) -> bool {
+        self.pending.len() + self.summation_array.len() >= self.nr_real as usize
+    }
+
+    /// sort and commit to the leafs if there are enough, the summation is computed by
+    /// gen_summation once the random point is drawn
     #[instrument(skip_all)]
     pub fn gen_tree(&mut self) -> bool {
-        if self.summation_array.len() < self.nr_real as usize {
-            warn!(
-                "Gen summation tree: {} < {}",
-                self.summation_array.len(),
-                self.nr_real
-            );
+        if !self.is_full() {
             false
         } else {
-            // first from the leafs to the tree first
-            // TODO check the rsa_pk appears in Mc
-            // still, since we don't focus on the aggregator cost, we don't implement it for now
-            let gc = start_timer!(|| "gen tree of ms");
-            warn!("start gen tree");
-            self.summation_array.sort_by(|a, b| {
-                a.get_leaf_rsa_pk()
-                    .partial_cmp(b.get_leaf_rsa_pk())
-                    .unwrap()
-            });
-            warn!("finish sort");
-            // get the non-leaf nodes
-            let mut left = 0;
-            let mut right = self.summation_array.len();
-            while left + 1 < right {
-                let c = match (&self.summation_array[left], &self.summation_array[left + 1]) {
-                    (SummationEntry::NonLeaf(l), SummationEntry::NonLeaf(r)) => l + r,
-                    (SummationEntry::NonLeaf(l), SummationEntry::Leaf(r)) => {
-                        l + &r.evaluate_at(self.random_pt)
-                    }
-                    (SummationEntry::Leaf(l), SummationEntry::NonLeaf(r)) => {
-                        &l.evaluate_at(self.random_pt) + r
-                    }
-                    (SummationEntry::Leaf(l), SummationEntry::Leaf(r)) => {
-                        l.evaluate_at(self.random_pt) + r.evaluate_at(self.random_pt)
-                    }
-                    _ => {
-                        panic!("gen_tree: Not a leaf or nonleaf node");
-                    }
-                };
-                self.summation_array.push(SummationEntry::NonLeaf(c));
-                left += 2;
-                right += 1;
-            }
-            warn!("finish adding leafs");
-
-            let root = self.get_root();
-            let nr_parameters = 4096;
-            self.ms = Some(MerkleTree::from_iter(
-                self.summation_array
+            // the server only inserts the data of the rsa_pk committed in Mc, see `KeyIndex`
+            // the map is sorted by rsa_pk, so are the leafs
+            let pending = std::mem::take(&mut self.pending);
+            self.summation_array
+                .extend(pending.into_iter().map(|(_, x)| SummationEntry::Leaf(x)));
+            let hash_fn = self.params.hash_fn;
+            self.leaf_tree = Some(PaddedMerkleTree::new(
+                hash_fn,
+                &self
+                    .summation_array
                     .par_iter()
                     .map(|x| match x {
-                        SummationEntry::Leaf(y) => y.hash(),
-                        SummationEntry::NonLeaf(y) => y.hash(),
-                        // just to make compiler happy
-                        // never reach here
+                        SummationEntry::Leaf(y) => y.hash(hash_fn),
                         _ => {
-                            error!("commitment in summation array");
+                            error!("non-leaf in the leafs of summation array");
                             [0u8; 32]
                         }
                     })
-                    .chain((0..(2 * self.nr_sybil)).into_par_iter().map(|_| [0u8; 32]))
                     .collect::<Vec<[u8; 32]>>(),
+                self.nr_sybil as usize,
             ));
-            end_timer!(gc);
             true
         }
     }
 
-    pub fn get_proof(&self, rsa_pk: &Vec<u8>) -> MerkleProof {
+    pub fn get_leaf_root(&self) -> Option<[u8; 32]> {
+        self.leaf_tree.as_ref().map(|t| t.root())
+    }
+
+    /// evaluate the leafs at `random_pt`, sum them up and build the summation merkle tree
+    #[instrument(skip_all)]
+    pub fn gen_summation(&mut self, random_pt: i128) {
+        let gc = start_timer!(|| "gen tree of ms");
+        self.random_pt = random_pt;
+        // get the non-leaf nodes
+        let mut left = 0;
+        let mut right = self.summation_array.len();
+        while left + 1 < right {
+            let c = match (&self.summation_array[left], &self.summation_array[left + 1]) {
+                (SummationEntry::NonLeaf(l), SummationEntry::NonLeaf(r)) => l + r,
+                (SummationEntry::NonLeaf(l), SummationEntry::Leaf(r)) => {
+                    l + &r.evaluate_at(self.random_pt, &self.params)
+                }
+                (SummationEntry::Leaf(l), SummationEntry::NonLeaf(r)) => {
+                    &l.evaluate_at(self.random_pt, &self.params) + r
+                }
+                (SummationEntry::Leaf(l), SummationEntry::Leaf(r)) => {
+                    l.evaluate_at(self.random_pt, &self.params)
+                        + r.evaluate_at(self.random_pt, &self.params)
+                }
+                _ => {
+                    panic!("gen_summation: Not a leaf or nonleaf node");
+                }
+            };
+            self.summation_array.push(SummationEntry::NonLeaf(c));
+            left += 2;
+            right += 1;
+        }
+        warn!("finish adding leafs");
+
+        let hash_fn = self.params.hash_fn;
+        self.ms = Some(PaddedMerkleTree::new(
+            hash_fn,
+            &self
+                .summation_array
+                .par_iter()
+                .map(|x| match x {
+                    SummationEntry::Leaf(y) => y.hash(hash_fn),
+                    SummationEntry::NonLeaf(y) => y.hash(hash_fn),
+                    // just to make compiler happy
+                    // never reach here
+                    _ => {
+                        error!("commitment in summation array");
+                        [0u8; 32]
+                    }
+                })
+                .collect::<Vec<[u8; 32]>>(),
+            2 * self.nr_sybil as usize,
+        ));
+        end_timer!(gc);
+    }
+
+    pub fn get_proof(&self, rsa_pk: &Vec<u8>) -> Option<MerkleProof> {
         if self.ms.is_none() {
             warn!("get_proof@MsTree called while None Ms tree");
+            return None;
         }
         let id = self.summation_array[0..self.nr_real as usize]
             .binary_search_by(|probe| probe.get_leaf_rsa_pk().cmp(rsa_pk))
-            .unwrap();
-        self.ms.as_ref().unwrap().gen_proof(id).into()
+            .ok()?;
+        Some(self.ms.as_ref().unwrap().gen_proof(id))
+    }
+
+    pub fn get_merkle_root(&self) -> Option<[u8; 32]> {
+        self.ms.as_ref().map(|t| t.root())
     }
 
     pub fn get_proof_by_id(&self, id: u32) -> MerkleProof {
-        self.ms.as_ref().unwrap().gen_proof(id as usize).into()
+        self.ms.as_ref().unwrap().gen_proof(id as usize)
+    }
+
+    /// the proof of the id-th leaf in the tree of the leafs, see `get_leaf_root`
+    pub fn get_leaf_proof_by_id(&self, id: u32) -> MerkleProof {
+        self.leaf_tree.as_ref().unwrap().gen_proof(id as usize)
     }
 
-    pub fn insert_node(&mut self, node: SummationLeaf) {
-        if self.summation_array.len() >= self.nr_real as usize {
-            error!("insert more then expected");
+    /// insert a leaf in O(log n), return false if the tree is full or built, or rsa_pk is already in
+    pub fn insert_node(&mut self, node: SummationLeaf) -> bool {
+        if self.leaf_tree.is_some() || self.is_full() || self.pending.contains_key(&node.rsa_pk) {
+            return false;
         }
-        self.summation_array.push(SummationEntry::Leaf(node));
+        self.pending.insert(node.rsa_pk.clone(), node);
+        true
     }
 
     pub fn clear(&mut self) {
+        self.pending.clear();
         self.summation_array.clear();
+        self.leaf_tree = None;
         self.ms = None;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_incremental_insert() {
+        let mut mc = McTree::new(4, 2, HashFunction::default());
+        let entry = |k: u8| CommitEntry {
+            rsa_pk: vec![k; 8],
+            hash: [k; 32],
+            signature: Vec::new(),
+        };
+        for k in [3u8, 1, 4].iter() {
+            assert!(mc.insert_node(entry(*k)));
+            assert!(!mc.gen_tree());
+        }
+        // a duplicate rsa_pk is rejected
+        assert!(!mc.insert_node(entry(1)));
+        assert!(mc.insert_node(entry(2)));
+        // so is anything once the tree is full
+        assert!(mc.is_full() && !mc.insert_node(entry(5)));
+        assert!(mc.gen_tree());
+        let keys: Vec<u8> = mc.commit_array.iter().map(|x| x.rsa_pk[0]).collect();
+        assert_eq!(keys, vec![1, 2, 3, 4]);
+        assert!(mc.get_proof(&vec![3u8; 8]).is_some());
+        assert!(mc.get_proof(&vec![5u8; 8]).is_none());
+
+        mc.clear();
+        assert!(!mc.is_full() && mc.insert_node(entry(5)));
+    }
+}
@@ -0,0 +1,105 @@
+use std::convert::TryFrom;
+use std::ops::Add;
+use tarpc::serde::{Deserialize, Serialize};
+
+/// An element of Z_q for the ciphertext modulus q of a parameter set, always kept in [0, q).
+/// q < 2^127, so the sum of two elements fits in u128 before it is reduced, whatever the depth
+/// of the summation tree, and every element has a single encoding to be hashed.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(try_from = "RawModQ")]
+pub struct ModQ {
+    value: u128,
+    modulus: u128,
+}
+
+// the unchecked form on the wire, a value out of [0, q) is rejected when deserializing
+#[derive(Deserialize)]
+struct RawModQ {
+    value: u128,
+    modulus: u128,
+}
+
+impl TryFrom<RawModQ> for ModQ {
+    type Error = String;
+    fn try_from(raw: RawModQ) -> Result<Self, Self::Error> {
+        if raw.modulus == 0 || raw.modulus > i128::MAX as u128 {
+            Err(format!("invalid modulus {}", raw.modulus))
+        } else if raw.value >= raw.modulus {
+            Err(format!("{} is not reduced mod {}", raw.value, raw.modulus))
+        } else {
+            Ok(Self {
+                value: raw.value,
+                modulus: raw.modulus,
+            })
+        }
+    }
+}
+
+impl ModQ {
+    /// x mod q
+    pub fn new(x: i128, q: i128) -> Self {
+        assert!(q > 0, "the modulus {} is not positive", q);
+        Self {
+            value: x.rem_euclid(q) as u128,
+            modulus: q as u128,
+        }
+    }
+
+    pub fn zero(q: i128) -> Self {
+        Self::new(0, q)
+    }
+
+    /// the representative in [0, q)
+    pub fn value(&self) -> i128 {
+        self.value as i128
+    }
+
+    pub fn modulus(&self) -> i128 {
+        self.modulus as i128
+    }
+
+    /// the canonical encoding, the representative in [0, q) in little endian
+    pub fn to_le_bytes(&self) -> [u8; 16] {
+        self.value.to_le_bytes()
+    }
+}
+
+impl Add for ModQ {
+    type Output = ModQ;
+    fn add(self, other: Self) -> Self {
+        assert_eq!(
+            self.modulus, other.modulus,
+            "adding elements of different moduli"
+        );
+        let sum = self.value + other.value;
+        Self {
+            value: if sum >= self.modulus {
+                sum - self.modulus
+            } else {
+                sum
+            },
+            modulus: self.modulus,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::params::ParameterSet;
+
+    #[test]
+    fn test_add_never_overflows() {
+        let q = ParameterSet::with_dimension(8192).modulus;
+        let x = ModQ::new(q - 1, q);
+        // summing 2^20 leafs of q - 1 one by one stays in [0, q)
+        let sum = (0..1 << 20).fold(ModQ::zero(q), |acc, _| acc + x);
+        assert_eq!(sum, ModQ::new(-(1 << 20), q));
+        assert_eq!(ModQ::new(-1, q).value(), q - 1);
+
+        let bytes = bincode::serialize(&x).unwrap();
+        assert_eq!(bincode::deserialize::<ModQ>(&bytes).unwrap(), x);
+        let unreduced = bincode::serialize(&(q as u128, q as u128)).unwrap();
+        assert!(bincode::deserialize::<ModQ>(&unreduced).is_err());
+    }
+}
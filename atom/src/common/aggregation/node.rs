@@ -1,20 +1,104 @@
-use crate::common::i128vec_to_le_bytes;
-#[cfg(feature = "hashfn_blake3")]
-extern crate blake3;
-#[cfg(not(feature = "hashfn_blake3"))]
-use crypto::{digest::Digest, sha3::Sha3};
+use super::modq::ModQ;
+use crate::common::hash::{
+    bytes_to_fr, fr_to_bytes, i128_to_fr, poseidon_sponge, Domain, HashFunction,
+};
+use crate::common::params::ParameterSet;
+use crate::common::rsa_verify;
+use bellperson::bls::Fr;
+use cupcake::integer_arith::scalar::Scalar;
+use cupcake::integer_arith::ArithUtils;
+use ff::Field;
 use rayon::iter::repeatn;
 use rayon::prelude::*;
+use rsa::RsaPublicKey;
 use std::ops::Add;
 use tarpc::serde::{Deserialize, Serialize};
 use tracing::{error, warn};
 
-pub const MODULUS: i128 = 649033470896967801447398927572993i128;
+/// # of bits a coefficient takes in an element of `ct_elements`, which holds two of them
+pub const CT_PACK_BITS: usize = 126;
+
+/// the digest of rsa_pk in the commitments of the client
+pub fn rsa_pk_digest(hash_fn: HashFunction, rsa_pk: &[u8]) -> [u8; 32] {
+    hash_fn.hash(Domain::RsaPk, &[rsa_pk])
+}
+
+/// What the commitment to a ct hashes: the digest of rsa_pk in its low 31 bytes, the nonce r, and
+/// the coefficients of c0 then c1 two per element, c[2i] + 2^CT_PACK_BITS * c[2i + 1]. Both are
+/// below 2^CT_PACK_BITS for a ct in [0, q), so the packing is one-to-one.
+pub fn ct_elements(rsa_pk_digest: &[u8; 32], r: &[u8; 16], c0: &[i128], c1: &[i128]) -> Vec<Fr> {
+    let mut shift = bytes_to_fr(&[1u8]);
+    (0..CT_PACK_BITS).for_each(|_| shift.double());
+    let pack = |c: &[i128]| {
+        let mut x = i128_to_fr(c.get(1).cloned().unwrap_or(0));
+        x.mul_assign(&shift);
+        x.add_assign(&i128_to_fr(c[0]));
+        x
+    };
+    let mut ret = Vec::with_capacity(2 + (c0.len() + c1.len()) / 2 + 1);
+    ret.push(bytes_to_fr(&rsa_pk_digest[..31]));
+    ret.push(bytes_to_fr(r));
+    ret.extend(c0.chunks(2).map(pack));
+    ret.extend(c1.chunks(2).map(pack));
+    ret
+}
+
+/// The commitment of the client `rsa_pk` to the ct (c0, c1) with the nonce r, which is both the
+/// hash in its leaf of Mc and the first part of its leaf of Ms. It is the Poseidon sponge of
+/// `ct_elements` whatever the hash function of the round, so the proof of the ct can show it
+/// opens to the ct, see `zksnark::constraints::Circuit`; only the digest of rsa_pk is in `hash_fn`.
+pub fn ct_commitment(
+    hash_fn: HashFunction,
+    rsa_pk: &[u8],
+    r: &[u8; 16],
+    c0: &[i128],
+    c1: &[i128],
+) -> [u8; 32] {
+    let elements = ct_elements(&rsa_pk_digest(hash_fn, rsa_pk), r, c0, c1);
+    fr_to_bytes(poseidon_sponge(Domain::Ct, &elements))
+}
+
+// x mod q for 0 <= x < 2^128, with 2^64 mod q precomputed in `r64`
+#[inline]
+fn reduce_i128(x: i128, q: &Scalar, r64: &Scalar) -> Scalar {
+    let x = x as u128;
+    let hi = Scalar::modulus(&Scalar::from((x >> 64) as u64), q);
+    let lo = Scalar::modulus(&Scalar::from(x as u64), q);
+    Scalar::add_mod(&Scalar::mul_mod(&hi, r64, q), &lo, q)
+}
+
+/// c(r) mod q, where c is a polynomial with coefficients in [0, q) and q is the modulus of `params`.
+/// The evaluation is done by Horner's rule for each RNS prime, and then lifted back by CRT.
+pub fn evaluate_poly_at(c: &[i128], r: i128, params: &ParameterSet) -> i128 {
+    let modulus = params.modulus;
+    let residues: Vec<u64> = params
+        .rns_moduli
+        .iter()
+        .map(|&p| {
+            let q = Scalar::new_modulus(p);
+            let r64 = Scalar::from(((1u128 << 64) % p as u128) as u64);
+            let pt = reduce_i128(r.rem_euclid(modulus), &q, &r64);
+            c.iter()
+                .rev()
+                .fold(Scalar::zero(), |acc, x| {
+                    Scalar::add_mod(
+                        &Scalar::mul_mod(&acc, &pt, &q),
+                        &reduce_i128(x.rem_euclid(modulus), &q, &r64),
+                        &q,
+                    )
+                })
+                .rep()
+        })
+        .collect();
+    params.crt(&residues)
+}
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct CommitEntry {
     pub rsa_pk: Vec<u8>,
     pub hash: [u8; 32],
+    /// the signature of rsa_pk over `signed_digest`
+    pub signature: Vec<u8>,
 }
 
 impl CommitEntry {
@@ -22,23 +106,30 @@ impl CommitEntry {
         CommitEntry {
             rsa_pk: vec![0u8; 500],
             hash: [0u8; 32],
+            signature: Vec::new(),
         }
     }
-    #[cfg(not(feature = "hashfn_blake3"))]
-    pub fn hash(&self) -> [u8; 32] {
-        let mut hasher = Sha3::sha3_256();
-        hasher.input(&self.rsa_pk);
-        hasher.input(&self.hash);
-        let mut h = [0u8; 32];
-        hasher.result(&mut h);
-        h
+    pub fn hash(&self, hash_fn: HashFunction) -> [u8; 32] {
+        hash_fn.hash(Domain::CommitLeaf, &[&self.rsa_pk, &self.hash])
     }
-    #[cfg(feature = "hashfn_blake3")]
-    pub fn hash(&self) -> [u8; 32] {
-        let mut hasher = blake3::Hasher::new();
-        hasher.update(&self.rsa_pk);
-        hasher.update(&self.hash);
-        hasher.finalize().into()
+
+    /// what the client signs to commit to its j-th ct in `round`
+    pub fn signed_digest(&self, hash_fn: HashFunction, round: u32, j: usize) -> [u8; 32] {
+        hash_fn.hash(
+            Domain::CommitSig,
+            &[&round.to_le_bytes(), &(j as u64).to_le_bytes(), &self.hash],
+        )
+    }
+
+    /// whether `pk`, parsed from rsa_pk, signed this as its j-th commitment in `round`
+    pub fn verify_signature(
+        &self,
+        pk: &RsaPublicKey,
+        hash_fn: HashFunction,
+        round: u32,
+        j: usize,
+    ) -> bool {
+        rsa_verify(pk, &self.signed_digest(hash_fn, round, j), &self.signature)
     }
 }
 
@@ -49,6 +140,8 @@ pub struct SummationLeaf {
     pub c1: Vec<i128>,
     pub r: [u8; 16],
     pub proof: Vec<u8>,
+    /// the signature of rsa_pk over `signed_digest`
+    pub signature: Vec<u8>,
 }
 
 impl SummationLeaf {
@@ -59,6 +152,7 @@ impl SummationLeaf {
             c1: Vec::new(),
             r: [0u8; 16],
             proof: Vec::new(),
+            signature: Vec::new(),
         }
     }
 
@@ -75,48 +169,60 @@ impl SummationLeaf {
             c1,
             r,
             proof: proof,
+            signature: Vec::new(),
         }
     }
-    #[cfg(not(feature = "hashfn_blake3"))]
-    pub fn hash(&self) -> [u8; 32] {
-        let mut hasher = Sha3::sha3_256();
 
-        hasher.input(&self.rsa_pk);
-        hasher.input(&i128vec_to_le_bytes(&self.c0));
-        hasher.input(&i128vec_to_le_bytes(&self.c1));
-        hasher.input(r);
+    pub fn with_signature(mut self, signature: Vec<u8>) -> Self {
+        self.signature = signature;
+        self
+    }
+    /// the commitment the client put in Mc for this ct
+    pub fn commitment(&self, hash_fn: HashFunction) -> [u8; 32] {
+        ct_commitment(hash_fn, &self.rsa_pk, &self.r, &self.c0, &self.c1)
+    }
 
-        let mut h = [0u8; 32];
-        hasher.result(&mut h);
-        h
+    pub fn hash(&self, hash_fn: HashFunction) -> [u8; 32] {
+        hash_fn.hash(Domain::DataLeaf, &[&self.commitment(hash_fn), &self.proof])
     }
 
-    #[cfg(feature = "hashfn_blake3")]
-    pub fn hash(&self) -> [u8; 32] {
-        let mut hasher = blake3::Hasher::new();
-        hasher.update(&self.rsa_pk);
-        hasher.update(&i128vec_to_le_bytes(&self.c0));
-        hasher.update(&i128vec_to_le_bytes(&self.c1));
-        hasher.update(&self.r);
-        hasher.finalize().into()
+    /// what the client signs to upload its j-th ct in `round`, binding the ct, the nonce and the proof
+    pub fn signed_digest(&self, hash_fn: HashFunction, round: u32, j: usize) -> [u8; 32] {
+        hash_fn.hash(
+            Domain::DataSig,
+            &[
+                &round.to_le_bytes(),
+                &(j as u64).to_le_bytes(),
+                &self.hash(hash_fn),
+            ],
+        )
     }
 
-    pub fn evaluate_at(&self, r: i128) -> SummationNonLeaf {
-        // TODO implement this
-        // for simplicity, let's assume r is 1 for now.
-        // ideally we can split the i128 into 3 u64 prime numbers
-        // and apply `fast-reduction` to accelerate this
+    /// whether `pk`, parsed from rsa_pk, signed this as its j-th ct in `round`
+    pub fn verify_signature(
+        &self,
+        pk: &RsaPublicKey,
+        hash_fn: HashFunction,
+        round: u32,
+        j: usize,
+    ) -> bool {
+        rsa_verify(pk, &self.signed_digest(hash_fn, round, j), &self.signature)
+    }
+
+    /// evaluate c0 and c1 at the random point r, so the sum of the leafs can be checked by evaluations
+    pub fn evaluate_at(&self, r: i128, params: &ParameterSet) -> SummationNonLeaf {
         assert_ne!(self.c0.len(), 0);
-        let c0 = self.c0.iter().fold(0i128, |x, y| (x + y) % MODULUS);
-        let c1 = self.c1.iter().fold(0i128, |x, y| (x + y) % MODULUS);
+        let c0 = ModQ::new(evaluate_poly_at(&self.c0, r, params), params.modulus);
+        let c1 = ModQ::new(evaluate_poly_at(&self.c1, r, params), params.modulus);
         SummationNonLeaf { c0, c1 }
     }
 }
 
+/// the evaluations of the summed c0 and c1 at the random point, in Z_q
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct SummationNonLeaf {
-    pub c0: i128,
-    pub c1: i128,
+    pub c0: ModQ,
+    pub c1: ModQ,
 }
 impl SummationNonLeaf {
     //pub fn new() -> Self {
@@ -124,22 +230,43 @@ impl SummationNonLeaf {
     //    let c1 = vec![0i128; 4096];
     //    SummationNonLeaf { c0, c1 }
     //}
-    #[cfg(not(feature = "hashfn_blake3"))]
-    pub fn hash(&self) -> [u8; 32] {
-        let mut hasher = Sha3::sha3_256();
-
-        hasher.input(&self.c0.to_le_bytes());
-        hasher.input(&self.c1.to_le_bytes());
-        let mut h = [0u8; 32];
-        hasher.result(&mut h);
-        h
+    pub fn hash(&self, hash_fn: HashFunction) -> [u8; 32] {
+        hash_fn.hash(
+            Domain::NonLeaf,
+            &[&self.c0.to_le_bytes(), &self.c1.to_le_bytes()],
+        )
     }
-    #[cfg(feature = "hashfn_blake3")]
-    pub fn hash(&self) -> [u8; 32] {
-        let mut hasher = blake3::Hasher::new();
-        hasher.update(&self.c0.to_le_bytes());
-        hasher.update(&self.c1.to_le_bytes());
-        hasher.finalize().into()
+
+    /// evaluate the k-th ct of the summed ct at the random point r, the expected root of the
+    /// k-th Ms tree; c_0 and c_1 are in coefficient form, one vector per RNS prime, as posted on
+    /// the board. None if they don't hold k + 1 cts.
+    pub fn of_summed_ct(
+        c_0: &[Vec<u64>],
+        c_1: &[Vec<u64>],
+        k: usize,
+        r: i128,
+        params: &ParameterSet,
+    ) -> Option<Self> {
+        let n = params.num_dimension;
+        let nr_moduli = params.rns_moduli.len();
+        let fits =
+            |c: &[Vec<u64>]| c.len() == nr_moduli && c.iter().all(|x| x.len() >= (k + 1) * n);
+        if !fits(c_0) || !fits(c_1) {
+            return None;
+        }
+        let evaluate = |c: &[Vec<u64>]| {
+            let coeffs: Vec<i128> = (k * n..(k + 1) * n)
+                .map(|i| {
+                    let residues: Vec<u64> = c.iter().map(|x| x[i]).collect();
+                    params.crt(&residues)
+                })
+                .collect();
+            ModQ::new(evaluate_poly_at(&coeffs, r, params), params.modulus)
+        };
+        Some(SummationNonLeaf {
+            c0: evaluate(c_0),
+            c1: evaluate(c_1),
+        })
     }
 }
 
@@ -172,10 +299,7 @@ fn add_two_vec(a: &Vec<i128>, b: &Vec<i128>) -> Vec<i128> {
 impl Add for SummationNonLeaf {
     type Output = SummationNonLeaf;
     fn add(self, other: Self) -> Self {
-        SummationNonLeaf {
-            c0: self.c0 + other.c0,
-            c1: self.c1 + other.c1,
-        }
+        &self + &other
     }
 }
 
@@ -234,6 +358,15 @@ impl SummationEntry {
         SummationEntry::Leaf(SummationLeaf::new())
     }
 
+    /// the item of the entry in its tree, Mc for a commitment and Ms otherwise
+    pub fn hash(&self, hash_fn: HashFunction) -> [u8; 32] {
+        match self {
+            SummationEntry::Leaf(x) => x.hash(hash_fn),
+            SummationEntry::NonLeaf(x) => x.hash(hash_fn),
+            SummationEntry::Commit(x) => x.hash(hash_fn),
+        }
+    }
+
     pub fn get_leaf_rsa_pk(&self) -> &Vec<u8> {
         if let SummationEntry::Leaf(l) = self {
             &l.rsa_pk
@@ -242,3 +375,127 @@ impl SummationEntry {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use num_bigint::BigInt;
+    use rand::Rng;
+
+    #[test]
+    fn test_commitment_domains() {
+        let hash_fn = HashFunction::default();
+        let leaf = SummationLeaf::from_ct(vec![7u8; 8], vec![1, 2], vec![3, 4], [5u8; 16], vec![6]);
+        let cm = ct_commitment(hash_fn, &leaf.rsa_pk, &leaf.r, &leaf.c0, &leaf.c1);
+        assert_eq!(leaf.commitment(hash_fn), cm);
+        assert_ne!(leaf.commitment(HashFunction::Poseidon), cm);
+        // the same bytes hash differently in different domains
+        let commit = CommitEntry {
+            rsa_pk: leaf.rsa_pk.clone(),
+            hash: cm,
+            signature: Vec::new(),
+        };
+        assert_ne!(
+            commit.hash(hash_fn),
+            hash_fn.hash(Domain::DataLeaf, &[&leaf.rsa_pk, &cm])
+        );
+    }
+
+    #[test]
+    fn test_signatures() {
+        use crate::common::{parse_rsa_pk, rsa_sign};
+        use rand::SeedableRng;
+        use rsa::{pkcs8::ToPublicKey, RsaPrivateKey};
+
+        let hash_fn = HashFunction::default();
+        let mut rng = rand::rngs::StdRng::seed_from_u64(7);
+        let sk = RsaPrivateKey::new(&mut rng, 1024).unwrap();
+        let rsa_pk = RsaPublicKey::from(&sk)
+            .to_public_key_pem()
+            .unwrap()
+            .into_bytes();
+        let pk = parse_rsa_pk(&rsa_pk).unwrap();
+        assert!(parse_rsa_pk(&[0u8; 16]).is_none());
+
+        let mut leaf =
+            SummationLeaf::from_ct(rsa_pk.clone(), vec![1, 2], vec![3], [4u8; 16], vec![5]);
+        leaf.signature = rsa_sign(&sk, &leaf.signed_digest(hash_fn, 3, 1));
+        assert!(leaf.verify_signature(&pk, hash_fn, 3, 1));
+        // bound to the round, the index of the ct and the proof
+        assert!(!leaf.verify_signature(&pk, hash_fn, 4, 1));
+        assert!(!leaf.verify_signature(&pk, hash_fn, 3, 0));
+        leaf.proof = vec![6];
+        assert!(!leaf.verify_signature(&pk, hash_fn, 3, 1));
+
+        let mut commit = CommitEntry {
+            rsa_pk,
+            hash: leaf.commitment(hash_fn),
+            signature: Vec::new(),
+        };
+        commit.signature = rsa_sign(&sk, &commit.signed_digest(hash_fn, 3, 1));
+        assert!(commit.verify_signature(&pk, hash_fn, 3, 1));
+        // a commitment signature doesn't pass for data
+        leaf.signature = commit.signature.clone();
+        assert!(!leaf.verify_signature(&pk, hash_fn, 3, 1));
+    }
+
+    #[test]
+    fn test_evaluate_poly_at() {
+        let mut rng = rand::thread_rng();
+        for params in [ParameterSet::default(), ParameterSet::with_dimension(8192)].iter() {
+            let (n, q) = (params.num_dimension, params.modulus);
+            let c: Vec<i128> = (0..n).map(|_| rng.gen_range(0..q)).collect();
+            let r = rng.gen_range(0..q);
+            let expected = c
+                .iter()
+                .rev()
+                .fold(BigInt::from(0), |acc, x| (acc * r + *x) % BigInt::from(q));
+            assert_eq!(BigInt::from(evaluate_poly_at(&c, r, params)), expected);
+            // the evaluation is linear
+            let d: Vec<i128> = (0..n).map(|_| rng.gen_range(0..q)).collect();
+            let sum: Vec<i128> = c.iter().zip(d.iter()).map(|(x, y)| (x + y) % q).collect();
+            assert_eq!(
+                evaluate_poly_at(&sum, r, params),
+                (evaluate_poly_at(&c, r, params) + evaluate_poly_at(&d, r, params)) % q
+            );
+        }
+    }
+
+    #[test]
+    fn test_of_summed_ct() {
+        let mut rng = rand::thread_rng();
+        let params = ParameterSet::with_dimension(1024);
+        let (n, q) = (params.num_dimension, params.modulus);
+        // 2 cts in coefficient form mod each RNS prime, as the board holds them
+        let c0: Vec<i128> = (0..2 * n).map(|_| rng.gen_range(0..q)).collect();
+        let c1: Vec<i128> = (0..2 * n).map(|_| rng.gen_range(0..q)).collect();
+        let rns = |c: &[i128]| -> Vec<Vec<u64>> {
+            params
+                .rns_moduli
+                .iter()
+                .map(|p| c.iter().map(|x| x.rem_euclid(*p as i128) as u64).collect())
+                .collect()
+        };
+        let (c_0, c_1) = (rns(&c0), rns(&c1));
+        let r = rng.gen_range(0..q);
+        let leaf = SummationLeaf::from_ct(
+            Vec::new(),
+            c0[n..].to_vec(),
+            c1[n..].to_vec(),
+            [0u8; 16],
+            Vec::new(),
+        );
+        assert_eq!(
+            SummationNonLeaf::of_summed_ct(&c_0, &c_1, 1, r, &params),
+            Some(leaf.evaluate_at(r, &params))
+        );
+        assert_eq!(
+            SummationNonLeaf::of_summed_ct(&c_0, &c_1, 2, r, &params),
+            None
+        );
+        assert_eq!(
+            SummationNonLeaf::of_summed_ct(&c_0[1..], &c_1, 0, r, &params),
+            None
+        );
+    }
+}
@@ -0,0 +1,537 @@
+use super::merkle::MerkleProof;
+use super::node::{CommitEntry, SummationEntry, SummationLeaf, SummationNonLeaf};
+use super::{McTree, MsTree};
+use crate::common::params::ParameterSet;
+use crate::common::{parse_rsa_pk, summation_array_size};
+use rand::Rng;
+use std::collections::HashMap;
+use tarpc::serde::{Deserialize, Serialize};
+
+/// The nodes a verifier samples from the trees of a ct, in the layout of the summation array:
+/// [0..n) are the leafs and the children of the non-leaf p are 2 * (p - n) and 2 * (p - n) + 1.
+/// s + 1 consecutive leafs from vinit (wrapping around), the parents of the sibling pairs among
+/// them, and some random grandparents together with their children.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sample {
+    /// the # of leafs
+    pub n: u32,
+    pub s: u32,
+    pub vinit: u32,
+    pub grandparents: Vec<u32>,
+}
+
+/// The nodes of a sample of the trees of a ct with their proofs, see `Sample::collect`
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct SampledNodes {
+    /// (commitment, Mc proof) and (leaf, Ms proof) for every leaf, then (node, Ms proof) for
+    /// every non-leaf
+    pub nodes: Vec<(SummationEntry, MerkleProof)>,
+    /// the proof of every sampled leaf in the tree of the leafs, whose root fixes the random point
+    pub leaf_proofs: Vec<MerkleProof>,
+    /// the root node of Ms with its proof, the summed ct evaluated at the random point
+    pub root: Option<(SummationEntry, MerkleProof)>,
+}
+
+/// Why a sampled node of a ct is rejected, with the id of the node in the summation array
+#[derive(Debug, Clone, PartialEq)]
+pub enum VerifyFailure {
+    /// the server sent back a different # of nodes than requested
+    MissingNodes,
+    /// not the kind of node expected at this id
+    WrongNode(u32),
+    /// the commitment doesn't open under the Mc root at this id
+    WrongMcProof(u32),
+    /// the node doesn't open under the Ms root at this id
+    WrongMsProof(u32),
+    /// the leaf doesn't open under the root of the leafs the random point is drawn after
+    WrongLeafProof(u32),
+    /// the uploaded ct is not the committed one
+    CommitMismatch(u32),
+    /// the zkproof of the ct doesn't verify
+    InvalidZkProof(u32),
+    /// the commitment or the ct is not signed by its rsa_pk
+    InvalidSignature(u32),
+    /// the non-leaf is not the sum of its children at the random point
+    WrongSum(u32),
+    /// the root of Ms is not the summed ct on the board at the random point
+    WrongSummedCt,
+    /// the random point doesn't follow from the contributions of the committee on the board
+    WrongRandomPoint,
+    /// fewer than 2 leafs asked for, so no parent can be checked
+    TooFewSamples,
+    /// the board doesn't tell the # of leafs
+    MissingLeafCount,
+    /// no leaf to sample
+    EmptyTree,
+}
+
+/// The outcome of one run of the verification, by the index of the ct
+#[derive(Debug, Clone, PartialEq)]
+pub struct VerifyReport {
+    pub round: u32,
+    /// the cts sampled
+    pub ct_id: Vec<usize>,
+    pub failures: Vec<(usize, VerifyFailure)>,
+}
+
+impl VerifyReport {
+    pub fn passed(&self) -> bool {
+        self.failures.is_empty()
+    }
+
+    /// none of the `nr_ct` cts can be sampled, for the same reason
+    pub fn failed(round: u32, nr_ct: usize, failure: VerifyFailure) -> Self {
+        VerifyReport {
+            round,
+            ct_id: (0..nr_ct).collect(),
+            failures: (0..nr_ct).map(|k| (k, failure.clone())).collect(),
+        }
+    }
+}
+
+impl Sample {
+    /// s is cut to n - 1 so that every leaf is sampled at most once
+    pub fn new(n: u32, s: u32, vinit: u32, grandparents: Vec<u32>) -> Self {
+        assert!(n > 0, "no leaf to sample");
+        Sample {
+            n,
+            s: s.min(n - 1),
+            vinit: vinit % n,
+            grandparents,
+        }
+    }
+
+    /// a random vinit and about s / 2 random grandparents, None if there is no leaf
+    pub fn random<R: Rng>(n: u32, s: u32, rng: &mut R) -> Option<Self> {
+        if n == 0 {
+            return None;
+        }
+        let vinit = rng.gen_range(0..n);
+        let s = s.min(n - 1);
+        // [0..N): leafs
+        // [N..N+N/2): non-leafs whose children are leafs
+        // N+N/2: possibly 1 leaf + 1 non-leaf
+        // [N+N/2..summation_array_size(N)): non-leafs with non-leaf children
+        let array_size = summation_array_size(n);
+        // if s is even, just pick s/2; otherwise either (s+1)/2 or s/2
+        // or put in another way, if s is odd and vmax is even, pick (s+1) / 2
+        // otherwise, pick s/2
+        let nr_gp = {
+            if (s & 0x1 != 0) && ((vinit + s) & 0x1 == 0) {
+                (s + 1) / 2
+            } else {
+                s / 2
+            }
+        };
+        // if no such non-leafs nodes exist, just skip
+        let grandparents = if n + n / 2 < array_size {
+            (0..nr_gp + 1)
+                .map(|_| rng.gen_range(n + n / 2..array_size))
+                .collect()
+        } else {
+            Vec::new()
+        };
+        Some(Self::new(n, s, vinit, grandparents))
+    }
+
+    pub fn leafs(&self) -> Vec<u32> {
+        (0..self.s + 1).map(|i| (self.vinit + i) % self.n).collect()
+    }
+
+    /// the parents whose children are both among the sampled leafs
+    pub fn parents(&self) -> Vec<u32> {
+        let mut ret = Vec::new();
+        let mut idx = 0;
+        while idx <= self.s {
+            let ii = (self.vinit + idx) % self.n;
+            if (ii & 0x1 == 0) && idx < self.s && ii + 1 < self.n {
+                ret.push(self.n + ii / 2);
+                idx += 2;
+            } else {
+                idx += 1;
+            }
+        }
+        ret
+    }
+
+    fn children(&self, p: u32) -> (u32, u32) {
+        let left = (p - self.n) * 2;
+        (left, left + 1)
+    }
+
+    /// the ids of the nodes returned after the leafs, in order
+    fn non_leafs(&self) -> Vec<u32> {
+        let mut ret = self.parents();
+        for gp in &self.grandparents {
+            let (left, right) = self.children(*gp);
+            ret.extend_from_slice(&[*gp, left, right]);
+        }
+        ret
+    }
+
+    /// the sampled nodes with their proofs, None if the sample doesn't fit the trees
+    pub fn collect(&self, mc: &McTree, ms: &MsTree) -> Option<SampledNodes> {
+        if mc.mc.is_none() || ms.ms.is_none() || mc.commit_array.len() != self.n as usize {
+            return None;
+        }
+        let array_size = summation_array_size(self.n);
+        if self
+            .grandparents
+            .iter()
+            .any(|gp| *gp < self.n || *gp >= array_size)
+        {
+            return None;
+        }
+        let mut ret = Vec::new();
+        let mut leaf_proofs = Vec::new();
+        for i in self.leafs() {
+            ret.push((
+                SummationEntry::Commit(mc.commit_array[i as usize].clone()),
+                mc.get_proof_by_id(i),
+            ));
+            ret.push((
+                ms.summation_array[i as usize].clone(),
+                ms.get_proof_by_id(i),
+            ));
+            leaf_proofs.push(ms.get_leaf_proof_by_id(i));
+        }
+        for i in self.non_leafs() {
+            ret.push((
+                ms.summation_array[i as usize].clone(),
+                ms.get_proof_by_id(i),
+            ));
+        }
+        let root = array_size - 1;
+        Some(SampledNodes {
+            nodes: ret,
+            leaf_proofs,
+            root: Some((
+                ms.summation_array[root as usize].clone(),
+                ms.get_proof_by_id(root),
+            )),
+        })
+    }
+
+    /// check the nodes from `collect` of the k-th ct against the roots on the board:
+    /// every node opens at its id, every sampled leaf is among the leafs fixed before the random
+    /// point and is the signed opening of its signed commitment with a valid zkproof, every
+    /// sampled non-leaf is the sum of its children, and the root is `summed`, the evaluation of
+    /// the summed ct on the board, see `SummationNonLeaf::of_summed_ct`
+    pub fn check<F>(
+        &self,
+        sampled: &SampledNodes,
+        round: u32,
+        k: usize,
+        mc_root: &[u8; 32],
+        ms_root: &[u8; 32],
+        leaf_root: &[u8; 32],
+        random_pt: i128,
+        summed: &SummationNonLeaf,
+        params: &ParameterSet,
+        verify_zkproof: F,
+    ) -> Vec<VerifyFailure>
+    where
+        F: Fn(&SummationLeaf, &CommitEntry) -> bool,
+    {
+        let hash_fn = params.hash_fn;
+        let leafs = self.leafs();
+        let non_leafs = self.non_leafs();
+        let nodes = &sampled.nodes;
+        let root = match &sampled.root {
+            Some(root)
+                if nodes.len() == 2 * leafs.len() + non_leafs.len()
+                    && sampled.leaf_proofs.len() == leafs.len() =>
+            {
+                root
+            }
+            _ => return vec![VerifyFailure::MissingNodes],
+        };
+        let opens = |(node, proof): &(SummationEntry, MerkleProof), id: u32, root: &[u8; 32]| {
+            proof.index() == id as usize
+                && proof.validate_against(&node.hash(hash_fn), root, hash_fn)
+        };
+        let mut failures = Vec::new();
+        // the evaluation at the random point of every node opened in Ms
+        let mut values: HashMap<u32, SummationNonLeaf> = HashMap::new();
+
+        for ((i, pair), leaf_proof) in leafs
+            .iter()
+            .zip(nodes.chunks(2))
+            .zip(sampled.leaf_proofs.iter())
+        {
+            let (i, mc_node, ms_node) = (*i, &pair[0], &pair[1]);
+            let (cm, s) = match (&mc_node.0, &ms_node.0) {
+                (SummationEntry::Commit(cm), SummationEntry::Leaf(s)) => (cm, s),
+                _ => {
+                    failures.push(VerifyFailure::WrongNode(i));
+                    continue;
+                }
+            };
+            // Commit_i appears in Mc and its ct in Ms
+            if !opens(mc_node, i, mc_root) {
+                failures.push(VerifyFailure::WrongMcProof(i));
+                continue;
+            }
+            if !opens(ms_node, i, ms_root) {
+                failures.push(VerifyFailure::WrongMsProof(i));
+                continue;
+            }
+            // and was fixed before the random point, so it can't be chosen after seeing the point
+            if leaf_proof.index() != i as usize
+                || !leaf_proof.validate_against(&s.hash(hash_fn), leaf_root, hash_fn)
+            {
+                failures.push(VerifyFailure::WrongLeafProof(i));
+                continue;
+            }
+            // the uploaded ct is the committed one
+            if s.rsa_pk != cm.rsa_pk || s.commitment(hash_fn) != cm.hash {
+                failures.push(VerifyFailure::CommitMismatch(i));
+                continue;
+            }
+            if !verify_zkproof(s, cm) {
+                failures.push(VerifyFailure::InvalidZkProof(i));
+            }
+            // and both are signed by the client, so the server can't forge them
+            let signed = parse_rsa_pk(&cm.rsa_pk).map_or(false, |pk| {
+                cm.verify_signature(&pk, hash_fn, round, k)
+                    && s.verify_signature(&pk, hash_fn, round, k)
+            });
+            if !signed {
+                failures.push(VerifyFailure::InvalidSignature(i));
+            }
+            values.insert(i, s.evaluate_at(random_pt, params));
+        }
+
+        for (i, node) in non_leafs.iter().zip(nodes[2 * leafs.len()..].iter()) {
+            if !opens(node, *i, ms_root) {
+                failures.push(VerifyFailure::WrongMsProof(*i));
+                continue;
+            }
+            // possibly a leaf as the left child of a non-leaf when the # of leafs is odd
+            match (&node.0, *i < self.n) {
+                (SummationEntry::Leaf(s), true) => {
+                    values.insert(*i, s.evaluate_at(random_pt, params));
+                }
+                // the hash only covers the values, so the modulus is checked on its own
+                (SummationEntry::NonLeaf(x), false)
+                    if x.c0.modulus() == params.modulus && x.c1.modulus() == params.modulus =>
+                {
+                    values.insert(*i, x.clone());
+                }
+                _ => failures.push(VerifyFailure::WrongNode(*i)),
+            }
+        }
+
+        let mut parents = self.parents();
+        parents.extend_from_slice(&self.grandparents);
+        for p in parents {
+            let (left, right) = self.children(p);
+            let sum = match (values.get(&left), values.get(&right)) {
+                (Some(a), Some(b)) => a + b,
+                // the failure of a child is already reported
+                _ => continue,
+            };
+            if values.get(&p) != Some(&sum) {
+                failures.push(VerifyFailure::WrongSum(p));
+            }
+        }
+
+        // the committee decrypts the sum on the board, so it must be the one the tree adds up
+        let root_id = summation_array_size(self.n) - 1;
+        if !opens(root, root_id, ms_root) {
+            failures.push(VerifyFailure::WrongMsProof(root_id));
+        } else {
+            let value = match (&root.0, root_id < self.n) {
+                (SummationEntry::Leaf(s), true) if !s.c0.is_empty() => {
+                    Some(s.evaluate_at(random_pt, params))
+                }
+                (SummationEntry::NonLeaf(x), false) => Some(x.clone()),
+                _ => None,
+            };
+            if value.as_ref() != Some(summed) {
+                failures.push(VerifyFailure::WrongSummedCt);
+            }
+        }
+        failures
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::aggregation::merkle::PaddedMerkleTree;
+    use crate::common::aggregation::modq::ModQ;
+    use crate::common::aggregation::node::ct_commitment;
+    use crate::common::rsa_sign;
+    use rand::SeedableRng;
+    use rsa::{pkcs8::ToPublicKey, RsaPrivateKey, RsaPublicKey};
+
+    #[test]
+    fn test_sample_layout() {
+        // 0..7 are the leafs, 7..13 the non-leafs
+        let sample = Sample::new(7, 4, 5, vec![11, 10]);
+        assert_eq!(sample.leafs(), vec![5, 6, 0, 1, 2]);
+        // 6 and 0 are not siblings, neither is 6 with the padding
+        assert_eq!(sample.parents(), vec![7]);
+        assert_eq!(sample.non_leafs(), vec![7, 11, 8, 9, 10, 6, 7]);
+        // s is cut to the # of leafs
+        assert_eq!(Sample::new(3, 10, 4, Vec::new()).leafs(), vec![1, 2, 0]);
+        // and there is nothing to sample without leafs
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        assert_eq!(Sample::random(0, 4, &mut rng).map(|x| x.n), None);
+        assert_eq!(Sample::random(1, 4, &mut rng).map(|x| x.s), Some(0));
+    }
+
+    #[test]
+    fn test_check_sample() {
+        let params = ParameterSet::default();
+        let hash_fn = params.hash_fn;
+        let (round, k, n) = (3u32, 0usize, 5u32);
+        let mut rng = rand::rngs::StdRng::seed_from_u64(7);
+        let mut mc = McTree::new(n, 2, hash_fn);
+        let mut ms = MsTree::new(n, 2, &params);
+        for _ in 0..n {
+            let sk = RsaPrivateKey::new(&mut rng, 1024).unwrap();
+            let rsa_pk = RsaPublicKey::from(&sk)
+                .to_public_key_pem()
+                .unwrap()
+                .into_bytes();
+            let c0: Vec<i128> = (0..8).map(|_| rng.gen_range(0..params.modulus)).collect();
+            let c1: Vec<i128> = (0..8).map(|_| rng.gen_range(0..params.modulus)).collect();
+            let r: [u8; 16] = rng.gen();
+            let mut cm = CommitEntry {
+                rsa_pk: rsa_pk.clone(),
+                hash: ct_commitment(hash_fn, &rsa_pk, &r, &c0, &c1),
+                signature: Vec::new(),
+            };
+            cm.signature = rsa_sign(&sk, &cm.signed_digest(hash_fn, round, k));
+            let leaf = SummationLeaf::from_ct(rsa_pk, c0, c1, r, vec![1u8]);
+            let digest = leaf.signed_digest(hash_fn, round, k);
+            assert!(mc.insert_node(cm));
+            assert!(ms.insert_node(leaf.with_signature(rsa_sign(&sk, &digest))));
+        }
+        assert!(mc.gen_tree() && ms.gen_tree());
+        let random_pt = 12345;
+        ms.gen_summation(random_pt);
+        let (mc_root, ms_root) = (mc.get_merkle_root().unwrap(), ms.get_merkle_root().unwrap());
+        let leaf_root = ms.get_leaf_root().unwrap();
+        // what the summed ct on the board evaluates to
+        let zero = ModQ::zero(params.modulus);
+        let summed = ms.summation_array[..n as usize].iter().fold(
+            SummationNonLeaf { c0: zero, c1: zero },
+            |acc, x| match x {
+                SummationEntry::Leaf(s) => &acc + &s.evaluate_at(random_pt, &params),
+                _ => unreachable!(),
+            },
+        );
+        // the checks of the k-th ct in `round` with every zkproof taken as `zk`
+        let check = |sample: &Sample, nodes: &SampledNodes, round, ms_root: &[u8; 32], zk| {
+            sample.check(
+                nodes,
+                round,
+                k,
+                &mc_root,
+                ms_root,
+                &leaf_root,
+                random_pt,
+                &summed,
+                &params,
+                |_, _| zk,
+            )
+        };
+
+        for vinit in 0..n {
+            let sample = Sample::new(n, 3, vinit, vec![7, 8]);
+            let nodes = sample.collect(&mc, &ms).unwrap();
+            assert_eq!(check(&sample, &nodes, round, &ms_root, true), Vec::new());
+        }
+        let sample = Sample::random(n, 3, &mut rng);
+        let nodes = sample.collect(&mc, &ms).unwrap();
+        assert_eq!(check(&sample, &nodes, round, &ms_root, true), Vec::new());
+
+        let sample = Sample::new(n, 3, 0, vec![8]);
+        let nodes = sample.collect(&mc, &ms).unwrap();
+        // a node from another position
+        let mut wrong = nodes.clone();
+        wrong.nodes.swap(1, 3);
+        assert!(
+            check(&sample, &wrong, round, &ms_root, true).contains(&VerifyFailure::WrongMsProof(0))
+        );
+        // a node short
+        wrong.nodes.pop();
+        assert_eq!(
+            check(&sample, &wrong, round, &ms_root, true),
+            vec![VerifyFailure::MissingNodes]
+        );
+        // a leaf not among the leafs fixed before the random point
+        let mut wrong = nodes.clone();
+        wrong.leaf_proofs.swap(0, 1);
+        assert_eq!(
+            check(&sample, &wrong, round, &ms_root, true),
+            vec![
+                VerifyFailure::WrongLeafProof(0),
+                VerifyFailure::WrongLeafProof(1)
+            ]
+        );
+        // a forged zkproof of each of the 4 leafs
+        assert_eq!(check(&sample, &nodes, round, &ms_root, false).len(), 4);
+        // a signature of another round
+        assert!(check(&sample, &nodes, round + 1, &ms_root, true)
+            .contains(&VerifyFailure::InvalidSignature(0)));
+        // a non-leaf under another modulus opens its proof, but is not summed
+        let mut wrong = nodes.clone();
+        let pos = sample.non_leafs().iter().position(|&i| i >= n).unwrap();
+        if let SummationEntry::NonLeaf(x) = &mut wrong.nodes[2 * sample.leafs().len() + pos].0 {
+            x.c0 = ModQ::new(x.c0.value(), params.modulus + 1);
+        }
+        assert!(check(&sample, &wrong, round, &ms_root, true)
+            .contains(&VerifyFailure::WrongNode(sample.non_leafs()[pos])));
+        // the root of another node, or of another sum than the one on the board
+        let root_id = summation_array_size(n) - 1;
+        let mut wrong = nodes.clone();
+        wrong.root = Some(nodes.nodes[2 * sample.leafs().len()].clone());
+        assert_eq!(
+            check(&sample, &wrong, round, &ms_root, true),
+            vec![VerifyFailure::WrongMsProof(root_id)]
+        );
+        let other = &summed + &summed;
+        assert_eq!(
+            sample.check(
+                &nodes,
+                round,
+                k,
+                &mc_root,
+                &ms_root,
+                &leaf_root,
+                random_pt,
+                &other,
+                &params,
+                |_, _| true,
+            ),
+            vec![VerifyFailure::WrongSummedCt]
+        );
+        wrong.root = None;
+        assert_eq!(
+            check(&sample, &wrong, round, &ms_root, true),
+            vec![VerifyFailure::MissingNodes]
+        );
+        // out of the trees
+        assert!(Sample::new(n, 3, 0, vec![2]).collect(&mc, &ms).is_none());
+        assert!(Sample::new(n + 1, 3, 0, Vec::new())
+            .collect(&mc, &ms)
+            .is_none());
+
+        // a wrong sum, even under valid proofs, is caught at the parent
+        if let SummationEntry::NonLeaf(x) = &mut ms.summation_array[5] {
+            x.c0 = x.c0 + x.c0;
+        }
+        let items: Vec<[u8; 32]> = ms.summation_array.iter().map(|x| x.hash(hash_fn)).collect();
+        ms.ms = Some(PaddedMerkleTree::new(hash_fn, &items, 4));
+        let ms_root = ms.get_merkle_root().unwrap();
+        let nodes = sample.collect(&mc, &ms).unwrap();
+        assert_eq!(
+            check(&sample, &nodes, round, &ms_root, true),
+            vec![VerifyFailure::WrongSum(5)]
+        );
+    }
+}
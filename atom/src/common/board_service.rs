@@ -1,10 +1,131 @@
-use super::aggregation::merkle::MerkleProof;
+use super::random_point::Contribution;
+use super::{rsa_sign, rsa_verify};
+use rsa::{RsaPrivateKey, RsaPublicKey};
+use tarpc::serde::{Deserialize, Serialize};
+
+/// What the server posts to the board, signed along with the values so the signature of one post
+/// can't pass for another
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[repr(u8)]
+pub enum Post {
+    McRoots = 1,
+    LeafRoots = 2,
+    MsRoots = 3,
+    SummedCt = 4,
+    Decryption = 5,
+}
+
+/// what the server signs to post `payload`, the bincode of the posted values, to `round`
+pub fn signed_digest(kind: Post, round: u32, payload: &[u8]) -> [u8; 32] {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(&[kind as u8]);
+    hasher.update(&round.to_le_bytes());
+    hasher.update(payload);
+    hasher.finalize().into()
+}
+
+/// the signature of the server over the values it posts, see `signed_digest`
+pub fn sign_post<T: Serialize>(identity: &RsaPrivateKey, kind: Post, round: u32, x: &T) -> Vec<u8> {
+    rsa_sign(
+        identity,
+        &signed_digest(kind, round, &bincode::serialize(x).unwrap()),
+    )
+}
+
+/// whether `pk`, the identity key of the server, signed the values posted
+pub fn verify_post<T: Serialize>(
+    pk: &RsaPublicKey,
+    kind: Post,
+    round: u32,
+    x: &T,
+    signature: &[u8],
+) -> bool {
+    bincode::serialize(x).map_or(false, |payload| {
+        rsa_verify(pk, &signed_digest(kind, round, &payload), signature)
+    })
+}
+
+/// What a committee member publishes in a resharing, see committee_reshare
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum Reshare {
+    /// the commitments of a dealer to its polynomials, with the quotients that open its C_0
+    Proof = 1,
+    /// whether the member complains about each dealer
+    Complaints = 2,
+    /// the subshares of a dealer to the members complaining about it
+    Response = 3,
+}
+
+/// what committee member `member` signs with its identity key to publish `payload`, the bincode
+/// of what it publishes, in the resharing of `epoch`
+pub fn reshare_digest(epoch: u32, kind: Reshare, member: u32, payload: &[u8]) -> [u8; 32] {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(&epoch.to_le_bytes());
+    hasher.update(&[kind as u8]);
+    hasher.update(&member.to_le_bytes());
+    hasher.update(payload);
+    hasher.finalize().into()
+}
 
 /// Trait for service of the bulletin board
+/// Only the server posts, signing every post with its identity key, see `sign_post`, except the
+/// committee members posting their contributions to the random point and what they publish in a
+/// resharing, and everything posted for a round is write-once.
+/// The getters wait till the value of the round is posted, but `get_reshare`.
 #[tarpc::service]
 pub trait BoardService {
-    /// Get the inclusion proof of a node inside the commitment merkle tree
-    async fn get_mc_proof(leaf_id: u32, round: u32) -> MerkleProof;
-    /// Get the inclusion proof of a node inside the summation merkle tree
-    async fn get_ms_proof(leaf_id: u32, round: u32) -> MerkleProof;
+    /// Post the roots of the commitment merkle trees, one per ct, and N, the # of leafs
+    async fn post_mc_roots(
+        round: u32,
+        roots: Vec<[u8; 32]>,
+        nr_leaf: u32,
+        signature: Vec<u8>,
+    ) -> bool;
+    /// Post the roots of the merkle trees of the Ms leafs, one per ct, before the random point is drawn
+    async fn post_leaf_roots(round: u32, roots: Vec<[u8; 32]>, signature: Vec<u8>) -> bool;
+    /// Post a contribution of a committee member to the random point, only after the leaf roots;
+    /// the point is fixed by the first t + 1 valid contributions, see `random_point`
+    async fn post_contribution(round: u32, contribution: Contribution) -> bool;
+    /// Post the roots of the summation merkle trees, one per ct
+    async fn post_ms_roots(round: u32, roots: Vec<[u8; 32]>, signature: Vec<u8>) -> bool;
+    /// Post the summed c_0 and c_1 in coefficient form, one vector per RNS prime, with the Ms roots;
+    /// the committee decrypts this c_0
+    async fn post_summed_ct(
+        round: u32,
+        c_0: Vec<Vec<u64>>,
+        c_1: Vec<Vec<u64>>,
+        signature: Vec<u8>,
+    ) -> bool;
+    /// Post the decryption from the committee, one vector per RNS prime; with the summed c_1 it
+    /// gives the sum of the plaintexts, see `aggregate::decrypt`. The board then drops the records
+    /// of the rounds before the last few.
+    async fn post_decryption(round: u32, pt: Vec<Vec<u64>>, signature: Vec<u8>) -> bool;
+    /// Publish what committee member `member` posts in the resharing of `epoch`, signed over
+    /// `reshare_digest`; the board keeps the first one of each kind and drops the earlier epochs
+    async fn post_reshare(
+        epoch: u32,
+        kind: Reshare,
+        member: u32,
+        payload: Vec<u8>,
+        signature: Vec<u8>,
+    ) -> bool;
+    /// Get what committee member `member` posted in the resharing of `epoch`, None if nothing yet
+    async fn get_reshare(epoch: u32, kind: Reshare, member: u32) -> Option<Vec<u8>>;
+    /// Get the roots of the commitment merkle trees
+    async fn get_mc_roots(round: u32) -> Vec<[u8; 32]>;
+    /// Get the # of leafs of the trees
+    async fn get_nr_leaf(round: u32) -> u32;
+    /// Get the roots of the merkle trees of the Ms leafs
+    async fn get_leaf_roots(round: u32) -> Vec<[u8; 32]>;
+    /// Get the random point of the summation
+    async fn get_random_pt(round: u32) -> i128;
+    /// Get the contributions the random point is hashed from, sorted by member
+    async fn get_contributions(round: u32) -> Vec<Contribution>;
+    /// Get the roots of the summation merkle trees
+    async fn get_ms_roots(round: u32) -> Vec<[u8; 32]>;
+    /// Get the summed c_0 and c_1, whose evaluations at the random point are the Ms roots
+    async fn get_summed_ct(round: u32) -> (Vec<Vec<u64>>, Vec<Vec<u64>>);
+    /// Get the decryption of the summed ciphertext
+    async fn get_decryption(round: u32) -> Vec<Vec<u64>>;
 }
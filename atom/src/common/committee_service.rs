@@ -1,7 +1,12 @@
 #[tarpc::service]
 pub trait CommitteeService {
     /// ask the committee to generate enough random bits and store locally, wait until the committee finishes it
-    async fn generate_random_bits(nr_slots:u32);
-    /// ask the committee to compute ntt(sk) * ntt(u) + ntt(e)
-    async fn partial_decrypt(ntt_u:Vec<i128>) -> Vec<i128>;
+    async fn generate_random_bits(nr_slots: u32) -> bool;
+    /// ask the committee to compute ntt(sk) * ntt(u) + ntt(e), one vector per RNS prime, for u the
+    /// summed c_0 of `round` on the board rather than anything from the server;
+    /// an empty vector means the member refuses, e.g. not enough random bits
+    async fn partial_decrypt(round: u32) -> Vec<Vec<u64>>;
+    /// ask the member to post a fresh contribution to the random point of `round` to the board,
+    /// bound to the leaf roots on the board; return whether the board accepts it
+    async fn random_point(round: u32) -> bool;
 }
@@ -0,0 +1,221 @@
+use bellperson::bls::{Bls12, Fr};
+use crypto::{digest::Digest, sha3::Sha3};
+use ff::{Field, PrimeField, PrimeFieldRepr};
+use neptune::poseidon::{Poseidon, PoseidonConstants};
+use std::sync::OnceLock;
+use tarpc::serde::{Deserialize, Serialize};
+use typenum::Unsigned;
+
+/// the version of the commitment encoding, hashed before anything else
+pub const COMMITMENT_VERSION: u8 = 1;
+
+/// What a hash commits to, hashed right after the version so hashes of different kinds never collide
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[repr(u8)]
+pub enum Domain {
+    /// the commitment of a client to one ct, see `ct_commitment`
+    Ct = 1,
+    /// a leaf of Mc, binding the commitment to rsa_pk
+    CommitLeaf = 2,
+    /// a leaf of Ms, the committed ct with its zkproof
+    DataLeaf = 3,
+    /// a non-leaf of Ms, the evaluation of the summed cts
+    NonLeaf = 4,
+    /// what the merkle trees hash an item into
+    MerkleLeaf = 5,
+    /// what the merkle trees hash two children into
+    MerkleNode = 6,
+    /// what a client signs for a leaf of Mc
+    CommitSig = 7,
+    /// what a client signs for a leaf of Ms
+    DataSig = 8,
+    /// the rsa_pk of a client in the commitment to one of its cts
+    RsaPk = 9,
+}
+
+/// The hash function of a round, behind the commitments, the nodes and the merkle trees.
+/// It is part of the round parameters and of every merkle proof, so hashes of different
+/// functions are never checked against each other.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashFunction {
+    Sha3,
+    Blake3,
+    /// Poseidon over BLS12-381 with arity 2, chained over 31-byte chunks
+    Poseidon,
+}
+
+impl Default for HashFunction {
+    fn default() -> Self {
+        if cfg!(feature = "hashfn_blake3") {
+            HashFunction::Blake3
+        } else {
+            HashFunction::Sha3
+        }
+    }
+}
+
+impl HashFunction {
+    /// sha3, blake3 or poseidon, as in the config
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "sha3" => Some(HashFunction::Sha3),
+            "blake3" => Some(HashFunction::Blake3),
+            "poseidon" => Some(HashFunction::Poseidon),
+            _ => None,
+        }
+    }
+
+    /// H(version || domain || len(part_0) || part_0 || ...), the lengths in 8 bytes little endian
+    pub fn hash(&self, domain: Domain, parts: &[&[u8]]) -> [u8; 32] {
+        let prefix = [COMMITMENT_VERSION, domain as u8];
+        let lengths: Vec<[u8; 8]> = parts
+            .iter()
+            .map(|x| (x.len() as u64).to_le_bytes())
+            .collect();
+        let mut input: Vec<&[u8]> = vec![&prefix];
+        for (part, len) in parts.iter().zip(lengths.iter()) {
+            input.push(len);
+            input.push(part);
+        }
+        match self {
+            HashFunction::Sha3 => {
+                let mut hasher = Sha3::sha3_256();
+                input.iter().for_each(|x| hasher.input(x));
+                let mut h = [0u8; 32];
+                hasher.result(&mut h);
+                h
+            }
+            HashFunction::Blake3 => {
+                let mut hasher = blake3::Hasher::new();
+                input.iter().for_each(|x| {
+                    hasher.update(x);
+                });
+                hasher.finalize().into()
+            }
+            HashFunction::Poseidon => poseidon(&input.concat()),
+        }
+    }
+}
+
+/// x < 2^248 in at most 31 bytes little endian, as a field element
+pub fn bytes_to_fr(x: &[u8]) -> Fr {
+    assert!(x.len() < 32, "{} bytes may not fit in the field", x.len());
+    let mut buf = [0u8; 32];
+    buf[..x.len()].copy_from_slice(x);
+    let mut repr = <Fr as PrimeField>::Repr::default();
+    repr.read_le(&buf[..]).unwrap();
+    Fr::from_repr(repr).unwrap()
+}
+
+/// x mod the field modulus, as the circuits take i128 values
+pub fn i128_to_fr(x: i128) -> Fr {
+    let mut ret = bytes_to_fr(&x.unsigned_abs().to_le_bytes());
+    if x < 0 {
+        ret.negate();
+    }
+    ret
+}
+
+pub fn fr_to_bytes(x: Fr) -> [u8; 32] {
+    let mut ret = [0u8; 32];
+    x.into_repr().write_le(&mut ret[..]).unwrap();
+    ret
+}
+
+// h_0 = len(bytes) and h_{i+1} = Poseidon(h_i, chunk_i) over the 31-byte chunks, each below the
+// field modulus as a little endian integer, and the last h in little endian
+fn poseidon(bytes: &[u8]) -> [u8; 32] {
+    static CONSTANTS: OnceLock<PoseidonConstants<Bls12, typenum::U2>> = OnceLock::new();
+    let constants = CONSTANTS.get_or_init(PoseidonConstants::new);
+    let h = bytes.chunks(31).fold(
+        bytes_to_fr(&(bytes.len() as u64).to_le_bytes()),
+        |h, chunk| Poseidon::new_with_preimage(&[h, bytes_to_fr(chunk)], constants).hash(),
+    );
+    fr_to_bytes(h)
+}
+
+/// the arity of the Poseidon `poseidon_sponge` chains
+pub type SpongeArity = typenum::U8;
+
+pub fn sponge_constants() -> &'static PoseidonConstants<Bls12, SpongeArity> {
+    static CONSTANTS: OnceLock<PoseidonConstants<Bls12, SpongeArity>> = OnceLock::new();
+    CONSTANTS.get_or_init(PoseidonConstants::new)
+}
+
+/// the first h of `poseidon_sponge`, from the version, the domain and the # of elements
+pub fn sponge_tag(domain: Domain, nr_elements: usize) -> Fr {
+    let mut tag = vec![COMMITMENT_VERSION, domain as u8];
+    tag.extend_from_slice(&(nr_elements as u64).to_le_bytes());
+    bytes_to_fr(&tag)
+}
+
+/// Poseidon over field elements instead of bytes, which a circuit recomputes at a fraction of the
+/// cost of `HashFunction::hash`: h_0 = `sponge_tag` and h_{i+1} = Poseidon(h_i, chunk_i) over the
+/// chunks of arity - 1 elements, the last one padded with zeros, and the last h
+pub fn poseidon_sponge(domain: Domain, xs: &[Fr]) -> Fr {
+    let width = SpongeArity::to_usize();
+    xs.chunks(width - 1)
+        .fold(sponge_tag(domain, xs.len()), |h, chunk| {
+            let mut preimage = Vec::with_capacity(width);
+            preimage.push(h);
+            preimage.extend_from_slice(chunk);
+            preimage.resize(width, Fr::zero());
+            Poseidon::new_with_preimage(&preimage, sponge_constants()).hash()
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash_functions_differ() {
+        let functions = [
+            HashFunction::Sha3,
+            HashFunction::Blake3,
+            HashFunction::Poseidon,
+        ];
+        let long = vec![7u8; 100];
+        for f in functions.iter() {
+            assert_eq!(HashFunction::from_name(&format!("{:?}", f)), Some(*f));
+            // the parts are length-prefixed, so moving a byte across them changes the hash
+            assert_ne!(
+                f.hash(Domain::Ct, &[&[1u8, 2], &[3u8]]),
+                f.hash(Domain::Ct, &[&[1u8], &[2u8, 3]])
+            );
+            assert_ne!(
+                f.hash(Domain::Ct, &[&long]),
+                f.hash(Domain::DataLeaf, &[&long])
+            );
+            assert_eq!(f.hash(Domain::Ct, &[&long]), f.hash(Domain::Ct, &[&long]));
+        }
+        assert_ne!(
+            HashFunction::Sha3.hash(Domain::Ct, &[&long]),
+            HashFunction::Blake3.hash(Domain::Ct, &[&long])
+        );
+        assert_eq!(HashFunction::from_name("md5"), None);
+    }
+
+    #[test]
+    fn test_poseidon_sponge() {
+        let xs: Vec<Fr> = (-10i128..10).map(|x| i128_to_fr(x << 100)).collect();
+        assert_eq!(
+            poseidon_sponge(Domain::Ct, &xs),
+            poseidon_sponge(Domain::Ct, &xs)
+        );
+        // the # of elements is in the tag, so padding with zeros changes the hash
+        let mut padded = xs.clone();
+        padded.push(Fr::zero());
+        assert_ne!(
+            poseidon_sponge(Domain::Ct, &xs),
+            poseidon_sponge(Domain::Ct, &padded)
+        );
+        assert_ne!(
+            poseidon_sponge(Domain::Ct, &xs),
+            poseidon_sponge(Domain::RsaPk, &xs)
+        );
+        let mut minus_one = i128_to_fr(1);
+        minus_one.negate();
+        assert_eq!(i128_to_fr(-1), minus_one);
+    }
+}
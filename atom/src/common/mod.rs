@@ -1,10 +1,17 @@
 use ark_bls12_381::Parameters;
 use ark_ec::bls12::Bls12;
 use rand::SeedableRng;
-use rsa::{pkcs8::ToPublicKey, RsaPrivateKey, RsaPublicKey};
+use rsa::{
+    pkcs8::{FromPrivateKey, FromPublicKey, ToPublicKey},
+    PaddingScheme, PublicKey, RsaPrivateKey, RsaPublicKey,
+};
 
 pub mod aggregation;
 pub mod board_service;
+pub mod committee_service;
+pub mod hash;
+pub mod params;
+pub mod random_point;
 pub mod server_service;
 
 // T(2k) = T(k) + 2k
@@ -34,4 +41,56 @@ pub fn new_rsa_pub_key() -> Vec<u8> {
     public_key.to_public_key_pem().unwrap().into_bytes()
 }
 
+/// the public key of a client from its identity, the PEM sent as rsa_pk
+pub fn parse_rsa_pk(rsa_pk: &[u8]) -> Option<RsaPublicKey> {
+    std::str::from_utf8(rsa_pk)
+        .ok()
+        .and_then(|x| RsaPublicKey::from_public_key_pem(x).ok())
+}
+
+/// sign a digest of the hash function of the round with PKCS#1 v1.5, so without the
+/// DigestInfo prefix of a fixed hash
+pub fn rsa_sign(sk: &RsaPrivateKey, digest: &[u8; 32]) -> Vec<u8> {
+    sk.sign(PaddingScheme::new_pkcs1v15_sign(None), digest)
+        .expect("failed to sign")
+}
+
+pub fn rsa_verify(pk: &RsaPublicKey, digest: &[u8; 32], signature: &[u8]) -> bool {
+    pk.verify(PaddingScheme::new_pkcs1v15_sign(None), digest, signature)
+        .is_ok()
+}
+
+/// the identity key of committee member `id`, from committee_identity
+pub fn committee_sk(id: usize) -> RsaPrivateKey {
+    let file_name = format!("./data/committee_sk{}.pem", id);
+    RsaPrivateKey::read_pkcs8_pem_file(&file_name)
+        .unwrap_or_else(|e| panic!("cannot read {}: {}", file_name, e))
+}
+
+/// the identity keys of the `nr_members` committee members, published with the member list
+pub fn committee_pks(nr_members: usize) -> Vec<RsaPublicKey> {
+    (0..nr_members)
+        .map(|i| {
+            let file_name = format!("./data/committee_pk{}.pem", i);
+            RsaPublicKey::read_public_key_pem_file(&file_name)
+                .unwrap_or_else(|e| panic!("cannot read {}: {}", file_name, e))
+        })
+        .collect()
+}
+
+/// the identity key of the server, from `committee_identity server`, which signs the posts to
+/// the board
+pub fn server_sk() -> RsaPrivateKey {
+    let file_name = "./data/server_sk.pem";
+    RsaPrivateKey::read_pkcs8_pem_file(file_name)
+        .unwrap_or_else(|e| panic!("cannot read {}: {}", file_name, e))
+}
+
+/// the identity key of the server, published with the address of the board
+pub fn server_pk() -> RsaPublicKey {
+    let file_name = "./data/server_pk.pem";
+    RsaPublicKey::read_public_key_pem_file(file_name)
+        .unwrap_or_else(|e| panic!("cannot read {}: {}", file_name, e))
+}
+
 pub type ZKProof = ark_groth16::Proof<Bls12<Parameters>>;
@@ -0,0 +1,177 @@
+use super::hash::HashFunction;
+use cupcake::integer_arith::scalar::Scalar;
+use cupcake::integer_arith::ArithUtils;
+use tarpc::serde::{Deserialize, Serialize};
+
+/// the RNS primes of the 4096-dimension ring, q = 1 mod 8192, so they also serve 1024 and 2048
+pub const RNS_MODULUS_4096: [u64; 3] = [0xffffee001u64, 0xffffc4001u64, 0x1ffffe0001u64];
+/// the RNS primes of the 8192-dimension ring, q = 1 mod 16384
+pub const RNS_MODULUS_8192: [u64; 3] = [0x100008c001u64, 0x1000090001u64, 0x10000c8001u64];
+/// # of uploads the plaintext modulus leaves room for in an aggregate
+pub const MAX_SUMMANDS: usize = 1 << 16;
+
+/// The parameters of the RLWE encryption: the ring dimension, the RNS primes of the
+/// ciphertext modulus and the bounds of the noise.
+/// The dimensions below 4096 are not secure and only meant for fast tests.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ParameterSet {
+    pub num_dimension: usize,
+    pub rns_moduli: Vec<u64>,
+    /// the product of the RNS primes
+    pub modulus: i128,
+    /// the standard deviation of the gaussian noise
+    pub stddev: f64,
+    /// the noise is clamped to [-max_noise, max_noise], which the circuits range-check with an
+    /// offset of max_noise
+    pub max_noise: i128,
+    /// # of bits of a plaintext coefficient m, which the circuits range-check
+    pub plaintext_bits: usize,
+    /// the hash function of the commitments and the merkle trees
+    pub hash_fn: HashFunction,
+}
+
+impl Default for ParameterSet {
+    fn default() -> Self {
+        Self::new(4096, RNS_MODULUS_4096.to_vec())
+    }
+}
+
+impl ParameterSet {
+    pub fn new(num_dimension: usize, rns_moduli: Vec<u64>) -> Self {
+        assert!(
+            num_dimension.is_power_of_two(),
+            "the dimension {} is not a power of 2",
+            num_dimension
+        );
+        for q in rns_moduli.iter() {
+            assert_eq!(
+                q % (2 * num_dimension as u64),
+                1,
+                "{:#x} is not NTT-friendly for dimension {}",
+                q,
+                num_dimension
+            );
+        }
+        let modulus = rns_moduli
+            .iter()
+            .try_fold(1i128, |acc, q| acc.checked_mul(*q as i128))
+            .expect("the modulus does not fit in i128");
+        // pk * r + e sums num_dimension products of (< modulus) * (|r| <= 1) in i128
+        assert!(
+            modulus.checked_mul(4 * num_dimension as i128).is_some(),
+            "the modulus is too large for dimension {}",
+            num_dimension
+        );
+        Self {
+            num_dimension,
+            rns_moduli,
+            modulus,
+            stddev: 3.2,
+            max_noise: 15,
+            plaintext_bits: 8,
+            hash_fn: HashFunction::default(),
+        }
+    }
+
+    /// the same parameters with plaintext coefficients of `bits` bits, at most 16
+    pub fn with_plaintext_bits(mut self, bits: usize) -> Self {
+        assert!(
+            bits >= 1 && bits <= 16,
+            "{} bits of plaintext are not supported",
+            bits
+        );
+        self.plaintext_bits = bits;
+        self
+    }
+
+    /// the same parameters hashing with `hash_fn`
+    pub fn with_hash_fn(mut self, hash_fn: HashFunction) -> Self {
+        self.hash_fn = hash_fn;
+        self
+    }
+
+    /// the preset of dimension 1024, 2048, 4096 or 8192
+    pub fn with_dimension(num_dimension: usize) -> Self {
+        match num_dimension {
+            1024 | 2048 | 4096 => Self::new(num_dimension, RNS_MODULUS_4096.to_vec()),
+            8192 => Self::new(num_dimension, RNS_MODULUS_8192.to_vec()),
+            _ => panic!("no parameter set of dimension {}", num_dimension),
+        }
+    }
+
+    /// # of cts to hold `nr_parameter` numbers
+    pub fn nr_ct(&self, nr_parameter: usize) -> usize {
+        nr_parameter / self.num_dimension
+    }
+
+    /// # of bits of the offset noise in [0, 2 * max_noise]
+    pub fn noise_bits(&self) -> usize {
+        (128 - (2 * self.max_noise).leading_zeros()) as usize
+    }
+
+    /// the plaintext modulus t, which holds the sum of up to MAX_SUMMANDS plaintexts
+    pub fn plaintext_modulus(&self) -> i128 {
+        (MAX_SUMMANDS as i128) << self.plaintext_bits
+    }
+
+    /// the scaling factor floor(q / t) of the plaintext in c_1
+    pub fn plaintext_scale(&self) -> i128 {
+        self.modulus / self.plaintext_modulus()
+    }
+
+    /// the bound of the noise each of `nr_players` committee members adds to a decryption, so that
+    /// their sum stays below Δ / 4 and leaves as much to the noise of the ciphertexts
+    pub fn smudging_bound(&self, nr_players: usize) -> i128 {
+        self.plaintext_scale() / (4 * nr_players as i128)
+    }
+
+    /// lift the residues mod the RNS primes back to [0, modulus) by Garner's algorithm,
+    /// x = a0 + p0 * t1 + p0 * p1 * t2 + ...
+    pub fn crt(&self, residues: &[u64]) -> i128 {
+        let inv = |x: i128, m: u64| {
+            let q = Scalar::new_modulus(m);
+            Scalar::inv_mod(&Scalar::from(x.rem_euclid(m as i128) as u64), &q).rep() as i128
+        };
+        let mut x = 0i128;
+        let mut prod = 1i128;
+        for (&a, &p) in residues.iter().zip(self.rns_moduli.iter()) {
+            let pi = p as i128;
+            let t = (a as i128 - x).rem_euclid(pi) * inv(prod, p) % pi;
+            x += prod * t;
+            prod *= pi;
+        }
+        x
+    }
+
+    /// # of bits of the quotient delta of (pk * r + e) / q in [-n, n], offset by num_dimension
+    pub fn delta_bits(&self) -> usize {
+        self.num_dimension.trailing_zeros() as usize + 2
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_presets() {
+        let params = ParameterSet::default();
+        assert_eq!(params.modulus, 649033470896967801447398927572993i128);
+        assert_eq!(params.noise_bits(), 5);
+        assert_eq!(params.delta_bits(), 14);
+        assert_eq!(params.plaintext_scale(), params.modulus >> 24);
+        // the 55 members of the shipped committee still smudge with more than 64 bits
+        assert!(params.smudging_bound(55) > 1 << 64);
+        for n in [1024, 2048, 4096, 8192].iter() {
+            let params = ParameterSet::with_dimension(*n);
+            assert_eq!(params.num_dimension, *n);
+            let x = params.modulus - 12345;
+            let residues: Vec<u64> = params
+                .rns_moduli
+                .iter()
+                .map(|p| x.rem_euclid(*p as i128) as u64)
+                .collect();
+            assert_eq!(params.crt(&residues), x);
+        }
+    }
+}
@@ -0,0 +1,130 @@
+use super::{rsa_sign, rsa_verify};
+use rand::Rng;
+use rsa::{RsaPrivateKey, RsaPublicKey};
+use tarpc::serde::{Deserialize, Serialize};
+
+/// A contribution of a committee member to the random point of a round. The member signs it with
+/// its identity key and posts it to the board itself. The board keeps the first t + 1 valid
+/// contributions after the leaf roots are posted and hides them until it has them all. So the
+/// server can neither pick the contributions nor see them before the point is fixed, and anyone
+/// recomputes the point from the contributions on the board.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Contribution {
+    pub member: u32,
+    pub value: [u8; 32],
+    /// the signature of the member over `signed_digest`
+    pub signature: Vec<u8>,
+}
+
+/// what the contributions of `round` are bound to, the roots of the Ms leafs
+pub fn seed(round: u32, leaf_roots: &[[u8; 32]]) -> [u8; 32] {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(&round.to_le_bytes());
+    leaf_roots.iter().for_each(|r| {
+        hasher.update(r);
+    });
+    hasher.finalize().into()
+}
+
+impl Contribution {
+    /// a fresh contribution of `member` to the point bound to `seed`
+    pub fn new<R: Rng>(sk: &RsaPrivateKey, member: u32, seed: &[u8; 32], rng: &mut R) -> Self {
+        let mut ret = Self {
+            member,
+            value: rng.gen(),
+            signature: Vec::new(),
+        };
+        ret.signature = rsa_sign(sk, &ret.signed_digest(seed));
+        ret
+    }
+
+    pub fn signed_digest(&self, seed: &[u8; 32]) -> [u8; 32] {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(seed);
+        hasher.update(&self.member.to_le_bytes());
+        hasher.update(&self.value);
+        hasher.finalize().into()
+    }
+
+    /// whether `pk`, the identity key of the member, signed this for `seed`
+    pub fn verify(&self, pk: &RsaPublicKey, seed: &[u8; 32]) -> bool {
+        rsa_verify(pk, &self.signed_digest(seed), &self.signature)
+    }
+}
+
+/// the random point in [0, modulus) from the contributions of exactly t + 1 members sorted by
+/// id, as the board publishes them; None if they aren't or a signature doesn't verify
+pub fn random_point(
+    seed: &[u8; 32],
+    contributions: &[Contribution],
+    committee: &[RsaPublicKey],
+    threshold: usize,
+    modulus: i128,
+) -> Option<i128> {
+    if contributions.len() != threshold + 1
+        || contributions.windows(2).any(|x| x[0].member >= x[1].member)
+        || contributions.iter().any(|x| {
+            committee
+                .get(x.member as usize)
+                .map_or(true, |pk| !x.verify(pk, seed))
+        })
+    {
+        return None;
+    }
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(seed);
+    contributions.iter().for_each(|x| {
+        hasher.update(&x.member.to_le_bytes());
+        hasher.update(&x.value);
+    });
+    let h: [u8; 32] = hasher.finalize().into();
+    let mut x = [0u8; 16];
+    x.copy_from_slice(&h[0..16]);
+    Some((u128::from_le_bytes(x) % modulus as u128) as i128)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_random_point() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(7);
+        let (nr_members, threshold, modulus) = (4, 1, 1i128 << 100);
+        let sks: Vec<RsaPrivateKey> = (0..nr_members)
+            .map(|_| RsaPrivateKey::new(&mut rng, 1024).unwrap())
+            .collect();
+        let committee: Vec<RsaPublicKey> = sks.iter().map(RsaPublicKey::from).collect();
+        let seed = seed(3, &[[1u8; 32], [2u8; 32]]);
+        let contributions: Vec<Contribution> = [1, 3]
+            .iter()
+            .map(|i| Contribution::new(&sks[*i], *i as u32, &seed, &mut rng))
+            .collect();
+        let random_pt = random_point(&seed, &contributions, &committee, threshold, modulus);
+        assert!(matches!(random_pt, Some(x) if (0..modulus).contains(&x)));
+
+        // bound to the leaf roots and the round
+        let other = super::seed(4, &[[1u8; 32], [2u8; 32]]);
+        assert_eq!(
+            random_point(&other, &contributions, &committee, threshold, modulus),
+            None
+        );
+        // t + 1 distinct members, sorted
+        let mut wrong = contributions.clone();
+        wrong.pop();
+        assert_eq!(random_point(&seed, &wrong, &committee, threshold, modulus), None);
+        wrong.push(contributions[0].clone());
+        assert_eq!(random_point(&seed, &wrong, &committee, threshold, modulus), None);
+        let mut wrong = contributions.clone();
+        wrong.reverse();
+        assert_eq!(random_point(&seed, &wrong, &committee, threshold, modulus), None);
+        // the value can't be changed, nor claimed by another member
+        let mut wrong = contributions.clone();
+        wrong[1].value[0] ^= 1;
+        assert_eq!(random_point(&seed, &wrong, &committee, threshold, modulus), None);
+        let mut wrong = contributions.clone();
+        wrong[1].member = 2;
+        assert_eq!(random_point(&seed, &wrong, &committee, threshold, modulus), None);
+    }
+}
@@ -1,11 +1,44 @@
 use super::aggregation::merkle::MerkleProof;
 use super::aggregation::node::{CommitEntry, SummationEntry, SummationLeaf, SummationNonLeaf};
+use super::aggregation::sample::SampledNodes;
+use tarpc::serde::{Deserialize, Serialize};
+
+/// Why the server rejects a commitment or the uploaded data
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum AggregationError {
+    /// the server is not collecting the commitments or the data of this round
+    WrongStage,
+    /// the # of commitments, cts, nonces or proofs don't match the model
+    MalformedUpload,
+    /// the trees already hold nr_real clients
+    TreeFull,
+    /// this rsa_pk has already committed in this round
+    DuplicateCommit,
+    /// this rsa_pk has already uploaded its data in this round
+    DuplicateUpload,
+    /// this rsa_pk has no commitment in Mc, so its data can't be accepted
+    NotCommitted,
+    /// rsa_pk is not a PEM-encoded RSA public key
+    InvalidKey,
+    /// the signatures of these commitments or cts don't verify under rsa_pk
+    InvalidSignature(Vec<usize>),
+    /// the proofs of these cts don't verify
+    InvalidProof(Vec<usize>),
+}
+
 // This is the service definition
 #[tarpc::service]
 pub trait ServerService {
-    /// send the commitment in the aggregation phase, not block, call get_mc_proof later
-    async fn aggregate_commit(round: u32, rsa_pk: Vec<u8>, commitment: Vec<[u8; 32]>);
-    /// send the data in the aggregation phase, not block, call get_ms_proof later
+    /// send the commitment in the aggregation phase, call get_mc_proof later if accepted;
+    /// signatures[j] is over `CommitEntry::signed_digest` of the j-th commitment
+    async fn aggregate_commit(
+        round: u32,
+        rsa_pk: Vec<u8>,
+        commitment: Vec<[u8; 32]>,
+        signatures: Vec<Vec<u8>>,
+    ) -> Result<(), AggregationError>;
+    /// send the data in the aggregation phase, call get_ms_proof later if accepted;
+    /// signatures[j] is over `SummationLeaf::signed_digest` of the j-th ct
     async fn aggregate_data(
         round: u32,
         rsa_pk: Vec<u8>,
@@ -13,20 +46,22 @@ pub trait ServerService {
         ct1: Vec<Vec<i128>>,
         nonce: Vec<[u8; 16]>,
         proofs: Vec<Vec<u8>>,
-    );
-    /// Get the inclusion proof of a node inside the commitment merkle tree
-    //async fn get_mc_proof(round: u32, rsa_pk: Vec<u8>) -> Vec<MerkleProof>;
-    async fn get_mc_proof(round: u32, rsa_pk: Vec<u8>);
-    /// Get the inclusion proof of a node inside the summation merkle tree
-    async fn get_ms_proof(round: u32, rsa_pk: Vec<u8>);
-    //async fn get_ms_proof(round: u32, rsa_pk: Vec<u8>) -> Vec<MerkleProof>;
+        signatures: Vec<Vec<u8>>,
+    ) -> Result<(), AggregationError>;
+    /// Get the inclusion proof of a node inside the commitment merkle tree, one per ciphertext
+    async fn get_mc_proof(round: u32, rsa_pk: Vec<u8>) -> Vec<MerkleProof>;
+    /// Get the inclusion proof of a node inside the summation merkle tree, one per ciphertext
+    async fn get_ms_proof(round: u32, rsa_pk: Vec<u8>) -> Vec<MerkleProof>;
 
+    /// Get the nodes of `Sample { n, s, vinit, grandparents }` with their proofs for every ct in
+    /// ct_id, in the layout of `Sample::collect`; nothing if the sample doesn't fit the trees
     async fn verify(
         round: u32,
+        s: u32,
         vinit: u32,
-        non_leaf_id: Vec<u32>,
+        grandparents: Vec<u32>,
         ct_id: Vec<usize>,
-    ) -> Vec<Vec<(SummationEntry, MerkleProof)>>;
+    ) -> Vec<SampledNodes>;
 
     async fn retrieve_model(round: u32) -> Vec<u8>;
     async fn retrieve_proving_key(round: u32) -> Vec<u8>;
@@ -1,23 +1,21 @@
 mod common;
 mod util;
 mod zksnark;
-use crate::common::aggregation::{
-    merkle::HashAlgorithm,
-    node::{SummationEntry, SummationLeaf, SummationNonLeaf},
+use crate::common::aggregation::node::{
+    CommitEntry, SummationEntry, SummationLeaf, SummationNonLeaf,
 };
+use crate::common::hash::HashFunction;
+use crate::common::params::ParameterSet;
 use crate::common::server_service::ServerServiceClient;
-use crate::common::{i128vec_to_le_bytes, summation_array_size, ZKProof};
+use crate::common::{i128vec_to_le_bytes, rsa_sign, summation_array_size, ZKProof};
 use crate::util::{config::ConfigUtils, log::init_tracing};
 use crate::zksnark::{Prover, Verifier};
 use ark_std::{end_timer, start_timer};
-#[cfg(feature = "hashfn_blake3")]
-extern crate blake3;
-#[cfg(not(feature = "hashfn_blake3"))]
-use crypto::{digest::Digest, sha3::Sha3};
 use quail::common::new_rsa_pub_key;
 use tracing::{error, event, instrument, span, warn, Level};
 
 use rand::{Rng, SeedableRng};
+use rayon::prelude::*;
 use rsa::{pkcs8::ToPublicKey, RsaPrivateKey, RsaPublicKey};
 use std::sync::Arc;
 use std::time::Duration;
@@ -31,38 +29,59 @@ use rlwe::PublicKey;
 use tracing_subscriber::filter::LevelFilter;
 
 const DEADLINE_TIME: u64 = 6000;
-const NUM_DIMENSION: u32 = 4096;
 pub struct LightClient {
     inner: ServerServiceClient,
     nr_lc: u32,
     // 451 bytes
     rsa_pk: Vec<Vec<u8>>,
+    rsa_sk: Vec<RsaPrivateKey>,
     c0s: Vec<Vec<i128>>,
     c1s: Vec<Vec<i128>>,
     // 192 bytes per proof
     proofs: Vec<Vec<u8>>,
     nonce: Vec<[u8; 16]>,
+    params: ParameterSet,
 }
 
 impl LightClient {
-    pub fn new(inner: ServerServiceClient, nr_lc: u32, nr_parameter: u32) -> Self {
-        let nr_ct: usize = nr_parameter as usize / 4096;
-        let mut rng = rand::rngs::StdRng::from_entropy();
-        let c0s = vec![vec![0i128; 4096]; nr_ct];
-        let c1s = vec![vec![0i128; 4096]; nr_ct];
+    pub fn new(
+        inner: ServerServiceClient,
+        nr_lc: u32,
+        nr_parameter: u32,
+        params: &ParameterSet,
+    ) -> Self {
+        let nr_ct: usize = params.nr_ct(nr_parameter as usize);
+        let c0s = vec![vec![0i128; params.num_dimension]; nr_ct];
+        let c1s = vec![vec![0i128; params.num_dimension]; nr_ct];
         let proofs = vec![vec![0u8; 192]; nr_ct];
-        let rsa_pk = (0..nr_lc)
-            .map(|_| (0..451).map(|_| rng.gen::<u8>()).collect())
+        // real keys, the server checks the signatures of every client
+        let rsa_sk: Vec<RsaPrivateKey> = (0..nr_lc)
+            .into_par_iter()
+            .map(|_| {
+                let mut rng = rand::rngs::StdRng::from_entropy();
+                RsaPrivateKey::new(&mut rng, 2048).expect("failed to generate a key")
+            })
+            .collect();
+        let rsa_pk = rsa_sk
+            .iter()
+            .map(|x| {
+                RsaPublicKey::from(x)
+                    .to_public_key_pem()
+                    .unwrap()
+                    .into_bytes()
+            })
             .collect();
         let nonce = vec![[0u8; 16]; nr_ct];
         Self {
             inner,
             nr_lc,
             rsa_pk: rsa_pk,
+            rsa_sk,
             c0s,
             c1s,
             proofs,
             nonce,
+            params: params.clone(),
         }
     }
 
@@ -71,6 +90,44 @@ impl LightClient {
         rand::thread_rng().gen::<[u8; 32]>()
     }
 
+    /// the signatures of the i-th light client over its commitments in `round`
+    fn sign_commit(&self, i: usize, round: u32, cm: &[[u8; 32]]) -> Vec<Vec<u8>> {
+        cm.par_iter()
+            .enumerate()
+            .map(|(j, h)| {
+                let entry = CommitEntry {
+                    rsa_pk: self.rsa_pk[i].clone(),
+                    hash: *h,
+                    signature: Vec::new(),
+                };
+                rsa_sign(
+                    &self.rsa_sk[i],
+                    &entry.signed_digest(self.params.hash_fn, round, j),
+                )
+            })
+            .collect()
+    }
+
+    /// the signatures of the i-th light client over its data in `round`
+    fn sign_data(&self, i: usize, round: u32) -> Vec<Vec<u8>> {
+        (0..self.c0s.len())
+            .into_par_iter()
+            .map(|j| {
+                let leaf = SummationLeaf::from_ct(
+                    self.rsa_pk[i].clone(),
+                    self.c0s[j].clone(),
+                    self.c1s[j].clone(),
+                    self.nonce[j],
+                    self.proofs[j].clone(),
+                );
+                rsa_sign(
+                    &self.rsa_sk[i],
+                    &leaf.signed_digest(self.params.hash_fn, round, j),
+                )
+            })
+            .collect()
+    }
+
     pub async fn train_model(&self, round: u32) {
         let rm = start_timer!(|| "retrieve the model");
         for _ in 0..self.nr_lc as usize {
@@ -93,7 +150,13 @@ impl LightClient {
             ctx.deadline = SystemTime::now() + Duration::from_secs(DEADLINE_TIME);
             let _ = self
                 .inner
-                .aggregate_commit(ctx, round, self.rsa_pk[i].clone(), cm.clone())
+                .aggregate_commit(
+                    ctx,
+                    round,
+                    self.rsa_pk[i].clone(),
+                    cm.clone(),
+                    self.sign_commit(i, round, &cm),
+                )
                 .await;
         }
         // get all the mc_proof
@@ -120,6 +183,7 @@ impl LightClient {
                     self.c1s.clone(),
                     self.nonce.clone(),
                     self.proofs.clone(),
+                    self.sign_data(i, round),
                 )
                 .await;
         }
@@ -165,7 +229,9 @@ async fn main() -> anyhow::Result<()> {
 
     let inner_client =
         ServerServiceClient::new(client::Config::default(), transport.await?).spawn();
-    let mut client = LightClient::new(inner_client, nr_lc, nr_parameter);
+    let params = ParameterSet::with_dimension(config.get_int("num_dimension") as usize)
+        .with_hash_fn(HashFunction::from_name(&config.get("hash_fn")).expect("unknown hash_fn"));
+    let mut client = LightClient::new(inner_client, nr_lc, nr_parameter, &params);
 
     for i in 0..nr_round {
         // begin uploading
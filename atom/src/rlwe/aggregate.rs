@@ -0,0 +1,252 @@
+use crate::common::params::{ParameterSet, MAX_SUMMANDS};
+use crate::rlwe::context::NTTContext;
+
+/// The running coefficient-wise sum of the accepted ciphertexts, kept mod each RNS prime.
+/// With pk_0 = a and pk_1 = a * s + e, c_1 - c_0 * s is the sum of Δ * m + e * r + e_1 - s * e_0,
+/// so only c_0 goes to the committee, which returns c_0 * s + noise. The sum of the m stays
+/// below the plaintext modulus t for up to MAX_SUMMANDS uploads, so rounding off the noise
+/// recovers it exactly as long as the noise is below Δ / 2.
+pub struct Aggregate {
+    pub params: ParameterSet,
+    /// # of uploads in the sum
+    pub nr_summands: usize,
+    // c_0[k] and c_1[k] hold all nr_ct * num_dimension slots mod the k-th RNS prime
+    c_0: Vec<Vec<u64>>,
+    c_1: Vec<Vec<u64>>,
+    ntt_context: Vec<NTTContext>,
+}
+
+impl Aggregate {
+    pub fn new(params: &ParameterSet, nr_ct: usize) -> Self {
+        let nr_slots = nr_ct * params.num_dimension;
+        let nr_moduli = params.rns_moduli.len();
+        Self {
+            params: params.clone(),
+            nr_summands: 0,
+            c_0: vec![vec![0u64; nr_slots]; nr_moduli],
+            c_1: vec![vec![0u64; nr_slots]; nr_moduli],
+            ntt_context: NTTContext::init_all(params),
+        }
+    }
+
+    pub fn nr_slots(&self) -> usize {
+        self.c_0[0].len()
+    }
+
+    fn accumulate(moduli: &[u64], sum: &mut [Vec<u64>], cts: &[Vec<i128>]) {
+        for (s, q) in sum.iter_mut().zip(moduli.iter()) {
+            let p = *q as i128;
+            s.iter_mut()
+                .zip(cts.iter().flatten())
+                .for_each(|(x, y)| *x = (*x + y.rem_euclid(p) as u64) % q);
+        }
+    }
+
+    /// add the cts of one upload, c_0[j] and c_1[j] being the j-th ct
+    pub fn add(&mut self, c_0: &[Vec<i128>], c_1: &[Vec<i128>]) {
+        assert!(
+            self.nr_summands < MAX_SUMMANDS,
+            "the sum of more than {} uploads overflows the plaintext modulus",
+            MAX_SUMMANDS
+        );
+        Self::accumulate(&self.params.rns_moduli, &mut self.c_0, c_0);
+        Self::accumulate(&self.params.rns_moduli, &mut self.c_1, c_1);
+        self.nr_summands += 1;
+    }
+
+    /// the summed c_0 and c_1 in coefficient form, one vector per RNS prime, as posted on the board
+    pub fn summed_ct(&self) -> (Vec<Vec<u64>>, Vec<Vec<u64>>) {
+        (self.c_0.clone(), self.c_1.clone())
+    }
+
+    /// the summed c_0 in NTT form for every num_dimension block, as the committee expects it
+    pub fn ntt_c_0(&self) -> Vec<Vec<u64>> {
+        to_ntt(&self.ntt_context, &self.c_0)
+    }
+
+    /// subtract c_0 * s + noise, decrypted by the committee in coefficient form, from the summed c_1
+    /// and round the result to the nearest multiple of Δ, giving the sum of the plaintexts
+    pub fn decrypt(&self, c_0_sk: &[Vec<u64>]) -> Vec<i128> {
+        decrypt(&self.params, &self.c_1, c_0_sk)
+    }
+
+    pub fn clear(&mut self) {
+        self.c_0.iter_mut().flatten().for_each(|x| *x = 0);
+        self.c_1.iter_mut().flatten().for_each(|x| *x = 0);
+        self.nr_summands = 0;
+    }
+}
+
+/// c in NTT form for every num_dimension block, one vector per RNS prime both ways
+pub fn to_ntt(ntt_context: &[NTTContext], c: &[Vec<u64>]) -> Vec<Vec<u64>> {
+    let n = ntt_context[0].num_dimension;
+    c.iter()
+        .zip(ntt_context.iter())
+        .map(|(c, context)| {
+            let mut a = c.clone();
+            a.chunks_mut(n).for_each(|x| context.lazy_ntt_inplace(x));
+            a
+        })
+        .collect()
+}
+
+/// the sum of the plaintexts from the summed c_1 and its decryption c_0 * s + noise, both in
+/// coefficient form, so anyone can recompute it from the board, see `Aggregate::decrypt`
+pub fn decrypt(params: &ParameterSet, c_1: &[Vec<u64>], c_0_sk: &[Vec<u64>]) -> Vec<i128> {
+    let t = params.plaintext_modulus();
+    let scale = params.plaintext_scale();
+    (0..c_1[0].len())
+        .map(|i| {
+            let residues: Vec<u64> = params
+                .rns_moduli
+                .iter()
+                .enumerate()
+                .map(|(k, p)| (c_1[k][i] + p - c_0_sk[k][i] % p) % p)
+                .collect();
+            // x in [0, q) and q = t * Δ + (q mod t), so a small negative noise on a sum of 0
+            // rounds to t
+            let x = params.crt(&residues);
+            ((x + scale / 2) / scale) % t
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rlwe::encoding::{Clipping, Encoder};
+    use crate::rlwe::tests::negacyclic_mul;
+    use crate::rlwe::PublicKey;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    /// a ternary secret s, e and the public key (a, a * s + e)
+    fn keys(params: &ParameterSet, rng: &mut StdRng) -> (Vec<i128>, Vec<i128>, PublicKey) {
+        let n = params.num_dimension;
+        let q = params.modulus;
+        let s: Vec<i128> = (0..n).map(|_| rng.gen_range(-1i128..2)).collect();
+        let e: Vec<i128> = (0..n).map(|_| rng.gen_range(-4i128..5)).collect();
+        let a: Vec<i128> = (0..n).map(|_| rng.gen_range(0..q)).collect();
+        let pk_1: Vec<i128> = negacyclic_mul(&a, &s)
+            .iter()
+            .zip(e.iter())
+            .map(|(x, y)| (x + y).rem_euclid(q))
+            .collect();
+        let pk = PublicKey::new(params, &a, &pk_1);
+        (s, e, pk)
+    }
+
+    /// what the committee returns, c_0 * s plus the largest noise it may add, in [-Δ / 4, Δ / 4]
+    fn c_0_sk(aggregate: &Aggregate, s: &[i128], rng: &mut StdRng) -> Vec<Vec<u64>> {
+        let n = aggregate.params.num_dimension;
+        let bound = aggregate.params.smudging_bound(1);
+        let noise: Vec<i128> = (0..aggregate.nr_slots())
+            .map(|_| rng.gen_range(-bound..=bound))
+            .collect();
+        aggregate
+            .ntt_c_0()
+            .iter()
+            .zip(NTTContext::init_all(&aggregate.params).iter())
+            .map(|(c, context)| {
+                let p = context.modulus.rep() as i128;
+                let mut ntt_s: Vec<u64> = s.iter().map(|x| x.rem_euclid(p) as u64).collect();
+                context.lazy_ntt_inplace(&mut ntt_s);
+                let mut ret = Vec::with_capacity(c.len());
+                for block in c.chunks(n) {
+                    let mut x = context.coeff_mul_mod(&ntt_s, block);
+                    context.lazy_inverse_ntt_inplace(&mut x);
+                    ret.extend(x);
+                }
+                ret.iter()
+                    .zip(noise.iter())
+                    .map(|(x, e)| (*x as i128 + e).rem_euclid(p) as u64)
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_decrypt_sum() {
+        let params = ParameterSet::with_dimension(1024);
+        let n = params.num_dimension;
+        let mut rng = StdRng::from_entropy();
+        let (s, e, pk) = keys(&params, &mut rng);
+
+        let nr_ct = 2;
+        let mut aggregate = Aggregate::new(&params, nr_ct);
+        let mut expected = vec![0i128; nr_ct * n];
+        let mut noise = vec![0i128; nr_ct * n];
+        for _ in 0..3 {
+            let mut c_0 = Vec::new();
+            let mut c_1 = Vec::new();
+            for j in 0..nr_ct {
+                let m: Vec<u16> = (0..n).map(|_| rng.gen_range(0..256)).collect();
+                let (r, e0, e1, _, _, ct) = pk.encrypt(&m);
+                let er = negacyclic_mul(&e, &r);
+                let se0 = negacyclic_mul(&s, &e0);
+                for i in 0..n {
+                    expected[j * n + i] += m[i] as i128;
+                    noise[j * n + i] += er[i] + e1[i] - se0[i];
+                }
+                c_0.push(ct.c_0);
+                c_1.push(ct.c_1);
+            }
+            aggregate.add(&c_0, &c_1);
+        }
+        assert_eq!(aggregate.nr_summands, 3);
+        // the noise is centred and far below Δ / 2, so it rounds off
+        assert!(noise.iter().any(|x| *x < 0));
+        assert!(noise.iter().all(|x| x.abs() < params.plaintext_scale() / 2));
+        assert_eq!(
+            aggregate.decrypt(&c_0_sk(&aggregate, &s, &mut rng)),
+            expected
+        );
+
+        aggregate.clear();
+        assert_eq!(aggregate.nr_summands, 0);
+        let zero = vec![vec![0u64; nr_ct * n]; params.rns_moduli.len()];
+        assert!(aggregate.decrypt(&zero).iter().all(|x| *x == 0));
+    }
+
+    #[test]
+    fn test_decode_sum_of_updates() {
+        let params = ParameterSet::with_dimension(1024);
+        let n = params.num_dimension;
+        let mut rng = StdRng::from_entropy();
+        let (s, _, pk) = keys(&params, &mut rng);
+        let encoder = Encoder::new(&params, 4, Clipping::LInf(4.0));
+
+        // known updates within the clipping bound in steps of 1/16, which the encoding holds
+        // exactly
+        let nr_ct = 2;
+        let updates: Vec<Vec<f32>> = (0..5)
+            .map(|k| {
+                (0..nr_ct * n)
+                    .map(|i| match (i + k) % 7 {
+                        0 => -4.0,
+                        1 => 4.0,
+                        _ => rng.gen_range(-64i32..64) as f32 / 16.0,
+                    })
+                    .collect()
+            })
+            .collect();
+        let mut aggregate = Aggregate::new(&params, nr_ct);
+        for x in updates.iter() {
+            let m = encoder.encode(x);
+            let (c_0, c_1): (Vec<Vec<i128>>, Vec<Vec<i128>>) = m
+                .chunks(n)
+                .map(|m| {
+                    let ct = pk.encrypt(m).5;
+                    (ct.c_0, ct.c_1)
+                })
+                .unzip();
+            aggregate.add(&c_0, &c_1);
+        }
+
+        let sum = aggregate.decrypt(&c_0_sk(&aggregate, &s, &mut rng));
+        let decoded = encoder.decode(&sum, aggregate.nr_summands);
+        for i in 0..nr_ct * n {
+            let expected: f32 = updates.iter().map(|x| x[i]).sum();
+            assert_eq!(decoded[i], expected, "{}", i);
+        }
+    }
+}
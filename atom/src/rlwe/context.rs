@@ -4,9 +4,7 @@ use cupcake::polyarith::lazy_ntt::{lazy_inverse_ntt_u64, lazy_ntt_u64};
 use cupcake::rqpoly::RqPolyContext;
 use rand::{Rng, SeedableRng};
 
-use super::NUM_DIMENSION;
-
-pub const MODULUS: [u64; 3] = [0xffffee001u64, 0xffffc4001u64, 0x1ffffe0001u64];
+use crate::common::params::ParameterSet;
 
 pub struct ShamirContext {
     pub modulus: Scalar,
@@ -15,6 +13,25 @@ pub struct ShamirContext {
     pub eval_matrix: Vec<Vec<Scalar>>,
 }
 
+/// The result of `ShamirContext::robust_reconstruct`: the secret and the players whose shares
+/// are off the polynomial
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reconstruction {
+    pub secret: u64,
+    pub wrong: Vec<usize>,
+}
+
+/// What `ShamirContext::robust_reconstruct` computes once for a set of players
+pub struct RobustDecoder {
+    ids: Vec<usize>,
+    degree: usize,
+    // the lagrange coefficients at zero of the first degree + 1 players
+    coeffs: Vec<Scalar>,
+    // checks[i]: the lagrange coefficients of the first degree + 1 players at the point of
+    // player ids[degree + 1 + i]
+    checks: Vec<Vec<Scalar>>,
+}
+
 impl ShamirContext {
     pub fn init(prime: u64, share_cnt: usize, threshold: usize) -> Self {
         let modulus = Scalar::new_modulus(prime);
@@ -35,6 +52,16 @@ impl ShamirContext {
             eval_matrix,
         }
     }
+
+    /// one context per RNS prime of `params`
+    pub fn init_all(params: &ParameterSet, share_cnt: usize, threshold: usize) -> Vec<Self> {
+        params
+            .rns_moduli
+            .iter()
+            .map(|q| Self::init(*q, share_cnt, threshold))
+            .collect()
+    }
+
     fn sample_polynomial(&self, secret: u64) -> Vec<Scalar> {
         let mut poly = vec![Scalar::from(secret)];
         //poly.extend((0..self.threshold).map(|_| Scalar::sample_blw(&self.modulus)));
@@ -106,6 +133,37 @@ impl ShamirContext {
         acc.rep()
     }
 
+    /// lagrange coefficients at zero for the players `ids`, where player i holds the evaluation at i + 1
+    pub fn lagrange_coefficients(&self, ids: &[usize]) -> Vec<Scalar> {
+        let q = &self.modulus;
+        ids.iter()
+            .map(|&i| {
+                let xi = Scalar::from((i + 1) as u64);
+                let mut num = Scalar::one();
+                let mut denum = Scalar::one();
+                for &j in ids.iter().filter(|&&j| j != i) {
+                    let xj = Scalar::from((j + 1) as u64);
+                    num = Scalar::mul_mod(&num, &xj, q);
+                    denum = Scalar::mul_mod(&denum, &Scalar::sub_mod(&xj, &xi, q), q);
+                }
+                Scalar::mul_mod(&num, &Scalar::inv_mod(&denum, q), q)
+            })
+            .collect()
+    }
+
+    /// reconstruct from the shares of the players whose lagrange coefficients are `coeffs`
+    pub fn reconstruct_with(&self, coeffs: &[Scalar], shares: &[u64]) -> u64 {
+        let q = &self.modulus;
+        coeffs
+            .iter()
+            .zip(shares.iter())
+            .fold(Scalar::zero(), |acc, (c, y)| {
+                let y = Scalar::modulus(&Scalar::from(*y), q);
+                Scalar::add_mod(&acc, &Scalar::mul_mod(c, &y, q), q)
+            })
+            .rep()
+    }
+
     pub fn reconstruct(&self, shares: &Vec<u64>) -> u64 {
         let recovered_indices: Vec<usize> = (0..self.threshold + 1).collect();
         let recovered_shares: Vec<Scalar> = shares[0..self.threshold + 1]
@@ -121,6 +179,235 @@ impl ShamirContext {
         //reconstructed_secret as u64
         Scalar::modulus(&Scalar::from(reconstructed_secret as u64), &self.modulus).rep()
     }
+
+    /// reconstruct from the shares of the players `ids`, distinct, on a polynomial of `degree`
+    /// (`threshold`, or twice that for a product of two sharings), even if up to
+    /// (ids.len() - degree - 1) / 2 of them are wrong, by Berlekamp-Welch decoding. None if
+    /// there are more wrong shares than that.
+    pub fn robust_reconstruct(
+        &self,
+        ids: &[usize],
+        shares: &[u64],
+        degree: usize,
+    ) -> Option<Reconstruction> {
+        let q = &self.modulus;
+        let n = ids.len();
+        if shares.len() != n || n <= degree {
+            return None;
+        }
+        let xs: Vec<Scalar> = ids.iter().map(|&i| Scalar::from((i + 1) as u64)).collect();
+        let ys: Vec<Scalar> = shares
+            .iter()
+            .map(|y| Scalar::modulus(&Scalar::from(*y), q))
+            .collect();
+        // usually every share is right, then the first degree + 1 already fit all the others
+        let poly = self.interpolate(&xs[..degree + 1], &ys[..degree + 1]);
+        let poly = if (degree + 1..n).all(|i| self.eval(&poly, &xs[i]).rep() == ys[i].rep()) {
+            poly
+        } else {
+            self.berlekamp_welch(&xs, &ys, degree)?
+        };
+        let wrong: Vec<usize> = (0..n)
+            .filter(|&i| self.eval(&poly, &xs[i]).rep() != ys[i].rep())
+            .map(|i| ids[i])
+            .collect();
+        if wrong.len() > (n - degree - 1) / 2 {
+            return None;
+        }
+        Some(Reconstruction {
+            secret: poly[0].rep(),
+            wrong,
+        })
+    }
+
+    /// precompute `robust_reconstruct` for the shares of the players `ids` on a polynomial of
+    /// `degree`, to reconstruct many secrets shared among them. None if there are too few
+    pub fn robust_decoder(&self, ids: &[usize], degree: usize) -> Option<RobustDecoder> {
+        if ids.len() <= degree {
+            return None;
+        }
+        let xs: Vec<Scalar> = ids[..degree + 1]
+            .iter()
+            .map(|&i| Scalar::from((i + 1) as u64))
+            .collect();
+        Some(RobustDecoder {
+            ids: ids.to_vec(),
+            degree,
+            coeffs: self.lagrange_basis(&xs, &Scalar::zero()),
+            checks: ids[degree + 1..]
+                .iter()
+                .map(|&i| self.lagrange_basis(&xs, &Scalar::from((i + 1) as u64)))
+                .collect(),
+        })
+    }
+
+    /// `robust_reconstruct` from the shares of the players of `decoder`, which only decodes if
+    /// a share is off the polynomial through the first degree + 1
+    pub fn robust_reconstruct_with(
+        &self,
+        decoder: &RobustDecoder,
+        shares: &[u64],
+    ) -> Option<Reconstruction> {
+        let d = decoder.degree + 1;
+        if shares.len() != decoder.ids.len() {
+            return None;
+        }
+        let p = self.modulus.rep();
+        if decoder
+            .checks
+            .iter()
+            .zip(shares[d..].iter())
+            .all(|(c, y)| self.reconstruct_with(c, &shares[..d]) == y % p)
+        {
+            Some(Reconstruction {
+                secret: self.reconstruct_with(&decoder.coeffs, &shares[..d]),
+                wrong: Vec::new(),
+            })
+        } else {
+            self.robust_reconstruct(&decoder.ids, shares, decoder.degree)
+        }
+    }
+
+    /// the lagrange basis polynomials of the points `xs`, at x
+    fn lagrange_basis(&self, xs: &[Scalar], x: &Scalar) -> Vec<Scalar> {
+        let q = &self.modulus;
+        (0..xs.len())
+            .map(|i| {
+                let mut num = Scalar::one();
+                let mut denum = Scalar::one();
+                for j in (0..xs.len()).filter(|&j| j != i) {
+                    num = Scalar::mul_mod(&num, &Scalar::sub_mod(x, &xs[j], q), q);
+                    denum = Scalar::mul_mod(&denum, &Scalar::sub_mod(&xs[i], &xs[j], q), q);
+                }
+                Scalar::mul_mod(&num, &Scalar::inv_mod(&denum, q), q)
+            })
+            .collect()
+    }
+
+    /// the coefficients of the polynomial through the points, by Newton's divided differences
+    fn interpolate(&self, xs: &[Scalar], ys: &[Scalar]) -> Vec<Scalar> {
+        let q = &self.modulus;
+        let d = xs.len() - 1;
+        let mut c = ys.to_vec();
+        for j in 1..d + 1 {
+            for i in (j..d + 1).rev() {
+                let denum = Scalar::sub_mod(&xs[i], &xs[i - j], q);
+                c[i] = Scalar::mul_mod(
+                    &Scalar::sub_mod(&c[i], &c[i - 1], q),
+                    &Scalar::inv_mod(&denum, q),
+                    q,
+                );
+            }
+        }
+        // expand c[0] + (x - x_0)(c[1] + (x - x_1)(...)) from the inside out
+        let mut poly = vec![c[d].clone()];
+        for i in (0..d).rev() {
+            let mut next = vec![Scalar::zero(); poly.len() + 1];
+            for (k, a) in poly.iter().enumerate() {
+                next[k + 1] = Scalar::add_mod(&next[k + 1], a, q);
+                next[k] = Scalar::sub_mod(&next[k], &Scalar::mul_mod(a, &xs[i], q), q);
+            }
+            next[0] = Scalar::add_mod(&next[0], &c[i], q);
+            poly = next;
+        }
+        poly
+    }
+
+    fn eval(&self, poly: &[Scalar], x: &Scalar) -> Scalar {
+        let q = &self.modulus;
+        poly.iter().rev().fold(Scalar::zero(), |acc, a| {
+            Scalar::add_mod(&Scalar::mul_mod(&acc, x, q), a, q)
+        })
+    }
+
+    /// find Q of degree e + degree and monic E of degree e with Q(x_i) = y_i * E(x_i), where
+    /// e is the # of errors to correct, then the polynomial is Q / E
+    fn berlekamp_welch(&self, xs: &[Scalar], ys: &[Scalar], degree: usize) -> Option<Vec<Scalar>> {
+        let q = &self.modulus;
+        let e = (xs.len() - degree - 1) / 2;
+        let nr_q = e + degree + 1;
+        // the unknowns are the coefficients of Q, then the ones of E but its leading 1, which
+        // moves to the right hand side
+        let rows: Vec<Vec<Scalar>> = xs
+            .iter()
+            .zip(ys.iter())
+            .map(|(x, y)| {
+                let pows: Vec<Scalar> = (0..nr_q)
+                    .scan(Scalar::one(), |x_pow, _| {
+                        let ret = x_pow.clone();
+                        *x_pow = Scalar::mul_mod(x_pow, x, q);
+                        Some(ret)
+                    })
+                    .collect();
+                let mut row = pows.clone();
+                row.extend(
+                    pows[..e]
+                        .iter()
+                        .map(|a| Scalar::sub_mod(&Scalar::zero(), &Scalar::mul_mod(y, a, q), q)),
+                );
+                row.push(Scalar::mul_mod(y, &pows[e], q));
+                row
+            })
+            .collect();
+        let sol = self.solve(rows)?;
+        let mut err_poly = sol[nr_q..].to_vec();
+        err_poly.push(Scalar::one());
+
+        // divide Q by E, which is monic
+        let mut rem = sol[..nr_q].to_vec();
+        let mut poly = vec![Scalar::zero(); degree + 1];
+        for k in (0..degree + 1).rev() {
+            let f = rem[k + e].clone();
+            for (j, a) in err_poly.iter().enumerate() {
+                rem[k + j] = Scalar::sub_mod(&rem[k + j], &Scalar::mul_mod(&f, a, q), q);
+            }
+            poly[k] = f;
+        }
+        if rem.iter().any(|a| a.rep() != 0) {
+            return None;
+        }
+        Some(poly)
+    }
+
+    /// a solution of the linear system with augmented matrix `rows`, with the free unknowns set
+    /// to 0, by gaussian elimination. None if there is none
+    fn solve(&self, mut rows: Vec<Vec<Scalar>>) -> Option<Vec<Scalar>> {
+        let q = &self.modulus;
+        let m = rows[0].len() - 1;
+        let mut pivots = Vec::with_capacity(m);
+        for c in 0..m {
+            let r = pivots.len();
+            let p = match (r..rows.len()).find(|&i| rows[i][c].rep() != 0) {
+                Some(p) => p,
+                None => continue,
+            };
+            rows.swap(r, p);
+            let inv = Scalar::inv_mod(&rows[r][c], q);
+            let pivot_row: Vec<Scalar> = rows[r]
+                .iter()
+                .map(|a| Scalar::mul_mod(a, &inv, q))
+                .collect();
+            for (i, row) in rows.iter_mut().enumerate() {
+                if i != r && row[c].rep() != 0 {
+                    let f = row[c].clone();
+                    for k in c..m + 1 {
+                        row[k] =
+                            Scalar::sub_mod(&row[k], &Scalar::mul_mod(&f, &pivot_row[k], q), q);
+                    }
+                }
+            }
+            rows[r] = pivot_row;
+            pivots.push(c);
+        }
+        if rows[pivots.len()..].iter().any(|row| row[m].rep() != 0) {
+            return None;
+        }
+        let mut sol = vec![Scalar::zero(); m];
+        for (r, c) in pivots.into_iter().enumerate() {
+            sol[c] = rows[r][m].clone();
+        }
+        Some(sol)
+    }
 }
 
 pub struct NTTContext {
@@ -130,13 +417,14 @@ pub struct NTTContext {
     pub invroots_u64: Vec<u64>,
     pub scaled_invroots_u64: Vec<u64>,
     pub ninv: Scalar,
+    pub num_dimension: usize,
 }
 
 impl NTTContext {
-    pub fn init(prime: u64) -> Self {
+    pub fn init(prime: u64, num_dimension: usize) -> Self {
         let modulus = Scalar::new_modulus(prime);
         let q = &modulus;
-        let context = RqPolyContext::new(NUM_DIMENSION, q);
+        let context = RqPolyContext::new(num_dimension, q);
         let roots_u64 = context.roots.iter().map(|elm| elm.rep()).collect();
         let scaledroots_u64 = context.scaled_roots.iter().map(|elm| elm.rep()).collect();
         let invroots_u64 = context.invroots.iter().map(|elm| elm.rep()).collect();
@@ -145,7 +433,7 @@ impl NTTContext {
             .iter()
             .map(|elm| elm.rep())
             .collect();
-        let ninv = Scalar::inv_mod(&Scalar::from_u32(NUM_DIMENSION as u32, q), q);
+        let ninv = Scalar::inv_mod(&Scalar::from_u32(num_dimension as u32, q), q);
         Self {
             modulus,
             roots_u64,
@@ -153,9 +441,19 @@ impl NTTContext {
             invroots_u64,
             scaled_invroots_u64,
             ninv,
+            num_dimension,
         }
     }
 
+    /// one context per RNS prime of `params`
+    pub fn init_all(params: &ParameterSet) -> Vec<Self> {
+        params
+            .rns_moduli
+            .iter()
+            .map(|q| Self::init(*q, params.num_dimension))
+            .collect()
+    }
+
     pub fn lazy_ntt_inplace(&self, a: &mut [u64]) {
         lazy_ntt_u64(
             a,
@@ -188,9 +486,9 @@ impl NTTContext {
     }
 
     pub fn poly_mul(&self, a: &[u64], b: &[u64]) -> Vec<u64> {
-        let mut aa = vec![0u64; NUM_DIMENSION];
+        let mut aa = vec![0u64; self.num_dimension];
         aa.clone_from_slice(a);
-        let mut bb = vec![0u64; NUM_DIMENSION];
+        let mut bb = vec![0u64; self.num_dimension];
         bb.clone_from_slice(b);
         self.lazy_ntt_inplace(&mut aa);
         self.lazy_ntt_inplace(&mut bb);
@@ -214,15 +512,134 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_reconstruct_with_subset() {
+        let context = ShamirContext::init(0xffffee001u64, 10, 4);
+        let ids = vec![9, 2, 5, 7, 0];
+        let coeffs = context.lagrange_coefficients(&ids);
+        for _ in 0..100 {
+            let secret = Scalar::sample_blw(&context.modulus).rep();
+            let shares = context.share(secret);
+            let subset: Vec<u64> = ids.iter().map(|&i| shares[i]).collect();
+            assert_eq!(context.reconstruct_with(&coeffs, &subset), secret);
+        }
+    }
+
+    #[test]
+    fn test_robust_reconstruct() {
+        let context = ShamirContext::init(0xffffee001u64, 10, 3);
+        let ids = vec![0, 1, 2, 3, 4, 5, 6, 7, 8, 9];
+        let mut rng = rand::rngs::StdRng::from_entropy();
+        // at degree 3, 10 shares correct up to 3 errors
+        for nr_wrong in 0..4 {
+            for _ in 0..20 {
+                let secret = Scalar::sample_blw(&context.modulus).rep();
+                let mut shares = context.share(secret);
+                let mut wrong: Vec<usize> = Vec::new();
+                while wrong.len() < nr_wrong {
+                    let i = rng.gen_range(0..10);
+                    if !wrong.contains(&i) {
+                        shares[i] = (shares[i] + rng.gen_range(1..context.modulus.rep()))
+                            % context.modulus.rep();
+                        wrong.push(i);
+                    }
+                }
+                wrong.sort();
+                let ret = context.robust_reconstruct(&ids, &shares, 3).unwrap();
+                assert_eq!(ret, Reconstruction { secret, wrong });
+            }
+        }
+        // a subset, in any order, and no redundancy
+        let secret = 42;
+        let shares = context.share(secret);
+        let subset = vec![8, 2, 5, 0, 6];
+        let mut y: Vec<u64> = subset.iter().map(|&i| shares[i]).collect();
+        y[1] += 1;
+        let ret = context.robust_reconstruct(&subset, &y, 3);
+        assert_eq!(ret, None);
+        let ret = context
+            .robust_reconstruct(&subset[..4], &y[..4], 3)
+            .unwrap();
+        assert_ne!(ret.secret, secret);
+        assert!(ret.wrong.is_empty());
+        y[1] -= 1;
+        let ret = context.robust_reconstruct(&subset, &y, 3).unwrap();
+        assert_eq!(ret.secret, secret);
+    }
+
+    #[test]
+    fn test_robust_reconstruct_with() {
+        let context = ShamirContext::init(0xffffee001u64, 10, 3);
+        let ids = vec![9, 3, 0, 7, 1, 5, 2, 8];
+        let decoder = context.robust_decoder(&ids, 3).unwrap();
+        let mut rng = rand::rngs::StdRng::from_entropy();
+        // the same as robust_reconstruct, up to 2 errors and beyond
+        for nr_wrong in 0..4 {
+            for _ in 0..20 {
+                let secret = Scalar::sample_blw(&context.modulus).rep();
+                let shares = context.share(secret);
+                let mut y: Vec<u64> = ids.iter().map(|&i| shares[i]).collect();
+                for i in 0..nr_wrong {
+                    let k = (i * 3 + 1) % ids.len();
+                    y[k] = (y[k] + rng.gen_range(1..context.modulus.rep())) % context.modulus.rep();
+                }
+                let ret = context.robust_reconstruct_with(&decoder, &y);
+                assert_eq!(ret, context.robust_reconstruct(&ids, &y, 3));
+                if nr_wrong < 3 {
+                    assert_eq!(ret.unwrap().secret, secret);
+                }
+            }
+        }
+        assert!(context.robust_decoder(&ids[..3], 3).is_none());
+    }
+
+    #[test]
+    fn test_robust_reconstruct_too_many_errors() {
+        let context = ShamirContext::init(0xffffee001u64, 7, 2);
+        let ids: Vec<usize> = (0..7).collect();
+        // at degree 2, 7 shares correct up to 2 errors, not 3
+        let shares = context.share(7);
+        let mut y = context.share(11);
+        y[..4].copy_from_slice(&shares[..4]);
+        assert_eq!(context.robust_reconstruct(&ids, &y, 2), None);
+        // a product of two sharings is at twice the degree
+        let a = context.share(3);
+        let b = context.share(5);
+        let q = context.modulus.rep() as u128;
+        let mut ab: Vec<u64> = (0..7)
+            .map(|i| (a[i] as u128 * b[i] as u128 % q) as u64)
+            .collect();
+        ab[3] = 0;
+        let ret = context.robust_reconstruct(&ids, &ab, 4).unwrap();
+        assert_eq!(ret.secret, 15);
+    }
+
     #[test]
     fn test_ntt_inv_ntt() {
-        let context = NTTContext::init(0xffffee001u64);
-        let mut a = vec![1u64; 4096];
-        let mut b = vec![0u64; 4096];
+        let params = ParameterSet::default();
+        let context = NTTContext::init(params.rns_moduli[0], params.num_dimension);
+        let mut a = vec![1u64; params.num_dimension];
+        let mut b = vec![0u64; params.num_dimension];
         b[0] = 1;
         context.lazy_ntt_inplace(&mut a);
         context.lazy_ntt_inplace(&mut b);
         let mut ntt_c = context.coeff_mul_mod(&a, &b);
         context.lazy_inverse_ntt_inplace(&mut ntt_c);
     }
+
+    #[test]
+    fn test_poly_mul_presets() {
+        for n in [1024, 2048, 4096, 8192].iter() {
+            let params = ParameterSet::with_dimension(*n);
+            for context in NTTContext::init_all(&params) {
+                let a: Vec<u64> = (0..*n as u64).collect();
+                let mut x = vec![0u64; *n];
+                x[1] = 1;
+                // a * x is a rotated negacyclically
+                let c = context.poly_mul(&a, &x);
+                assert_eq!(c[0], context.modulus.rep() - a[*n - 1]);
+                assert_eq!(&c[1..], &a[..*n - 1]);
+            }
+        }
+    }
 }
@@ -0,0 +1,121 @@
+use crate::common::params::ParameterSet;
+
+/// how a gradient is clipped before it is quantized
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Clipping {
+    /// scale the whole vector down to an L2 norm of at most the bound
+    L2(f32),
+    /// clamp every coordinate to [-bound, bound]
+    LInf(f32),
+}
+
+/// Fixed-point encoding of real-valued gradients into plaintext coefficients of `bits` bits:
+/// x is clipped, scaled to round(x * 2^frac_bits), saturated to the signed range of `bits` bits
+/// and then shifted by 2^(bits - 1) to be unsigned, as the circuits range-check m in [0, 2^bits).
+#[derive(Debug, Clone)]
+pub struct Encoder {
+    pub bits: usize,
+    pub frac_bits: usize,
+    pub clipping: Clipping,
+}
+
+impl Encoder {
+    /// the width of the coefficients is the one the circuits of `params` prove
+    pub fn new(params: &ParameterSet, frac_bits: usize, clipping: Clipping) -> Self {
+        assert!(
+            frac_bits < params.plaintext_bits,
+            "{} fractional bits leave no integer bit in {} bits",
+            frac_bits,
+            params.plaintext_bits
+        );
+        Self {
+            bits: params.plaintext_bits,
+            frac_bits,
+            clipping,
+        }
+    }
+
+    /// the offset turning the signed fixed-point numbers unsigned
+    pub fn offset(&self) -> i64 {
+        1i64 << (self.bits - 1)
+    }
+
+    pub fn clip(&self, xs: &[f32]) -> Vec<f32> {
+        match self.clipping {
+            Clipping::L2(bound) => {
+                let norm = xs.iter().map(|x| x * x).sum::<f32>().sqrt();
+                let ratio = if norm > bound { bound / norm } else { 1.0 };
+                xs.iter().map(|x| x * ratio).collect()
+            }
+            Clipping::LInf(bound) => xs.iter().map(|x| x.max(-bound).min(bound)).collect(),
+        }
+    }
+
+    pub fn encode(&self, xs: &[f32]) -> Vec<u16> {
+        let scale = (1u64 << self.frac_bits) as f32;
+        let offset = self.offset();
+        self.clip(xs)
+            .iter()
+            .map(|x| {
+                let v = (x * scale).round() as i64;
+                (v.max(-offset).min(offset - 1) + offset) as u16
+            })
+            .collect()
+    }
+
+    /// decode the coefficient-wise sum of `nr_summands` encodings, e.g. the aggregated plaintext
+    /// the committee recovers, to the sum of the clipped gradients
+    pub fn decode(&self, sum: &[i128], nr_summands: usize) -> Vec<f32> {
+        let scale = (1u64 << self.frac_bits) as f64;
+        let offset = self.offset() as i128 * nr_summands as i128;
+        sum.iter()
+            .map(|x| ((x - offset) as f64 / scale) as f32)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_decode_sum() {
+        let params = ParameterSet::with_dimension(1024).with_plaintext_bits(12);
+        let encoder = Encoder::new(&params, 6, Clipping::LInf(4.0));
+        let xs: Vec<Vec<f32>> = (0..5)
+            .map(|k| {
+                (0..16)
+                    .map(|i| (i as f32 - 8.0) * 0.25 + k as f32 * 0.5)
+                    .collect()
+            })
+            .collect();
+        let mut sum = vec![0i128; 16];
+        for x in xs.iter() {
+            for (s, m) in sum.iter_mut().zip(encoder.encode(x).iter()) {
+                assert!((*m as u32) < (1 << params.plaintext_bits));
+                *s += *m as i128;
+            }
+        }
+        let decoded = encoder.decode(&sum, xs.len());
+        for i in 0..16 {
+            let expected: f32 = xs.iter().map(|x| x[i].max(-4.0).min(4.0)).sum();
+            assert!((decoded[i] - expected).abs() < 1e-3, "{}", i);
+        }
+    }
+
+    #[test]
+    fn test_clipping() {
+        let params = ParameterSet::with_dimension(1024);
+        let encoder = Encoder::new(&params, 4, Clipping::L2(1.0));
+        let clipped = encoder.clip(&[3.0, 4.0]);
+        assert!((clipped[0] - 0.6).abs() < 1e-6 && (clipped[1] - 0.8).abs() < 1e-6);
+        assert_eq!(encoder.clip(&[0.3, 0.4]), vec![0.3, 0.4]);
+
+        // 8 bits with 4 fractional bits hold [-8, 8 - 1/16], the rest saturates
+        let encoder = Encoder::new(&params, 4, Clipping::LInf(100.0));
+        assert_eq!(
+            encoder.encode(&[-100.0, -8.0, 0.0, 1.5, 100.0]),
+            vec![0, 0, 128, 152, 255]
+        );
+    }
+}
@@ -0,0 +1,295 @@
+use super::context::NTTContext;
+use super::reshare::{div_rem, NewShare, ReshareContext, Subshare};
+use super::{rand_poly, PublicKey};
+use crate::common::params::ParameterSet;
+use curve25519_dalek::ristretto::RistrettoPoint;
+use curve25519_dalek::traits::Identity;
+use num_bigint::BigUint;
+use rand::{Rng, SeedableRng};
+
+/// Generation of the RLWE key pair by the committee, without a dealer. pk_0 = a is sampled from
+/// a seed all players agree on. Player i samples a ternary s_i and a gaussian e_i, publishes
+/// b_i = a * s_i + e_i and deals s_i in NTT form per RNS prime as a resharing does, with
+/// Pedersen commitments to its polynomials, so every player checks the subshares dealt to it.
+/// The secret key is s = sum s_i over the dealers that count, of which a player holds the sum of
+/// the subshares dealt to it, and pk_1 = sum b_i = a * s + sum e_i. The sums of the commitments
+/// commit to the shares, which the first resharing checks. A player commits to b_i with `commit`
+/// before any b_i is revealed, so none picks its b_i from those of the others, and the players
+/// check they got the same public key by its `digest` before publishing it. Otherwise the players
+/// are trusted to follow the protocol: nothing checks the b_i against the shares.
+pub struct KeyGenContext {
+    pub params: ParameterSet,
+    pub share_cnt: usize,
+    pub threshold: usize,
+    /// pk_0, with coefficients in [0, q)
+    pub a: Vec<i128>,
+    ntt_context: Vec<NTTContext>,
+    reshare_context: ReshareContext,
+    // a in NTT form per RNS prime
+    ntt_a: Vec<Vec<u64>>,
+}
+
+impl KeyGenContext {
+    pub fn new(params: &ParameterSet, share_cnt: usize, threshold: usize, seed: [u8; 32]) -> Self {
+        let mut rng = rand::rngs::StdRng::from_seed(seed);
+        let a: Vec<i128> = (0..params.num_dimension)
+            .map(|_| rng.gen_range(0..params.modulus))
+            .collect();
+        let ntt_context = NTTContext::init_all(params);
+        let ntt_a = ntt_context
+            .iter()
+            .map(|context| {
+                let p = context.modulus.rep() as i128;
+                let mut x: Vec<u64> = a.iter().map(|y| y.rem_euclid(p) as u64).collect();
+                context.lazy_ntt_inplace(&mut x);
+                x
+            })
+            .collect();
+        Self {
+            params: params.clone(),
+            share_cnt,
+            threshold,
+            a,
+            ntt_context,
+            reshare_context: ReshareContext::new(params, share_cnt, threshold),
+            ntt_a,
+        }
+    }
+
+    /// the contribution of a player with a fresh secret and error, see `contribute_with`
+    pub fn contribute(&self) -> (Vec<Vec<Subshare>>, Vec<Vec<RistrettoPoint>>, Vec<Vec<u64>>) {
+        let s = rand_poly::sample_ternary(&self.params);
+        let e = rand_poly::sample_gaussian(&self.params);
+        self.contribute_with(&s, &e)
+    }
+
+    /// the contribution of a player with secret `s` and error `e`: the subshares of s dealt to
+    /// player j in ret.0[j], the commitments to the polynomials in ret.1, as by
+    /// `ReshareContext::deal`, and b = a * s + e in ret.2, one vector per RNS prime
+    pub fn contribute_with(
+        &self,
+        s: &[i128],
+        e: &[i128],
+    ) -> (Vec<Vec<Subshare>>, Vec<Vec<RistrettoPoint>>, Vec<Vec<u64>>) {
+        let nr_moduli = self.params.rns_moduli.len();
+        let mut ntt_s = Vec::with_capacity(nr_moduli);
+        let mut b = Vec::with_capacity(nr_moduli);
+        for (context, ntt_a) in self.ntt_context.iter().zip(self.ntt_a.iter()) {
+            let p = context.modulus.rep() as i128;
+            let mut x: Vec<u64> = s.iter().map(|x| x.rem_euclid(p) as u64).collect();
+            context.lazy_ntt_inplace(&mut x);
+
+            let mut y = context.coeff_mul_mod(ntt_a, &x);
+            context.lazy_inverse_ntt_inplace(&mut y);
+            b.push(
+                y.iter()
+                    .zip(e.iter())
+                    .map(|(y, z)| (*y as i128 + z).rem_euclid(p) as u64)
+                    .collect(),
+            );
+            ntt_s.push(x);
+        }
+        let (subshares, commitments) = self.reshare_context.deal(&ntt_s, None);
+        (subshares, commitments, b)
+    }
+
+    /// whether the subshares dealt to `player` open the `commitments` of their dealer, for
+    /// every residue of the secret key
+    pub fn verify(
+        &self,
+        player: usize,
+        subshares: &[Subshare],
+        commitments: &[Vec<RistrettoPoint>],
+    ) -> bool {
+        subshares.len() == self.params.num_dimension * self.params.rns_moduli.len()
+            && self.reshare_context.verify(player, subshares, commitments)
+    }
+
+    /// The share of the secret key of a player, from the subshares dealt to it by the dealers
+    /// that count, with the opening of the sums of their commitments, see `combine_commitments`.
+    /// The share is the sum of the subshares, not their interpolation as in a resharing.
+    pub fn combine(&self, subshares: &[Vec<Subshare>]) -> NewShare {
+        let n = self.params.num_dimension;
+        let nr_residues = n * self.params.rns_moduli.len();
+        let mut ret = NewShare {
+            share: Vec::with_capacity(self.params.rns_moduli.len()),
+            blinding: Vec::with_capacity(nr_residues),
+            quotients: Vec::with_capacity(nr_residues),
+        };
+        for (k, p) in self.params.rns_moduli.iter().enumerate() {
+            let p = BigUint::from(*p);
+            let mut share = Vec::with_capacity(n);
+            for i in k * n..(k + 1) * n {
+                // the sum of the subshares over the integers is the share plus a multiple of p
+                let sum: BigUint = subshares
+                    .iter()
+                    .map(|x| BigUint::from_bytes_le(x[i].value.as_bytes()))
+                    .sum();
+                let (residue, quotient) = div_rem(&sum, &p);
+                share.push(residue);
+                ret.quotients.push(quotient);
+                ret.blinding
+                    .push(subshares.iter().map(|x| x[i].blinding).sum());
+            }
+            ret.share.push(share);
+        }
+        ret
+    }
+
+    /// the commitments to the polynomials the shares of `combine` are on, the sums of the
+    /// commitments of the dealers that count, taken one at a time
+    pub fn combine_commitments<I>(&self, commitments: I) -> Vec<Vec<RistrettoPoint>>
+    where
+        I: IntoIterator<Item = Vec<Vec<RistrettoPoint>>>,
+    {
+        let nr_residues = self.params.num_dimension * self.params.rns_moduli.len();
+        let mut ret = vec![vec![RistrettoPoint::identity(); self.threshold + 1]; nr_residues];
+        for c in commitments {
+            for (acc, c) in ret.iter_mut().zip(c.iter()) {
+                for (x, y) in acc.iter_mut().zip(c.iter()) {
+                    *x += y;
+                }
+            }
+        }
+        ret
+    }
+
+    /// the public key (a, sum b_i) from the b_i of the dealers that count
+    pub fn public_key(&self, b: &[Vec<Vec<u64>>]) -> PublicKey {
+        let b: Vec<Vec<u64>> = self
+            .params
+            .rns_moduli
+            .iter()
+            .enumerate()
+            .map(|(k, p)| {
+                (0..self.params.num_dimension)
+                    .map(|i| b.iter().fold(0u64, |acc, x| (acc + x[k][i]) % p))
+                    .collect()
+            })
+            .collect();
+        let pk_1: Vec<i128> = (0..self.params.num_dimension)
+            .map(|i| {
+                let residues: Vec<u64> = b.iter().map(|x| x[i]).collect();
+                self.params.crt(&residues)
+            })
+            .collect();
+        PublicKey::new(&self.params, &self.a, &pk_1)
+    }
+}
+
+/// the commitment of a player to its b, one vector per RNS prime
+pub fn commit(b: &[Vec<u64>]) -> [u8; 32] {
+    let mut hasher = blake3::Hasher::new();
+    for x in b.iter().flatten() {
+        hasher.update(&x.to_le_bytes());
+    }
+    hasher.finalize().into()
+}
+
+/// the hash of a public key the players compare before publishing it
+pub fn digest(pk: &PublicKey) -> [u8; 32] {
+    let mut hasher = blake3::Hasher::new();
+    for x in pk.pk_0.iter().chain(pk.pk_1.iter()) {
+        hasher.update(&x.to_le_bytes());
+    }
+    hasher.finalize().into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rlwe::aggregate::Aggregate;
+    use crate::rlwe::context::ShamirContext;
+    use crate::rlwe::tests::negacyclic_mul;
+
+    #[test]
+    fn test_keygen_then_decrypt() {
+        let params = ParameterSet::with_dimension(1024);
+        let n = params.num_dimension;
+        let q = params.modulus;
+        let (nr_players, threshold) = (5, 2);
+        let mut rng = rand::rngs::StdRng::from_entropy();
+        let keygen_context = KeyGenContext::new(&params, nr_players, threshold, [7u8; 32]);
+        assert_eq!(
+            keygen_context.a,
+            KeyGenContext::new(&params, nr_players, threshold, [7u8; 32]).a
+        );
+
+        // every player contributes, with the secrets kept to check the result
+        let s_i: Vec<Vec<i128>> = (0..nr_players)
+            .map(|_| (0..n).map(|_| rng.gen_range(-1i128..2)).collect())
+            .collect();
+        let e_i: Vec<Vec<i128>> = (0..nr_players)
+            .map(|_| (0..n).map(|_| rng.gen_range(-4i128..5)).collect())
+            .collect();
+        let contributions: Vec<_> = s_i
+            .iter()
+            .zip(e_i.iter())
+            .map(|(s, e)| keygen_context.contribute_with(s, e))
+            .collect();
+        for (j, x) in contributions.iter().enumerate() {
+            assert!(keygen_context.verify((j + 1) % nr_players, &x.0[(j + 1) % nr_players], &x.1));
+            assert!(!keygen_context.verify(j, &x.0[(j + 1) % nr_players], &x.1));
+        }
+        let shares: Vec<NewShare> = (0..nr_players)
+            .map(|j| {
+                let dealt: Vec<Vec<Subshare>> =
+                    contributions.iter().map(|x| x.0[j].clone()).collect();
+                keygen_context.combine(&dealt)
+            })
+            .collect();
+        let sk_shares: Vec<Vec<Vec<u64>>> = shares.iter().map(|x| x.share.clone()).collect();
+        let b: Vec<Vec<Vec<u64>>> = contributions.iter().map(|x| x.2.clone()).collect();
+
+        // the first resharing checks the shares against the sums of the commitments
+        let reshare_context = ReshareContext::new(&params, nr_players, threshold);
+        let commitments =
+            keygen_context.combine_commitments(contributions.iter().map(|x| x.1.clone()));
+        let (_, c) = reshare_context.deal(&shares[1].share, Some(&shares[1].blinding));
+        assert!(reshare_context.verify_old_share(1, &c, &commitments, &shares[1].quotients));
+        assert!(!reshare_context.verify_old_share(2, &c, &commitments, &shares[1].quotients));
+        let pk = keygen_context.public_key(&b);
+        assert_ne!(commit(&b[0]), commit(&b[1]));
+        assert_eq!(digest(&pk), digest(&keygen_context.public_key(&b)));
+
+        // pk_1 = a * s + e for s and e the sums of the contributions
+        let s: Vec<i128> = (0..n).map(|i| s_i.iter().map(|x| x[i]).sum()).collect();
+        let e: Vec<i128> = (0..n).map(|i| e_i.iter().map(|x| x[i]).sum()).collect();
+        let pk_1: Vec<i128> = negacyclic_mul(&pk.pk_0, &s)
+            .iter()
+            .zip(e.iter())
+            .map(|(x, y)| (x + y).rem_euclid(q))
+            .collect();
+        assert_eq!(pk.pk_1, pk_1);
+
+        let m: Vec<u16> = (0..n).map(|_| rng.gen_range(0..256)).collect();
+        let (_, _, _, _, _, ct) = pk.encrypt(&m);
+        let mut aggregate = Aggregate::new(&params, 1);
+        aggregate.add(&[ct.c_0], &[ct.c_1]);
+
+        // any t + 1 of the shares decrypt, without the noise of the committee
+        let contexts = NTTContext::init_all(&params);
+        let shamir_context = ShamirContext::init_all(&params, nr_players, threshold);
+        let ids = vec![3, 0, 4];
+        let ntt_c_0 = aggregate.ntt_c_0();
+        let c_0_sk: Vec<Vec<u64>> = (0..params.rns_moduli.len())
+            .map(|k| {
+                let partial: Vec<Vec<u64>> = ids
+                    .iter()
+                    .map(|j| contexts[k].coeff_mul_mod(&sk_shares[*j][k], &ntt_c_0[k]))
+                    .collect();
+                let coeffs = shamir_context[k].lagrange_coefficients(&ids);
+                let mut x: Vec<u64> = (0..n)
+                    .map(|i| {
+                        let y: Vec<u64> = partial.iter().map(|x| x[i]).collect();
+                        shamir_context[k].reconstruct_with(&coeffs, &y)
+                    })
+                    .collect();
+                contexts[k].lazy_inverse_ntt_inplace(&mut x);
+                x
+            })
+            .collect();
+        let expected: Vec<i128> = m.iter().map(|x| *x as i128).collect();
+        assert_eq!(aggregate.decrypt(&c_0_sk), expected);
+    }
+}
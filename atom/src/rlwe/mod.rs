@@ -1,17 +1,41 @@
+use crate::common::params::ParameterSet;
+use std::{
+    fs::File,
+    io::{prelude::*, BufReader},
+};
 use tracing::error;
 
-pub const NUM_DIMENSION: usize = 4096;
-pub const MODULUS: i128 = 649033470896967801447398927572993i128;
-
+pub mod aggregate;
 pub mod context;
+pub mod encoding;
+pub mod keygen;
 pub mod rand_poly;
+pub mod reshare;
+
+use context::NTTContext;
+use cupcake::integer_arith::scalar::Scalar;
+use cupcake::integer_arith::ArithUtils;
+
+/// An NTT-friendly prime (= 1 mod 16384) outside the RNS primes of every parameter set.
+/// pk * r + e is also computed mod this prime, so the quotient delta can be recovered
+/// from the result mod q without the exact 122-bit integers.
+pub const AUX_MODULUS: u64 = 0xffff00001u64;
+
+/// pk_0 and pk_1 are polynomials in Z[x]/(x^n + 1) with coefficients in [0, q),
+/// kept in NTT form for each RNS prime and the auxiliary prime, so that
+/// pk * r = [c0, c1, ..., c4095] is computed in O(n log n) instead of by the negacyclic matrix
 /// [p0, -p4095, ..., -p1]
-/// [p1, p0, -p4095, ..., -p2] * [r0 r1 ... r4095] = [c0, c1, ..., c4095]
+/// [p1, p0, -p4095, ..., -p2]
 ///        ...
 /// [p4095, p4094, ..., p0]
 pub struct PublicKey {
     pub pk_0: Vec<i128>,
     pub pk_1: Vec<i128>,
+    pub params: ParameterSet,
+    // one context per RNS prime, then the one of AUX_MODULUS
+    ntt_context: Vec<NTTContext>,
+    ntt_pk_0: Vec<Vec<u64>>,
+    ntt_pk_1: Vec<Vec<u64>>,
 }
 
 pub struct Ciphertext {
@@ -20,63 +44,122 @@ pub struct Ciphertext {
 }
 
 impl PublicKey {
-    pub fn new(opk0: &Vec<i128>, opk1: &Vec<i128>) -> Self {
-        if opk0.len() < NUM_DIMENSION || opk1.len() < NUM_DIMENSION {
-            error!("Not enough elements when creating the public key");
+    pub fn new(params: &ParameterSet, opk0: &Vec<i128>, opk1: &Vec<i128>) -> Self {
+        let num_dimension = params.num_dimension;
+        if opk0.len() != num_dimension || opk1.len() != num_dimension {
+            error!("Wrong # of elements when creating the public key");
         }
+        assert!(
+            !params.rns_moduli.contains(&AUX_MODULUS),
+            "the auxiliary prime is one of the RNS primes"
+        );
 
-        let mut pk0 = opk0.clone();
-        let mut pk1 = opk1.clone();
-
-        let pk_0 = {
-            let mut tmp_pk = Vec::<i128>::with_capacity(NUM_DIMENSION * NUM_DIMENSION);
-            pk0.reverse();
-            pk0.rotate_right(1);
-            pk0.iter_mut().for_each(|x| *x = -*x);
-            pk0[0] = -pk0[0];
-            tmp_pk.extend(pk0.iter());
-            for _ in 0..NUM_DIMENSION - 1 {
-                pk0.rotate_right(1);
-                pk0[0] = -pk0[0];
-                tmp_pk.extend(pk0.iter());
-            }
-            tmp_pk
-        };
-        let pk_1 = {
-            let mut tmp_pk = Vec::<i128>::with_capacity(NUM_DIMENSION * NUM_DIMENSION);
-            pk1.reverse();
-            pk1.rotate_right(1);
-            pk1.iter_mut().for_each(|x| *x = -*x);
-            pk1[0] = -pk1[0];
-            tmp_pk.extend(pk1.iter());
-            for _ in 0..NUM_DIMENSION - 1 {
-                pk1.rotate_right(1);
-                pk1[0] = -pk1[0];
-                tmp_pk.extend(pk1.iter());
-            }
-            tmp_pk
+        let ntt_context: Vec<NTTContext> = params
+            .rns_moduli
+            .iter()
+            .chain(std::iter::once(&AUX_MODULUS))
+            .map(|q| NTTContext::init(*q, num_dimension))
+            .collect();
+        let to_ntt = |pk: &Vec<i128>| -> Vec<Vec<u64>> {
+            ntt_context
+                .iter()
+                .map(|context| {
+                    let p = context.modulus.rep() as i128;
+                    let mut a: Vec<u64> = pk.iter().map(|x| x.rem_euclid(p) as u64).collect();
+                    context.lazy_ntt_inplace(&mut a);
+                    a
+                })
+                .collect()
         };
+        let ntt_pk_0 = to_ntt(opk0);
+        let ntt_pk_1 = to_ntt(opk1);
 
-        PublicKey { pk_0, pk_1 }
+        PublicKey {
+            pk_0: opk0.clone(),
+            pk_1: opk1.clone(),
+            params: params.clone(),
+            ntt_context,
+            ntt_pk_0,
+            ntt_pk_1,
+        }
     }
 
-    pub fn matrix_mut(mat: &Vec<i128>, v: &Vec<i128>) -> Vec<i128> {
-        let mut ret = Vec::<i128>::with_capacity(NUM_DIMENSION);
-        for i in 0..NUM_DIMENSION {
-            let mut s: i128 = 0;
-            for j in 0..NUM_DIMENSION {
-                //s = s + v[i] * mat[i * NUM_DIMENSION + j];
-                s = s + v[j] * mat[i * NUM_DIMENSION + j];
+    /// read pk_0 and pk_1 from the lines starting with "pk_0" and "pk_1" in `enc_path`
+    pub fn from_file(params: &ParameterSet, enc_path: &str) -> Self {
+        let mut pk_0 = vec![0i128; params.num_dimension];
+        let mut pk_1 = vec![0i128; params.num_dimension];
+        let reader = BufReader::new(File::open(enc_path).unwrap());
+        for l in reader.lines().flatten() {
+            let pk = if l.contains("pk_0") {
+                &mut pk_0
+            } else if l.contains("pk_1") {
+                &mut pk_1
+            } else {
+                continue;
+            };
+            for (x, y) in l.split(" ").skip(1).zip(pk.iter_mut()) {
+                if let Ok(x) = i128::from_str_radix(x, 10) {
+                    *y = x;
+                }
             }
-            ret.push(s);
         }
-        ret
+        Self::new(params, &pk_0, &pk_1)
     }
-    // TODO maybe accelerate the matrix multiplication here
-    /// message will be consumed
+
+    /// pk * r + e as an integer polynomial, returned as (pk * r + e mod q, (pk * r + e) / q).
+    /// Each RNS prime gives pk * r + e mod q by CRT, and the auxiliary prime p gives
+    /// delta = ((pk * r + e) - (pk * r + e mod q)) / q mod p, which is exact as |delta| <= 2n < p / 2.
+    fn mul_add(
+        &self,
+        ntt_r: &[Vec<u64>],
+        ntt_pk: &[Vec<u64>],
+        e: &[i128],
+    ) -> (Vec<i128>, Vec<i128>) {
+        let nr_moduli = self.params.rns_moduli.len();
+        let residues: Vec<Vec<u64>> = self
+            .ntt_context
+            .iter()
+            .enumerate()
+            .map(|(k, context)| {
+                let p = &context.modulus;
+                let mut a = context.coeff_mul_mod(&ntt_pk[k], &ntt_r[k]);
+                context.lazy_inverse_ntt_inplace(&mut a);
+                a.iter()
+                    .zip(e.iter())
+                    .map(|(x, y)| {
+                        let y = Scalar::from(y.rem_euclid(p.rep() as i128) as u64);
+                        Scalar::add_mod(&Scalar::from(*x), &y, p).rep()
+                    })
+                    .collect()
+            })
+            .collect();
+
+        let aux = &self.ntt_context[nr_moduli].modulus;
+        let q_aux = Scalar::from(self.params.modulus.rem_euclid(aux.rep() as i128) as u64);
+        let q_inv = Scalar::inv_mod(&q_aux, aux);
+        let half = (aux.rep() / 2) as i128;
+        (0..self.params.num_dimension)
+            .map(|i| {
+                let rns: Vec<u64> = (0..nr_moduli).map(|k| residues[k][i]).collect();
+                let c = self.params.crt(&rns);
+                let c_aux = Scalar::from(c.rem_euclid(aux.rep() as i128) as u64);
+                let diff = Scalar::sub_mod(&Scalar::from(residues[nr_moduli][i]), &c_aux, aux);
+                let delta = Scalar::mul_mod(&diff, &q_inv, aux).rep() as i128;
+                let delta = if delta > half {
+                    delta - aux.rep() as i128
+                } else {
+                    delta
+                };
+                (c, delta)
+            })
+            .unzip()
+    }
+
+    /// encrypt the plaintext coefficients `m`, see `encoding::Encoder` to get them from gradients:
+    /// c_0 = pk_0 * r + e_0 and c_1 = pk_1 * r + e_1 + Δ * m, Δ being `plaintext_scale`
     pub fn encrypt(
         &self,
-        m: &[u8],
+        m: &[u16],
     ) -> (
         Vec<i128>,
         Vec<i128>,
@@ -85,42 +168,51 @@ impl PublicKey {
         Vec<i128>,
         Ciphertext,
     ) {
-        let r = rand_poly::sample_ternary();
-        let e0 = rand_poly::sample_gaussian();
-        let e1 = rand_poly::sample_gaussian();
-        // 109-bit * 4096 * 2 = 122 bit
-        let mut pkr0 = PublicKey::matrix_mut(&self.pk_0, &r);
-        let mut pkr1 = PublicKey::matrix_mut(&self.pk_1, &r);
-        for i in 0..NUM_DIMENSION {
-            pkr0[i] += e0[i];
-            pkr1[i] += e1[i] + m[i] as i128;
-        }
-        let delta_0 = pkr0.iter().map(|x| x.div_euclid(MODULUS)).collect();
-        let delta_1 = pkr1.iter().map(|x| x.div_euclid(MODULUS)).collect();
-        pkr0.iter_mut().for_each(|x| *x = x.rem_euclid(MODULUS));
-        pkr1.iter_mut().for_each(|x| *x = x.rem_euclid(MODULUS));
-        (
-            r,
-            e0,
-            e1,
-            delta_0,
-            delta_1,
-            Ciphertext {
-                c_0: pkr0,
-                c_1: pkr1,
-            },
-        )
+        let r = rand_poly::sample_ternary(&self.params);
+        let e0 = rand_poly::sample_gaussian(&self.params);
+        let e1 = rand_poly::sample_gaussian(&self.params);
+        let ntt_r: Vec<Vec<u64>> = self
+            .ntt_context
+            .iter()
+            .map(|context| {
+                let p = context.modulus.rep() as i128;
+                let mut a: Vec<u64> = r.iter().map(|x| x.rem_euclid(p) as u64).collect();
+                context.lazy_ntt_inplace(&mut a);
+                a
+            })
+            .collect();
+        let scale = self.params.plaintext_scale();
+        let e1m: Vec<i128> = e1
+            .iter()
+            .zip(m.iter())
+            .map(|(e, m)| e + scale * *m as i128)
+            .collect();
+        let (c_0, delta_0) = self.mul_add(&ntt_r, &self.ntt_pk_0, &e0);
+        let (c_1, delta_1) = self.mul_add(&ntt_r, &self.ntt_pk_1, &e1m);
+        (r, e0, e1, delta_0, delta_1, Ciphertext { c_0, c_1 })
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::{
-        fs::File,
-        io::{prelude::*, BufReader},
-        time::Instant,
-    };
+    use std::time::Instant;
+    /// the negacyclic product a * b over the integers, by the schoolbook method
+    pub(crate) fn negacyclic_mul(a: &[i128], b: &[i128]) -> Vec<i128> {
+        let n = a.len();
+        let mut ret = vec![0i128; n];
+        for i in 0..n {
+            for j in 0..n {
+                if i + j < n {
+                    ret[i + j] += a[i] * b[j];
+                } else {
+                    ret[i + j - n] -= a[i] * b[j];
+                }
+            }
+        }
+        ret
+    }
+
     fn encrypt_internal(
         pk: &PublicKey,
         m: Vec<i128>,
@@ -130,109 +222,95 @@ mod tests {
         d0: Vec<i128>,
         d1: Vec<i128>,
     ) -> Ciphertext {
+        let q = pk.params.modulus;
         // 109-bit * 4096 * 2 = 122 bit
-        let mut pkr0 = PublicKey::matrix_mut(pk.pk_0.as_ref(), &r);
-        let mut pkr1 = PublicKey::matrix_mut(pk.pk_1.as_ref(), &r);
-        for i in 0..NUM_DIMENSION {
+        let mut pkr0 = negacyclic_mul(&pk.pk_0, &r);
+        let mut pkr1 = negacyclic_mul(&pk.pk_1, &r);
+        for i in 0..pk.params.num_dimension {
             pkr0[i] += e0[i];
-            pkr1[i] += e1[i] + m[i];
+            pkr1[i] += e1[i] + pk.params.plaintext_scale() * m[i];
         }
-        let delta_0: Vec<i32> = pkr0.iter().map(|x| x.div_euclid(MODULUS) as i32).collect();
-        let delta_1: Vec<i32> = pkr1.iter().map(|x| x.div_euclid(MODULUS) as i32).collect();
+        let delta_0: Vec<i32> = pkr0.iter().map(|x| x.div_euclid(q) as i32).collect();
+        let delta_1: Vec<i32> = pkr1.iter().map(|x| x.div_euclid(q) as i32).collect();
 
-        for i in 0..NUM_DIMENSION {
+        for i in 0..pk.params.num_dimension {
             assert_eq!(delta_0[i], d0[i] as i32, "different {}", i);
             assert_eq!(delta_1[i], d1[i] as i32, "different {}", i);
         }
 
-        pkr0.iter_mut().for_each(|x| *x = x.rem_euclid(MODULUS));
-        pkr1.iter_mut().for_each(|x| *x = x.rem_euclid(MODULUS));
+        pkr0.iter_mut().for_each(|x| *x = x.rem_euclid(q));
+        pkr1.iter_mut().for_each(|x| *x = x.rem_euclid(q));
         Ciphertext {
             c_0: pkr0.to_vec(),
             c_1: pkr1.to_vec(),
         }
     }
 
-    // run the following 2 tests with RUST_MIN_STACK=8388608 cargo test test_create_proof --release
+    #[test]
+    fn test_encrypt_exact_delta() {
+        use rand::Rng;
+        let mut rng = rand::thread_rng();
+        for n in [1024, 8192].iter() {
+            let params = ParameterSet::with_dimension(*n);
+            let pk0: Vec<i128> = (0..*n).map(|_| rng.gen_range(0..params.modulus)).collect();
+            let pk1: Vec<i128> = (0..*n).map(|_| rng.gen_range(0..params.modulus)).collect();
+            let pk = PublicKey::new(&params, &pk0, &pk1);
+            let m: Vec<u16> = (0..*n).map(|_| rng.gen_range(0..256)).collect();
+            let (r, e0, e1, d0, d1, ct) = pk.encrypt(&m);
+            let expected = encrypt_internal(
+                &pk,
+                m.iter().map(|x| *x as i128).collect(),
+                r,
+                e0,
+                e1,
+                d0,
+                d1,
+            );
+            assert_eq!(ct.c_0, expected.c_0);
+            assert_eq!(ct.c_1, expected.c_1);
+        }
+    }
+
     #[test]
     fn test_create_public_key() {
-        let mut c_0 = [0i128; 4096];
-        let mut c_1 = [0i128; 4096];
-        let mut r = [0i128; 4096];
-        let mut e_0 = [0i128; 4096];
-        let mut e_1 = [0i128; 4096];
-        let mut m = [0i128; 4096];
-        let mut pk_0 = [1i128; 4096];
-        let mut pk_1 = [0i128; 4096];
-        let mut delta_0 = [0i128; 4096];
-        let mut delta_1 = [0i128; 4096];
-        let file = match File::open("./data/encryption.txt") {
-            Ok(f) => f,
-            Err(_) => panic!(),
-        };
-        let reader = BufReader::new(file);
-        for line in reader.lines() {
-            if let Ok(l) = line {
-                let vec = l.split(" ").collect::<Vec<&str>>();
-                for i in 1..vec.len() {
-                    if l.contains("r") {
-                        if let Ok(x) = i128::from_str_radix(vec[i], 10) {
-                            r[i - 1] = x;
-                        }
-                    } else if l.contains("c_0") {
-                        if let Ok(x) = i128::from_str_radix(vec[i], 10) {
-                            c_0[i - 1] = x;
-                        }
-                    } else if l.contains("c_1") {
-                        if let Ok(x) = i128::from_str_radix(vec[i], 10) {
-                            c_1[i - 1] = x;
-                        }
-                    } else if l.contains("pk_0") {
-                        if let Ok(x) = i128::from_str_radix(vec[i], 10) {
-                            pk_0[i - 1] = x;
-                        }
-                    } else if l.contains("pk_1") {
-                        if let Ok(x) = i128::from_str_radix(vec[i], 10) {
-                            pk_1[i - 1] = x;
-                        }
-                    } else if l.contains("e_0") {
-                        if let Ok(x) = i128::from_str_radix(vec[i], 10) {
-                            e_0[i - 1] = x;
-                        }
-                    } else if l.contains("e_1") {
-                        if let Ok(x) = i128::from_str_radix(vec[i], 10) {
-                            e_1[i - 1] = x;
-                        }
-                    } else if l.contains("delta_0") {
-                        if let Ok(x) = i128::from_str_radix(vec[i], 10) {
-                            delta_0[i - 1] = x;
-                        }
-                    } else if l.contains("delta_1") {
-                        if let Ok(x) = i128::from_str_radix(vec[i], 10) {
-                            delta_1[i - 1] = x;
-                        }
-                    }
-                }
+        use rand::Rng;
+        let mut rng = rand::thread_rng();
+        let params = ParameterSet::default();
+        let n = params.num_dimension;
+        let pk_0: Vec<i128> = (0..n).map(|_| rng.gen_range(0..params.modulus)).collect();
+        let pk_1: Vec<i128> = (0..n).map(|_| rng.gen_range(0..params.modulus)).collect();
+
+        // ./data/encryption.txt as committee_keygen publishes it
+        let path = std::env::temp_dir().join(format!("encryption{}.txt", std::process::id()));
+        {
+            let mut f = File::create(&path).unwrap();
+            for (name, x) in [("pk_0", &pk_0), ("pk_1", &pk_1)].iter() {
+                let coeffs: Vec<String> = x.iter().map(|y| y.to_string()).collect();
+                writeln!(f, "{} {}", name, coeffs.join(" ")).unwrap();
             }
         }
-        let public_key = PublicKey::new(&pk_0.to_vec(), &pk_1.to_vec());
+        let public_key = PublicKey::from_file(&params, path.to_str().unwrap());
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(public_key.pk_0, pk_0);
+        assert_eq!(public_key.pk_1, pk_1);
+
+        let m: Vec<u16> = (0..n).map(|_| rng.gen_range(0..256)).collect();
         let start = Instant::now();
-        let ct = encrypt_internal(
-            &public_key,
-            m.to_vec(),
-            r.to_vec(),
-            e_0.to_vec(),
-            e_1.to_vec(),
-            delta_0.to_vec(),
-            delta_1.to_vec(),
-        );
+        let (r, e_0, e_1, delta_0, delta_1, ct) = public_key.encrypt(&m);
         let d = start.elapsed();
         let t = d.subsec_nanos() as f64 / 1_000_000_000f64 + (d.as_secs() as f64);
         println!("time to encrypt {} seconds", t);
 
-        for i in 0..NUM_DIMENSION {
-            assert_eq!(ct.c_0[i], c_0[i]);
-            assert_eq!(ct.c_1[i], c_1[i]);
-        }
+        let expected = encrypt_internal(
+            &public_key,
+            m.iter().map(|x| *x as i128).collect(),
+            r,
+            e_0,
+            e_1,
+            delta_0,
+            delta_1,
+        );
+        assert_eq!(ct.c_0, expected.c_0);
+        assert_eq!(ct.c_1, expected.c_1);
     }
 }
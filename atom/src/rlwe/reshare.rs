@@ -0,0 +1,418 @@
+use super::context::ShamirContext;
+use crate::common::params::ParameterSet;
+use curve25519_dalek::constants::RISTRETTO_BASEPOINT_TABLE;
+use curve25519_dalek::ristretto::{RistrettoBasepointTable, RistrettoPoint};
+use curve25519_dalek::scalar::Scalar as CurveScalar;
+use curve25519_dalek::traits::{Identity, VartimeMultiscalarMul};
+use num_bigint::BigUint;
+use rand::{Rng, SeedableRng};
+use tarpc::serde::{Deserialize, Serialize};
+
+/// log2 of the order of ristretto255, rounded down
+const GROUP_ORDER_BITS: f64 = 252.0;
+/// the random coefficients are HIDING_BITS bits wider than the secret, so that the integer
+/// subshares of t players are statistically independent of it
+pub const HIDING_BITS: u32 = 64;
+
+/// A subshare dealt to a player: the evaluation of the polynomial of the secret and of the
+/// blinding polynomial of the Pedersen commitments
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Subshare {
+    pub value: CurveScalar,
+    pub blinding: CurveScalar,
+}
+
+/// Resharing of a secret key shared per RNS prime, as by `ShamirContext::share`, to a new set of
+/// players. Each residue below the prime p is shared over the integers, with random coefficients
+/// in [0, p * 2^HIDING_BITS), and the dealer publishes Pedersen commitments to its polynomial, so
+/// neither the subshares of t players nor the commitments reveal the residue. The players
+/// evaluate at small points, so the evaluations never wrap around the group order: mod p, they
+/// are a sharing of the residue, and the new shares are reconstructed prime by prime.
+pub struct ReshareContext {
+    pub params: ParameterSet,
+    pub share_cnt: usize,
+    pub threshold: usize,
+    // eval_matrix[i][j] = (i + 1)^j, exactly
+    pub eval_matrix: Vec<Vec<CurveScalar>>,
+    // the second generator of the Pedersen commitments, whose discrete log nobody knows
+    h: RistrettoBasepointTable,
+    shamir_context: Vec<ShamirContext>,
+}
+
+/// H, hashed to the group from a fixed string
+fn pedersen_generator() -> RistrettoPoint {
+    let mut bytes = [0u8; 64];
+    blake3::Hasher::new()
+        .update(b"quail reshare pedersen generator")
+        .finalize_xof()
+        .fill(&mut bytes);
+    RistrettoPoint::from_uniform_bytes(&bytes)
+}
+
+/// uniform mod the group order
+fn random_scalar<R: Rng>(rng: &mut R) -> CurveScalar {
+    let mut bytes = [0u8; 64];
+    rng.fill(&mut bytes[..]);
+    CurveScalar::from_bytes_mod_order_wide(&bytes)
+}
+
+impl ReshareContext {
+    pub fn new(params: &ParameterSet, share_cnt: usize, threshold: usize) -> Self {
+        // the largest evaluation is below p * 2^HIDING_BITS * (t + 1) * share_cnt^t
+        let max_prime = *params.rns_moduli.iter().max().unwrap();
+        let bits = (64 - max_prime.leading_zeros()) as f64
+            + HIDING_BITS as f64
+            + ((threshold + 1) as f64).log2()
+            + threshold as f64 * (share_cnt as f64).log2();
+        assert!(
+            bits < GROUP_ORDER_BITS,
+            "the evaluations of {} players at threshold {} may wrap around the group order",
+            share_cnt,
+            threshold
+        );
+        let eval_matrix = (0..share_cnt)
+            .map(|i| {
+                let pt = CurveScalar::from((i + 1) as u64);
+                (0..threshold + 1)
+                    .scan(CurveScalar::one(), |x_pow, _| {
+                        let x = *x_pow;
+                        *x_pow *= pt;
+                        Some(x)
+                    })
+                    .collect()
+            })
+            .collect();
+        Self {
+            params: params.clone(),
+            share_cnt,
+            threshold,
+            eval_matrix,
+            h: RistrettoBasepointTable::create(&pedersen_generator()),
+            shamir_context: ShamirContext::init_all(params, share_cnt, threshold),
+        }
+    }
+
+    /// x * G + r * H
+    fn commit(&self, x: &CurveScalar, r: &CurveScalar) -> RistrettoPoint {
+        x * &RISTRETTO_BASEPOINT_TABLE + r * &self.h
+    }
+
+    /// deal `share`, one vector per RNS prime: the subshares of player j in ret.0[j] and the
+    /// commitments to the coefficients of the polynomial of the i-th residue in ret.1[i], the
+    /// residues of the k-th prime being at [k * n, (k + 1) * n). The constant terms are blinded
+    /// by `blinding`, the opening of the commitments to the share from the last resharing, so
+    /// that C_0 is that commitment, or at random if there is none.
+    pub fn deal(
+        &self,
+        share: &[Vec<u64>],
+        blinding: Option<&[CurveScalar]>,
+    ) -> (Vec<Vec<Subshare>>, Vec<Vec<RistrettoPoint>>) {
+        let nr_residues = share.iter().map(|x| x.len()).sum();
+        let mut rng = rand::rngs::StdRng::from_entropy();
+        let blinding: Vec<CurveScalar> = match blinding {
+            Some(x) => x.to_vec(),
+            None => (0..nr_residues).map(|_| random_scalar(&mut rng)).collect(),
+        };
+        assert_eq!(blinding.len(), nr_residues);
+        let mut to_send = vec![Vec::with_capacity(nr_residues); self.share_cnt];
+        let mut commitments = Vec::with_capacity(nr_residues);
+        let secrets = share
+            .iter()
+            .zip(self.params.rns_moduli.iter())
+            .flat_map(|(x, p)| x.iter().map(move |y| (*y, *p)));
+        for ((secret, p), r_0) in secrets.zip(blinding.iter()) {
+            let bound = (p as u128) << HIDING_BITS;
+            let poly: Vec<CurveScalar> = std::iter::once(CurveScalar::from(secret))
+                .chain((0..self.threshold).map(|_| CurveScalar::from(rng.gen_range(0..bound))))
+                .collect();
+            let blinding: Vec<CurveScalar> = std::iter::once(*r_0)
+                .chain((0..self.threshold).map(|_| random_scalar(&mut rng)))
+                .collect();
+            for (j, x) in to_send.iter_mut().enumerate() {
+                let eval = |poly: &[CurveScalar]| -> CurveScalar {
+                    self.eval_matrix[j]
+                        .iter()
+                        .zip(poly.iter())
+                        .map(|(a, b)| a * b)
+                        .sum()
+                };
+                x.push(Subshare {
+                    value: eval(&poly),
+                    blinding: eval(&blinding),
+                });
+            }
+            commitments.push(
+                poly.iter()
+                    .zip(blinding.iter())
+                    .map(|(x, r)| self.commit(x, r))
+                    .collect(),
+            );
+        }
+        (to_send, commitments)
+    }
+
+    /// whether the subshares dealt to `player` open the Pedersen commitments of the dealer,
+    /// i.e., s * G + r * H == sum_k x^k * C_k for every residue
+    pub fn verify(
+        &self,
+        player: usize,
+        subshares: &[Subshare],
+        commitments: &[Vec<RistrettoPoint>],
+    ) -> bool {
+        let eval_row = &self.eval_matrix[player];
+        subshares.len() == commitments.len()
+            && subshares.iter().zip(commitments.iter()).all(|(s, c)| {
+                c.len() == eval_row.len()
+                    && self.commit(&s.value, &s.blinding)
+                        == RistrettoPoint::vartime_multiscalar_mul(eval_row.iter(), c.iter())
+            })
+    }
+
+    /// the new share of a player, from subshares[x], the subshares dealt to it by dealers[x]
+    pub fn combine(&self, dealers: &[usize], subshares: &[Vec<Subshare>]) -> NewShare {
+        assert!(
+            dealers.len() > self.threshold,
+            "{} dealers can't reconstruct a sharing at threshold {}",
+            dealers.len(),
+            self.threshold
+        );
+        let num_dimension = subshares[0].len() / self.params.rns_moduli.len();
+        let values: Vec<Vec<BigUint>> = subshares
+            .iter()
+            .map(|x| {
+                x.iter()
+                    .map(|y| BigUint::from_bytes_le(y.value.as_bytes()))
+                    .collect()
+            })
+            .collect();
+        let mut ret = NewShare {
+            share: Vec::with_capacity(self.shamir_context.len()),
+            blinding: Vec::with_capacity(subshares[0].len()),
+            quotients: Vec::with_capacity(subshares[0].len()),
+        };
+        for (k, context) in self.shamir_context.iter().enumerate() {
+            let p = BigUint::from(context.modulus.rep());
+            let coeffs: Vec<u64> = context
+                .lagrange_coefficients(dealers)
+                .iter()
+                .map(|x| x.rep())
+                .collect();
+            let mut share = Vec::with_capacity(num_dimension);
+            for i in k * num_dimension..(k + 1) * num_dimension {
+                // the sum of the subshares over the integers is the new share plus a multiple of p
+                let sum: BigUint = coeffs
+                    .iter()
+                    .zip(values.iter())
+                    .map(|(c, x)| &x[i] * *c)
+                    .sum();
+                let (residue, quotient) = div_rem(&sum, &p);
+                share.push(residue);
+                ret.quotients.push(quotient);
+                ret.blinding.push(
+                    coeffs
+                        .iter()
+                        .zip(subshares.iter())
+                        .map(|(c, x)| CurveScalar::from(*c) * x[i].blinding)
+                        .sum(),
+                );
+            }
+            ret.share.push(share);
+        }
+        ret
+    }
+
+    /// the commitments to the coefficients of the new polynomials, sum_d lambda_d * C_d, from
+    /// the commitments of `dealers`, in order, taken one at a time
+    pub fn combine_commitments<I>(
+        &self,
+        dealers: &[usize],
+        commitments: I,
+    ) -> Vec<Vec<RistrettoPoint>>
+    where
+        I: IntoIterator<Item = Vec<Vec<RistrettoPoint>>>,
+    {
+        let coeffs: Vec<Vec<CurveScalar>> = self
+            .shamir_context
+            .iter()
+            .map(|context| {
+                context
+                    .lagrange_coefficients(dealers)
+                    .iter()
+                    .map(|x| CurveScalar::from(x.rep()))
+                    .collect()
+            })
+            .collect();
+        let mut ret: Vec<Vec<RistrettoPoint>> = Vec::new();
+        for (x, c) in commitments.into_iter().enumerate() {
+            let num_dimension = c.len() / self.params.rns_moduli.len();
+            if ret.is_empty() {
+                ret = vec![vec![RistrettoPoint::identity(); self.threshold + 1]; c.len()];
+            }
+            for (i, (acc, c)) in ret.iter_mut().zip(c.iter()).enumerate() {
+                let lambda = &coeffs[i / num_dimension][x];
+                for (y, z) in acc.iter_mut().zip(c.iter()) {
+                    *y += lambda * z;
+                }
+            }
+        }
+        ret
+    }
+
+    /// whether the constant terms of the `commitments` of `dealer` are the commitments to its
+    /// share from the last resharing: sum_k x^k * A_k - q * p * G, from the commitments `old`
+    /// of `combine_commitments` and the `quotients` of its new share
+    pub fn verify_old_share(
+        &self,
+        dealer: usize,
+        commitments: &[Vec<RistrettoPoint>],
+        old: &[Vec<RistrettoPoint>],
+        quotients: &[CurveScalar],
+    ) -> bool {
+        if commitments.len() != old.len() || quotients.len() != old.len() {
+            return false;
+        }
+        let num_dimension = old.len() / self.params.rns_moduli.len();
+        let eval_row = &self.eval_matrix[dealer];
+        (0..old.len()).all(|i| {
+            let p = CurveScalar::from(self.params.rns_moduli[i / num_dimension]);
+            commitments[i].len() == eval_row.len()
+                && old[i].len() == eval_row.len()
+                && commitments[i][0] + &(quotients[i] * p) * &RISTRETTO_BASEPOINT_TABLE
+                    == RistrettoPoint::vartime_multiscalar_mul(eval_row.iter(), old[i].iter())
+        })
+    }
+}
+
+/// (x mod p, x / p) for a sum x of subshares, the quotient as a scalar
+pub(crate) fn div_rem(x: &BigUint, p: &BigUint) -> (u64, CurveScalar) {
+    let quotient = x / p;
+    let residue = (x - &quotient * p)
+        .to_u64_digits()
+        .first()
+        .cloned()
+        .unwrap_or(0);
+    let mut bytes = [0u8; 64];
+    for (x, y) in bytes.iter_mut().zip(quotient.to_bytes_le()) {
+        *x = y;
+    }
+    (residue, CurveScalar::from_bytes_mod_order_wide(&bytes))
+}
+
+/// The new share of a player after a resharing
+pub struct NewShare {
+    /// one vector per RNS prime
+    pub share: Vec<Vec<u64>>,
+    /// the opening of the commitments to the share, to blind the constant terms of the next
+    /// resharing with, one per residue
+    pub blinding: Vec<CurveScalar>,
+    /// the sums of the subshares are share + quotient * p, one per residue, for the others to
+    /// compute the commitments to the share
+    pub quotients: Vec<CurveScalar>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rlwe::aggregate::Aggregate;
+    use crate::rlwe::context::NTTContext;
+    use crate::rlwe::tests::negacyclic_mul;
+    use crate::rlwe::PublicKey;
+
+    #[test]
+    fn test_reshare_then_decrypt() {
+        let params = ParameterSet::with_dimension(1024);
+        let n = params.num_dimension;
+        let q = params.modulus;
+        let (nr_players, threshold) = (5, 2);
+        let mut rng = rand::rngs::StdRng::from_entropy();
+        let contexts = NTTContext::init_all(&params);
+        let shamir_context = ShamirContext::init_all(&params, nr_players, threshold);
+        let reshare_context = ReshareContext::new(&params, nr_players, threshold);
+        // the committee of the config fits the group order
+        ReshareContext::new(&ParameterSet::default(), 55, 22);
+
+        // a ternary secret, shared in NTT form per RNS prime like the data generator does
+        let s: Vec<i128> = (0..n).map(|_| rng.gen_range(-1i128..2)).collect();
+        let mut shares = vec![vec![Vec::with_capacity(n); params.rns_moduli.len()]; nr_players];
+        for (k, (context, shamir)) in contexts.iter().zip(shamir_context.iter()).enumerate() {
+            let p = context.modulus.rep() as i128;
+            let mut ntt_s: Vec<u64> = s.iter().map(|x| x.rem_euclid(p) as u64).collect();
+            context.lazy_ntt_inplace(&mut ntt_s);
+            for x in ntt_s {
+                for (j, y) in shamir.share(x % p as u64).into_iter().enumerate() {
+                    shares[j][k].push(y);
+                }
+            }
+        }
+
+        // every player deals its share, dealer 3 cheats on player 0
+        let mut dealt: Vec<_> = shares
+            .iter()
+            .map(|x| reshare_context.deal(x, None))
+            .collect();
+        assert_eq!(dealt[0].1.len(), params.rns_moduli.len() * n);
+        dealt[3].0[0][7].value += CurveScalar::one();
+        // the blinding is bound too
+        dealt[2].0[0][n + 5].blinding += CurveScalar::one();
+        let qualified: Vec<usize> = (0..nr_players)
+            .filter(|&i| reshare_context.verify(0, &dealt[i].0[0], &dealt[i].1))
+            .collect();
+        assert_eq!(qualified, vec![0, 1, 4]);
+        let new_shares: Vec<NewShare> = (0..nr_players)
+            .map(|j| {
+                let subshares: Vec<Vec<Subshare>> =
+                    qualified.iter().map(|i| dealt[*i].0[j].clone()).collect();
+                reshare_context.combine(&qualified, &subshares)
+            })
+            .collect();
+
+        // in the next resharing, C_0 of a dealer is the commitment to its new share
+        let commitments = qualified.iter().map(|i| dealt[*i].1.clone());
+        let old = reshare_context.combine_commitments(&qualified, commitments);
+        let (_, next) = reshare_context.deal(&new_shares[1].share, Some(&new_shares[1].blinding));
+        assert!(reshare_context.verify_old_share(1, &next, &old, &new_shares[1].quotients));
+        assert!(!reshare_context.verify_old_share(2, &next, &old, &new_shares[1].quotients));
+        // another share, or the same at another blinding, is caught
+        let (_, next) = reshare_context.deal(&new_shares[2].share, Some(&new_shares[1].blinding));
+        assert!(!reshare_context.verify_old_share(1, &next, &old, &new_shares[1].quotients));
+        let (_, next) = reshare_context.deal(&new_shares[1].share, None);
+        assert!(!reshare_context.verify_old_share(1, &next, &old, &new_shares[1].quotients));
+
+        // pk_0 = a and pk_1 = a * s + e
+        let e: Vec<i128> = (0..n).map(|_| rng.gen_range(-4i128..5)).collect();
+        let a: Vec<i128> = (0..n).map(|_| rng.gen_range(0..q)).collect();
+        let pk_1: Vec<i128> = negacyclic_mul(&a, &s)
+            .iter()
+            .zip(e.iter())
+            .map(|(x, y)| (x + y).rem_euclid(q))
+            .collect();
+        let pk = PublicKey::new(&params, &a, &pk_1);
+        let m: Vec<u16> = (0..n).map(|_| rng.gen_range(0..256)).collect();
+        let (_, _, _, _, _, ct) = pk.encrypt(&m);
+        let mut aggregate = Aggregate::new(&params, 1);
+        aggregate.add(&[ct.c_0], &[ct.c_1]);
+
+        // any t + 1 of the new shares decrypt, without the noise of the committee
+        let ids = vec![4, 1, 3];
+        let ntt_c_0 = aggregate.ntt_c_0();
+        let c_0_sk: Vec<Vec<u64>> = (0..params.rns_moduli.len())
+            .map(|k| {
+                let partial: Vec<Vec<u64>> = ids
+                    .iter()
+                    .map(|j| contexts[k].coeff_mul_mod(&new_shares[*j].share[k], &ntt_c_0[k]))
+                    .collect();
+                let coeffs = shamir_context[k].lagrange_coefficients(&ids);
+                let mut x: Vec<u64> = (0..n)
+                    .map(|i| {
+                        let y: Vec<u64> = partial.iter().map(|x| x[i]).collect();
+                        shamir_context[k].reconstruct_with(&coeffs, &y)
+                    })
+                    .collect();
+                contexts[k].lazy_inverse_ntt_inplace(&mut x);
+                x
+            })
+            .collect();
+        let expected: Vec<i128> = m.iter().map(|x| *x as i128).collect();
+        assert_eq!(aggregate.decrypt(&c_0_sk), expected);
+    }
+}
@@ -14,7 +14,7 @@ use std::{
     time::Duration,
 };
 use tarpc::{
-    context,
+    client, context,
     server::{self, incoming::Incoming, Channel},
     tokio_serde::formats::Bincode,
 };
@@ -27,15 +27,25 @@ use crate::common::{
     aggregation::{
         merkle::*,
         node::{CommitEntry, SummationEntry, SummationLeaf},
+        sample::SampledNodes,
         McTree, MsTree,
     },
-    server_service::ServerService,
+    board_service::BoardServiceClient,
+    hash::HashFunction,
+    params::ParameterSet,
+    server_service::{AggregationError, ServerService},
 };
 
+mod rlwe;
+use crate::rlwe::encoding::{Clipping, Encoder};
 mod util;
 use crate::util::{config::ConfigUtils, log::init_tracing};
 mod back_server;
 use back_server::Server;
+mod committee_driver;
+use committee_driver::CommitteeDriver;
+#[cfg(test)]
+mod committee_member;
 
 #[derive(Debug, Clone, Copy)]
 #[repr(u8)]
@@ -88,13 +98,17 @@ impl ServerService for InnerServer {
         round: u32,
         rsa_pk: Vec<u8>,
         commitment: Vec<[u8; 32]>,
-    ) {
+        signatures: Vec<Vec<u8>>,
+    ) -> Result<(), AggregationError> {
         //self.pool
         //    .as_ref()
         //    .install(|| self.server.aggregate_commit(round, rsa_pk, commitment))
-        std::thread::spawn(move || self.server.aggregate_commit(round, rsa_pk, commitment))
-            .join()
-            .unwrap()
+        std::thread::spawn(move || {
+            self.server
+                .aggregate_commit(round, rsa_pk, commitment, signatures)
+        })
+        .join()
+        .unwrap()
     }
 
     async fn aggregate_data(
@@ -106,10 +120,11 @@ impl ServerService for InnerServer {
         c1: Vec<Vec<i128>>,
         nonce: Vec<[u8; 16]>,
         proofs: Vec<Vec<u8>>,
-    ) {
+        signatures: Vec<Vec<u8>>,
+    ) -> Result<(), AggregationError> {
         std::thread::spawn(move || {
             self.server
-                .aggregate_data(round, rsa_pk, c0, c1, nonce, proofs)
+                .aggregate_data(round, rsa_pk, c0, c1, nonce, proofs, signatures)
         })
         .join()
         .unwrap()
@@ -117,14 +132,12 @@ impl ServerService for InnerServer {
         //});
     }
 
-    //type GetMcProofFut = Ready<MerkleProof>;
-    //async fn get_mc_proof(
-    //    self,
-    //    _: context::Context,
-    //    round: u32,
-    //    rsa_pk: Vec<u8>,
-    //) -> Vec<MerkleProof> {
-    async fn get_mc_proof(self, _: context::Context, round: u32, rsa_pk: Vec<u8>) {
+    async fn get_mc_proof(
+        self,
+        _: context::Context,
+        round: u32,
+        rsa_pk: Vec<u8>,
+    ) -> Vec<MerkleProof> {
         std::thread::spawn(move || self.server.get_mc_proof(round, rsa_pk))
             .join()
             .unwrap()
@@ -133,14 +146,12 @@ impl ServerService for InnerServer {
         //    .install(|| self.server.get_mc_proof(round, rsa_pk))
     }
 
-    //type GetMsProofFut = Ready<MerkleProof>;
-    //async fn get_ms_proof(
-    //    self,
-    //    _: context::Context,
-    //    round: u32,
-    //    rsa_pk: Vec<u8>,
-    //) -> Vec<MerkleProof> {
-    async fn get_ms_proof(self, _: context::Context, round: u32, rsa_pk: Vec<u8>) {
+    async fn get_ms_proof(
+        self,
+        _: context::Context,
+        round: u32,
+        rsa_pk: Vec<u8>,
+    ) -> Vec<MerkleProof> {
         //self.pool
         //    .as_ref()
         //    .install(|| self.server.get_ms_proof(round, rsa_pk))
@@ -154,14 +165,15 @@ impl ServerService for InnerServer {
         self,
         _: context::Context,
         round: u32,
+        s: u32,
         vinit: u32,
-        non_leaf_id: Vec<u32>,
+        grandparents: Vec<u32>,
         ct_id: Vec<usize>,
-    ) -> Vec<Vec<(SummationEntry, MerkleProof)>> {
+    ) -> Vec<SampledNodes> {
         //self.pool
         //    .as_ref()
         //    .install(|| self.server.verify(round, vinit, non_leaf_id))
-        std::thread::spawn(move || self.server.verify(round, vinit, non_leaf_id, ct_id))
+        std::thread::spawn(move || self.server.verify(round, s, vinit, grandparents, ct_id))
             .join()
             .unwrap()
     }
@@ -177,13 +189,13 @@ impl ServerService for InnerServer {
     }
 
     //type RetrieveProvingKeyFut = Ready<Vec<u8>>;
-    async fn retrieve_proving_key(self, _: context::Context, round: u32) -> Vec<u8> {
-        std::thread::spawn(move || self.server.retrieve_proving_key(round))
+    async fn retrieve_proving_key(self, _: context::Context, _: u32) -> Vec<u8> {
+        std::thread::spawn(move || self.server.retrieve_proving_key())
             .join()
             .unwrap()
         //self.pool
         //    .as_ref()
-        //    .install(|| self.server.retrieve_proving_key(round))
+        //    .install(|| self.server.retrieve_proving_key())
     }
 }
 #[tokio::main]
@@ -201,14 +213,63 @@ async fn main() -> anyhow::Result<()> {
     let nr_sim = config.get_int("nr_simulated") as u32;
     let nr_sybil = config.get_int("nr_sybil") as u32;
     let nr_parameter = config.get_int("nr_parameter") as u32;
+    let params = ParameterSet::with_dimension(config.get_int("num_dimension") as usize)
+        .with_plaintext_bits(config.get_int("plaintext_bits") as usize)
+        .with_hash_fn(HashFunction::from_name(&config.get("hash_fn")).expect("unknown hash_fn"));
+    let clip_bound = config.get_f64("clip_bound") as f32;
+    let clipping = match config.get("clipping").as_str() {
+        "l2" => Clipping::L2(clip_bound),
+        _ => Clipping::LInf(clip_bound),
+    };
+    // decodes the decrypted sum the same way the clients encode their updates
+    let encoder = Encoder::new(&params, config.get_int("frac_bits") as usize, clipping);
 
     let server_addr = (
         IpAddr::V4(config.get_addr("server_addr")),
         config.get_int("server_port") as u16,
     );
 
+    // the committee serves at the player address with a shifted port
+    let port_offset = config.get_int("committee_port_offset") as u16;
+    let committee_addrs: Vec<String> = config
+        .settings
+        .get_array("players")
+        .unwrap()
+        .into_iter()
+        .map(|x| {
+            let mut addr: SocketAddr = x.into_str().unwrap().parse().unwrap();
+            addr.set_port(addr.port() + port_offset);
+            addr.to_string()
+        })
+        .collect();
+    let committee = CommitteeDriver::connect(
+        committee_addrs,
+        config.get_int("threshold") as usize,
+        Duration::from_secs(config.get_int("committee_timeout") as u64),
+        &params,
+    )
+    .await;
+
+    let board_addr = (
+        IpAddr::V4(config.get_addr("board_addr")),
+        config.get_int("board_port") as u16,
+    );
+    let mut transport = tarpc::serde_transport::tcp::connect(board_addr, Bincode::default);
+    transport.config_mut().max_frame_length(usize::MAX);
+    let board = BoardServiceClient::new(client::Config::default(), transport.await?).spawn();
+
     let pool = Arc::new(ThreadPoolBuilder::new().build().unwrap());
-    let server = Server::setup(nr_real, nr_sim, nr_sybil, nr_parameter, &pool);
+    let server = Server::setup(
+        nr_real,
+        nr_sim,
+        nr_sybil,
+        nr_parameter,
+        &pool,
+        committee,
+        board,
+        params,
+        encoder,
+    );
 
     #[cfg(feature = "json")]
     let mut listener = tarpc::serde_transport::tcp::listen(&server_addr, Json::default).await?;
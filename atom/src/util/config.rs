@@ -8,10 +8,15 @@ pub struct ConfigUtils {
     pub settings: config::Config,
 }
 
+/// the environment variable naming a config to read instead of the one the binary asks for,
+/// e.g. config_local.yaml to run everything on one machine
+pub const CONFIG_OVERRIDE: &str = "AERO_CONFIG";
+
 impl ConfigUtils {
     pub fn init(fpath: &str) -> Self {
+        let fpath = std::env::var(CONFIG_OVERRIDE).unwrap_or_else(|_| fpath.to_string());
         let mut settings = config::Config::default();
-        settings.merge(config::File::with_name(fpath)).unwrap();
+        settings.merge(config::File::with_name(&fpath)).unwrap();
         Self { settings }
     }
 
@@ -3,33 +3,36 @@ mod rlwe;
 mod util;
 mod zksnark;
 use crate::common::aggregation::{
-    merkle::{HashAlgorithm, MerkleProof},
-    node::{SummationEntry, SummationLeaf, SummationNonLeaf},
+    merkle::MerkleProof,
+    node::{ct_commitment, rsa_pk_digest, CommitEntry, SummationLeaf, SummationNonLeaf},
+    sample::{Sample, VerifyFailure, VerifyReport},
 };
+use crate::common::board_service::BoardServiceClient;
+use crate::common::hash::HashFunction;
+use crate::common::params::ParameterSet;
+use crate::common::random_point;
 use crate::common::server_service::ServerServiceClient;
-use crate::common::{i128vec_to_le_bytes, summation_array_size, ZKProof};
+use crate::common::{committee_pks, rsa_sign, ZKProof};
 use crate::util::{config::ConfigUtils, log::init_tracing};
-use crate::zksnark::Verifier;
+use crate::zksnark::{statement, Verifier};
 use ark_std::{end_timer, start_timer};
-use bincode::deserialize_from;
 use cpu_time::ProcessTime;
 //#[cfg(not(feature = "online"))]
-use quail::zksnark::Prover;
+use crate::zksnark::Prover;
 //#[cfg(feature = "online")]
-//use quail::zksnark::ProverOnline as Prover;
-#[cfg(feature = "hashfn_blake3")]
-extern crate blake3;
-use crate::rlwe::PublicKey;
-#[cfg(not(feature = "hashfn_blake3"))]
-use crypto::{digest::Digest, sha3::Sha3};
+//use crate::zksnark::ProverOnline as Prover;
+use crate::rlwe::{
+    encoding::{Clipping, Encoder},
+    PublicKey,
+};
 use tracing::{error, event, instrument, span, warn, Level};
 
-use rand::{Rng, SeedableRng};
+use rand::{rngs::OsRng, Rng, SeedableRng};
 use rsa::{pkcs8::ToPublicKey, RsaPrivateKey, RsaPublicKey};
 use std::io::prelude::*;
 use std::time::Duration;
 use std::time::SystemTime;
-use std::{fs::File, io::BufReader, net::IpAddr};
+use std::{fs::File, net::IpAddr};
 use std::{io::BufRead, process::id};
 use std::{sync::Arc, time::Instant};
 use std::{thread, time};
@@ -37,11 +40,11 @@ use tarpc::{client, context, tokio_serde::formats::Bincode};
 use tracing_subscriber::filter::LevelFilter;
 
 const DEADLINE_TIME: u64 = 6000;
-const NUM_DIMENSION: u32 = 4096;
 pub struct Client {
     inner: ServerServiceClient,
+    board: BoardServiceClient,
     rsa_pk: Vec<u8>,
-    _rsa_vk: RsaPrivateKey,
+    rsa_sk: RsaPrivateKey,
     c0s: Vec<Vec<i128>>,
     c1s: Vec<Vec<i128>>,
     rs: Vec<Vec<i128>>,
@@ -54,50 +57,35 @@ pub struct Client {
     prover: Prover,
     verifier: Verifier,
     enc_pk: PublicKey,
+    params: ParameterSet,
+    encoder: Encoder,
+    // the identity keys of the committee, which draws the random point
+    committee: Vec<RsaPublicKey>,
+    threshold: usize,
 }
 
 impl Client {
-    pub fn new(inner: ServerServiceClient) -> Self {
+    pub fn new(
+        inner: ServerServiceClient,
+        board: BoardServiceClient,
+        params: ParameterSet,
+        encoder: Encoder,
+        committee: Vec<RsaPublicKey>,
+        threshold: usize,
+    ) -> Self {
         let bits = 2048;
         let mut rng = rand::rngs::StdRng::from_entropy();
         let private_key = RsaPrivateKey::new(&mut rng, bits).expect("failed to generate a key");
         let public_key = RsaPublicKey::from(&private_key);
-        let enc_pk = {
-            let (pk0, pk1) = {
-                let mut pk_0 = [0i128; 4096];
-                let mut pk_1 = [0i128; 4096];
-                let file = match File::open("./data/encryption.txt") {
-                    Ok(f) => f,
-                    Err(_) => panic!(),
-                };
-                let reader = BufReader::new(file);
-                for line in reader.lines() {
-                    if let Ok(l) = line {
-                        let vec = l.split(" ").collect::<Vec<&str>>();
-                        for i in 1..vec.len() {
-                            if l.contains("pk_0") {
-                                if let Ok(x) = i128::from_str_radix(vec[i], 10) {
-                                    pk_0[i - 1] = x;
-                                }
-                            } else if l.contains("pk_1") {
-                                if let Ok(x) = i128::from_str_radix(vec[i], 10) {
-                                    pk_1[i - 1] = x;
-                                }
-                            }
-                        }
-                    }
-                }
-                (pk_0.to_vec(), pk_1.to_vec())
-            };
-            PublicKey::new(&pk0, &pk1)
-        };
-        let prover = Prover::new("./data/encryption.txt", "./data/proving_key.txt");
+        let enc_pk = PublicKey::from_file(&params, "./data/encryption.txt");
+        let prover = Prover::new(&params, "./data/encryption.txt", "./data/proving_key.txt");
         let verifier = Verifier::new("./data/verifying_key.txt");
         //let prover = ProverOnline::new("./data/encryption.txt", "./data/proving_key.txt");
         Self {
             inner,
+            board,
             rsa_pk: public_key.to_public_key_pem().unwrap().into_bytes(),
-            _rsa_vk: private_key,
+            rsa_sk: private_key,
             c0s: Vec::new(),
             c1s: Vec::new(),
             rs: Vec::new(),
@@ -110,6 +98,10 @@ impl Client {
             prover,
             verifier,
             enc_pk,
+            params,
+            encoder,
+            committee,
+            threshold,
         }
     }
     #[inline(always)]
@@ -121,16 +113,16 @@ impl Client {
         self.e1s.clear();
         self.d0s.clear();
         self.d1s.clear();
+        self.m.clear();
         self.nonce.clear();
     }
 
     #[instrument(skip_all, name = "encrypt")]
-    pub fn encrypt(&mut self, xs: Vec<u8>) {
+    pub fn encrypt(&mut self, xs: Vec<u16>) {
         self.clear();
-        for i in 0..xs.len() / NUM_DIMENSION as usize {
-            let (r, e0, e1, d0, d1, ct) = self
-                .enc_pk
-                .encrypt(&xs[i * NUM_DIMENSION as usize..(i + 1) * NUM_DIMENSION as usize]);
+        let n = self.params.num_dimension;
+        for i in 0..xs.len() / n {
+            let (r, e0, e1, d0, d1, ct) = self.enc_pk.encrypt(&xs[i * n..(i + 1) * n]);
             //let r = vec![0i128;4096];
             //let e0 = vec![0i128;4096];
             //let e1 = vec![0i128;4096];
@@ -147,15 +139,37 @@ impl Client {
             self.c1s.push(ct.c_1);
             //self.c0s.push(c0);
             //self.c1s.push(c1);
-            let m = xs[i * NUM_DIMENSION as usize..(i + 1) * NUM_DIMENSION as usize]
-                .iter()
-                .map(|x| *x as i128)
-                .collect();
+            let m = xs[i * n..(i + 1) * n].iter().map(|x| *x as i128).collect();
             self.m.push(m);
-            // TODO random this nonce
-            self.nonce.push([0u8; 16]);
+            // a fresh nonce from the OS for every ct, so its commitment hides the ct
+            self.nonce.push(OsRng.gen());
         }
     }
+    /// prove every ct from `encrypt` is well-formed
+    #[cfg(not(feature = "simulation"))]
+    #[instrument(skip_all, name = "generate_proof")]
+    pub fn generate_proof(&self) -> Vec<Vec<u8>> {
+        let gc = start_timer!(|| "start proof generation");
+        let ret = self.prover.create_proof_in_bytes(
+            &self.c0s,
+            &self.c1s,
+            &self.hash(),
+            &rsa_pk_digest(self.params.hash_fn, &self.rsa_pk),
+            &self.nonce,
+            &self.rs,
+            &self.e0s,
+            &self.e1s,
+            &self.d0s,
+            &self.d1s,
+            &self.m,
+        );
+        // # of ct * 192
+        end_timer!(gc);
+        ret
+    }
+
+    /// for benchmark, reuse the proof in ./data/proof.txt for every ct
+    #[cfg(feature = "simulation")]
     #[instrument(skip_all, name = "generate_proof")]
     pub fn generate_proof(&self) -> Vec<Vec<u8>> {
         let gc = start_timer!(|| "start proof generation");
@@ -163,33 +177,19 @@ impl Client {
         let mut buffer = Vec::<u8>::new();
         file.read_to_end(&mut buffer).unwrap();
         let ret = vec![buffer; self.c0s.len()];
-        //#[cfg(not(feature = "online"))]
-        //let ret = self.prover.create_proof_in_bytes(
-        //    &self.c0s, &self.c1s, &self.rs, &self.e0s, &self.e1s, &self.d0s, &self.d1s, &self.m,
-        //);
-        //#[cfg(feature = "online")]
-        //let ret = self.prover.create_proof_in_bytes(
-        //    &self.c0s,
-        //    &self.rs,
-        //    &self.e0s,
-        //    &self.d0s,
-        //    &self.m,
-        //    &vec![[0u8; 224]],
-        //);
-        //println!("proof len {} * {}", ret.len(), ret[0].len());
-        // # of ct * 192
         end_timer!(gc);
         ret
     }
 
     // the whole aggregation phase (except the encryption)
     #[instrument(skip_all)]
-    pub async fn upload(&mut self, round: u32, xs: Vec<u8>, pvk: Vec<u8>) -> bool {
+    pub async fn upload(&mut self, round: u32, xs: Vec<f32>, pvk: Vec<u8>) -> bool {
         // set the deadline of the context
         let gc1 = start_timer!(|| "encrypt the gradients");
-        self.encrypt(xs);
+        self.encrypt(self.encoder.encode(&xs));
         // generate commitment to all the CTs
         let cm = self.hash();
+        let commits = self.commit_entries(round, &cm);
         end_timer!(gc1);
 
         let gc2 = start_timer!(|| "upload the mc+proof generation");
@@ -197,10 +197,23 @@ impl Client {
             // send this commitment to the server
             let mut ctx = context::current();
             ctx.deadline = SystemTime::now() + Duration::from_secs(DEADLINE_TIME);
-            let _ = self
+            let committed = self
                 .inner
-                .aggregate_commit(ctx, round, self.rsa_pk.clone(), cm)
+                .aggregate_commit(
+                    ctx,
+                    round,
+                    self.rsa_pk.clone(),
+                    cm,
+                    commits.iter().map(|x| x.signature.clone()).collect(),
+                )
                 .await;
+            if let Ok(Err(e)) = committed {
+                error!(
+                    "Atom: commitment rejected by the server in round {}: {:?}",
+                    round, e
+                );
+                return false;
+            }
             // let mut ret = self
             //     .inner
             //     .get_mc_proof(ctx, round, self.rsa_pk.clone())
@@ -229,12 +242,13 @@ impl Client {
 
         let gc3 = start_timer!(|| "upload the data");
         // proceed to summation tree
+        let leafs = self.summation_leafs(round, &proofs);
 
         warn!("data prepared");
         let result_data = {
             let mut ctx = context::current();
             ctx.deadline = SystemTime::now() + Duration::from_secs(DEADLINE_TIME);
-            let _ = self
+            let uploaded = self
                 .inner
                 .aggregate_data(
                     ctx,
@@ -244,8 +258,16 @@ impl Client {
                     self.c1s.clone(),
                     self.nonce.clone(),
                     proofs,
+                    leafs.iter().map(|x| x.signature.clone()).collect(),
                 )
                 .await;
+            if let Ok(Err(e)) = uploaded {
+                error!(
+                    "Atom: data rejected by the server in round {}: {:?}",
+                    round, e
+                );
+                return false;
+            }
             warn!("data uploaded,receving ms proof");
             // let mut ret = self
             //     .inner
@@ -289,226 +311,229 @@ impl Client {
         flag
     }
 
-    #[cfg(not(feature = "hashfn_blake3"))]
-    fn hash(&mut self) -> [u8; 32] {
-        // t = Hash(r, c0, c1,..., pi)
-        let mut hasher = Sha3::sha3_256();
-        hasher.input(&self.nonce);
-        hasher.input(&i128vec_to_le_bytes(&self.c0s));
-        hasher.input(&i128vec_to_le_bytes(&self.c1s));
-        hasher.input(&self.rsa_pk);
-        let mut h = [0u8; 32];
-        hasher.result(&mut h);
-        h
+    /// the leafs of Mc of this client in `round`, each signed with rsa_sk
+    fn commit_entries(&self, round: u32, cm: &[[u8; 32]]) -> Vec<CommitEntry> {
+        cm.iter()
+            .enumerate()
+            .map(|(j, h)| {
+                let mut entry = CommitEntry {
+                    rsa_pk: self.rsa_pk.clone(),
+                    hash: *h,
+                    signature: Vec::new(),
+                };
+                let digest = entry.signed_digest(self.params.hash_fn, round, j);
+                entry.signature = rsa_sign(&self.rsa_sk, &digest);
+                entry
+            })
+            .collect()
     }
-    #[cfg(feature = "hashfn_blake3")]
-    fn hash(&mut self) -> Vec<[u8; 32]> {
+
+    /// the leafs of Ms of this client in `round`, the cts with their proofs, each signed with rsa_sk
+    fn summation_leafs(&self, round: u32, proofs: &[Vec<u8>]) -> Vec<SummationLeaf> {
         (0..self.c0s.len())
-            .into_iter()
-            .map(|i| {
-                let mut hasher = blake3::Hasher::new();
-                hasher.update(&self.nonce[i]);
-                hasher.update(&i128vec_to_le_bytes(&self.c0s[i]));
-                hasher.update(&i128vec_to_le_bytes(&self.c1s[i]));
-                hasher.update(&self.rsa_pk);
-                hasher.finalize().into()
+            .map(|j| {
+                let leaf = SummationLeaf::from_ct(
+                    self.rsa_pk.clone(),
+                    self.c0s[j].clone(),
+                    self.c1s[j].clone(),
+                    self.nonce[j],
+                    proofs[j].clone(),
+                );
+                let digest = leaf.signed_digest(self.params.hash_fn, round, j);
+                leaf.with_signature(rsa_sign(&self.rsa_sk, &digest))
             })
             .collect()
     }
 
-    //TODO maybe fix when "# of clients < s"
-    pub fn get_random_non_leafs(n: u32, s: u32, vinit: u32) -> Vec<u32> {
-        let mut rng = rand::rngs::StdRng::from_entropy();
-        // [0..N): leafs
-        // [N..N+N/2): non-leafs whose children are leafs
-        // N+N/2: possibly 1 leaf + 1 non-leaf
-        // [N+N/2..summation_array_size(N)): non-leafs with non-leaf children
-        let mut non_leafs: Vec<u32> = Vec::new();
-        let array_size = summation_array_size(n);
-        // first pick about s/2 non-leafs whose children are leafs
-        // if no such non-leafs nodes exist, just skip
-        let mut idx = 0;
-        while idx <= s {
-            let ii = (idx + vinit) % n;
-            if (ii & 0x1 == 0) && (ii + 1 <= n) {
-                non_leafs.push(n + ii / 2);
-                idx += 2;
-            } else {
-                idx += 1;
-            }
-        }
-
-        // now randomly pick non-leaf nodes whose children are non-leafs
-        // if s is even, just pick s/2; otherwise either (s+1)/2 or s/2
-        // or put in another way, if s is odd and vmax is even, pick (s+1) / 2
-        // otherwise, pick s/2
-        let nr_gp = {
-            if (s & 0x1 != 0) && ((vinit + s) & 0x1 == 0) {
-                (s + 1) / 2
-            } else {
-                s / 2
-            }
-        };
-        // [0..N)
-        // a[N] = 0 + 1, a[N+1] = 2 + 3,...
-        // a[id_gp - N] = 2 * (id_gp - N),
-        for _ in 0..nr_gp + 1 {
-            // id of grand parent
-            if n + n / 2 < array_size {
-                let id_gp = rng.gen_range(n + n / 2..array_size);
-                // the id of the children
-                let left = (id_gp - n) * 2;
-                let right = left + 1;
-                non_leafs.push(id_gp);
-                non_leafs.push(left);
-                non_leafs.push(right);
-            }
-        }
-        non_leafs
+    /// the commitment to each ct, opened by its leaf of Ms
+    fn hash(&self) -> Vec<[u8; 32]> {
+        (0..self.c0s.len())
+            .map(|i| {
+                ct_commitment(
+                    self.params.hash_fn,
+                    &self.rsa_pk,
+                    &self.nonce[i],
+                    &self.c0s[i],
+                    &self.c1s[i],
+                )
+            })
+            .collect()
     }
 
-    // this N should be known from the board
-    // s has to be at least 1
+    /// sample s + 1 leafs from a random vinit, their parents and random grandparents of the trees
+    /// of each ct with probability pr, and check them against the roots on the board
+    // s has to be at least 1, or every ct fails with TooFewSamples
     #[instrument(skip_all)]
-    pub async fn verify(&self, round: u32, n: u32, s: u32, pr: f64) {
+    pub async fn verify(&self, round: u32, s: u32, pr: f64) -> VerifyReport {
         let gc = start_timer!(|| "verify");
 
-        assert!(s >= 1, "s should be at least 1");
+        let nr_ct = self.c0s.len();
+        if s < 1 {
+            error!("Atom: verify round {} with s = {}, at least 1", round, s);
+            return VerifyReport::failed(round, nr_ct, VerifyFailure::TooFewSamples);
+        }
+        // N is published on the board with the Mc roots
+        let n = {
+            let mut ctx = context::current();
+            ctx.deadline = SystemTime::now() + Duration::from_secs(DEADLINE_TIME);
+            self.board.get_nr_leaf(ctx, round)
+        }
+        .await;
+        let n = match n {
+            Ok(n) => n,
+            Err(e) => {
+                error!(
+                    "Atom: no # of leafs of round {} on the board: {:?}",
+                    round, e
+                );
+                return VerifyReport::failed(round, nr_ct, VerifyFailure::MissingLeafCount);
+            }
+        };
+        // the point the leafs are evaluated at in the summation tree, recomputed from the
+        // contributions of the committee bound to the leaf roots
+        let leaf_roots = {
+            let mut ctx = context::current();
+            ctx.deadline = SystemTime::now() + Duration::from_secs(DEADLINE_TIME);
+            self.board.get_leaf_roots(ctx, round)
+        }
+        .await
+        .unwrap_or_default();
+        let contributions = {
+            let mut ctx = context::current();
+            ctx.deadline = SystemTime::now() + Duration::from_secs(DEADLINE_TIME);
+            self.board.get_contributions(ctx, round)
+        }
+        .await
+        .unwrap_or_default();
+        let random_pt = random_point::random_point(
+            &random_point::seed(round, &leaf_roots),
+            &contributions,
+            &self.committee,
+            self.threshold,
+            self.params.modulus,
+        );
+        let mc_roots = {
+            let mut ctx = context::current();
+            ctx.deadline = SystemTime::now() + Duration::from_secs(DEADLINE_TIME);
+            self.board.get_mc_roots(ctx, round)
+        }
+        .await
+        .unwrap_or_default();
+        let ms_roots = {
+            let mut ctx = context::current();
+            ctx.deadline = SystemTime::now() + Duration::from_secs(DEADLINE_TIME);
+            self.board.get_ms_roots(ctx, round)
+        }
+        .await
+        .unwrap_or_default();
+        // the sum the committee decrypts, which the roots must evaluate
+        let (summed_c_0, summed_c_1) = {
+            let mut ctx = context::current();
+            ctx.deadline = SystemTime::now() + Duration::from_secs(DEADLINE_TIME);
+            self.board.get_summed_ct(ctx, round)
+        }
+        .await
+        .unwrap_or_default();
         let mut rng = rand::rngs::StdRng::from_entropy();
-        // TODO random here
-        //let vinit: u32 = rng.gen::<u32>() % N;
-        let vinit: u32 = 0;
-
-        //let non_leafs: Vec<u32> = Self::get_random_non_leafs(n, s, vinit);
-        let non_leafs: Vec<u32> = vec![0u32];
+        let sample = match Sample::random(n, s, &mut rng) {
+            Some(x) => x,
+            None => {
+                error!("Atom: no leaf to sample in round {}", round);
+                return VerifyReport::failed(round, nr_ct, VerifyFailure::EmptyTree);
+            }
+        };
 
         let gc1 = start_timer!(|| "receive verify");
-        let ct_id: Vec<usize> = {
-            (0..self.c0s.len()).into_iter().filter(|_| {
-                let mut rng = rand::thread_rng();
-                rng.gen::<f64>() < pr
-            })
-        }
-        .collect();
-        //println!("{:?}", ct_id);
+        let ct_id: Vec<usize> = (0..nr_ct).filter(|_| rng.gen::<f64>() < pr).collect();
         let ret = {
             let mut ctx = context::current();
             ctx.deadline = SystemTime::now() + Duration::from_secs(DEADLINE_TIME);
-            self.inner
-                .verify(ctx, round, vinit, non_leafs, ct_id.clone())
+            self.inner.verify(
+                ctx,
+                round,
+                sample.s,
+                sample.vinit,
+                sample.grandparents.clone(),
+                ct_id.clone(),
+            )
         }
         .await
-        .unwrap();
+        .unwrap_or_default();
         end_timer!(gc1);
 
-        // if not able to retrieve the proof
-        if ret.len() == 0 {
-            return;
-        }
-        let ct0: Vec<i128> = {
-            let mut f = BufReader::new(File::open("./data/ct0.txt").unwrap());
-            deserialize_from(&mut f).unwrap()
-        };
-        let ct1: Vec<i128> = {
-            let mut f = BufReader::new(File::open("./data/ct1.txt").unwrap());
-            deserialize_from(&mut f).unwrap()
-        };
-        let inputs: Vec<i128> = ct0.iter().cloned().chain(ct1.iter().cloned()).collect();
-
-        // verify all the leafs
+        // verify the nodes of every ct sampled, a ct without any is a failure too
         let gc2 = start_timer!(|| "verify the proofs");
-        for result in &ret {
-            for i in 0..s + 1 {
-                let mc_node = &result[2 * i as usize];
-                let ms_node = &result[(2 * i + 1) as usize];
-                // Commit_i appears in Mc
-                assert!(
-                    mc_node.1.clone().to_proof().validate::<HashAlgorithm>(),
-                    "wrong merkle proofs"
-                );
-                if let SummationEntry::Leaf(s) = &ms_node.0 {
-                    //let inputs: Vec<i128> =
-                    //    s.c0.iter().cloned().chain(s.c1.iter().cloned()).collect();
-                    let _proof = self.verifier.verify_proof_from_bytes(&s.proof, &inputs);
-                    //println!("verify the zkproof {:?}", _proof);
-                    //assert!(_proof);
-                    let h = s.hash();
-                    if let SummationEntry::Commit(cm) = &mc_node.0 {
-                        //TODO fix this
-                        // assert_eq!(h, cm.hash);
-                    } else {
-                        error!("Atom: Verify not commit entry!");
-                    }
-                } else {
-                    error!("Atom: Verify not leaf nodes entry!");
+        let mut failures = Vec::new();
+        for (i, k) in ct_id.iter().enumerate() {
+            let random_pt = match random_pt {
+                Some(x) => x,
+                None => {
+                    failures.push((*k, VerifyFailure::WrongRandomPoint));
+                    continue;
                 }
-            }
-
-            // offset by the commit and leafs
-            let mut i = (s + 1 + s + 1) as usize;
-            let mut idx = vinit;
-            //let mut ii = 0 as usize;
-            while idx <= vinit + s {
-                let ii = idx % n;
-                if (ii & 0x1 == 0) && (ii + 1 <= vinit + s) {
-                    // check these nodes by ref to the leaf nodes
-                    let parent = &result[i];
-                    let left = &result[2 * (idx - vinit) as usize + 1];
-                    let right = &result[2 * (idx - vinit + 1) as usize + 1];
-                    // check the proofs
-                    assert!(parent.1.clone().to_proof().validate::<HashAlgorithm>());
-
-                    let c = match (&left.0, &right.0) {
-                        // TODO add random pt here
-                        (SummationEntry::Leaf(a), SummationEntry::Leaf(b)) => {
-                            &a.evaluate_at(0) + &b.evaluate_at(0)
-                        }
-                        //(SummationEntry::Leaf(a), SummationEntry::NonLeaf(b)) => a + b,
-                        //(SummationEntry::NonLeaf(a), SummationEntry::Leaf(b)) => a + b,
-                        //(SummationEntry::NonLeaf(a), SummationEntry::NonLeaf(b)) => a + b,
-                        _ => {
-                            panic!("Not leaf nodes in verifying summation");
-                        }
-                    };
-
-                    if let SummationEntry::NonLeaf(a) = &parent.0 {
-                        assert_eq!(&c, a);
-                    } else {
-                        //error!("Parent not a nonleaf node when leaf");
-                    }
-                    i = i + 1;
-                    idx += 2;
-                } else {
-                    idx += 1;
+            };
+            let (nodes, mc_root, ms_root, leaf_root) = match (
+                ret.get(i),
+                mc_roots.get(*k),
+                ms_roots.get(*k),
+                leaf_roots.get(*k),
+            ) {
+                (Some(x), Some(y), Some(z), Some(w)) => (x, y, z, w),
+                _ => {
+                    failures.push((*k, VerifyFailure::MissingNodes));
+                    continue;
                 }
-            }
-            while i < result.len() {
-                let parent = &result[i];
-                let left = &result[i + 1];
-                let right = &result[i + 2];
-                // check the proofs
-                assert!(parent.1.clone().to_proof().validate::<HashAlgorithm>());
-                assert!(left.1.clone().to_proof().validate::<HashAlgorithm>());
-                assert!(right.1.clone().to_proof().validate::<HashAlgorithm>());
-                // possibly leaf + non_leaf when the # of leafs is odd
-                let c = match (&left.0, &right.0) {
-                    (SummationEntry::NonLeaf(a), SummationEntry::NonLeaf(b)) => a + b,
-                    (SummationEntry::Leaf(a), SummationEntry::NonLeaf(b)) => &a.evaluate_at(0) + b,
-                    //(SummationEntry::Leaf(a), SummationEntry::Leaf(b)) => a + b,
-                    //(SummationEntry::NonLeaf(a), SummationEntry::Leaf(b)) => a + b,
-                    _ => {
-                        panic!("Not leaf nodes in verifying summation");
-                    }
-                };
-                if let SummationEntry::NonLeaf(a) = &parent.0 {
-                    assert_eq!(&c, a);
-                } else {
-                    //error!("Parent not a nonleaf node when nonleaf");
+            };
+            let summed = match SummationNonLeaf::of_summed_ct(
+                &summed_c_0,
+                &summed_c_1,
+                *k,
+                random_pt,
+                &self.params,
+            ) {
+                Some(x) => x,
+                None => {
+                    failures.push((*k, VerifyFailure::WrongSummedCt));
+                    continue;
                 }
-                i += 3;
-            }
+            };
+            // the zkproof is of this ct under this commitment; in simulation every client
+            // reuses the same proof, so it can't be checked
+            let verify_zkproof = |leaf: &SummationLeaf, cm: &CommitEntry| {
+                let proof = self
+                    .verifier
+                    .verify_proof_from_bytes(&leaf.proof, &statement(&leaf.c0, &leaf.c1, &cm.hash));
+                proof || cfg!(feature = "simulation")
+            };
+            failures.extend(
+                sample
+                    .check(
+                        nodes,
+                        round,
+                        *k,
+                        mc_root,
+                        ms_root,
+                        leaf_root,
+                        random_pt,
+                        &summed,
+                        &self.params,
+                        verify_zkproof,
+                    )
+                    .into_iter()
+                    .map(|x| (*k, x)),
+            );
         }
         end_timer!(gc2);
+        end_timer!(gc);
+        let report = VerifyReport {
+            round,
+            ct_id,
+            failures,
+        };
+        if !report.passed() {
+            error!("Atom: verify round {} failed {:?}", round, report.failures);
+        }
+        report
     }
 
     #[instrument(skip_all)]
@@ -564,7 +589,34 @@ async fn main() -> anyhow::Result<()> {
 
     let inner_client =
         ServerServiceClient::new(client::Config::default(), transport.await?).spawn();
-    let mut client = Client::new(inner_client);
+
+    let board_addr = (
+        IpAddr::V4(config.get_addr("board_addr")),
+        config.get_int("board_port") as u16,
+    );
+    let mut transport = tarpc::serde_transport::tcp::connect(board_addr, Bincode::default);
+    transport.config_mut().max_frame_length(usize::MAX);
+    let board_client = BoardServiceClient::new(client::Config::default(), transport.await?).spawn();
+    let params = ParameterSet::with_dimension(config.get_int("num_dimension") as usize)
+        .with_plaintext_bits(config.get_int("plaintext_bits") as usize)
+        .with_hash_fn(HashFunction::from_name(&config.get("hash_fn")).expect("unknown hash_fn"));
+    let clip_bound = config.get_f64("clip_bound") as f32;
+    let clipping = match config.get("clipping").as_str() {
+        "l2" => Clipping::L2(clip_bound),
+        _ => Clipping::LInf(clip_bound),
+    };
+    let encoder = Encoder::new(&params, config.get_int("frac_bits") as usize, clipping);
+    let players = config.settings.get_array("players").unwrap();
+    let committee = committee_pks(players.len());
+    let threshold = config.get_int("threshold") as usize;
+    let mut client = Client::new(
+        inner_client,
+        board_client,
+        params,
+        encoder,
+        committee,
+        threshold,
+    );
 
     let start = start_timer!(|| "clients");
 
@@ -575,7 +627,7 @@ async fn main() -> anyhow::Result<()> {
     //let sr = start_timer!(|| "one round");
     //let train = start_timer!(|| "train model");
     ////let data = client.train_model(i).await;
-    //let data = vec![0u8; nr_parameter as usize];
+    //let data = vec![0f32; nr_parameter as usize];
     //end_timer!(train);
 
     //let rs = start_timer!(|| "upload data");
@@ -589,7 +641,7 @@ async fn main() -> anyhow::Result<()> {
     //let vr = start_timer!(|| "verify the data");
     let verifier_cpu = ProcessTime::now();
     for _ in 0..nr_round {
-        client.verify(0, nr_real + nr_sim, 5, pr).await;
+        client.verify(0, 5, pr).await;
     }
     let verifier_cpu_time = verifier_cpu.elapsed();
     println!(
@@ -4,46 +4,60 @@
 // 256 bit 	gaussianSampler := ring.NewGaussianSampler(prng, q, params.Sigma(),
 // int(6*params.Sigma())) DefaultSigma in ckks = 3.2
 // bound of error = 3.2 . * 6 = 19
-use ark_ff::Field;
+use crate::common::aggregation::node::{ct_elements, CT_PACK_BITS};
+use crate::common::hash::{sponge_constants, sponge_tag, Domain, SpongeArity};
+use crate::common::params::ParameterSet;
+use crate::zksnark::NR_COMMITMENT_LIMBS;
+use ark_bls12_381::Fr as ArkFr;
+use ark_ff::{Field, One, Zero};
 use ark_relations::{
     lc,
     r1cs::{
         ConstraintSynthesizer, ConstraintSystemRef, LinearCombination, SynthesisError, Variable,
     },
 };
+use bellperson::bls::{Bls12, Fr as BPFr};
+use ff::Field as BPField;
+use neptune::bls381num::{bp2ark, AllocatedNum};
 use std::{
     fs::File,
     io::{prelude::*, BufReader},
     marker::PhantomData,
 };
+use typenum::Unsigned;
 #[derive(Clone)]
 pub struct Circuit<F: Field> {
-    pub num_dimension: usize,
-    pub c_0: [i128; 4096],
-    pub c_1: [i128; 4096],
-    pub r: [i128; 4096],
-    pub e_0: [i128; 4096],
-    pub e_1: [i128; 4096],
-    pub m: [i128; 4096],
-    pub pk_0: [i128; 4096],
-    pub pk_1: [i128; 4096],
-    pub delta_0: [i128; 4096],
-    pub delta_1: [i128; 4096],
+    pub params: ParameterSet,
+    pub c_0: Vec<i128>,
+    pub c_1: Vec<i128>,
+    /// the commitment to (rsa_pk, nonce, c_0, c_1) in limbs, see `statement`
+    pub commitment: Vec<i128>,
+    /// the digest of rsa_pk and the nonce the commitment opens with, see `node::ct_elements`
+    pub rsa_pk_digest: [u8; 32],
+    pub nonce: [u8; 16],
+    pub r: Vec<i128>,
+    pub e_0: Vec<i128>,
+    pub e_1: Vec<i128>,
+    pub m: Vec<i128>,
+    pub pk_0: Vec<i128>,
+    pub pk_1: Vec<i128>,
+    pub delta_0: Vec<i128>,
+    pub delta_1: Vec<i128>,
     pub _engine: PhantomData<F>,
 }
 
 impl<F: Field> Circuit<F> {
-    pub fn new(num_dimension: usize, file_path: &str) -> Self {
-        let mut pk_0 = [0i128; 4096];
-        let mut pk_1 = [0i128; 4096];
-        let mut c_0 = [0i128; 4096];
-        let mut c_1 = [0i128; 4096];
-        let mut r = [0i128; 4096];
-        let mut e_0 = [0i128; 4096];
-        let mut e_1 = [0i128; 4096];
-        let mut m = [0i128; 4096];
-        let mut delta_0 = [0i128; 4096];
-        let mut delta_1 = [0i128; 4096];
+    pub fn new(params: &ParameterSet, file_path: &str) -> Self {
+        let num_dimension = params.num_dimension;
+        let mut pk_0 = vec![0i128; num_dimension];
+        let mut pk_1 = vec![0i128; num_dimension];
+        let mut c_0 = vec![0i128; num_dimension];
+        let mut c_1 = vec![0i128; num_dimension];
+        let mut r = vec![0i128; num_dimension];
+        let mut e_0 = vec![0i128; num_dimension];
+        let mut e_1 = vec![0i128; num_dimension];
+        let mut delta_0 = vec![0i128; num_dimension];
+        let mut delta_1 = vec![0i128; num_dimension];
         let file = match File::open(file_path) {
             Ok(f) => f,
             Err(_) => panic!(),
@@ -52,7 +66,7 @@ impl<F: Field> Circuit<F> {
         for line in reader.lines() {
             if let Ok(l) = line {
                 let vec = l.split(" ").collect::<Vec<&str>>();
-                for i in 1..vec.len() {
+                for i in 1..vec.len().min(num_dimension + 1) {
                     if l.contains("pk_0") {
                         if let Ok(x) = i128::from_str_radix(vec[i], 10) {
                             pk_0[i - 1] = x;
@@ -94,13 +108,16 @@ impl<F: Field> Circuit<F> {
             }
         }
         Self {
-            num_dimension,
+            params: params.clone(),
             c_0,
             c_1,
+            commitment: vec![0i128; NR_COMMITMENT_LIMBS],
+            rsa_pk_digest: [0u8; 32],
+            nonce: [0u8; 16],
             r,
             e_0,
             e_1,
-            m: [0i128; 4096],
+            m: vec![0i128; num_dimension],
             pk_0,
             pk_1,
             delta_0,
@@ -109,6 +126,28 @@ impl<F: Field> Circuit<F> {
         }
     }
 
+    /// the circuit of the public key, with every other value zero
+    pub fn from_pk(params: &ParameterSet, pk_0: &[i128], pk_1: &[i128]) -> Self {
+        let zero = vec![0i128; params.num_dimension];
+        Self {
+            params: params.clone(),
+            c_0: zero.clone(),
+            c_1: zero.clone(),
+            commitment: vec![0i128; NR_COMMITMENT_LIMBS],
+            rsa_pk_digest: [0u8; 32],
+            nonce: [0u8; 16],
+            r: zero.clone(),
+            e_0: zero.clone(),
+            e_1: zero.clone(),
+            m: zero.clone(),
+            pk_0: pk_0.to_vec(),
+            pk_1: pk_1.to_vec(),
+            delta_0: zero.clone(),
+            delta_1: zero,
+            _engine: PhantomData,
+        }
+    }
+
     pub fn i128to_field(&self, x: i128) -> F {
         if x < 0 {
             -F::from_random_bytes(&((-x).to_le_bytes())[..]).unwrap()
@@ -118,8 +157,47 @@ impl<F: Field> Circuit<F> {
     }
 }
 
-impl<F: Field> ConstraintSynthesizer<F> for Circuit<F> {
-    fn generate_constraints(self, cs: ConstraintSystemRef<F>) -> Result<(), SynthesisError> {
+/// Allocate `elements` and chain Poseidon over them as `hash::poseidon_sponge` does, returning
+/// the variables of the elements and of the hash. The witnesses are all allocated here, so
+/// `Prover::assign` fills them in by running it on its own constraint system.
+pub fn sponge_gadget(
+    cs: &ConstraintSystemRef<ArkFr>,
+    domain: Domain,
+    elements: &[BPFr],
+) -> Result<(Vec<Variable>, Variable), SynthesisError> {
+    let width = SpongeArity::to_usize();
+    let nr_padded = (elements.len() + width - 2) / (width - 1) * (width - 1);
+    let mut vars = Vec::with_capacity(nr_padded);
+    for i in 0..nr_padded {
+        let x = elements.get(i).cloned().unwrap_or_else(BPFr::zero);
+        vars.push(AllocatedNum::<Bls12>::alloc(cs, || Ok(x))?);
+    }
+    // the padding is zero
+    for x in vars[elements.len()..].iter() {
+        cs.enforce_constraint(lc!() + x.get_variable(), lc!() + Variable::One, lc!())?;
+    }
+    let tag = sponge_tag(domain, elements.len());
+    let mut h = AllocatedNum::<Bls12>::alloc(cs, || Ok(tag))?;
+    cs.enforce_constraint(
+        lc!() + h.get_variable(),
+        lc!() + Variable::One,
+        lc!() + (bp2ark(tag), Variable::One),
+    )?;
+    for chunk in vars.chunks(width - 1) {
+        let mut preimage = Vec::with_capacity(width);
+        preimage.push(h);
+        preimage.extend_from_slice(chunk);
+        h = neptune::circuit::poseidon_hash(cs, preimage, sponge_constants())?;
+    }
+    let ret = vars[..elements.len()]
+        .iter()
+        .map(|x| x.get_variable())
+        .collect();
+    Ok((ret, h.get_variable()))
+}
+
+impl ConstraintSynthesizer<ArkFr> for Circuit<ArkFr> {
+    fn generate_constraints(self, cs: ConstraintSystemRef<ArkFr>) -> Result<(), SynthesisError> {
         let pk_0 = self
             .pk_0
             .to_vec()
@@ -132,12 +210,17 @@ impl<F: Field> ConstraintSynthesizer<F> for Circuit<F> {
             .iter()
             .map(|&x| self.i128to_field(x))
             .collect::<Vec<_>>();
-        let pf = F::from(0xffffee001u64) * F::from(0xffffc4001u64) * F::from(0x1ffffe0001u64);
+        let num_dimension = self.params.num_dimension;
+        let noise_bits = self.params.noise_bits();
+        let delta_bits = self.params.delta_bits();
+        let max_noise = self.params.max_noise;
+        let pf = self.i128to_field(self.params.modulus);
+        let scale = self.i128to_field(self.params.plaintext_scale());
 
         // c0
         let mut c0_val_vec = Vec::new();
         let mut c0_var_vec = Vec::new();
-        for i in 0..self.num_dimension {
+        for i in 0..num_dimension {
             c0_val_vec.push(self.i128to_field(self.c_0[i]));
             c0_var_vec.push(cs.new_input_variable(|| Ok(c0_val_vec[i]))?);
             //c0_var_vec.push(cs.new_input_variable(|| Ok(F::zero()))?);
@@ -145,15 +228,21 @@ impl<F: Field> ConstraintSynthesizer<F> for Circuit<F> {
         // c1
         let mut c1_val_vec = Vec::new();
         let mut c1_var_vec = Vec::new();
-        for i in 0..self.num_dimension {
+        for i in 0..num_dimension {
             c1_val_vec.push(self.i128to_field(self.c_1[i]));
             c1_var_vec.push(cs.new_input_variable(|| Ok(c1_val_vec[i]))?);
             //c1_var_vec.push(cs.new_input_variable(|| Ok(F::zero()))?);
         }
+        // the commitment, shown to open to c0 and c1 at the end
+        let mut commitment_var_vec = Vec::new();
+        for i in 0..NR_COMMITMENT_LIMBS {
+            let x = self.i128to_field(self.commitment[i]);
+            commitment_var_vec.push(cs.new_input_variable(|| Ok(x))?);
+        }
         // r
         let mut r_val_vec = Vec::new();
         let mut r_var_vec = Vec::new();
-        for i in 0..self.num_dimension {
+        for i in 0..num_dimension {
             r_val_vec.push(self.i128to_field(self.r[i]));
             r_var_vec.push(cs.new_witness_variable(|| Ok(r_val_vec[i]))?);
             //r_var_vec.push(cs.new_witness_variable(|| Ok(F::zero()))?);
@@ -161,7 +250,7 @@ impl<F: Field> ConstraintSynthesizer<F> for Circuit<F> {
         // e0
         let mut e0_val_vec = Vec::new();
         let mut e0_var_vec = Vec::new();
-        for i in 0..self.num_dimension {
+        for i in 0..num_dimension {
             e0_val_vec.push(self.i128to_field(self.e_0[i]));
             e0_var_vec.push(cs.new_witness_variable(|| Ok(e0_val_vec[i]))?);
             //e1_var_vec.push(cs.new_witness_variable(|| Ok(F::zero()))?);
@@ -169,7 +258,7 @@ impl<F: Field> ConstraintSynthesizer<F> for Circuit<F> {
         // e2
         let mut e1_val_vec = Vec::new();
         let mut e1_var_vec = Vec::new();
-        for i in 0..self.num_dimension {
+        for i in 0..num_dimension {
             e1_val_vec.push(self.i128to_field(self.e_1[i]));
             e1_var_vec.push(cs.new_witness_variable(|| Ok(e1_val_vec[i]))?);
             //e1_var_vec.push(cs.new_witness_variable(|| Ok(F::zero()))?);
@@ -177,7 +266,7 @@ impl<F: Field> ConstraintSynthesizer<F> for Circuit<F> {
         // delta_0
         let mut delta_0_val_vec = Vec::new();
         let mut delta_0_var_vec = Vec::new();
-        for i in 0..self.num_dimension {
+        for i in 0..num_dimension {
             delta_0_val_vec.push(self.i128to_field(self.delta_0[i]));
             delta_0_var_vec.push(cs.new_witness_variable(|| Ok(delta_0_val_vec[i]))?);
             //delta_0_var_vec.push(cs.new_witness_variable(|| Ok(F::zero()))?);
@@ -185,19 +274,19 @@ impl<F: Field> ConstraintSynthesizer<F> for Circuit<F> {
         // delta_1
         let mut delta_1_val_vec = Vec::new();
         let mut delta_1_var_vec = Vec::new();
-        for i in 0..self.num_dimension {
+        for i in 0..num_dimension {
             delta_1_val_vec.push(self.i128to_field(self.delta_1[i]));
             delta_1_var_vec.push(cs.new_witness_variable(|| Ok(delta_1_val_vec[i]))?);
             //delta_1_var_vec.push(cs.new_witness_variable(|| Ok(F::zero()))?);
         }
         // pk_0 * r + e_0 = c_0 + delta_0 * q
-        for i in 0..self.num_dimension {
+        for i in 0..num_dimension {
             let mut elc = LinearCombination::zero();
             // (pk * r) [i]
             // pk[j] * r[i-j%4096]
-            for j in 0..self.num_dimension {
-                let k = (i - j) % self.num_dimension;
-                if j + k >= self.num_dimension {
+            for j in 0..num_dimension {
+                let k = (i + num_dimension - j) % num_dimension;
+                if j + k >= num_dimension {
                     elc.0.push((-pk_0[j], r_var_vec[k]));
                 } else {
                     elc.0.push((pk_0[j], r_var_vec[k]));
@@ -213,19 +302,19 @@ impl<F: Field> ConstraintSynthesizer<F> for Circuit<F> {
         // m
         let mut m_val_vec = Vec::new();
         let mut m_var_vec = Vec::new();
-        for i in 0..self.num_dimension {
+        for i in 0..num_dimension {
             m_val_vec.push(self.i128to_field(self.m[i]));
             m_var_vec.push(cs.new_witness_variable(|| Ok(m_val_vec[i]))?);
             //m_var_vec.push(cs.new_witness_variable(|| Ok(F::zero()))?);
         }
-        // pk_1 * r + e_0 + m = c_1 + delta_1 * q
-        for i in 0..self.num_dimension {
+        // pk_1 * r + e_1 + Δ * m = c_1 + delta_1 * q
+        for i in 0..num_dimension {
             let mut elc = LinearCombination::zero();
             // (pk * r) [i]
             // pk[j] * r[i-j%4096]
-            for j in 0..self.num_dimension {
-                let k = (i - j) % self.num_dimension;
-                if j + k >= self.num_dimension {
+            for j in 0..num_dimension {
+                let k = (i + num_dimension - j) % num_dimension;
+                if j + k >= num_dimension {
                     elc.0.push((-pk_1[j], r_var_vec[k]));
                 } else {
                     elc.0.push((pk_1[j], r_var_vec[k]));
@@ -233,88 +322,97 @@ impl<F: Field> ConstraintSynthesizer<F> for Circuit<F> {
             }
             elc.0.sort_by_key(|e| e.1);
             cs.enforce_constraint(
-                lc!() + elc + e1_var_vec[i] + m_var_vec[i],
+                lc!() + elc + e1_var_vec[i] + (scale, m_var_vec[i]),
                 lc!() + Variable::One,
                 lc!() + c1_var_vec[i] + (pf, delta_1_var_vec[i]),
             )?;
         }
-        // range_proof of e1 [-19,19] -> [0,38]
-        for i in 0..self.num_dimension {
+        // range_proof of e0 [-max_noise, max_noise] -> [0, 2 * max_noise]
+        for i in 0..num_dimension {
             let mut bit_val_vec = Vec::new();
             let mut bit_var_vec = Vec::new();
-            let mut x = F::one();
-            for k in 0..5 {
-                if (self.e_0[i] & (0x1 << k)) == 0 {
-                    bit_val_vec.push(F::zero());
+            let mut x = ArkFr::one();
+            for k in 0..noise_bits {
+                if ((self.e_0[i] + max_noise) & (0x1 << k)) == 0 {
+                    bit_val_vec.push(ArkFr::zero());
                 } else {
-                    bit_val_vec.push(F::one());
+                    bit_val_vec.push(ArkFr::one());
                 }
                 //bit_val_vec.push(F::zero());
                 bit_var_vec.push(cs.new_witness_variable(|| Ok(bit_val_vec[k]))?);
             }
             let mut tmp_lc = LinearCombination::zero();
-            for k in 0..5 {
+            for k in 0..noise_bits {
                 tmp_lc = tmp_lc + (x, bit_var_vec[k]);
                 x = x + x;
                 // bit
                 cs.enforce_constraint(
                     lc!() + bit_var_vec[k],
-                    lc!() + bit_var_vec[k] + (-F::one(), Variable::One),
+                    lc!() + bit_var_vec[k] + (-ArkFr::one(), Variable::One),
                     lc!(),
                 )?;
             }
             // bit decompose
-            cs.enforce_constraint(lc!() + tmp_lc, lc!() + Variable::One, lc!() + e0_var_vec[i])?;
+            cs.enforce_constraint(
+                lc!() + tmp_lc,
+                lc!() + Variable::One,
+                lc!() + e0_var_vec[i] + (ArkFr::from(max_noise as u64), Variable::One),
+            )?;
         }
 
-        // range_proof of e2
-        for i in 0..self.num_dimension {
+        // range_proof of e1
+        for i in 0..num_dimension {
             let mut bit_val_vec = Vec::new();
             let mut bit_var_vec = Vec::new();
-            let mut x = F::one();
-            for k in 0..5 {
-                if (self.e_1[i] & (0x1 << k)) == 0 {
-                    bit_val_vec.push(F::zero());
+            let mut x = ArkFr::one();
+            for k in 0..noise_bits {
+                if ((self.e_1[i] + max_noise) & (0x1 << k)) == 0 {
+                    bit_val_vec.push(ArkFr::zero());
                 } else {
-                    bit_val_vec.push(F::one());
+                    bit_val_vec.push(ArkFr::one());
                 }
                 //bit_val_vec.push(F::zero());
                 bit_var_vec.push(cs.new_witness_variable(|| Ok(bit_val_vec[k]))?);
             }
             let mut tmp_lc = LinearCombination::zero();
-            for k in 0..5 {
+            for k in 0..noise_bits {
                 tmp_lc = tmp_lc + (x, bit_var_vec[k]);
                 x = x + x;
                 cs.enforce_constraint(
                     lc!() + bit_var_vec[k],
-                    lc!() + bit_var_vec[k] + (-F::one(), Variable::One),
+                    lc!() + bit_var_vec[k] + (-ArkFr::one(), Variable::One),
                     lc!(),
                 )?;
             }
             // bit decompose
-            cs.enforce_constraint(lc!() + tmp_lc, lc!() + Variable::One, lc!() + e1_var_vec[i])?;
+            cs.enforce_constraint(
+                lc!() + tmp_lc,
+                lc!() + Variable::One,
+                lc!() + e1_var_vec[i] + (ArkFr::from(max_noise as u64), Variable::One),
+            )?;
         }
 
         // range_proof of m
-        for i in 0..self.num_dimension {
+        let m_bits = self.params.plaintext_bits;
+        for i in 0..num_dimension {
             let mut bit_val_vec = Vec::new();
             let mut bit_var_vec = Vec::new();
-            let mut x = F::one();
-            for k in 0..8 {
+            let mut x = ArkFr::one();
+            for k in 0..m_bits {
                 if (self.m[i] & (0x1 << k)) == 0 {
-                    bit_val_vec.push(F::zero());
+                    bit_val_vec.push(ArkFr::zero());
                 } else {
-                    bit_val_vec.push(F::one());
+                    bit_val_vec.push(ArkFr::one());
                 }
                 //bit_val_vec.push(F::zero());
                 bit_var_vec.push(cs.new_witness_variable(|| Ok(bit_val_vec[k]))?);
             }
             let mut tmp_lc = LinearCombination::zero();
-            for k in 0..8 {
+            for k in 0..m_bits {
                 tmp_lc = tmp_lc + (x, bit_var_vec[k]);
                 cs.enforce_constraint(
                     lc!() + bit_var_vec[k],
-                    lc!() + bit_var_vec[k] + (-F::one(), Variable::One),
+                    lc!() + bit_var_vec[k] + (-ArkFr::one(), Variable::One),
                     lc!(),
                 )?;
                 x = x + x;
@@ -323,25 +421,25 @@ impl<F: Field> ConstraintSynthesizer<F> for Circuit<F> {
             cs.enforce_constraint(lc!() + tmp_lc, lc!() + Variable::One, lc!() + m_var_vec[i])?;
         }
         // range_proof of delta_0
-        for i in 0..self.num_dimension {
+        for i in 0..num_dimension {
             let mut bit_val_vec = Vec::new();
             let mut bit_var_vec = Vec::new();
-            let mut x = F::one();
-            for k in 0..13 {
-                if ((self.delta_0[i] + 4096i128) & (0x1 << k)) == 0 {
-                    bit_val_vec.push(F::zero());
+            let mut x = ArkFr::one();
+            for k in 0..delta_bits {
+                if ((self.delta_0[i] + num_dimension as i128) & (0x1 << k)) == 0 {
+                    bit_val_vec.push(ArkFr::zero());
                 } else {
-                    bit_val_vec.push(F::one());
+                    bit_val_vec.push(ArkFr::one());
                 }
                 //bit_val_vec.push(F::zero());
                 bit_var_vec.push(cs.new_witness_variable(|| Ok(bit_val_vec[k]))?);
             }
             let mut tmp_lc = LinearCombination::zero();
-            for k in 0..13 {
+            for k in 0..delta_bits {
                 tmp_lc = tmp_lc + (x, bit_var_vec[k]);
                 cs.enforce_constraint(
                     lc!() + bit_var_vec[k],
-                    lc!() + bit_var_vec[k] + (-F::one(), Variable::One),
+                    lc!() + bit_var_vec[k] + (-ArkFr::one(), Variable::One),
                     lc!(),
                 )?;
                 x = x + x;
@@ -350,29 +448,29 @@ impl<F: Field> ConstraintSynthesizer<F> for Circuit<F> {
             cs.enforce_constraint(
                 lc!() + tmp_lc,
                 lc!() + Variable::One,
-                lc!() + delta_0_var_vec[i] + (F::from(4096u64), Variable::One),
+                lc!() + delta_0_var_vec[i] + (ArkFr::from(num_dimension as u64), Variable::One),
             )?;
         }
         // range_proof of delta_1
-        for i in 0..self.num_dimension {
+        for i in 0..num_dimension {
             let mut bit_val_vec = Vec::new();
             let mut bit_var_vec = Vec::new();
-            let mut x = F::one();
-            for k in 0..13 {
-                if ((self.delta_1[i] + 4096i128) & (0x1 << k)) == 0 {
-                    bit_val_vec.push(F::zero());
+            let mut x = ArkFr::one();
+            for k in 0..delta_bits {
+                if ((self.delta_1[i] + num_dimension as i128) & (0x1 << k)) == 0 {
+                    bit_val_vec.push(ArkFr::zero());
                 } else {
-                    bit_val_vec.push(F::one());
+                    bit_val_vec.push(ArkFr::one());
                 }
                 //bit_val_vec.push(F::zero());
                 bit_var_vec.push(cs.new_witness_variable(|| Ok(bit_val_vec[k]))?);
             }
             let mut tmp_lc = LinearCombination::zero();
-            for k in 0..13 {
+            for k in 0..delta_bits {
                 tmp_lc = tmp_lc + (x, bit_var_vec[k]);
                 cs.enforce_constraint(
                     lc!() + bit_var_vec[k],
-                    lc!() + bit_var_vec[k] + (-F::one(), Variable::One),
+                    lc!() + bit_var_vec[k] + (-ArkFr::one(), Variable::One),
                     lc!(),
                 )?;
                 x = x + x;
@@ -381,9 +479,31 @@ impl<F: Field> ConstraintSynthesizer<F> for Circuit<F> {
             cs.enforce_constraint(
                 lc!() + tmp_lc,
                 lc!() + Variable::One,
-                lc!() + delta_1_var_vec[i] + (F::from(4096u64), Variable::One),
+                lc!() + delta_1_var_vec[i] + (ArkFr::from(num_dimension as u64), Variable::One),
             )?;
         }
+        // the commitment is the Poseidon sponge of the digest of rsa_pk, the nonce and c0, c1
+        // packed two coefficients per element, see `node::ct_commitment`. The sponge allocates
+        // the last witnesses, see `sponge_gadget`.
+        let elements = ct_elements(&self.rsa_pk_digest, &self.nonce, &self.c_0, &self.c_1);
+        let (element_var_vec, h) = sponge_gadget(&cs, Domain::Ct, &elements)?;
+        let shift = (0..CT_PACK_BITS).fold(ArkFr::one(), |x, _| x.double());
+        let packed = c0_var_vec.chunks(2).chain(c1_var_vec.chunks(2));
+        for (c, x) in packed.zip(element_var_vec[2..].iter()) {
+            let mut l = lc!() + c[0];
+            if c.len() > 1 {
+                l = l + (shift, c[1]);
+            }
+            cs.enforce_constraint(l, lc!() + Variable::One, lc!() + *x)?;
+        }
+        // h = sum of limb_k * 2^(64k), h being below the field modulus
+        let mut limbs = lc!();
+        let mut x = ArkFr::one();
+        for v in commitment_var_vec.iter() {
+            limbs = limbs + (x, *v);
+            x = (0..64).fold(x, |x, _| x.double());
+        }
+        cs.enforce_constraint(lc!() + h, lc!() + Variable::One, limbs)?;
         //eprintln!("# of constraints {}", cs.num_constraints());
         //eprintln!("# of instances {}", cs.num_instance_variables());
         //eprintln!("# of witness {}", cs.num_witness_variables());
@@ -1,6 +1,7 @@
 #![warn(unused)]
 use std::{
     borrow::{Borrow, BorrowMut},
+    convert::TryInto,
     fs::File,
     io::{BufReader, BufWriter},
 };
@@ -17,16 +18,18 @@ use ark_bls12_381::{Bls12_381, Fr, FrParameters, Parameters};
 use ark_ec::models::bls12::Bls12;
 use ark_groth16::{
     create_random_proof, generate_random_parameters, lonhh_create_proof, verify_proof,
-    PreparedVerifyingKey, Proof, ProvingKey, VerifyingKey,
+    verify_proofs_batch, PreparedVerifyingKey, Proof, ProvingKey, VerifyingKey,
 };
-use ark_std::test_rng;
+use ark_std::rand::{rngs::StdRng, SeedableRng};
 mod constraints;
 mod offline;
 mod online;
 use self::offline::CircuitOffline;
 use self::online::CircuitOnline;
-use crate::zksnark::constraints::Circuit;
-const NUM_DIMENSION: usize = 4096;
+use crate::common::aggregation::node::ct_elements;
+use crate::common::hash::Domain;
+use crate::common::params::ParameterSet;
+use crate::zksnark::constraints::{sponge_gadget, Circuit};
 
 pub fn i128_to_field(x: i128) -> Fr {
     if x < 0 {
@@ -37,16 +40,29 @@ pub fn i128_to_field(x: i128) -> Fr {
     }
 }
 
+/// # of 64-bit limbs of a commitment in the public input
+pub const NR_COMMITMENT_LIMBS: usize = 4;
+
+/// The public input of the proof of one ct: c0, c1 and the commitment to the ct, the latter in
+/// 64-bit little endian limbs. The proof shows the commitment opens to c0 and c1 under some
+/// rsa_pk and nonce, see `node::ct_commitment`.
+pub fn statement(c0: &[i128], c1: &[i128], commitment: &[u8; 32]) -> Vec<i128> {
+    let limbs = commitment
+        .chunks(8)
+        .map(|x| u64::from_le_bytes(x.try_into().unwrap()) as i128);
+    c0.iter().chain(c1.iter()).cloned().chain(limbs).collect()
+}
+
 #[derive(Clone)]
 pub struct Prover {
     pub proving_key: ProvingKey<Bls12<Parameters>>,
     pub circuit: Circuit<Fr>,
 }
 impl Prover {
-    pub fn setup(enc_path: &str) -> Self {
-        let c = Circuit::<Fr>::new(NUM_DIMENSION, enc_path);
-        //TODO use OsRng here
-        let rng = &mut test_rng();
+    pub fn setup(params: &ParameterSet, enc_path: &str) -> Self {
+        let c = Circuit::<Fr>::new(params, enc_path);
+        // the toxic waste of the setup must not be reproducible
+        let rng = &mut StdRng::from_seed(rand::random());
 
         let params = generate_random_parameters::<Bls12_381, _, _>(c.clone(), rng).unwrap();
         // write the proving key
@@ -63,8 +79,8 @@ impl Prover {
             circuit: c,
         }
     }
-    pub fn new(enc_path: &str, pvk_path: &str) -> Self {
-        let c = Circuit::<Fr>::new(NUM_DIMENSION, enc_path);
+    pub fn new(params: &ParameterSet, enc_path: &str, pvk_path: &str) -> Self {
+        let c = Circuit::<Fr>::new(params, enc_path);
 
         let pvk = BufReader::new(File::open(pvk_path).unwrap());
 
@@ -86,10 +102,115 @@ impl Prover {
     // pub fn get_circuit(&mut self) -> &mut Circuit<Fr> {
     //     &mut self.circuit
     // }
+    /// the constraint system of the circuit, to be filled in by `assign` for each ct
+    pub fn constraint_system(&self) -> (ConstraintSystemRef<Fr>, ConstraintMatrices<Fr>) {
+        let cs = ConstraintSystem::new_ref();
+        cs.set_optimization_goal(OptimizationGoal::Constraints);
+        self.circuit
+            .clone()
+            .generate_constraints(cs.clone())
+            .unwrap();
+        cs.finalize();
+        let matrices = cs.to_matrices().unwrap();
+        (cs, matrices)
+    }
+
+    /// update the instance and the witness in `cs` to those of one ct, committed to with the
+    /// digest of rsa_pk and the nonce
+    pub fn assign(
+        &self,
+        cs: &ConstraintSystemRef<Fr>,
+        c0: &[i128],
+        c1: &[i128],
+        commitment: &[u8; 32],
+        rsa_pk_digest: &[u8; 32],
+        nonce: &[u8; 16],
+        r: &[i128],
+        e0: &[i128],
+        e1: &[i128],
+        delta0: &[i128],
+        delta1: &[i128],
+        m: &[i128],
+    ) {
+        let params = &self.circuit.params;
+        let (noise_bits, delta_bits) = (params.noise_bits(), params.delta_bits());
+        let offset = params.num_dimension as i128;
+        let max_noise = params.max_noise;
+        //r[i].iter().chain(e0[i].iter()).chain(e1[i].iter)
+        let e0_bit: Vec<i128> = e0
+            .iter()
+            .flat_map(|x| {
+                (0..noise_bits)
+                    .map(|l| ((x + max_noise) >> l) & 0x1)
+                    .collect::<Vec<i128>>()
+            })
+            .collect();
+        let e1_bit: Vec<i128> = e1
+            .iter()
+            .flat_map(|x| {
+                (0..noise_bits)
+                    .map(|l| ((x + max_noise) >> l) & 0x1)
+                    .collect::<Vec<i128>>()
+            })
+            .collect();
+        let m_bit: Vec<i128> = m
+            .iter()
+            .flat_map(|x| {
+                (0..params.plaintext_bits)
+                    .map(|l| (x >> l) & 0x1)
+                    .collect::<Vec<i128>>()
+            })
+            .collect();
+        let delta0_bit: Vec<i128> = delta0
+            .iter()
+            .flat_map(|x| {
+                (0..delta_bits)
+                    .map(|l| ((x + offset) >> l) & 0x1)
+                    .collect::<Vec<i128>>()
+            })
+            .collect();
+        let delta1_bit: Vec<i128> = delta1
+            .iter()
+            .flat_map(|x| {
+                (0..delta_bits)
+                    .map(|l| ((x + offset) >> l) & 0x1)
+                    .collect::<Vec<i128>>()
+            })
+            .collect();
+        r.iter()
+            .chain(e0.iter())
+            .chain(e1.iter())
+            .chain(delta0.iter())
+            .chain(delta1.iter())
+            .chain(m.iter())
+            .chain(e0_bit.iter())
+            .chain(e1_bit.iter())
+            .chain(m_bit.iter())
+            .chain(delta0_bit.iter())
+            .chain(delta1_bit.iter())
+            .zip(cs.borrow_mut().unwrap().witness_assignment.iter_mut())
+            .for_each(|(x, y)| *y = i128_to_field(*x));
+        statement(c0, c1, commitment)
+            .iter()
+            .zip(cs.borrow_mut().unwrap().instance_assignment[1..].iter_mut())
+            .for_each(|(x, y)| *y = i128_to_field(*x));
+        // the witnesses of the sponge of the commitment are the last ones
+        let sponge = ConstraintSystem::new_ref();
+        let elements = ct_elements(rsa_pk_digest, nonce, c0, c1);
+        sponge_gadget(&sponge, Domain::Ct, &elements).unwrap();
+        let tail = sponge.borrow().unwrap().witness_assignment.clone();
+        let mut cs = cs.borrow_mut().unwrap();
+        let offset = cs.witness_assignment.len() - tail.len();
+        cs.witness_assignment[offset..].copy_from_slice(&tail);
+    }
+
     pub fn create_proof_in_bytes(
         &self,
         c0: &Vec<Vec<i128>>,
         c1: &Vec<Vec<i128>>,
+        commitment: &[[u8; 32]],
+        rsa_pk_digest: &[u8; 32],
+        nonce: &[[u8; 16]],
         r: &Vec<Vec<i128>>,
         e0: &Vec<Vec<i128>>,
         e1: &Vec<Vec<i128>>,
@@ -97,67 +218,28 @@ impl Prover {
         delta1: &Vec<Vec<i128>>,
         m: &Vec<Vec<i128>>,
     ) -> Vec<Vec<u8>> {
-        let cs = ConstraintSystem::new_ref();
-        cs.set_optimization_goal(OptimizationGoal::Constraints);
-        self.circuit
-            .clone()
-            .generate_constraints(cs.clone())
-            .unwrap();
-        cs.finalize();
-        let matrices = cs.to_matrices().unwrap();
-        let rng = &mut test_rng();
+        let (cs, matrices) = self.constraint_system();
+        // fresh blinding for every proof, or the proofs are not zero-knowledge
+        let rng = &mut StdRng::from_seed(rand::random());
 
         let mut ret = Vec::with_capacity(c0.len());
         for i in 0..c0.len() {
             let cs = cs.clone();
             // update the witness in constraint system
-            //r[i].iter().chain(e0[i].iter()).chain(e1[i].iter)
-            let e0_bit: Vec<i128> = e0[i]
-                .iter()
-                .flat_map(|x| (0..5).map(|l| (x >> l) & 0x1).collect::<Vec<i128>>())
-                .collect();
-            let e1_bit: Vec<i128> = e1[i]
-                .iter()
-                .flat_map(|x| (0..5).map(|l| (x >> l) & 0x1).collect::<Vec<i128>>())
-                .collect();
-            let m_bit: Vec<i128> = m[i]
-                .iter()
-                .flat_map(|x| (0..8).map(|l| (x >> l) & 0x1).collect::<Vec<i128>>())
-                .collect();
-            let delta0_bit: Vec<i128> = delta0[i]
-                .iter()
-                .flat_map(|x| {
-                    (0..13)
-                        .map(|l| ((x + 4096) >> l) & 0x1)
-                        .collect::<Vec<i128>>()
-                })
-                .collect();
-            let delta1_bit: Vec<i128> = delta1[i]
-                .iter()
-                .flat_map(|x| {
-                    (0..13)
-                        .map(|l| ((x + 4096) >> l) & 0x1)
-                        .collect::<Vec<i128>>()
-                })
-                .collect();
-            r[i].iter()
-                .chain(e0[i].iter())
-                .chain(e1[i].iter())
-                .chain(delta0[i].iter())
-                .chain(delta1[i].iter())
-                .chain(m[i].iter())
-                .chain(e0_bit.iter())
-                .chain(e1_bit.iter())
-                .chain(m_bit.iter())
-                .chain(delta0_bit.iter())
-                .chain(delta1_bit.iter())
-                .zip(cs.borrow_mut().unwrap().witness_assignment.iter_mut())
-                .for_each(|(x, y)| *y = i128_to_field(*x));
-            c0[i]
-                .iter()
-                .chain(c1[0].iter())
-                .zip(cs.borrow_mut().unwrap().instance_assignment[1..].iter_mut())
-                .for_each(|(x, y)| *y = i128_to_field(*x));
+            self.assign(
+                &cs,
+                &c0[i],
+                &c1[i],
+                &commitment[i],
+                rsa_pk_digest,
+                &nonce[i],
+                &r[i],
+                &e0[i],
+                &e1[i],
+                &delta0[i],
+                &delta1[i],
+                &m[i],
+            );
             let rr = Fr::rand(rng);
             let rs = Fr::rand(rng);
             let proof = lonhh_create_proof::<Bls12<Parameters>, Circuit<Fr>>(
@@ -177,6 +259,10 @@ impl Prover {
     pub fn deserialize_proof(pf: &Vec<u8>) -> Proof<Bls12<Parameters>> {
         Proof::<Bls12<Parameters>>::deserialize_unchecked(&**pf).unwrap()
     }
+    /// deserialize a proof from an untrusted party, checking the points are in the group
+    pub fn try_deserialize_proof(pf: &[u8]) -> Option<Proof<Bls12<Parameters>>> {
+        Proof::<Bls12<Parameters>>::deserialize_uncompressed(pf).ok()
+    }
     pub fn serialize_pvk(&self) -> Vec<u8> {
         let mut buf: Vec<u8> = Vec::new();
         self.proving_key.serialize_unchecked(&mut buf).unwrap();
@@ -207,6 +293,47 @@ impl Verifier {
         let n_inputs: Vec<Fr> = inputs.iter().map(|x| i128_to_field(*x)).collect();
         verify_proof(&self.pvk, &pf, &n_inputs).unwrap()
     }
+
+    /// Verify the proofs of several cts at once, `inputs[i]` is the public input of `pfs[i]`.
+    /// Return the indices of the malformed or invalid proofs if any.
+    pub fn verify_proofs_batch_from_bytes(
+        &self,
+        pfs: &[Vec<u8>],
+        inputs: &[Vec<i128>],
+    ) -> Result<(), Vec<usize>> {
+        let nr_inputs = self.pvk.vk.gamma_abc_g1.len() - 1;
+        let mut bad = Vec::new();
+        let mut ids = Vec::with_capacity(pfs.len());
+        let mut proofs = Vec::with_capacity(pfs.len());
+        let mut n_inputs = Vec::with_capacity(pfs.len());
+        for (i, (pf, x)) in pfs.iter().zip(inputs.iter()).enumerate() {
+            match Prover::try_deserialize_proof(pf) {
+                Some(proof) if x.len() == nr_inputs => {
+                    ids.push(i);
+                    proofs.push(proof);
+                    n_inputs.push(x.iter().map(|x| i128_to_field(*x)).collect::<Vec<Fr>>());
+                }
+                _ => bad.push(i),
+            }
+        }
+        bad.extend(pfs.len().min(inputs.len())..pfs.len().max(inputs.len()));
+
+        let mut rng = StdRng::from_seed(rand::random());
+        if !verify_proofs_batch(&self.pvk, &proofs, &n_inputs, &mut rng).unwrap_or(false) {
+            // find out the invalid ones
+            for (k, i) in ids.into_iter().enumerate() {
+                if !verify_proof(&self.pvk, &proofs[k], &n_inputs[k]).unwrap_or(false) {
+                    bad.push(i);
+                }
+            }
+            bad.sort_unstable();
+        }
+        if bad.is_empty() {
+            Ok(())
+        } else {
+            Err(bad)
+        }
+    }
 }
 
 pub struct ProverOnline {
@@ -214,10 +341,10 @@ pub struct ProverOnline {
     pub circuit: CircuitOnline,
 }
 impl ProverOnline {
-    pub fn setup(enc_path: &str) -> Self {
-        let c = CircuitOnline::new(enc_path);
-        //TODO use OsRng here
-        let rng = &mut test_rng();
+    pub fn setup(params: &ParameterSet, enc_path: &str) -> Self {
+        let c = CircuitOnline::new(params, enc_path);
+        // the toxic waste of the setup must not be reproducible
+        let rng = &mut StdRng::from_seed(rand::random());
 
         let params = generate_random_parameters::<Bls12_381, _, _>(c.clone(), rng).unwrap();
         // write the proving key
@@ -234,8 +361,8 @@ impl ProverOnline {
             circuit: c,
         }
     }
-    pub fn new(enc_path: &str, pvk_path: &str) -> Self {
-        let c = CircuitOnline::new(enc_path);
+    pub fn new(params: &ParameterSet, enc_path: &str, pvk_path: &str) -> Self {
+        let c = CircuitOnline::new(params, enc_path);
 
         let pvk = BufReader::new(File::open(pvk_path).unwrap());
 
@@ -287,7 +414,8 @@ impl ProverOnline {
         circuit.generate_constraints(cs.clone()).unwrap();
         cs.finalize();
         let matrices = cs.to_matrices().unwrap();
-        let rng = &mut test_rng();
+        // fresh blinding for every proof, or the proofs are not zero-knowledge
+        let rng = &mut StdRng::from_seed(rand::random());
 
         let mut ret = Vec::with_capacity(c1.len());
         for i in 0..c1.len() {
@@ -320,6 +448,10 @@ impl ProverOnline {
     pub fn deserialize_proof(pf: &Vec<u8>) -> Proof<Bls12<Parameters>> {
         Proof::<Bls12<Parameters>>::deserialize_unchecked(&**pf).unwrap()
     }
+    /// deserialize a proof from an untrusted party, checking the points are in the group
+    pub fn try_deserialize_proof(pf: &[u8]) -> Option<Proof<Bls12<Parameters>>> {
+        Proof::<Bls12<Parameters>>::deserialize_uncompressed(pf).ok()
+    }
     pub fn serialize_pvk(&self) -> Vec<u8> {
         let mut buf: Vec<u8> = Vec::new();
         self.proving_key.serialize_unchecked(&mut buf).unwrap();
@@ -332,10 +464,10 @@ pub struct ProverOffline {
     pub circuit: CircuitOffline,
 }
 impl ProverOffline {
-    pub fn setup(enc_path: &str) -> Self {
-        let c = CircuitOffline::new(enc_path);
-        //TODO use OsRng here
-        let rng = &mut test_rng();
+    pub fn setup(params: &ParameterSet, enc_path: &str) -> Self {
+        let c = CircuitOffline::new(params, enc_path);
+        // the toxic waste of the setup must not be reproducible
+        let rng = &mut StdRng::from_seed(rand::random());
 
         let params = generate_random_parameters::<Bls12_381, _, _>(c.clone(), rng).unwrap();
         // write the proving key
@@ -352,8 +484,8 @@ impl ProverOffline {
             circuit: c,
         }
     }
-    pub fn new(enc_path: &str, pvk_path: &str) -> Self {
-        let c = CircuitOffline::new(enc_path);
+    pub fn new(params: &ParameterSet, enc_path: &str, pvk_path: &str) -> Self {
+        let c = CircuitOffline::new(params, enc_path);
 
         let pvk = BufReader::new(File::open(pvk_path).unwrap());
 
@@ -404,7 +536,8 @@ impl ProverOffline {
         circuit.generate_constraints(cs.clone()).unwrap();
         cs.finalize();
         let matrices = cs.to_matrices().unwrap();
-        let rng = &mut test_rng();
+        // fresh blinding for every proof, or the proofs are not zero-knowledge
+        let rng = &mut StdRng::from_seed(rand::random());
 
         let mut ret = Vec::with_capacity(c0.len());
         for i in 0..c0.len() {
@@ -437,9 +570,135 @@ impl ProverOffline {
     pub fn deserialize_proof(pf: &Vec<u8>) -> Proof<Bls12<Parameters>> {
         Proof::<Bls12<Parameters>>::deserialize_unchecked(&**pf).unwrap()
     }
+    /// deserialize a proof from an untrusted party, checking the points are in the group
+    pub fn try_deserialize_proof(pf: &[u8]) -> Option<Proof<Bls12<Parameters>>> {
+        Proof::<Bls12<Parameters>>::deserialize_uncompressed(pf).ok()
+    }
     pub fn serialize_pvk(&self) -> Vec<u8> {
         let mut buf: Vec<u8> = Vec::new();
         self.proving_key.serialize_unchecked(&mut buf).unwrap();
         buf
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::aggregation::node::{ct_commitment, rsa_pk_digest};
+    use crate::common::hash::HashFunction;
+    use crate::common::params;
+    use crate::rlwe::PublicKey;
+    use ark_std::test_rng;
+    use rand::Rng;
+
+    const NR_CT: usize = 3;
+
+    #[test]
+    fn test_prove_every_ct() {
+        // small enough to prove in a test, not secure
+        let params = ParameterSet::new(16, params::RNS_MODULUS_4096.to_vec());
+        let n = params.num_dimension;
+        let mut rng = rand::thread_rng();
+        let pk0: Vec<i128> = (0..n).map(|_| rng.gen_range(0..params.modulus)).collect();
+        let pk1: Vec<i128> = (0..n).map(|_| rng.gen_range(0..params.modulus)).collect();
+        let pk = PublicKey::new(&params, &pk0, &pk1);
+
+        let circuit = Circuit::<Fr>::from_pk(&params, &pk0, &pk1);
+        let params =
+            generate_random_parameters::<Bls12_381, _, _>(circuit.clone(), &mut test_rng())
+                .unwrap();
+        let verifier = Verifier {
+            pvk: params.vk.clone().into(),
+        };
+        let prover = Prover {
+            proving_key: params,
+            circuit,
+        };
+
+        let (mut c0, mut c1, mut r, mut e0, mut e1, mut d0, mut d1, mut m) = (
+            vec![],
+            vec![],
+            vec![],
+            vec![],
+            vec![],
+            vec![],
+            vec![],
+            vec![],
+        );
+        for _ in 0..NR_CT {
+            let x: Vec<u16> = (0..n).map(|_| rng.gen_range(0..256)).collect();
+            let (ri, e0i, e1i, d0i, d1i, ct) = pk.encrypt(&x);
+            c0.push(ct.c_0);
+            c1.push(ct.c_1);
+            r.push(ri);
+            e0.push(e0i);
+            e1.push(e1i);
+            d0.push(d0i);
+            d1.push(d1i);
+            m.push(x.iter().map(|x| *x as i128).collect::<Vec<i128>>());
+        }
+
+        let rsa_pk = vec![3u8; 270];
+        let digest = rsa_pk_digest(HashFunction::Blake3, &rsa_pk);
+        let nonce: Vec<[u8; 16]> = (0..NR_CT).map(|_| rng.gen()).collect();
+        let cm: Vec<[u8; 32]> = (0..NR_CT)
+            .map(|i| ct_commitment(HashFunction::Blake3, &rsa_pk, &nonce[i], &c0[i], &c1[i]))
+            .collect();
+
+        // the witness of every ct satisfies the circuit
+        let (cs, _) = prover.constraint_system();
+        for i in 0..NR_CT {
+            prover.assign(
+                &cs, &c0[i], &c1[i], &cm[i], &digest, &nonce[i], &r[i], &e0[i], &e1[i], &d0[i],
+                &d1[i], &m[i],
+            );
+            assert_eq!(cs.which_is_unsatisfied().unwrap(), None, "ct {}", i);
+        }
+        // but not under a commitment to another ct, or with another nonce
+        prover.assign(
+            &cs, &c0[1], &c1[1], &cm[0], &digest, &nonce[1], &r[1], &e0[1], &e1[1], &d0[1], &d1[1],
+            &m[1],
+        );
+        assert!(cs.which_is_unsatisfied().unwrap().is_some());
+        prover.assign(
+            &cs, &c0[1], &c1[1], &cm[1], &digest, &nonce[0], &r[1], &e0[1], &e1[1], &d0[1], &d1[1],
+            &m[1],
+        );
+        assert!(cs.which_is_unsatisfied().unwrap().is_some());
+
+        let proofs = prover
+            .create_proof_in_bytes(&c0, &c1, &cm, &digest, &nonce, &r, &e0, &e1, &d0, &d1, &m);
+        let inputs: Vec<Vec<i128>> = (0..NR_CT)
+            .map(|i| statement(&c0[i], &c1[i], &cm[i]))
+            .collect();
+        for i in 0..NR_CT {
+            let proof = Prover::deserialize_proof(&proofs[i]);
+            assert!(verifier.verify_proof(&proof, &inputs[i]), "ct {}", i);
+        }
+        assert_eq!(
+            verifier.verify_proofs_batch_from_bytes(&proofs, &inputs),
+            Ok(())
+        );
+
+        // the proof is bound to the c1 of its own ct
+        let wrong = statement(&c0[1], &c1[0], &cm[1]);
+        assert!(!verifier.verify_proof(&Prover::deserialize_proof(&proofs[1]), &wrong));
+        // and to its own commitment, so it can't be replayed under another nonce or rsa_pk
+        let wrong = statement(&c0[1], &c1[1], &cm[0]);
+        assert!(!verifier.verify_proof(&Prover::deserialize_proof(&proofs[1]), &wrong));
+
+        // a proof under a commitment to another ct doesn't verify, as the commitment doesn't
+        // open to the ct of the statement
+        let cm_wrong = vec![cm[1], cm[0], cm[2]];
+        let proofs = prover.create_proof_in_bytes(
+            &c0, &c1, &cm_wrong, &digest, &nonce, &r, &e0, &e1, &d0, &d1, &m,
+        );
+        let inputs: Vec<Vec<i128>> = (0..NR_CT)
+            .map(|i| statement(&c0[i], &c1[i], &cm_wrong[i]))
+            .collect();
+        assert_eq!(
+            verifier.verify_proofs_batch_from_bytes(&proofs, &inputs),
+            Err(vec![0, 1])
+        );
+    }
+}
@@ -1,3 +1,4 @@
+use crate::common::params::ParameterSet;
 use ark_bls12_381::Fr as ArkFr;
 use ark_ff::{BigInteger, BigInteger256, Field, One, Zero};
 use ark_relations::{
@@ -21,12 +22,12 @@ use typenum::*;
 
 #[derive(Clone)]
 pub struct CircuitOffline {
-    pub num_dimension: usize,
-    pub c_0: [i128; 4096],
-    pub r: [i128; 4096],
-    pub e_0: [i128; 4096],
-    pub pk_0: [i128; 4096],
-    pub delta_0: [i128; 4096],
+    pub params: ParameterSet,
+    pub c_0: Vec<i128>,
+    pub r: Vec<i128>,
+    pub e_0: Vec<i128>,
+    pub pk_0: Vec<i128>,
+    pub delta_0: Vec<i128>,
     pub nonce: [u8; 32],
     pub hash: [u8; 32],
     constants: PoseidonConstants<Bls12, typenum::U34>,
@@ -35,13 +36,13 @@ pub struct CircuitOffline {
     pub _engine: PhantomData<ArkFr>,
 }
 impl CircuitOffline {
-    pub fn new(enc_path: &str) -> Self {
-        let num_dimension = 4096;
-        let mut c_0 = [0i128; 4096];
-        let mut r = [0i128; 4096];
-        let mut e_0 = [0i128; 4096];
-        let mut pk_0 = [0i128; 4096];
-        let mut delta_0 = [0i128; 4096];
+    pub fn new(params: &ParameterSet, enc_path: &str) -> Self {
+        let num_dimension = params.num_dimension;
+        let mut c_0 = vec![0i128; num_dimension];
+        let mut r = vec![0i128; num_dimension];
+        let mut e_0 = vec![0i128; num_dimension];
+        let mut pk_0 = vec![0i128; num_dimension];
+        let mut delta_0 = vec![0i128; num_dimension];
         let nonce = [0u8; 32];
         let hash = [0u8; 32];
         let file = File::open(enc_path).unwrap();
@@ -53,7 +54,7 @@ impl CircuitOffline {
         for line in reader.lines() {
             if let Ok(l) = line {
                 let vec = l.split(" ").collect::<Vec<&str>>();
-                for i in 1..vec.len() {
+                for i in 1..vec.len().min(num_dimension + 1) {
                     if l.contains("r") {
                         if let Ok(x) = i128::from_str_radix(vec[i], 10) {
                             r[i - 1] = x;
@@ -79,7 +80,7 @@ impl CircuitOffline {
             }
         }
         Self {
-            num_dimension,
+            params: params.clone(),
             c_0,
             r,
             e_0,
@@ -118,27 +119,29 @@ impl ConstraintSynthesizer<ArkFr> for CircuitOffline {
             .iter()
             .map(|&x| self.i128toField(x))
             .collect::<Vec<_>>();
-        let pf = ArkFr::from(0xffffee001u64)
-            * ArkFr::from(0xffffc4001u64)
-            * ArkFr::from(0x1ffffe0001u64);
+        let num_dimension = self.params.num_dimension;
+        let noise_bits = self.params.noise_bits();
+        let delta_bits = self.params.delta_bits();
+        let max_noise = self.params.max_noise;
+        let pf = self.i128toField(self.params.modulus);
 
         // c0
         let mut c0_val_vec = Vec::new();
         let mut c0_var_vec = Vec::new();
-        for i in 0..self.num_dimension {
+        for i in 0..num_dimension {
             c0_val_vec.push(self.i128toField(self.c_0[i]));
             c0_var_vec.push(cs.new_input_variable(|| Ok(c0_val_vec[i]))?);
         }
         // r
-        // r_bit
+        // r_bit, the bits of r + 1 in {0, 1, 2}
         let mut r_bit_val_vec = Vec::new();
         let mut r_bit_var_vec = Vec::new();
         let mut r_agg_val_vec = Vec::new();
         let mut r_agg_var_vec = Vec::new();
-        for i in 0..self.num_dimension {
-            r_bit_val_vec.push(self.i128toField(self.r[i] & 0x1));
+        for i in 0..num_dimension {
+            r_bit_val_vec.push(self.i128toField((self.r[i] + 1) & 0x1));
             r_bit_var_vec.push(cs.new_witness_variable(|| Ok(r_bit_val_vec[2 * i]))?);
-            r_bit_val_vec.push(self.i128toField(self.r[i] & 0x2));
+            r_bit_val_vec.push(self.i128toField(((self.r[i] + 1) >> 1) & 0x1));
             r_bit_var_vec.push(cs.new_witness_variable(|| Ok(r_bit_val_vec[2 * i + 1]))?);
         }
         // aggregate of all bits into 34 elements
@@ -175,34 +178,38 @@ impl ConstraintSynthesizer<ArkFr> for CircuitOffline {
         // e0
         let mut e0_val_vec = Vec::new();
         let mut e0_var_vec = Vec::new();
-        for i in 0..self.num_dimension {
+        for i in 0..num_dimension {
             e0_val_vec.push(self.i128toField(self.e_0[i]));
             e0_var_vec.push(cs.new_witness_variable(|| Ok(e0_val_vec[i]))?);
         }
         // delta_0
         let mut delta_0_val_vec = Vec::new();
         let mut delta_0_var_vec = Vec::new();
-        for i in 0..self.num_dimension {
+        for i in 0..num_dimension {
             delta_0_val_vec.push(self.i128toField(self.delta_0[i]));
             delta_0_var_vec.push(cs.new_witness_variable(|| Ok(delta_0_val_vec[i]))?);
         }
         // pk_0 * r + e_0 = c_0 + delta_0 * q
-        for i in 0..self.num_dimension {
+        for i in 0..num_dimension {
             let mut elc = LinearCombination::zero();
-            //(pk * r) [i]
+            //(pk * r) [i] = (pk * (r + 1)) [i] - (pk * 1) [i]
             //pk[j] * r[i-j%4096]
-            for j in 0..self.num_dimension {
-                let k = (i - j) % self.num_dimension;
-                if j + k >= self.num_dimension {
+            let mut pk_one = ArkFr::zero();
+            for j in 0..num_dimension {
+                let k = (i + num_dimension - j) % num_dimension;
+                if j + k >= num_dimension {
                     elc.0.push((-pk_0[j], r_bit_var_vec[2 * k]));
                     elc.0
                         .push((-pk_0[j] * ArkFr::from(2u64), r_bit_var_vec[2 * k + 1]));
+                    pk_one -= pk_0[j];
                 } else {
                     elc.0.push((pk_0[j], r_bit_var_vec[2 * k]));
                     elc.0
                         .push((pk_0[j] * ArkFr::from(2u64), r_bit_var_vec[2 * k + 1]));
+                    pk_one += pk_0[j];
                 }
             }
+            elc.0.push((-pk_one, Variable::One));
             elc.0.sort_by_key(|e| e.1);
             cs.enforce_constraint(
                 lc!() + elc + e0_var_vec[i],
@@ -210,13 +217,13 @@ impl ConstraintSynthesizer<ArkFr> for CircuitOffline {
                 lc!() + c0_var_vec[i] + (pf, delta_0_var_vec[i]),
             )?;
         }
-        // range_proof of e0 [-19,19] -> [0,38]
-        for i in 0..self.num_dimension {
+        // range_proof of e0 [-max_noise, max_noise] -> [0, 2 * max_noise]
+        for i in 0..num_dimension {
             let mut bit_val_vec = Vec::new();
             let mut bit_var_vec = Vec::new();
             let mut x = ArkFr::one();
-            for k in 0..5 {
-                if (self.e_0[i] & (0x1 << k)) == 0 {
+            for k in 0..noise_bits {
+                if ((self.e_0[i] + max_noise) & (0x1 << k)) == 0 {
                     bit_val_vec.push(ArkFr::zero());
                 } else {
                     bit_val_vec.push(ArkFr::one());
@@ -224,7 +231,7 @@ impl ConstraintSynthesizer<ArkFr> for CircuitOffline {
                 bit_var_vec.push(cs.new_witness_variable(|| Ok(bit_val_vec[k]))?);
             }
             let mut tmp_lc = LinearCombination::zero();
-            for k in 0..5 {
+            for k in 0..noise_bits {
                 tmp_lc = tmp_lc + (x, bit_var_vec[k]);
                 x = x + x;
                 // bit
@@ -235,15 +242,19 @@ impl ConstraintSynthesizer<ArkFr> for CircuitOffline {
                 )?;
             }
             // bit decompose
-            cs.enforce_constraint(lc!() + tmp_lc, lc!() + Variable::One, lc!() + e0_var_vec[i])?;
+            cs.enforce_constraint(
+                lc!() + tmp_lc,
+                lc!() + Variable::One,
+                lc!() + e0_var_vec[i] + (ArkFr::from(max_noise as u64), Variable::One),
+            )?;
         }
         // range_proof of delta_0
-        for i in 0..self.num_dimension {
+        for i in 0..num_dimension {
             let mut bit_val_vec = Vec::new();
             let mut bit_var_vec = Vec::new();
             let mut x = ArkFr::one();
-            for k in 0..13 {
-                if ((self.delta_0[i] + 4096i128) & (0x1 << k)) == 0 {
+            for k in 0..delta_bits {
+                if ((self.delta_0[i] + num_dimension as i128) & (0x1 << k)) == 0 {
                     bit_val_vec.push(ArkFr::zero());
                 } else {
                     bit_val_vec.push(ArkFr::one());
@@ -251,7 +262,7 @@ impl ConstraintSynthesizer<ArkFr> for CircuitOffline {
                 bit_var_vec.push(cs.new_witness_variable(|| Ok(bit_val_vec[k]))?);
             }
             let mut tmp_lc = LinearCombination::zero();
-            for k in 0..13 {
+            for k in 0..delta_bits {
                 tmp_lc = tmp_lc + (x, bit_var_vec[k]);
                 cs.enforce_constraint(
                     lc!() + bit_var_vec[k],
@@ -264,9 +275,17 @@ impl ConstraintSynthesizer<ArkFr> for CircuitOffline {
             cs.enforce_constraint(
                 lc!() + tmp_lc,
                 lc!() + Variable::One,
-                lc!() + delta_0_var_vec[i] + (ArkFr::from(4096u64), Variable::One),
+                lc!() + delta_0_var_vec[i] + (ArkFr::from(num_dimension as u64), Variable::One),
             )?;
         }
+        // the bits of r are packed into at most `arity` elements, the rest is padded with zeros
+        assert!(
+            r_agg_val_vec.len() <= self.arity,
+            "dimension {} does not fit the Poseidon arity {}",
+            num_dimension,
+            self.arity
+        );
+        r_agg_val_vec.resize(self.arity, ArkFr::zero());
         let data: Vec<AllocatedNum<Bls12>> = r_agg_val_vec
             .iter()
             .map(|x| AllocatedNum::alloc(&cs, || Ok(neptune::bls381num::ark2bp(*x))).unwrap())
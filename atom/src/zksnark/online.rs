@@ -1,3 +1,4 @@
+use crate::common::params::ParameterSet;
 use ark_bls12_381::Fr as ArkFr;
 use ark_ff::{BigInteger, BigInteger256, Field, One, Zero};
 use ark_relations::{
@@ -11,6 +12,7 @@ use ark_std::{end_timer, start_timer, test_rng};
 use bellperson::bls::{Bls12, Fr as BPFr};
 use ff::{Field as BPField, ScalarEngine as BPEngine};
 use neptune::{self, bls381num::AllocatedNum, poseidon::PoseidonConstants, Arity};
+use rand::{rngs::OsRng, RngCore};
 use std::{
     any::type_name,
     fs::File,
@@ -20,13 +22,13 @@ use std::{
 use typenum::*;
 #[derive(Clone)]
 pub struct CircuitOnline {
-    pub num_dimension: usize,
-    pub c_0: [i128; 4096],
-    pub r: [i128; 4096],
-    pub e_0: [i128; 4096],
-    pub m: [i128; 4096],
-    pub pk_0: [i128; 4096],
-    pub delta_0: [i128; 4096],
+    pub params: ParameterSet,
+    pub c_0: Vec<i128>,
+    pub r: Vec<i128>,
+    pub e_0: Vec<i128>,
+    pub m: Vec<i128>,
+    pub pk_0: Vec<i128>,
+    pub delta_0: Vec<i128>,
     constants: PoseidonConstants<Bls12, typenum::U34>,
     arity: usize,
     // 2 256-bit numbers used in hashing function
@@ -36,21 +38,23 @@ pub struct CircuitOnline {
     pub _engine: PhantomData<ArkFr>,
 }
 impl CircuitOnline {
-    pub fn new(enc_path: &str) -> Self {
-        let num_dimension = 4096usize;
-        let mut c_0 = [0i128; 4096];
-        let mut r = [0i128; 4096];
-        let mut e_0 = [0i128; 4096];
-        let mut pk_0 = [0i128; 4096];
-        let mut delta_0 = [0i128; 4096];
+    pub fn new(params: &ParameterSet, enc_path: &str) -> Self {
+        let num_dimension = params.num_dimension;
+        let mut c_0 = vec![0i128; num_dimension];
+        let mut r = vec![0i128; num_dimension];
+        let mut e_0 = vec![0i128; num_dimension];
+        let mut pk_0 = vec![0i128; num_dimension];
+        let mut delta_0 = vec![0i128; num_dimension];
         let hash = [0u8; 32];
-        let nonce = [0u8; 32];
+        // a fresh nonce from the OS, only the low 16 bytes so it is below the field modulus
+        let mut nonce = [0u8; 32];
+        OsRng.fill_bytes(&mut nonce[..16]);
         let file = File::open(enc_path).unwrap();
         let reader = BufReader::new(file);
         for line in reader.lines() {
             if let Ok(l) = line {
                 let vec = l.split(" ").collect::<Vec<&str>>();
-                for i in 1..vec.len() {
+                for i in 1..vec.len().min(num_dimension + 1) {
                     if l.contains("pk_0") {
                         if let Ok(x) = i128::from_str_radix(vec[i], 10) {
                             pk_0[i - 1] = x;
@@ -85,11 +89,11 @@ impl CircuitOnline {
         );
         let arity = typenum::U34::to_usize();
         Self {
-            num_dimension,
+            params: params.clone(),
             c_0,
             r,
             e_0,
-            m: [0i128; 4096],
+            m: vec![0i128; num_dimension],
             pk_0,
             delta_0,
             constants,
@@ -125,27 +129,30 @@ impl ConstraintSynthesizer<ArkFr> for CircuitOnline {
             .iter()
             .map(|&x| self.i128toField(x))
             .collect::<Vec<_>>();
-        let pf = ArkFr::from(0xffffee001u64)
-            * ArkFr::from(0xffffc4001u64)
-            * ArkFr::from(0x1ffffe0001u64);
+        let num_dimension = self.params.num_dimension;
+        let noise_bits = self.params.noise_bits();
+        let delta_bits = self.params.delta_bits();
+        let max_noise = self.params.max_noise;
+        let pf = self.i128toField(self.params.modulus);
+        let scale = self.i128toField(self.params.plaintext_scale());
 
         // c0
         let mut c0_val_vec = Vec::new();
         let mut c0_var_vec = Vec::new();
-        for i in 0..self.num_dimension {
+        for i in 0..num_dimension {
             c0_val_vec.push(self.i128toField(self.c_0[i]));
             c0_var_vec.push(cs.new_input_variable(|| Ok(c0_val_vec[i]))?);
         }
         // r
-        // r_bit
+        // r_bit, the bits of r + 1 in {0, 1, 2}
         let mut r_bit_val_vec = Vec::new();
         let mut r_bit_var_vec = Vec::new();
         let mut r_agg_val_vec = Vec::new();
         let mut r_agg_var_vec = Vec::new();
-        for i in 0..self.num_dimension {
-            r_bit_val_vec.push(self.i128toField(self.r[i] & 0x1));
+        for i in 0..num_dimension {
+            r_bit_val_vec.push(self.i128toField((self.r[i] + 1) & 0x1));
             r_bit_var_vec.push(cs.new_witness_variable(|| Ok(r_bit_val_vec[2 * i]))?);
-            r_bit_val_vec.push(self.i128toField((self.r[i] >> 1) & 0x1));
+            r_bit_val_vec.push(self.i128toField(((self.r[i] + 1) >> 1) & 0x1));
             r_bit_var_vec.push(cs.new_witness_variable(|| Ok(r_bit_val_vec[2 * i + 1]))?);
         }
         // aggregate of all bits into 33 elements + one random elements
@@ -184,55 +191,59 @@ impl ConstraintSynthesizer<ArkFr> for CircuitOnline {
         // e0
         let mut e0_val_vec = Vec::new();
         let mut e0_var_vec = Vec::new();
-        for i in 0..self.num_dimension {
+        for i in 0..num_dimension {
             e0_val_vec.push(self.i128toField(self.e_0[i]));
             e0_var_vec.push(cs.new_witness_variable(|| Ok(e0_val_vec[i]))?);
         }
         // delta_0
         let mut delta_0_val_vec = Vec::new();
         let mut delta_0_var_vec = Vec::new();
-        for i in 0..self.num_dimension {
+        for i in 0..num_dimension {
             delta_0_val_vec.push(self.i128toField(self.delta_0[i]));
             delta_0_var_vec.push(cs.new_witness_variable(|| Ok(delta_0_val_vec[i]))?);
         }
         // m
         let mut m_val_vec = Vec::new();
         let mut m_var_vec = Vec::new();
-        for i in 0..self.num_dimension {
+        for i in 0..num_dimension {
             m_val_vec.push(self.i128toField(self.m[i]));
             m_var_vec.push(cs.new_witness_variable(|| Ok(m_val_vec[i]))?);
         }
-        // pk_0 * r + e_0 + m = c_0 + delta_0 * q
-        for i in 0..self.num_dimension {
+        // pk_0 * r + e_0 + Δ * m = c_0 + delta_0 * q
+        for i in 0..num_dimension {
             let mut elc = LinearCombination::zero();
-            //(pk * r) [i]
+            //(pk * r) [i] = (pk * (r + 1)) [i] - (pk * 1) [i]
             //pk[j] * r[i-j%4096]
-            for j in 0..self.num_dimension {
-                let k = (i - j) % self.num_dimension;
-                if j + k >= self.num_dimension {
+            let mut pk_one = ArkFr::zero();
+            for j in 0..num_dimension {
+                let k = (i + num_dimension - j) % num_dimension;
+                if j + k >= num_dimension {
                     elc.0.push((-pk_0[j], r_bit_var_vec[2 * k]));
                     elc.0
                         .push((-pk_0[j] * ArkFr::from(2u64), r_bit_var_vec[2 * k + 1]));
+                    pk_one -= pk_0[j];
                 } else {
                     elc.0.push((pk_0[j], r_bit_var_vec[2 * k]));
                     elc.0
                         .push((pk_0[j] * ArkFr::from(2u64), r_bit_var_vec[2 * k + 1]));
+                    pk_one += pk_0[j];
                 }
             }
+            elc.0.push((-pk_one, Variable::One));
             elc.0.sort_by_key(|e| e.1);
             cs.enforce_constraint(
-                lc!() + elc + e0_var_vec[i] + m_var_vec[i],
+                lc!() + elc + e0_var_vec[i] + (scale, m_var_vec[i]),
                 lc!() + Variable::One,
                 lc!() + c0_var_vec[i] + (pf, delta_0_var_vec[i]),
             )?;
         }
-        // range_proof of e0 [-19,19] -> [0,38]
-        for i in 0..self.num_dimension {
+        // range_proof of e0 [-max_noise, max_noise] -> [0, 2 * max_noise]
+        for i in 0..num_dimension {
             let mut bit_val_vec = Vec::new();
             let mut bit_var_vec = Vec::new();
             let mut x = ArkFr::one();
-            for k in 0..5 {
-                if (self.e_0[i] & (0x1 << k)) == 0 {
+            for k in 0..noise_bits {
+                if ((self.e_0[i] + max_noise) & (0x1 << k)) == 0 {
                     bit_val_vec.push(ArkFr::zero());
                 } else {
                     bit_val_vec.push(ArkFr::one());
@@ -240,7 +251,7 @@ impl ConstraintSynthesizer<ArkFr> for CircuitOnline {
                 bit_var_vec.push(cs.new_witness_variable(|| Ok(bit_val_vec[k]))?);
             }
             let mut tmp_lc = LinearCombination::zero();
-            for k in 0..5 {
+            for k in 0..noise_bits {
                 tmp_lc = tmp_lc + (x, bit_var_vec[k]);
                 x = x + x;
                 // bit
@@ -251,14 +262,19 @@ impl ConstraintSynthesizer<ArkFr> for CircuitOnline {
                 )?;
             }
             // bit decompose
-            cs.enforce_constraint(lc!() + tmp_lc, lc!() + Variable::One, lc!() + e0_var_vec[i])?;
+            cs.enforce_constraint(
+                lc!() + tmp_lc,
+                lc!() + Variable::One,
+                lc!() + e0_var_vec[i] + (ArkFr::from(max_noise as u64), Variable::One),
+            )?;
         }
         // range_proof of m
-        for i in 0..self.num_dimension {
+        let m_bits = self.params.plaintext_bits;
+        for i in 0..num_dimension {
             let mut bit_val_vec = Vec::new();
             let mut bit_var_vec = Vec::new();
             let mut x = ArkFr::one();
-            for k in 0..8 {
+            for k in 0..m_bits {
                 if (self.m[i] & (0x1 << k)) == 0 {
                     bit_val_vec.push(ArkFr::zero());
                 } else {
@@ -267,7 +283,7 @@ impl ConstraintSynthesizer<ArkFr> for CircuitOnline {
                 bit_var_vec.push(cs.new_witness_variable(|| Ok(bit_val_vec[k]))?);
             }
             let mut tmp_lc = LinearCombination::zero();
-            for k in 0..8 {
+            for k in 0..m_bits {
                 tmp_lc = tmp_lc + (x, bit_var_vec[k]);
                 cs.enforce_constraint(
                     lc!() + bit_var_vec[k],
@@ -280,12 +296,12 @@ impl ConstraintSynthesizer<ArkFr> for CircuitOnline {
             cs.enforce_constraint(lc!() + tmp_lc, lc!() + Variable::One, lc!() + m_var_vec[i])?;
         }
         // range_proof of delta_0
-        for i in 0..self.num_dimension {
+        for i in 0..num_dimension {
             let mut bit_val_vec = Vec::new();
             let mut bit_var_vec = Vec::new();
             let mut x = ArkFr::one();
-            for k in 0..13 {
-                if ((self.delta_0[i] + 4096i128) & (0x1 << k)) == 0 {
+            for k in 0..delta_bits {
+                if ((self.delta_0[i] + num_dimension as i128) & (0x1 << k)) == 0 {
                     bit_val_vec.push(ArkFr::zero());
                 } else {
                     bit_val_vec.push(ArkFr::one());
@@ -293,12 +309,12 @@ impl ConstraintSynthesizer<ArkFr> for CircuitOnline {
                 bit_var_vec.push(cs.new_witness_variable(|| Ok(bit_val_vec[k]))?);
             }
             let mut tmp_lc = LinearCombination::zero();
-            for k in 0..13 {
+            for k in 0..delta_bits {
                 tmp_lc = tmp_lc + (x, bit_var_vec[k]);
                 cs.enforce_constraint(
                     lc!() + bit_var_vec[k],
                     lc!() + bit_var_vec[k] + (-ArkFr::one(), Variable::One),
-                    lc!() + (ArkFr::from(4096u64), Variable::One),
+                    lc!() + (ArkFr::from(num_dimension as u64), Variable::One),
                 )?;
                 x = x + x;
             }
@@ -306,10 +322,18 @@ impl ConstraintSynthesizer<ArkFr> for CircuitOnline {
             cs.enforce_constraint(
                 lc!() + tmp_lc,
                 lc!() + Variable::One,
-                lc!() + delta_0_var_vec[i] + (ArkFr::from(4096u64), Variable::One),
+                lc!() + delta_0_var_vec[i] + (ArkFr::from(num_dimension as u64), Variable::One),
             )?;
         }
 
+        // the bits of r are packed into at most `arity` elements, the rest is padded with zeros
+        assert!(
+            r_agg_val_vec.len() <= self.arity,
+            "dimension {} does not fit the Poseidon arity {}",
+            num_dimension,
+            self.arity
+        );
+        r_agg_val_vec.resize(self.arity, ArkFr::zero());
         let data: Vec<AllocatedNum<Bls12>> = r_agg_val_vec
             .iter()
             .map(|x| AllocatedNum::alloc(&cs, || Ok(neptune::bls381num::ark2bp(*x))).unwrap())
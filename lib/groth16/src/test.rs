@@ -1,6 +1,6 @@
 use crate::{
     create_random_proof, generate_random_parameters, prepare_verifying_key, rerandomize_proof,
-    verify_proof,
+    verify_proof, verify_proofs_batch,
 };
 use ark_ec::PairingEngine;
 use ark_ff::UniformRand;
@@ -77,6 +77,46 @@ where
     }
 }
 
+fn test_batch_verify<E>(n_proofs: usize)
+where
+    E: PairingEngine,
+{
+    let rng = &mut test_rng();
+
+    let params =
+        generate_random_parameters::<E, _, _>(MySillyCircuit { a: None, b: None }, rng).unwrap();
+
+    let pvk = prepare_verifying_key::<E>(&params.vk);
+
+    let mut proofs = Vec::new();
+    let mut inputs = Vec::new();
+    for _ in 0..n_proofs {
+        let a = E::Fr::rand(rng);
+        let b = E::Fr::rand(rng);
+        let proof = create_random_proof(
+            MySillyCircuit {
+                a: Some(a),
+                b: Some(b),
+            },
+            &params,
+            rng,
+        )
+        .unwrap();
+        proofs.push(proof);
+        inputs.push(vec![a * &b]);
+    }
+
+    assert!(verify_proofs_batch(&pvk, &proofs, &inputs, rng).unwrap());
+
+    // a single wrong instance fails the whole batch
+    inputs[n_proofs - 1] = vec![E::Fr::zero()];
+    assert!(!verify_proofs_batch(&pvk, &proofs, &inputs, rng).unwrap());
+
+    // the # of inputs must match the verifying key
+    inputs[0] = vec![];
+    assert!(verify_proofs_batch(&pvk, &proofs, &inputs, rng).is_err());
+}
+
 fn test_rerandomize<E>()
 where
     E: PairingEngine,
@@ -126,7 +166,7 @@ where
 }
 
 mod bls12_377 {
-    use super::{test_prove_and_verify, test_rerandomize};
+    use super::{test_batch_verify, test_prove_and_verify, test_rerandomize};
     use ark_bls12_377::Bls12_377;
 
     #[test]
@@ -134,6 +174,11 @@ mod bls12_377 {
         test_prove_and_verify::<Bls12_377>(100);
     }
 
+    #[test]
+    fn batch_verify() {
+        test_batch_verify::<Bls12_377>(10);
+    }
+
     #[test]
     fn rerandomize() {
         test_rerandomize::<Bls12_377>();
@@ -1,5 +1,6 @@
-use ark_ec::{AffineCurve, PairingEngine, ProjectiveCurve};
-use ark_ff::PrimeField;
+use ark_ec::{msm::VariableBaseMSM, AffineCurve, PairingEngine, ProjectiveCurve};
+use ark_ff::{Field, PrimeField, UniformRand, Zero};
+use ark_std::{rand::Rng, vec::Vec};
 
 use super::{PreparedVerifyingKey, Proof, VerifyingKey};
 
@@ -70,3 +71,56 @@ pub fn verify_proof<E: PairingEngine>(
     let prepared_inputs = prepare_inputs(pvk, public_inputs)?;
     verify_proof_with_prepared_inputs(pvk, proof, &prepared_inputs)
 }
+
+/// Verify a batch of proofs against the prepared verification key `pvk`, with respect to
+/// the instance `public_inputs[i]` of `proofs[i]`. The verification equations are combined
+/// with random scalars, so the cost is one MSM over the inputs, one miller loop per proof
+/// and a single final exponentiation. A `false` means at least one proof is invalid.
+pub fn verify_proofs_batch<E: PairingEngine, R: Rng>(
+    pvk: &PreparedVerifyingKey<E>,
+    proofs: &[Proof<E>],
+    public_inputs: &[Vec<E::Fr>],
+    rng: &mut R,
+) -> R1CSResult<bool> {
+    if proofs.len() != public_inputs.len() {
+        return Ok(false);
+    }
+    if public_inputs
+        .iter()
+        .any(|x| x.len() + 1 != pvk.vk.gamma_abc_g1.len())
+    {
+        return Err(SynthesisError::MalformedVerifyingKey);
+    }
+    if proofs.is_empty() {
+        return Ok(true);
+    }
+
+    let z: Vec<E::Fr> = (0..proofs.len()).map(|_| E::Fr::rand(rng)).collect();
+
+    // sum_i z_i * (IC_0 + sum_j x_ij * IC_j) = (sum_i z_i) * IC_0 + sum_j (sum_i z_i * x_ij) * IC_j
+    let mut scalars = vec![E::Fr::zero(); pvk.vk.gamma_abc_g1.len()];
+    for (zi, inputs) in z.iter().zip(public_inputs.iter()) {
+        scalars[0] += zi;
+        for (s, x) in scalars[1..].iter_mut().zip(inputs.iter()) {
+            *s += &(*zi * x);
+        }
+    }
+    let scalars: Vec<_> = scalars.iter().map(|s| s.into_repr()).collect();
+    let g_ic = VariableBaseMSM::multi_scalar_mul(&pvk.vk.gamma_abc_g1, &scalars);
+
+    let mut c = E::G1Projective::zero();
+    let mut pairs = Vec::with_capacity(proofs.len() + 2);
+    for (zi, proof) in z.iter().zip(proofs.iter()) {
+        let zi = zi.into_repr();
+        pairs.push((proof.a.mul(zi).into_affine().into(), proof.b.into()));
+        c += &proof.c.mul(zi);
+    }
+    pairs.push((g_ic.into_affine().into(), pvk.gamma_g2_neg_pc.clone()));
+    pairs.push((c.into_affine().into(), pvk.delta_g2_neg_pc.clone()));
+
+    let qap = E::miller_loop(pairs.iter());
+    let test = E::final_exponentiation(&qap).ok_or(SynthesisError::UnexpectedIdentity)?;
+
+    let sum_z = z.iter().fold(E::Fr::zero(), |acc, x| acc + x);
+    Ok(test == pvk.alpha_g1_beta_g2.pow(sum_z.into_repr()))
+}